@@ -0,0 +1,181 @@
+{ Declaration stubs of the Delphi RTL unit System.Classes, shipped with
+  the language server for go-to-definition and hover. Bodies are omitted;
+  this file does not compile. }
+unit System.Classes;
+
+interface
+
+uses
+  System, System.SysUtils;
+
+type
+  TNotifyEvent = procedure(Sender: TObject) of object;
+
+  TDuplicates = (dupIgnore, dupAccept, dupError);
+
+  TSeekOrigin = (soBeginning, soCurrent, soEnd);
+
+  TPersistent = class(TObject)
+  public
+    procedure Assign(Source: TPersistent); virtual;
+    function GetNamePath: string; virtual;
+  end;
+
+  TList = class(TObject)
+  private
+    FCount: Integer;
+    FCapacity: Integer;
+    function Get(Index: Integer): Pointer;
+    procedure Put(Index: Integer; Item: Pointer);
+  public
+    destructor Destroy; override;
+    function Add(Item: Pointer): Integer;
+    procedure Clear; virtual;
+    procedure Delete(Index: Integer);
+    function IndexOf(Item: Pointer): Integer;
+    procedure Insert(Index: Integer; Item: Pointer);
+    function Remove(Item: Pointer): Integer;
+    procedure Sort(Compare: TListSortCompare);
+    property Capacity: Integer read FCapacity;
+    property Count: Integer read FCount;
+    property Items[Index: Integer]: Pointer read Get write Put; default;
+  end;
+
+  TStrings = class(TPersistent)
+  private
+    function GetCommaText: string;
+    procedure SetCommaText(const Value: string);
+    function GetText: string;
+    procedure SetText(const Value: string);
+    function GetName(Index: Integer): string;
+    function GetValue(const Name: string): string;
+    procedure SetValue(const Name, Value: string);
+  protected
+    function Get(Index: Integer): string; virtual; abstract;
+    function GetCount: Integer; virtual; abstract;
+    function GetObject(Index: Integer): TObject; virtual;
+    procedure Put(Index: Integer; const S: string); virtual;
+    procedure PutObject(Index: Integer; AObject: TObject); virtual;
+  public
+    function Add(const S: string): Integer; virtual;
+    function AddObject(const S: string; AObject: TObject): Integer; virtual;
+    procedure AddStrings(Strings: TStrings); virtual;
+    procedure Assign(Source: TPersistent); override;
+    procedure BeginUpdate;
+    procedure EndUpdate;
+    procedure Clear; virtual; abstract;
+    procedure Delete(Index: Integer); virtual; abstract;
+    function IndexOf(const S: string): Integer; virtual;
+    function IndexOfName(const Name: string): Integer; virtual;
+    procedure Insert(Index: Integer; const S: string); virtual; abstract;
+    procedure LoadFromFile(const FileName: string); virtual;
+    procedure SaveToFile(const FileName: string); virtual;
+    property CommaText: string read GetCommaText write SetCommaText;
+    property Count: Integer read GetCount;
+    property Names[Index: Integer]: string read GetName;
+    property Objects[Index: Integer]: TObject read GetObject write PutObject;
+    property Values[const Name: string]: string read GetValue write SetValue;
+    property Strings[Index: Integer]: string read Get write Put; default;
+    property Text: string read GetText write SetText;
+  end;
+
+  TStringList = class(TStrings)
+  private
+    FCount: Integer;
+    FSorted: Boolean;
+    FDuplicates: TDuplicates;
+    FCaseSensitive: Boolean;
+    FOnChange: TNotifyEvent;
+    procedure SetSorted(Value: Boolean);
+    procedure SetCaseSensitive(const Value: Boolean);
+  protected
+    function Get(Index: Integer): string; override;
+    function GetCount: Integer; override;
+  public
+    constructor Create; overload;
+    constructor Create(OwnsObjects: Boolean); overload;
+    destructor Destroy; override;
+    function Add(const S: string): Integer; override;
+    procedure Clear; override;
+    procedure Delete(Index: Integer); override;
+    function Find(const S: string; var Index: Integer): Boolean; virtual;
+    function IndexOf(const S: string): Integer; override;
+    procedure Insert(Index: Integer; const S: string); override;
+    procedure Sort; virtual;
+    property Duplicates: TDuplicates read FDuplicates write FDuplicates;
+    property Sorted: Boolean read FSorted write SetSorted;
+    property CaseSensitive: Boolean read FCaseSensitive write SetCaseSensitive;
+    property OnChange: TNotifyEvent read FOnChange write FOnChange;
+  end;
+
+  TStream = class(TObject)
+  private
+    function GetPosition: Int64;
+    procedure SetPosition(const Pos: Int64);
+    function GetSize: Int64; virtual;
+  public
+    function Read(var Buffer; Count: Integer): Integer; virtual; abstract;
+    function Write(const Buffer; Count: Integer): Integer; virtual; abstract;
+    function Seek(const Offset: Int64; Origin: TSeekOrigin): Int64; virtual; abstract;
+    procedure ReadBuffer(var Buffer; Count: Integer);
+    procedure WriteBuffer(const Buffer; Count: Integer);
+    function CopyFrom(Source: TStream; Count: Int64): Int64;
+    property Position: Int64 read GetPosition write SetPosition;
+    property Size: Int64 read GetSize;
+  end;
+
+  TMemoryStream = class(TStream)
+  public
+    destructor Destroy; override;
+    procedure Clear;
+    procedure LoadFromFile(const FileName: string);
+    procedure SaveToFile(const FileName: string);
+  end;
+
+  TFileStream = class(TStream)
+  public
+    constructor Create(const FileName: string; Mode: Word);
+    destructor Destroy; override;
+  end;
+
+  TComponent = class(TPersistent)
+  private
+    FOwner: TComponent;
+    FName: string;
+    FTag: NativeInt;
+    function GetComponent(Index: Integer): TComponent;
+    function GetComponentCount: Integer;
+    procedure SetName(const NewName: string);
+  public
+    constructor Create(AOwner: TComponent); virtual;
+    destructor Destroy; override;
+    function FindComponent(const AName: string): TComponent;
+    property ComponentCount: Integer read GetComponentCount;
+    property Components[Index: Integer]: TComponent read GetComponent;
+    property Owner: TComponent read FOwner;
+  published
+    property Name: string read FName write SetName;
+    property Tag: NativeInt read FTag write FTag;
+  end;
+
+  TThread = class(TObject)
+  private
+    FTerminated: Boolean;
+    FFreeOnTerminate: Boolean;
+  protected
+    procedure Execute; virtual; abstract;
+  public
+    constructor Create(CreateSuspended: Boolean);
+    destructor Destroy; override;
+    procedure Start;
+    procedure Terminate;
+    function WaitFor: Cardinal;
+    class procedure Synchronize(AThread: TThread; AMethod: TThreadMethod); static;
+    class procedure Queue(AThread: TThread; AMethod: TThreadMethod); static;
+    property FreeOnTerminate: Boolean read FFreeOnTerminate write FFreeOnTerminate;
+    property Terminated: Boolean read FTerminated;
+  end;
+
+implementation
+
+end.
@@ -0,0 +1,89 @@
+{ Declaration stubs of the Delphi RTL unit System.SysUtils, shipped with
+  the language server for go-to-definition and hover. Bodies are omitted;
+  this file does not compile. }
+unit System.SysUtils;
+
+interface
+
+uses
+  System;
+
+type
+  Exception = class(TObject)
+  private
+    FMessage: string;
+  public
+    constructor Create(const Msg: string);
+    constructor CreateFmt(const Msg: string; const Args: array of const);
+    property Message: string read FMessage write FMessage;
+  end;
+
+  ExceptClass = class of Exception;
+
+  EAbort = class(Exception);
+  EConvertError = class(Exception);
+  EArgumentException = class(Exception);
+  EArgumentOutOfRangeException = class(EArgumentException);
+  EInvalidOperation = class(Exception);
+  ENotImplemented = class(Exception);
+  EAccessViolation = class(Exception);
+  EInOutError = class(Exception);
+
+  TFormatSettings = record
+    DecimalSeparator: Char;
+    ThousandSeparator: Char;
+    DateSeparator: Char;
+    TimeSeparator: Char;
+    ShortDateFormat: string;
+    LongDateFormat: string;
+    class function Create: TFormatSettings; static;
+  end;
+
+procedure Abort;
+procedure FreeAndNil(var Obj);
+function Supports(const Instance: IInterface; const IID: TGUID; out Intf): Boolean;
+
+function IntToStr(Value: Integer): string;
+function IntToHex(Value: Integer; Digits: Integer): string;
+function StrToInt(const S: string): Integer;
+function StrToIntDef(const S: string; Default: Integer): Integer;
+function TryStrToInt(const S: string; out Value: Integer): Boolean;
+function FloatToStr(Value: Extended): string;
+function StrToFloat(const S: string): Extended;
+function BoolToStr(B: Boolean; UseBoolStrs: Boolean = False): string;
+function Format(const Format: string; const Args: array of const): string;
+
+function Trim(const S: string): string;
+function TrimLeft(const S: string): string;
+function TrimRight(const S: string): string;
+function UpperCase(const S: string): string;
+function LowerCase(const S: string): string;
+function SameText(const S1, S2: string): Boolean;
+function CompareText(const S1, S2: string): Integer;
+function CompareStr(const S1, S2: string): Integer;
+function QuotedStr(const S: string): string;
+function StringReplace(const Source, OldPattern, NewPattern: string; Flags: TReplaceFlags): string;
+
+function Now: TDateTime;
+function Date: TDateTime;
+function Time: TDateTime;
+function DateToStr(const DateTime: TDateTime): string;
+function DateTimeToStr(const DateTime: TDateTime): string;
+function FormatDateTime(const Format: string; DateTime: TDateTime): string;
+
+function FileExists(const FileName: string): Boolean;
+function DirectoryExists(const Directory: string): Boolean;
+function ForceDirectories(Dir: string): Boolean;
+function DeleteFile(const FileName: string): Boolean;
+function ExtractFilePath(const FileName: string): string;
+function ExtractFileName(const FileName: string): string;
+function ExtractFileExt(const FileName: string): string;
+function ChangeFileExt(const FileName, Extension: string): string;
+function IncludeTrailingPathDelimiter(const S: string): string;
+function GetCurrentDir: string;
+
+procedure Sleep(Milliseconds: Cardinal);
+
+implementation
+
+end.
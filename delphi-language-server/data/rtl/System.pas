@@ -0,0 +1,88 @@
+{ Declaration stubs of the Delphi RTL unit System, shipped with the
+  language server for go-to-definition and hover. Bodies are omitted;
+  this file does not compile. }
+unit System;
+
+interface
+
+type
+  TDateTime = type Double;
+  PChar = ^Char;
+  TArray<T> = array of T;
+
+  TClass = class of TObject;
+
+  TObject = class
+  public
+    constructor Create;
+    destructor Destroy; virtual;
+    procedure Free;
+    procedure AfterConstruction; virtual;
+    procedure BeforeDestruction; virtual;
+    function ClassType: TClass;
+    class function ClassName: string;
+    class function ClassNameIs(const Name: string): Boolean;
+    class function ClassParent: TClass;
+    class function InheritsFrom(AClass: TClass): Boolean;
+    function Equals(Obj: TObject): Boolean; virtual;
+    function GetHashCode: Integer; virtual;
+    function ToString: string; virtual;
+  end;
+
+  TGUID = record
+    D1: Cardinal;
+    D2: Word;
+    D3: Word;
+    D4: array[0..7] of Byte;
+  end;
+
+  IInterface = interface
+    ['{00000000-0000-0000-C000-000000000046}']
+    function QueryInterface(const IID: TGUID; out Obj): HResult; stdcall;
+    function _AddRef: Integer; stdcall;
+    function _Release: Integer; stdcall;
+  end;
+
+  TInterfacedObject = class(TObject, IInterface)
+  protected
+    FRefCount: Integer;
+    function QueryInterface(const IID: TGUID; out Obj): HResult; stdcall;
+    function _AddRef: Integer; stdcall;
+    function _Release: Integer; stdcall;
+  public
+    property RefCount: Integer read FRefCount;
+  end;
+
+procedure Assert(Condition: Boolean; const Message: string);
+function Assigned(const P): Boolean;
+procedure Inc(var X; N: Integer = 1);
+procedure Dec(var X; N: Integer = 1);
+function Length(const S): Integer;
+procedure SetLength(var S; NewLength: Integer);
+function High(const X): Integer;
+function Low(const X): Integer;
+function Copy(const S: string; Index, Count: Integer): string;
+procedure Delete(var S: string; Index, Count: Integer);
+procedure Insert(const Source: string; var Dest: string; Index: Integer);
+function Pos(const SubStr, Str: string; Offset: Integer = 1): Integer;
+function Ord(const X): Integer;
+function Chr(X: Byte): Char;
+function Round(X: Extended): Int64;
+function Trunc(X: Extended): Int64;
+function Abs(X: Integer): Integer;
+procedure Exit;
+procedure Halt(ExitCode: Integer = 0);
+procedure FreeMem(var P: Pointer);
+procedure GetMem(var P: Pointer; Size: Integer);
+procedure Write(const Args);
+procedure Writeln(const Args);
+procedure Read(var Args);
+procedure Readln(var Args);
+function ParamCount: Integer;
+function ParamStr(Index: Integer): string;
+procedure Randomize;
+function Random(Range: Integer): Integer;
+
+implementation
+
+end.
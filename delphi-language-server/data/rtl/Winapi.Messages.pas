@@ -0,0 +1,126 @@
+{ Declaration stubs of the Delphi RTL unit Winapi.Messages, shipped with
+  the language server for go-to-definition and hover. Bodies are omitted;
+  this file does not compile. }
+unit Winapi.Messages;
+
+interface
+
+uses
+  System;
+
+const
+  WM_NULL = $0000;
+  WM_CREATE = $0001;
+  WM_DESTROY = $0002;
+  WM_MOVE = $0003;
+  WM_SIZE = $0005;
+  WM_ACTIVATE = $0006;
+  WM_SETFOCUS = $0007;
+  WM_KILLFOCUS = $0008;
+  WM_ENABLE = $000A;
+  WM_SETTEXT = $000C;
+  WM_GETTEXT = $000D;
+  WM_PAINT = $000F;
+  WM_CLOSE = $0010;
+  WM_QUIT = $0012;
+  WM_ERASEBKGND = $0014;
+  WM_SHOWWINDOW = $0018;
+  WM_SETCURSOR = $0020;
+  WM_GETMINMAXINFO = $0024;
+  WM_COPYDATA = $004A;
+  WM_NOTIFY = $004E;
+  WM_CONTEXTMENU = $007B;
+  WM_NCHITTEST = $0084;
+  WM_KEYDOWN = $0100;
+  WM_KEYUP = $0101;
+  WM_CHAR = $0102;
+  WM_SYSKEYDOWN = $0104;
+  WM_COMMAND = $0111;
+  WM_SYSCOMMAND = $0112;
+  WM_TIMER = $0113;
+  WM_HSCROLL = $0114;
+  WM_VSCROLL = $0115;
+  WM_MOUSEMOVE = $0200;
+  WM_LBUTTONDOWN = $0201;
+  WM_LBUTTONUP = $0202;
+  WM_LBUTTONDBLCLK = $0203;
+  WM_RBUTTONDOWN = $0204;
+  WM_RBUTTONUP = $0205;
+  WM_MOUSEWHEEL = $020A;
+  WM_DROPFILES = $0233;
+  WM_USER = $0400;
+  WM_APP = $8000;
+
+type
+  TMessage = record
+    Msg: Cardinal;
+    WParam: NativeUInt;
+    LParam: NativeInt;
+    Result: NativeInt;
+  end;
+
+  TWMNoParams = record
+    Msg: Cardinal;
+    Unused: array[0..1] of NativeInt;
+    Result: NativeInt;
+  end;
+
+  TWMPaint = record
+    Msg: Cardinal;
+    DC: NativeUInt;
+    Unused: NativeInt;
+    Result: NativeInt;
+  end;
+
+  TWMSize = record
+    Msg: Cardinal;
+    SizeType: NativeUInt;
+    Width: Word;
+    Height: Word;
+    Result: NativeInt;
+  end;
+
+  TWMKey = record
+    Msg: Cardinal;
+    CharCode: Word;
+    KeyData: NativeInt;
+    Result: NativeInt;
+  end;
+
+  TWMMouse = record
+    Msg: Cardinal;
+    Keys: NativeInt;
+    XPos: Smallint;
+    YPos: Smallint;
+    Result: NativeInt;
+  end;
+
+  TWMCommand = record
+    Msg: Cardinal;
+    ItemID: Word;
+    NotifyCode: Word;
+    Ctl: NativeUInt;
+    Result: NativeInt;
+  end;
+
+  TWMTimer = record
+    Msg: Cardinal;
+    TimerID: NativeUInt;
+    TimerProc: Pointer;
+    Result: NativeInt;
+  end;
+
+  TWMClose = TWMNoParams;
+  TWMDestroy = TWMNoParams;
+  TWMChar = TWMKey;
+  TWMKeyDown = TWMKey;
+  TWMKeyUp = TWMKey;
+  TWMMouseMove = TWMMouse;
+  TWMLButtonDown = TWMMouse;
+  TWMLButtonUp = TWMMouse;
+  TWMRButtonDown = TWMMouse;
+  TWMRButtonUp = TWMMouse;
+
+implementation
+
+end.
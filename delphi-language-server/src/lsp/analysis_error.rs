@@ -0,0 +1,143 @@
+use crate::lsp::protocol_ext::lsp_schema;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tower_lsp::lsp_types::Position;
+
+/// How many failures are kept per document, the oldest dropped first.
+const MAX_RECORDED_FAILURES: usize = 20;
+
+/// Why the analysis of a document could not answer a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnalysisError {
+    /// The document is not open.
+    NotOpen,
+    /// The parser returned no tree.
+    ParseFailed,
+    /// The analyzer has no syntax tree.
+    NoTree,
+    /// The node at the position is inside a syntax error.
+    SyntaxError,
+    /// The node at the position, of the given kind, is not an identifier.
+    NotIdentifier(String),
+    /// The identifier is a use rather than a declaration, and no
+    /// declaration of it outside the document was found.
+    NotDeclaration(String),
+    /// Neither the document nor the units it uses declare the name.
+    NotDeclared(String),
+    /// The document declares the name, but not in scope at the position.
+    NotInScope(String),
+    /// The workspace is still being indexed, so the units the document
+    /// uses were not searched.
+    IndexNotReady,
+    /// The document is analyzed in outline mode for its size.
+    LargeFile,
+}
+
+impl AnalysisError {
+    /// A stable identifier of the variant, for clients to match on.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AnalysisError::NotOpen => "not-open",
+            AnalysisError::ParseFailed => "parse-failed",
+            AnalysisError::NoTree => "no-tree",
+            AnalysisError::SyntaxError => "syntax-error",
+            AnalysisError::NotIdentifier(_) => "not-identifier",
+            AnalysisError::NotDeclaration(_) => "not-declaration",
+            AnalysisError::NotDeclared(_) => "not-declared",
+            AnalysisError::NotInScope(_) => "not-in-scope",
+            AnalysisError::IndexNotReady => "index-not-ready",
+            AnalysisError::LargeFile => "large-file",
+        }
+    }
+}
+
+impl fmt::Display for AnalysisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnalysisError::NotOpen => write!(f, "The document is not open"),
+            AnalysisError::ParseFailed => write!(f, "The document could not be parsed"),
+            AnalysisError::NoTree => write!(f, "The document has no syntax tree"),
+            AnalysisError::SyntaxError => write!(f, "The node at the position is a syntax error"),
+            AnalysisError::NotIdentifier(kind) => {
+                write!(
+                    f,
+                    "The node at the position is a {}, not an identifier",
+                    kind
+                )
+            }
+            AnalysisError::NotDeclaration(name) => write!(
+                f,
+                "'{}' is not a declaration, and was not found in the RTL",
+                name
+            ),
+            AnalysisError::NotDeclared(name) => write!(
+                f,
+                "'{}' is declared neither in the document nor in the units it uses",
+                name
+            ),
+            AnalysisError::NotInScope(name) => write!(
+                f,
+                "'{}' is declared in the document, but not in scope at the position",
+                name
+            ),
+            AnalysisError::IndexNotReady => write!(f, "The workspace is still being indexed"),
+            AnalysisError::LargeFile => write!(
+                f,
+                "The document is too large for a full analysis; run dls.analyzeFully to enable it"
+            ),
+        }
+    }
+}
+
+/// A request a document failed to answer, as reported by `dls/status` and
+/// `dls.showDocumentDiagnostics`.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AnalysisFailure {
+    /// The request that failed, such as `hover`.
+    pub operation: String,
+    #[schemars(with = "Option<lsp_schema::Position>")]
+    pub position: Option<Position>,
+    /// The [`AnalysisError::code`] of the error.
+    pub code: &'static str,
+    pub message: String,
+    /// When the request failed, in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+impl AnalysisFailure {
+    pub fn new(operation: &str, position: Option<Position>, error: &AnalysisError) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+        Self {
+            operation: operation.to_string(),
+            position,
+            code: error.code(),
+            message: error.to_string(),
+            timestamp,
+        }
+    }
+}
+
+/// The last failures of a document, oldest first.
+#[derive(Debug, Clone, Default)]
+pub struct FailureLog {
+    failures: VecDeque<AnalysisFailure>,
+}
+
+impl FailureLog {
+    pub fn record(&mut self, failure: AnalysisFailure) {
+        if self.failures.len() == MAX_RECORDED_FAILURES {
+            self.failures.pop_front();
+        }
+        self.failures.push_back(failure);
+    }
+
+    pub fn failures(&self) -> Vec<AnalysisFailure> {
+        self.failures.iter().cloned().collect()
+    }
+}
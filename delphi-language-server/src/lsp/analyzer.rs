@@ -1,7 +1,178 @@
-use std::collections::HashMap;
+use crate::lsp::analysis_error::AnalysisError;
+use crate::lsp::conditionals::{self, Conditional, ConditionalSite, ConditionalUse};
+use crate::lsp::config::{OutlineSort, Settings};
+use crate::lsp::constants::ConstEvaluator;
+use crate::lsp::directives::{self, Dialect};
+use crate::lsp::docs::format_signature;
+use crate::lsp::document::{read_source, slice_text};
+use crate::lsp::fixes::{self, MechanicalFix};
+use crate::lsp::flow::{self, UNINITIALIZED_VARIABLE};
+use crate::lsp::format::Formatter;
+use crate::lsp::guid::{self, InterfaceGuid};
+use crate::lsp::keywords::{
+    collides_with_keyword, completion_keywords, is_reserved_word, unescape_identifier, INTRINSICS,
+};
+use crate::lsp::members::{
+    self, split_generic, substitute_type_params, AccessContext, AccessorKind, Member, MemberKind,
+    Parameter, PropertySignature, TypeTable, Visibility,
+};
+use crate::lsp::naming::{NameCategory, NamingRule, NAMING_CONVENTION};
+use crate::lsp::protocol_ext::{
+    lsp_schema, OutlineParams, OutlineSymbol, Section, TypeMember, TypeMembers,
+};
+use crate::lsp::rtl::RtlQuery;
+use crate::lsp::stats::ParseStats;
+use crate::lsp::strings;
+use crate::lsp::symbol_id::SymbolId;
+use crate::lsp::text_position::{range_contains, LineEnding, LineIndex, PositionEncoding};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
 use tower_lsp::lsp_types::*;
 use tree_sitter::Node;
 
+/// Code of the diagnostic reporting access to a member hidden by visibility.
+pub const INACCESSIBLE_MEMBER: &str = "inaccessible-member";
+
+/// Code of the diagnostic reporting a missing or incompatible property
+/// read/write accessor.
+pub const INVALID_PROPERTY_ACCESSOR: &str = "invalid-property-accessor";
+
+/// Code of the diagnostic reporting a declaration named after a keyword or
+/// directive of the configured language version.
+pub const RESERVED_IDENTIFIER: &str = "reserved-identifier";
+
+/// Code of the diagnostic reporting a case label whose value is already
+/// covered by an earlier label of the same case statement.
+pub const DUPLICATE_CASE_LABEL: &str = "duplicate-case-label";
+
+/// Code of the diagnostic reporting a `{$R *.dfm}` directive without a form
+/// file next to the unit, or a form file without the directive.
+pub const FORM_FILE_MISMATCH: &str = "form-file-mismatch";
+
+/// Code of the diagnostic reporting a private member or implementation
+/// routine never used in its unit.
+pub const UNUSED_PRIVATE_MEMBER: &str = "unused-private-member";
+
+/// Code of the diagnostic reporting a `uses` entry naming a unit whose file
+/// was deleted or moved on disk.
+pub const MISSING_UNIT: &str = "missing-unit";
+
+/// Code of the diagnostic reporting a `uses` entry naming a unit found
+/// neither on the search path nor among the RTL stubs. The search path is
+/// the `search_paths` of the `dls.toml` governing the document, besides
+/// its own folder, or the workspace folders without one.
+pub const UNRESOLVED_UNIT: &str = "unresolved-unit";
+
+/// How many searched folders the message of an unresolved unit lists; the
+/// diagnostic data holds them all.
+const MAX_LISTED_SEARCH_PATHS: usize = 3;
+
+/// Code of the diagnostic reporting syntax the dialect of the file lacks,
+/// such as FreePascal's `generic` and `specialize` outside objfpc mode.
+pub const DIALECT_SYNTAX: &str = "dialect-syntax";
+
+/// Code of the diagnostic reporting an interface GUID literal that is not
+/// in `{XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX}` format.
+pub const INVALID_GUID: &str = "invalid-guid";
+
+/// Code of the diagnostic reporting an interface GUID already used by
+/// another interface, which makes `QueryInterface` and `Supports` return the
+/// wrong interface.
+pub const DUPLICATE_GUID: &str = "duplicate-guid";
+
+/// Code of the diagnostic reporting a method resolution clause naming a
+/// missing interface method, or a target missing or not matching it.
+pub const INVALID_METHOD_RESOLUTION: &str = "invalid-method-resolution";
+
+/// Code of the diagnostic reporting a method of an implemented interface
+/// that the class does not implement.
+pub const MISSING_INTERFACE_METHOD: &str = "missing-interface-method";
+
+/// Code of the diagnostic reporting a declaration named after an intrinsic
+/// routine that the unit calls, so the calls resolve to the declaration.
+pub const SHADOWED_INTRINSIC: &str = "shadowed-intrinsic";
+
+/// Code of the diagnostic reporting an `override` method with no virtual
+/// or dynamic method of its name in an ancestor.
+pub const INVALID_OVERRIDE: &str = "invalid-override";
+
+/// Code of the diagnostic reporting a `message` method that does not take
+/// exactly one `var` parameter.
+pub const INVALID_MESSAGE_HANDLER: &str = "invalid-message-handler";
+
+/// Code of the diagnostic reporting a routine implementation whose calling
+/// convention or `class` keyword differs from its declaration.
+pub const DECLARATION_MISMATCH: &str = "declaration-mismatch";
+
+/// Code of the diagnostic reporting a routine assigned to a procedural
+/// variable or event property whose type declares another calling
+/// convention.
+pub const INCOMPATIBLE_CALLING_CONVENTION: &str = "incompatible-calling-convention";
+
+/// Code of the diagnostic reporting a routine declared more than once in
+/// a scope without the `overload` directive.
+pub const MISSING_OVERLOAD: &str = "missing-overload";
+
+/// Code of the diagnostic reporting overloads that a call cannot tell
+/// apart.
+pub const AMBIGUOUS_OVERLOAD: &str = "ambiguous-overload";
+
+/// Code of the diagnostic reporting a conditional symbol tested by
+/// `{$IFDEF}` or `Defined()` that nothing known defines, likely a typo.
+pub const UNDEFINED_CONDITIONAL: &str = "undefined-conditional";
+
+/// The longest name of an outline node synthesized from a statement, in
+/// characters.
+const MAX_BLOCK_NAME_LENGTH: usize = 40;
+
+/// Node kinds of the calling-convention directives on a routine header.
+const CALLING_CONVENTIONS: &[&str] = &[
+    "kStdcall",
+    "kCdecl",
+    "kPascal",
+    "kRegister",
+    "kSafecall",
+    "kWinapi",
+    "kCppdecl",
+    "kMwpascal",
+    "kVectorcall",
+    "kMs_abi_default",
+    "kMs_abi_cdecl",
+    "kSysv_abi_default",
+    "kSysv_abi_cdecl",
+];
+
+/// The generic RTL collections whose `for..in` loops yield their first
+/// type argument.
+const ENUMERABLE_COLLECTIONS: &[&str] = &[
+    "TArray",
+    "TEnumerable",
+    "IEnumerable",
+    "TList",
+    "TObjectList",
+    "TThreadList",
+    "TQueue",
+    "TObjectQueue",
+    "TStack",
+    "TObjectStack",
+];
+
+/// The generic RTL dictionaries, whose `for..in` loops yield `TPair`s.
+const ENUMERABLE_DICTIONARIES: &[&str] = &["TDictionary", "TObjectDictionary"];
+
+/// How many type aliases are followed to resolve a type, guarding against
+/// alias cycles in broken code.
+const MAX_ALIAS_DEPTH: usize = 8;
+
+/// How many lookups a [`ChainResolver`] makes for one request, counting
+/// each type searched for a member and each unit consulted, so that long
+/// chains or inheritance cycles across units cannot stall it.
+const MAX_CHAIN_STEPS: usize = 64;
+
 #[derive(Debug, Clone)]
 pub struct Symbol {
     pub name: String,
@@ -10,13 +181,300 @@ pub struct Symbol {
     pub selection_range: Range,
     pub children: Vec<Symbol>,
     pub detail: Option<String>,
+    /// Set for routines declared `external`; they never have a Pascal body.
+    pub external: Option<ExternalImport>,
+    pub id: SymbolId,
+    /// The visibility of a type member, `None` outside types.
+    pub visibility: Option<Visibility>,
+    /// Routine directives such as `virtual` or `overload`, lowercase.
+    pub directives: Vec<String>,
+    pub deprecated: bool,
+    /// The routine a parameter, local declaration or nested routine belongs
+    /// to; `None` for symbols visible throughout the unit.
+    pub scope: Option<Range>,
+}
+
+/// Import metadata of a routine declared with an `external` clause.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ExternalImport {
+    /// The library the routine is imported from, `None` for object-file links.
+    pub library: Option<String>,
+    /// The exported name given with `name '...'`.
+    pub name: Option<String>,
+    /// The export ordinal given with `index N`.
+    pub index: Option<String>,
+    pub delayed: bool,
+    pub calling_convention: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ExternalRoutine {
+    pub name: String,
+    #[schemars(with = "lsp_schema::Range")]
+    pub range: Range,
+    #[serde(flatten)]
+    pub import: ExternalImport,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ExternalLibrary {
+    pub library: String,
+    pub imports: Vec<ExternalRoutine>,
+}
+
+/// The chain of symbols enclosing a position, outermost first, as returned
+/// by `dls.symbolPath`.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SymbolPath {
+    /// The labels of the segments joined with ` / `, e.g.
+    /// `MyUnit / TCustomerList / Add(const Item: TCustomer)`.
+    pub display: String,
+    pub segments: Vec<SymbolPathSegment>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SymbolPathSegment {
+    /// The name, followed by the parameter list for routines.
+    pub label: String,
+    #[schemars(with = "lsp_schema::SymbolKind")]
+    pub kind: SymbolKind,
+    #[schemars(with = "lsp_schema::Range")]
+    pub range: Range,
+    #[schemars(with = "lsp_schema::Range")]
+    pub selection_range: Range,
+}
+
+/// A routine declaration as compared with its overloads.
+struct RoutineSignature<'a> {
+    header: Node<'a>,
+    name: Node<'a>,
+    /// The header of the implementation of a declaration made in the
+    /// interface section, with `forward` or inside a type.
+    implementation: Option<Node<'a>>,
+    /// The type of each parameter, lowercase with aliases resolved, and
+    /// whether it is passed by reference.
+    params: Vec<(String, bool)>,
+    /// How many parameters come before the first default value.
+    required: usize,
+}
+
+/// The implicit `Result` or `Self` variable of a routine.
+struct ImplicitIdentifier<'a> {
+    name: String,
+    type_name: String,
+    /// Where go-to-definition leads: the function's return type for
+    /// `Result`, the class declaration for `Self`.
+    declaration: Range,
+    header: Node<'a>,
+}
+
+/// An identifier of the document, as indexed by name.
+#[derive(Debug, Clone, Copy)]
+struct Occurrence {
+    range: Range,
+    /// Whether the identifier names a declaration rather than uses it.
+    declaration: bool,
+    /// Whether the identifier follows a dot, as in `Customer.Name`, where
+    /// it never names a local.
+    qualified: bool,
+    /// For a parameter of a routine header without a body, the header,
+    /// the only place where the name means that parameter.
+    scope: Option<Range>,
+}
+
+/// What an occurrence edited by a rename is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenameKind {
+    Declaration,
+    Reference,
+    /// A word in a string or comment, which may or may not mean the
+    /// identifier.
+    Text,
+}
+
+#[derive(Debug, Clone)]
+pub struct RenameOccurrence {
+    pub range: Range,
+    pub kind: RenameKind,
+}
+
+/// A private member or implementation-section routine without uses.
+struct UnusedDeclaration<'a> {
+    name: Node<'a>,
+    /// "Private field", "Private method" or "Routine".
+    kind: &'static str,
+    /// The declaration and, for methods, the implementation bodies. Empty
+    /// when the declaration cannot be removed on its own, as in `A, B: T`.
+    removal: Vec<Node<'a>>,
+}
+
+/// A block construct enclosing a position, with the ranges of its opening
+/// and closing keyword tokens when it has them (case branches do not).
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EnclosingBlock {
+    pub kind: String,
+    #[schemars(with = "lsp_schema::Range")]
+    pub range: Range,
+    #[schemars(with = "Option<lsp_schema::Range>")]
+    pub open_range: Option<Range>,
+    #[schemars(with = "Option<lsp_schema::Range>")]
+    pub close_range: Option<Range>,
+}
+
+/// How the analyzer arrived at a resolution. With `analysis.strict`, a
+/// resolution relying on a heuristic counts as unknown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Provenance {
+    /// Followed from declarations the analyzer sees.
+    Declared,
+    /// Relied on a heuristic, described as what was assumed, e.g. `TList
+    /// assumed to be the System.Generics.Collections type`.
+    Heuristic(String),
+}
+
+/// A resolved value with how it was resolved.
+#[derive(Debug, Clone)]
+pub struct Resolution<T> {
+    pub value: T,
+    pub provenance: Provenance,
+}
+
+impl<T> Resolution<T> {
+    pub fn declared(value: T) -> Self {
+        Self {
+            value,
+            provenance: Provenance::Declared,
+        }
+    }
+
+    pub fn heuristic(value: T, reason: String) -> Self {
+        Self {
+            value,
+            provenance: Provenance::Heuristic(reason),
+        }
+    }
+}
+
+/// How much of a document is analyzed. Documents larger than
+/// `performance.largeFileSize` get the outline mode until the user asks for
+/// a full analysis with `dls.analyzeFully`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum AnalysisMode {
+    #[default]
+    Full,
+    /// Symbols for the outline, folding and syntax errors only: no
+    /// occurrence index and no semantic diagnostics. The server fails the
+    /// other requests with [`AnalysisError::LargeFile`].
+    Outline,
+}
+
+/// The analysis of one version of a document. It is never modified once
+/// built, so requests sharing a snapshot answer from the same text and tree.
+pub type AnalysisSnapshot = Arc<SymbolAnalyzer>;
+
+/// A member reached through a member access chain, such as `Address` in
+/// `Order.Customer.Address.City`.
+#[derive(Debug, Clone)]
+pub struct ChainMember {
+    /// The member, with the type arguments of a generic owner substituted
+    /// in its type.
+    pub member: Member,
+    /// The type declaring the member, an ancestor for inherited members.
+    pub declaring_type: String,
+    /// The unit declaring the type, `None` for the document itself.
+    pub unit: Option<String>,
+    pub location: Location,
+}
+
+/// Finds the unit declaring a name the analysis asking does not declare,
+/// among the units it uses: the unit's name and analysis.
+type UnitLookup<'a> = dyn FnMut(&SymbolAnalyzer, &str) -> Option<(String, AnalysisSnapshot)> + 'a;
+
+/// Follows member access chains for one request. The units found to
+/// declare a name are cached, since a chain tends to name the same types
+/// again, and the lookups are capped at [`MAX_CHAIN_STEPS`].
+pub struct ChainResolver<'a> {
+    units: Box<UnitLookup<'a>>,
+    cache: HashMap<String, Option<(String, AnalysisSnapshot)>>,
+    steps: usize,
+}
+
+impl<'a> ChainResolver<'a> {
+    pub fn new(
+        units: impl FnMut(&SymbolAnalyzer, &str) -> Option<(String, AnalysisSnapshot)> + 'a,
+    ) -> Self {
+        Self {
+            units: Box::new(units),
+            cache: HashMap::new(),
+            steps: 0,
+        }
+    }
+
+    /// A resolver knowing only the declarations of the document.
+    pub fn local() -> Self {
+        Self::new(|_, _| None)
+    }
+
+    /// Counts a lookup; `false` once the budget is spent.
+    fn step(&mut self) -> bool {
+        self.steps += 1;
+        self.steps <= MAX_CHAIN_STEPS
+    }
+
+    fn unit_declaring(
+        &mut self,
+        analyzer: &SymbolAnalyzer,
+        name: &str,
+    ) -> Option<(String, AnalysisSnapshot)> {
+        let key = name.to_lowercase();
+        if let Some(found) = self.cache.get(&key) {
+            return found.clone();
+        }
+        let found = if self.step() {
+            (self.units)(analyzer, name)
+        } else {
+            None
+        };
+        self.cache.insert(key, found.clone());
+        found
+    }
+}
+
+/// What a step of a member access chain looks up in a type.
+#[derive(Debug, Clone, Copy)]
+enum MemberQuery<'a> {
+    Named(&'a str),
+    /// The default array property, read by indexing an instance.
+    DefaultProperty,
 }
 
+#[derive(Debug)]
 pub struct SymbolAnalyzer {
     tree: Option<tree_sitter::Tree>,
     source: String,
+    line_index: LineIndex,
+    /// The symbol tree of the document, top-level symbols first.
+    symbols: Vec<Symbol>,
+    /// All symbols of the tree by lowercase name.
     symbol_map: HashMap<String, Vec<Symbol>>,
+    /// Every identifier of the tree by lowercase name, in source order.
+    occurrences: HashMap<String, Vec<Occurrence>>,
+    type_table: TypeTable,
     document_uri: Option<Url>,
+    /// Version of the document the content was taken from, `None` for
+    /// files read from disk.
+    document_version: Option<i32>,
+    dialect: Dialect,
+    settings: Settings,
+    position_encoding: PositionEncoding,
+    mode: AnalysisMode,
 }
 
 impl SymbolAnalyzer {
@@ -24,365 +482,5437 @@ impl SymbolAnalyzer {
         Self {
             tree: None,
             source: String::new(),
+            line_index: LineIndex::new("", PositionEncoding::default()),
+            symbols: Vec::new(),
             symbol_map: HashMap::new(),
+            occurrences: HashMap::new(),
+            type_table: TypeTable::default(),
             document_uri: None,
+            document_version: None,
+            dialect: Dialect::default(),
+            settings: Settings::default(),
+            position_encoding: PositionEncoding::default(),
+            mode: AnalysisMode::default(),
         }
     }
 
-    pub fn set_content(&mut self, tree: tree_sitter::Tree, source: String, uri: Url) {
+    pub fn set_settings(&mut self, settings: Settings) {
+        self.settings = settings;
+    }
+
+    pub fn set_position_encoding(&mut self, encoding: PositionEncoding) {
+        self.position_encoding = encoding;
+    }
+
+    /// Sets how much of the content `set_content` analyzes.
+    pub fn set_mode(&mut self, mode: AnalysisMode) {
+        self.mode = mode;
+    }
+
+    pub fn mode(&self) -> AnalysisMode {
+        self.mode
+    }
+
+    pub fn set_content(
+        &mut self,
+        tree: tree_sitter::Tree,
+        source: String,
+        uri: Url,
+        version: Option<i32>,
+    ) {
         self.tree = Some(tree);
+        self.line_index = LineIndex::new(&source, self.position_encoding);
         self.source = source;
         self.document_uri = Some(uri);
+        self.document_version = version;
+        self.dialect = self.detect_dialect();
         self.update_symbol_map();
     }
 
+    /// The dialect selected by the first `{$MODE}` directive, or the default
+    /// dialect for the file extension.
+    fn detect_dialect(&self) -> Dialect {
+        let mut stack: Vec<Node> = self.tree.iter().map(|tree| tree.root_node()).collect();
+        let mut modes = Vec::new();
+        while let Some(node) = stack.pop() {
+            if node.kind() == "pp" {
+                if let Some(dialect) = directives::mode_directive(&self.get_node_text(node)) {
+                    modes.push((node.start_byte(), dialect));
+                }
+            }
+            let mut cursor = node.walk();
+            stack.extend(node.children(&mut cursor));
+        }
+        modes
+            .into_iter()
+            .min_by_key(|(start, _)| *start)
+            .map(|(_, dialect)| dialect)
+            .or_else(|| self.unit_path().map(|path| Dialect::from_path(&path)))
+            .unwrap_or_default()
+    }
+
+    pub fn get_dialect(&self) -> Dialect {
+        self.dialect
+    }
+
+    pub fn get_version(&self) -> Option<i32> {
+        self.document_version
+    }
+
+    /// Sets the document version of an analysis made from a file read from
+    /// disk, once the editor opens the file with the same text.
+    pub fn set_version(&mut self, version: Option<i32>) {
+        self.document_version = version;
+    }
+
+    pub fn get_source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn get_tree(&self) -> Option<&tree_sitter::Tree> {
+        self.tree.as_ref()
+    }
+
+    pub fn get_parse_stats(&self) -> ParseStats {
+        self.tree
+            .as_ref()
+            .map(|tree| ParseStats::collect(tree.root_node(), self.source.len()))
+            .unwrap_or_default()
+    }
+
     fn update_symbol_map(&mut self) {
         self.symbol_map.clear();
+        self.symbols.clear();
+        self.occurrences.clear();
         if let Some(tree) = &self.tree {
-            let symbols = self.collect_symbols(tree.root_node());
-            for symbol in symbols {
+            // Symbols of method implementations read their declarations
+            self.type_table = TypeTable::build(tree.root_node(), &self.source, &self.line_index);
+            self.symbols = self.collect_symbols(tree.root_node(), None);
+            let mut symbols = self.symbols.clone();
+            while let Some(symbol) = symbols.pop() {
+                symbols.extend(symbol.children.iter().cloned());
                 self.symbol_map
-                    .entry(symbol.name.clone())
-                    .or_insert_with(Vec::new)
+                    .entry(symbol.name.to_lowercase())
+                    .or_default()
                     .push(symbol);
             }
+            if self.mode == AnalysisMode::Full {
+                let mut occurrences = HashMap::new();
+                self.collect_occurrences(tree.root_node(), &mut occurrences);
+                self.occurrences = occurrences;
+            }
         }
     }
 
-    pub fn get_document_symbols(&self) -> Option<Vec<DocumentSymbol>> {
-        let tree = self.tree.as_ref()?;
-        let root_node = tree.root_node();
+    /// The header declaring the parameter named by `identifier` when the
+    /// routine has no body, as in the interface section or a type.
+    fn bodiless_header_scope(&self, identifier: Node) -> Option<Range> {
+        let arg = identifier.parent().filter(|arg| arg.kind() == "declArg")?;
+        let header = arg.parent()?.parent()?;
+        let has_body = header
+            .parent()
+            .is_some_and(|parent| parent.kind() == "defProc");
+        (!has_body).then(|| self.node_to_range(header))
+    }
 
-        let symbols = self.collect_symbols(root_node);
-        Some(
-            symbols
-                .into_iter()
-                .map(|s| self.to_document_symbol(s))
-                .collect(),
-        )
+    fn collect_occurrences(&self, node: Node, occurrences: &mut HashMap<String, Vec<Occurrence>>) {
+        if node.kind() == "identifier" {
+            occurrences
+                .entry(self.get_name(node).to_lowercase())
+                .or_default()
+                .push(Occurrence {
+                    range: self.node_to_range(node),
+                    declaration: self.is_declaration_name(node),
+                    qualified: node.parent().is_some_and(|parent| {
+                        parent.kind() == "exprDot"
+                            && parent.child_by_field_name("rhs") == Some(node)
+                    }),
+                    scope: self.bodiless_header_scope(node),
+                });
+        }
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            self.collect_occurrences(child, occurrences);
+        }
+    }
+
+    pub fn get_document_symbols(&self) -> Option<Vec<DocumentSymbol>> {
+        self.tree.as_ref()?;
+        Some(self.to_document_symbols(self.symbols.clone()))
     }
 
-    fn collect_symbols(&self, node: Node) -> Vec<Symbol> {
+    fn collect_symbols(&self, node: Node, parent: Option<&SymbolId>) -> Vec<Symbol> {
         let mut symbols = Vec::new();
 
         match node.kind() {
-            "program" | "unit" => {
+            "root" | "interface" | "implementation" | "declTypes" | "declVars" | "declConsts"
+            | "ERROR" => {
+                // Containers without a symbol of their own; error recovery
+                // wraps the whole unit in an ERROR node while a call is
+                // being typed
+                symbols.extend(self.collect_children_symbols(node, parent));
+            }
+            "program" | "unit" | "library" => {
                 // Handle program/unit declarations
-                if let Some(name_node) = self.find_identifier(node) {
+                if let Some(name_node) = self.find_child(node, "moduleName") {
+                    let name = self.get_name(name_node);
+                    let id = SymbolId::new(None, &name, None);
                     symbols.push(Symbol {
-                        name: self.get_node_text(name_node),
+                        name,
                         kind: SymbolKind::MODULE,
                         range: self.node_to_range(node),
                         selection_range: self.node_to_range(name_node),
-                        children: self.collect_children_symbols(node),
+                        children: self.collect_children_symbols(node, Some(&id)),
                         detail: None,
+                        external: None,
+                        id,
+                        visibility: None,
+                        directives: Vec::new(),
+                        deprecated: false,
+                        scope: None,
                     });
                 }
             }
-            "type_declaration" => {
+            "declType" => {
                 // Handle type declarations
-                if let Some(name_node) = self.find_identifier(node) {
+                if let Some(name_node) = node.child_by_field_name("name") {
+                    let name = self.get_name(name_node);
+                    let id = SymbolId::new(parent, &name, None);
                     symbols.push(Symbol {
-                        name: self.get_node_text(name_node),
+                        name,
                         kind: SymbolKind::CLASS,
                         range: self.node_to_range(node),
                         selection_range: self.node_to_range(name_node),
-                        children: self.collect_children_symbols(node),
+                        children: self.collect_children_symbols(node, Some(&id)),
                         detail: None,
+                        external: None,
+                        id,
+                        visibility: None,
+                        directives: Vec::new(),
+                        deprecated: false,
+                        scope: self.routine_scope(node),
                     });
                 }
             }
-            "procedure_declaration" | "function_declaration" => {
+            "declProc" | "defProc" => {
                 // Handle procedure and function declarations
-                if let Some(name_node) = self.find_identifier(node) {
+                let header = node.child_by_field_name("header").unwrap_or(node);
+                if let Some(name_node) = header.child_by_field_name("name") {
+                    let name = self.get_name(name_node);
+                    let params = self.get_parameter_types(header);
+                    let directives = self.get_directives(header);
+                    let mut detail = self.get_declaration_detail(header);
+                    // Directives are written on the declaration only
+                    if let Some(method) = self.implemented_method(header) {
+                        for directive in &method.directives {
+                            if directive.name != "class" && !directives.contains(&directive.name) {
+                                detail.push_str(&format!(" {};", directive));
+                            }
+                        }
+                    }
+                    let id = SymbolId::new(parent, &name, Some(&params));
+                    let children = if node.kind() == "defProc" {
+                        self.collect_local_symbols(node, &id)
+                    } else {
+                        Vec::new()
+                    };
                     symbols.push(Symbol {
-                        name: self.get_node_text(name_node),
+                        name,
                         kind: SymbolKind::FUNCTION,
                         range: self.node_to_range(node),
                         selection_range: self.node_to_range(name_node),
-                        children: Vec::new(),
-                        detail: Some(self.get_declaration_detail(node)),
+                        children,
+                        detail: Some(detail),
+                        external: self.get_external_import(header),
+                        id,
+                        visibility: None,
+                        deprecated: directives.iter().any(|d| d == "deprecated"),
+                        directives,
+                        scope: self.routine_scope(node),
                     });
                 }
             }
-            "var_declaration" => {
-                // Handle variable declarations
-                if let Some(name_node) = self.find_identifier(node) {
+            "declVar" | "declConst" | "declArg" => {
+                // Handle variable, constant and parameter declarations;
+                // `var a, b: Integer` declares one symbol per name
+                let kind = if node.kind() == "declConst" {
+                    SymbolKind::CONSTANT
+                } else {
+                    SymbolKind::VARIABLE
+                };
+                let detail = (node.kind() == "declArg").then(|| self.get_declaration_detail(node));
+                let mut cursor = node.walk();
+                let names = node
+                    .children_by_field_name("name", &mut cursor)
+                    .filter(|name| name.kind() == "identifier");
+                for name_node in names {
+                    let name = self.get_name(name_node);
                     symbols.push(Symbol {
-                        name: self.get_node_text(name_node),
-                        kind: SymbolKind::VARIABLE,
+                        id: SymbolId::new(parent, &name, None),
+                        name,
+                        kind,
                         range: self.node_to_range(node),
                         selection_range: self.node_to_range(name_node),
                         children: Vec::new(),
-                        detail: None,
+                        detail: detail.clone(),
+                        external: None,
+                        visibility: None,
+                        directives: Vec::new(),
+                        deprecated: false,
+                        scope: self.routine_scope(node),
                     });
                 }
             }
+            "declClass" | "declIntf" | "declHelper" => {
+                self.collect_member_symbols(node, Visibility::Public, parent, &mut symbols);
+            }
             _ => {}
         }
 
         symbols
     }
 
-    fn collect_children_symbols(&self, node: Node) -> Vec<Symbol> {
+    /// Collects the parameters of a routine with a body, followed by its
+    /// local declarations and nested routines.
+    fn collect_local_symbols(&self, routine: Node, id: &SymbolId) -> Vec<Symbol> {
         let mut symbols = Vec::new();
+        if let Some(args) = routine
+            .child_by_field_name("header")
+            .and_then(|header| header.child_by_field_name("args"))
+        {
+            symbols.extend(self.collect_children_symbols(args, Some(id)));
+        }
+        let mut cursor = routine.walk();
+        for local in routine.children_by_field_name("local", &mut cursor) {
+            symbols.extend(self.collect_symbols(local, Some(id)));
+        }
+        symbols
+    }
+
+    /// The range of the routine body a declaration is local to, `None` for
+    /// declarations outside routines.
+    fn routine_scope(&self, node: Node) -> Option<Range> {
+        let mut current = node.parent();
+        while let Some(ancestor) = current {
+            if ancestor.kind() == "defProc" {
+                return Some(self.node_to_range(ancestor));
+            }
+            current = ancestor.parent();
+        }
+        None
+    }
+
+    /// Collects the fields, methods and properties of a type body. Members
+    /// before the first visibility section are public.
+    fn collect_member_symbols(
+        &self,
+        node: Node,
+        visibility: Visibility,
+        parent: Option<&SymbolId>,
+        symbols: &mut Vec<Symbol>,
+    ) {
         let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            let (kind, params, directives) = match child.kind() {
+                "declSection" => {
+                    let visibility = Visibility::from_section(child).unwrap_or(visibility);
+                    self.collect_member_symbols(child, visibility, parent, symbols);
+                    continue;
+                }
+                // `const` and `class var` sections, and the variant part of
+                // a record
+                "declConsts" | "declVars" | "declVariant" | "declVariantClause" => {
+                    self.collect_member_symbols(child, visibility, parent, symbols);
+                    continue;
+                }
+                "declField" | "declVar" => (SymbolKind::FIELD, None, Vec::new()),
+                "declConst" => (SymbolKind::CONSTANT, None, Vec::new()),
+                // Method resolution clauses declare nothing
+                "declProc" if child.child_by_field_name("assign").is_some() => continue,
+                "declProc" => (
+                    SymbolKind::METHOD,
+                    Some(self.get_parameter_types(child)),
+                    self.get_directives(child),
+                ),
+                "declProp" => (SymbolKind::PROPERTY, None, Vec::new()),
+                _ => continue,
+            };
+            let mut names = child.walk();
+            let names = child
+                .children_by_field_name("name", &mut names)
+                .filter(|name| name.kind() == "identifier");
+            for name_node in names {
+                let name = self.get_name(name_node);
+                symbols.push(Symbol {
+                    id: SymbolId::new(parent, &name, params.as_deref()),
+                    name,
+                    kind,
+                    range: self.node_to_range(child),
+                    selection_range: self.node_to_range(name_node),
+                    children: Vec::new(),
+                    detail: Some(self.get_declaration_detail(child)),
+                    external: None,
+                    visibility: Some(visibility),
+                    deprecated: directives.iter().any(|d| d == "deprecated"),
+                    directives: directives.clone(),
+                    scope: self.routine_scope(child),
+                });
+            }
+        }
+    }
 
-        if cursor.goto_first_child() {
-            loop {
-                symbols.extend(self.collect_symbols(cursor.node()));
-                if !cursor.goto_next_sibling() {
-                    break;
+    /// The directive keywords of a routine header in source order, e.g.
+    /// `["class", "static"]` for `class function Make: TFoo; static;`.
+    fn get_directives(&self, header: Node) -> Vec<String> {
+        members::routine_directives(header, &self.source)
+            .into_iter()
+            .map(|directive| directive.name)
+            .collect()
+    }
+
+    /// Returns the declared type of each parameter of a routine header, one
+    /// entry per parameter name.
+    fn get_parameter_types(&self, header: Node) -> Vec<String> {
+        let mut types = Vec::new();
+        let Some(args) = header.child_by_field_name("args") else {
+            return types;
+        };
+        let mut cursor = args.walk();
+        for arg in args.children(&mut cursor) {
+            if arg.kind() != "declArg" {
+                continue;
+            }
+            let type_name = arg
+                .child_by_field_name("type")
+                .map(|t| self.get_node_text(t))
+                .unwrap_or_default();
+            let mut names = arg.walk();
+            let count = arg
+                .children_by_field_name("name", &mut names)
+                .filter(|name| name.kind() == "identifier")
+                .count();
+            types.extend(std::iter::repeat_n(type_name, count));
+        }
+        types
+    }
+
+    /// Extracts the calling convention and `external` clause of a routine
+    /// header. Returns `None` for routines implemented in Pascal.
+    fn get_external_import(&self, header: Node) -> Option<ExternalImport> {
+        let mut calling_convention = None;
+        let mut external = None;
+        let mut cursor = header.walk();
+
+        for child in header.children(&mut cursor) {
+            match child.kind() {
+                "procAttribute" => {
+                    if let Some(keyword) = child.child(0) {
+                        if CALLING_CONVENTIONS.contains(&keyword.kind()) {
+                            calling_convention = Some(self.get_node_text(keyword).to_lowercase());
+                        }
+                    }
                 }
+                "procExternal" => external = Some(child),
+                _ => {}
             }
         }
 
-        symbols
+        let external = external?;
+        let mut import = ExternalImport {
+            library: None,
+            name: None,
+            index: None,
+            delayed: false,
+            calling_convention,
+        };
+        let mut previous_kind = "";
+        let mut cursor = external.walk();
+        for child in external.children(&mut cursor) {
+            match (previous_kind, child.kind()) {
+                (_, "kDelayed") => import.delayed = true,
+                (_, "kName" | "kIndex" | ";") => {}
+                ("kExternal", _) => import.library = Some(self.get_literal_text(child)),
+                ("kName", _) => import.name = Some(self.get_literal_text(child)),
+                ("kIndex", _) => import.index = Some(self.get_node_text(child)),
+                _ => {}
+            }
+            previous_kind = child.kind();
+        }
+
+        Some(import)
+    }
+
+    /// Returns the value of a string literal without its quotes, or the raw
+    /// text for any other expression (e.g. a constant naming the DLL).
+    fn get_literal_text(&self, node: Node) -> String {
+        let text = self.get_node_text(node);
+        if node.kind() == "literalString" {
+            text.trim_matches('\'').replace("''", "'")
+        } else {
+            text
+        }
+    }
+
+    /// Lists the external imports of the document grouped by library, with
+    /// libraries ordered case-insensitively by name.
+    pub fn get_external_imports(&self) -> Vec<ExternalLibrary> {
+        let mut libraries: BTreeMap<String, ExternalLibrary> = BTreeMap::new();
+        for symbol in self.symbol_map.values().flatten().cloned() {
+            let Some(import) = symbol.external else {
+                continue;
+            };
+            let library = import.library.clone().unwrap_or_default();
+            libraries
+                .entry(library.to_lowercase())
+                .or_insert_with(|| ExternalLibrary {
+                    library,
+                    imports: Vec::new(),
+                })
+                .imports
+                .push(ExternalRoutine {
+                    name: symbol.name,
+                    range: symbol.selection_range,
+                    import,
+                });
+        }
+
+        let mut libraries: Vec<ExternalLibrary> = libraries.into_values().collect();
+        for library in &mut libraries {
+            library.imports.sort_by_key(|routine| routine.range.start);
+        }
+        libraries
+    }
+
+    fn find_child<'a>(&self, node: Node<'a>, kind: &str) -> Option<Node<'a>> {
+        let mut cursor = node.walk();
+        let child = node
+            .children(&mut cursor)
+            .find(|child| child.kind() == kind);
+        child
     }
 
-    fn find_identifier<'a>(&self, node: Node<'a>) -> Option<Node<'a>> {
+    fn collect_children_symbols(&self, node: Node, parent: Option<&SymbolId>) -> Vec<Symbol> {
+        let mut symbols = Vec::new();
         let mut cursor = node.walk();
+
         if cursor.goto_first_child() {
             loop {
-                if cursor.node().kind() == "identifier" {
-                    return Some(cursor.node());
-                }
+                symbols.extend(self.collect_symbols(cursor.node(), parent));
                 if !cursor.goto_next_sibling() {
                     break;
                 }
             }
         }
-        None
+
+        symbols
     }
 
     fn get_node_text(&self, node: Node) -> String {
-        self.source[node.byte_range()].to_string()
+        self.get_text(node.byte_range()).to_string()
+    }
+
+    /// Slices the source, tolerating ranges from a tree that does not match
+    /// it; see [`slice_text`].
+    fn get_text(&self, range: std::ops::Range<usize>) -> &str {
+        slice_text(&self.source, range, || {
+            let uri = self
+                .document_uri
+                .as_ref()
+                .map_or("<unknown>".to_string(), Url::to_string);
+            match self.document_version {
+                Some(version) => format!("{} (version {})", uri, version),
+                None => uri,
+            }
+        })
+    }
+
+    /// The text of an identifier or dotted name with `&` escapes removed.
+    fn get_name(&self, node: Node) -> String {
+        unescape_identifier(&self.get_node_text(node))
     }
 
     fn get_declaration_detail(&self, node: Node) -> String {
         // Get the full declaration text for hover info
-        self.source[node.byte_range()].to_string()
+        self.get_node_text(node)
     }
 
     fn node_to_range(&self, node: Node) -> Range {
-        Range {
-            start: Position {
-                line: node.start_position().row as u32,
-                character: node.start_position().column as u32,
-            },
-            end: Position {
-                line: node.end_position().row as u32,
-                character: node.end_position().column as u32,
-            },
-        }
+        self.line_index.byte_range_to_range(node.byte_range())
     }
 
-    fn to_document_symbol(&self, symbol: Symbol) -> DocumentSymbol {
-        DocumentSymbol {
-            name: symbol.name,
-            detail: symbol.detail,
-            kind: symbol.kind,
-            tags: None,
-            deprecated: None,
-            range: symbol.range,
-            selection_range: symbol.selection_range,
-            children: Some(
-                symbol
-                    .children
-                    .into_iter()
-                    .map(|s| self.to_document_symbol(s))
-                    .collect(),
-            ),
+    /// Returns the smallest node at `position`, located by byte offset so
+    /// that lines ending in a bare CR resolve correctly.
+    fn node_at(&self, position: Position) -> Result<Node<'_>, AnalysisError> {
+        let tree = self.tree.as_ref().ok_or(AnalysisError::NoTree)?;
+        let offset = self.line_index.position_to_offset(position);
+        tree.root_node()
+            .descendant_for_byte_range(offset, offset)
+            .ok_or(AnalysisError::NoTree)
+    }
+
+    /// Why nothing is known about `node`, an identifier or the node a
+    /// request found at its position, when no more specific reason
+    /// applies.
+    fn unanswerable(&self, node: Node) -> AnalysisError {
+        if std::iter::successors(Some(node), |node| node.parent()).any(|node| node.is_error()) {
+            AnalysisError::SyntaxError
+        } else if node.kind() == "identifier" {
+            AnalysisError::NotDeclaration(self.get_name(node))
+        } else {
+            AnalysisError::NotIdentifier(node.kind().to_string())
         }
     }
 
-    pub fn get_hover_info(&self, position: Position) -> Option<Hover> {
-        let tree = self.tree.as_ref()?;
-        let point = tree_sitter::Point {
-            row: position.line as usize,
-            column: position.character as usize,
-        };
+    /// The location of `range` in the document.
+    fn location(&self, range: Range) -> Result<Location, AnalysisError> {
+        Ok(Location {
+            uri: self.document_uri.clone().ok_or(AnalysisError::NoTree)?,
+            range,
+        })
+    }
 
-        let node = tree.root_node().descendant_for_point_range(point, point)?;
+    /// The symbol tree for `dls/outline`, filtered by `params`.
+    pub fn get_outline(&self, params: &OutlineParams) -> Option<Vec<OutlineSymbol>> {
+        self.tree.as_ref()?;
+        Some(
+            self.arrange_symbols(self.symbols.clone())
+                .into_iter()
+                .filter_map(|symbol| self.to_outline_symbol(symbol, params))
+                .collect(),
+        )
+    }
 
-        // Try to find the closest meaningful parent node
-        let hover_node = self.find_hover_node(node);
+    /// Converts a symbol and its children, dropping those that fail the
+    /// filters and have no descendant passing them.
+    fn to_outline_symbol(&self, symbol: Symbol, params: &OutlineParams) -> Option<OutlineSymbol> {
+        let section = self.section_at(symbol.selection_range.start);
+        let children: Vec<OutlineSymbol> = self
+            .arrange_symbols(symbol.children)
+            .into_iter()
+            .filter_map(|child| self.to_outline_symbol(child, params))
+            .collect();
 
-        match hover_node.kind() {
-            "identifier" => {
-                let parent = hover_node.parent()?;
-                match parent.kind() {
-                    "procedure_declaration" | "function_declaration" => Some(self.create_hover(
-                        self.get_node_text(parent),
-                        Some("function".to_string()),
-                        self.node_to_range(hover_node),
-                    )),
-                    "type_declaration" => Some(self.create_hover(
-                        self.get_node_text(parent),
-                        Some("type".to_string()),
-                        self.node_to_range(hover_node),
-                    )),
-                    "var_declaration" => Some(self.create_hover(
-                        self.get_node_text(parent),
-                        Some("variable".to_string()),
-                        self.node_to_range(hover_node),
-                    )),
-                    _ => None,
-                }
-            }
-            _ => None,
+        let effective_visibility = symbol.visibility.unwrap_or(match section {
+            Some(Section::Implementation) => Visibility::Private,
+            _ => Visibility::Public,
+        });
+        let matches = params
+            .kinds
+            .as_ref()
+            .is_none_or(|kinds| kinds.contains(&symbol.kind))
+            && params
+                .visibility
+                .as_ref()
+                .is_none_or(|visibility| visibility.contains(&effective_visibility))
+            && params
+                .query
+                .as_ref()
+                .is_none_or(|query| symbol.name.to_lowercase().contains(&query.to_lowercase()));
+        if !matches && children.is_empty() {
+            return None;
         }
-    }
 
-    pub fn find_definition(&self, position: Position) -> Option<Location> {
-        let tree = self.tree.as_ref()?;
-        let point = tree_sitter::Point {
-            row: position.line as usize,
-            column: position.character as usize,
-        };
-
-        let node = tree.root_node().descendant_for_point_range(point, point)?;
-        let hover_node = self.find_hover_node(node);
+        Some(OutlineSymbol {
+            name: symbol.name,
+            kind: symbol.kind,
+            detail: symbol.detail,
+            range: symbol.range,
+            selection_range: symbol.selection_range,
+            id: symbol.id,
+            visibility: symbol.visibility,
+            directives: symbol.directives,
+            deprecated: symbol.deprecated,
+            section,
+            external: symbol.external,
+            children,
+        })
+    }
 
-        if hover_node.kind() == "identifier" {
-            let name = self.get_node_text(hover_node);
-            if let Some(symbols) = self.symbol_map.get(&name) {
-                return Some(Location {
-                    uri: self.document_uri.clone()?,
-                    range: symbols[0].range,
-                });
+    /// The unit section containing `position`.
+    fn section_at(&self, position: Position) -> Option<Section> {
+        let mut current = self.node_at(position).ok();
+        while let Some(node) = current {
+            match node.kind() {
+                "interface" => return Some(Section::Interface),
+                "implementation" => return Some(Section::Implementation),
+                _ => current = node.parent(),
             }
         }
         None
     }
 
-    pub fn find_references(&self, position: Position) -> Option<Vec<Location>> {
-        let tree = self.tree.as_ref()?;
-        let point = tree_sitter::Point {
-            row: position.line as usize,
-            column: position.character as usize,
+    /// Converts sibling symbols. With `outline.groupOverloads`, overloads of
+    /// a routine declared in the same section become the children of one
+    /// node named after them.
+    fn to_document_symbols(&self, symbols: Vec<Symbol>) -> Vec<DocumentSymbol> {
+        let symbols = self.arrange_symbols(symbols);
+        if !self.settings.outline.group_overloads {
+            return symbols
+                .into_iter()
+                .map(|s| self.to_document_symbol(s))
+                .collect();
+        }
+
+        let mut groups: Vec<Vec<Symbol>> = Vec::new();
+        for symbol in symbols {
+            match groups
+                .iter_mut()
+                .find(|group| self.is_overload_of(&group[0], &symbol))
+            {
+                Some(group) => group.push(symbol),
+                None => groups.push(vec![symbol]),
+            }
+        }
+        groups
+            .into_iter()
+            .map(|mut group| {
+                if group.len() == 1 {
+                    return self.to_document_symbol(group.pop().unwrap());
+                }
+                self.to_overload_group(group)
+            })
+            .collect()
+    }
+
+    /// Drops the unit-level variables and constants left out by
+    /// `outline.includeVariables` and `outline.includeConstants`, and orders
+    /// the other sibling symbols by `outline.sort`. Only runs of siblings in
+    /// the same section and with the same visibility are sorted, and the
+    /// sort is stable, so equal names keep their source order.
+    fn arrange_symbols(&self, mut symbols: Vec<Symbol>) -> Vec<Symbol> {
+        let outline = &self.settings.outline;
+        symbols.retain(|symbol| {
+            let unit_level = symbol.scope.is_none() && symbol.visibility.is_none();
+            !unit_level
+                || match symbol.kind {
+                    SymbolKind::VARIABLE => outline.include_variables,
+                    SymbolKind::CONSTANT => outline.include_constants,
+                    _ => true,
+                }
+        });
+        if outline.sort == OutlineSort::Position {
+            return symbols;
+        }
+
+        let groups: Vec<_> = symbols
+            .iter()
+            .map(|symbol| {
+                (
+                    self.section_at(symbol.selection_range.start),
+                    symbol.visibility,
+                )
+            })
+            .collect();
+        let kind_rank = |kind| match kind {
+            _ if outline.sort == OutlineSort::Name => 0,
+            SymbolKind::CONSTANT => 0,
+            SymbolKind::CLASS => 1,
+            SymbolKind::VARIABLE => 2,
+            SymbolKind::FIELD => 3,
+            SymbolKind::PROPERTY => 4,
+            SymbolKind::METHOD | SymbolKind::FUNCTION => 5,
+            _ => 6,
+        };
+        let mut start = 0;
+        while start < symbols.len() {
+            let end = (start..symbols.len())
+                .find(|&i| groups[i] != groups[start])
+                .unwrap_or(symbols.len());
+            symbols[start..end]
+                .sort_by_cached_key(|symbol| (kind_rank(symbol.kind), symbol.name.to_lowercase()));
+            start = end;
+        }
+        symbols
+    }
+
+    /// Whether `symbol` overloads `first`: a routine of the same name and
+    /// section with other parameters, so that a routine does not group with
+    /// its forward declaration.
+    fn is_overload_of(&self, first: &Symbol, symbol: &Symbol) -> bool {
+        let is_routine = |kind| kind == SymbolKind::FUNCTION || kind == SymbolKind::METHOD;
+        is_routine(first.kind)
+            && first.kind == symbol.kind
+            && first.name.eq_ignore_ascii_case(&symbol.name)
+            && !first.id.matches(&symbol.id)
+            && self.section_at(first.selection_range.start)
+                == self.section_at(symbol.selection_range.start)
+    }
+
+    /// A node named after overloads of one routine, spanning them all, with
+    /// each overload as a child.
+    #[allow(deprecated)]
+    fn to_overload_group(&self, overloads: Vec<Symbol>) -> DocumentSymbol {
+        let first = &overloads[0];
+        let start = overloads.iter().map(|s| s.range.start).min().unwrap();
+        let end = overloads.iter().map(|s| s.range.end).max().unwrap();
+        DocumentSymbol {
+            name: first.name.clone(),
+            detail: Some(format!("{} overloads", overloads.len())),
+            kind: first.kind,
+            tags: None,
+            deprecated: None,
+            range: Range { start, end },
+            selection_range: first.selection_range,
+            children: Some(
+                overloads
+                    .into_iter()
+                    .map(|s| self.to_document_symbol(s))
+                    .collect(),
+            ),
+        }
+    }
+
+    #[allow(deprecated)]
+    fn to_document_symbol(&self, symbol: Symbol) -> DocumentSymbol {
+        let mut children = self.to_document_symbols(symbol.children);
+        if self.settings.outline.show_blocks && symbol.kind == SymbolKind::FUNCTION {
+            let routine =
+                std::iter::successors(self.node_at(symbol.selection_range.start).ok(), |node| {
+                    node.parent()
+                })
+                .find(|node| node.kind() == "defProc");
+            children.extend(
+                routine
+                    .map(|routine| self.block_symbols(routine))
+                    .unwrap_or_default(),
+            );
+        }
+        DocumentSymbol {
+            name: symbol.name,
+            detail: symbol.detail,
+            kind: symbol.kind,
+            tags: None,
+            deprecated: None,
+            range: symbol.range,
+            selection_range: symbol.selection_range,
+            children: Some(children),
+        }
+    }
+
+    /// The outline nodes of the top-level structural statements and labels
+    /// of a routine body, for `outline.showBlocks`. They are named after
+    /// their heading, e.g. `for I := 0 to Count - 1`, and a `case` has its
+    /// branches as children.
+    fn block_symbols(&self, routine: Node) -> Vec<DocumentSymbol> {
+        let Some(body) = routine.child_by_field_name("body") else {
+            return Vec::new();
+        };
+        let mut cursor = body.walk();
+        let statements: Vec<Node> = body.named_children(&mut cursor).collect();
+        statements
+            .into_iter()
+            .filter_map(|statement| {
+                // A label names the statement after it
+                if statement.kind() == "label" {
+                    let end = statement
+                        .next_named_sibling()
+                        .map_or(statement.end_byte(), |next| next.end_byte());
+                    return Some(self.block_symbol(
+                        &self.get_node_text(statement),
+                        SymbolKind::KEY,
+                        statement.start_byte()..end,
+                        statement.byte_range(),
+                        Vec::new(),
+                    ));
+                }
+                let heading_end = match statement.kind() {
+                    "for" | "foreach" | "while" | "with" => {
+                        self.find_child(statement, "kDo")?.start_byte()
+                    }
+                    "case" => self.find_child(statement, "kOf")?.end_byte(),
+                    "try" => self.find_child(statement, "kTry")?.end_byte(),
+                    "repeat" => self.find_child(statement, "kRepeat")?.end_byte(),
+                    _ => return None,
+                };
+                let mut name = self
+                    .get_text(statement.start_byte()..heading_end)
+                    .to_string();
+                match statement.kind() {
+                    "try" => {
+                        let handler = self
+                            .find_child(statement, "kFinally")
+                            .or_else(|| self.find_child(statement, "kExcept"));
+                        if let Some(handler) = handler {
+                            name = format!("{} {}", name, self.get_node_text(handler));
+                        }
+                    }
+                    "repeat" => {
+                        if let Some(until) = self.find_child(statement, "kUntil") {
+                            name = format!(
+                                "{} {}",
+                                name,
+                                self.get_text(until.start_byte()..statement.end_byte())
+                            );
+                        }
+                    }
+                    _ => {}
+                }
+                let children = if statement.kind() == "case" {
+                    self.case_branch_symbols(statement)
+                } else {
+                    Vec::new()
+                };
+                Some(self.block_symbol(
+                    &name,
+                    SymbolKind::NAMESPACE,
+                    statement.byte_range(),
+                    statement.start_byte()..heading_end,
+                    children,
+                ))
+            })
+            .collect()
+    }
+
+    /// The branches of a `case` statement, named after their labels, and
+    /// its `else` part.
+    fn case_branch_symbols(&self, case: Node) -> Vec<DocumentSymbol> {
+        let mut branches = Vec::new();
+        let mut cursor = case.walk();
+        let children: Vec<Node> = case.children(&mut cursor).collect();
+        for (i, child) in children.iter().enumerate() {
+            match child.kind() {
+                "caseCase" => {
+                    let Some(label) = child.child_by_field_name("label") else {
+                        continue;
+                    };
+                    branches.push(self.block_symbol(
+                        &self.get_node_text(label),
+                        SymbolKind::ENUM_MEMBER,
+                        child.byte_range(),
+                        label.byte_range(),
+                        Vec::new(),
+                    ));
+                }
+                "kElse" => {
+                    let end = children[i + 1..]
+                        .iter()
+                        .take_while(|next| next.kind() != "kEnd")
+                        .last()
+                        .map_or(child.end_byte(), |last| last.end_byte());
+                    branches.push(self.block_symbol(
+                        "else",
+                        SymbolKind::ENUM_MEMBER,
+                        child.start_byte()..end,
+                        child.byte_range(),
+                        Vec::new(),
+                    ));
+                }
+                _ => {}
+            }
+        }
+        branches
+    }
+
+    /// An outline node for a statement, named after its heading with the
+    /// whitespace collapsed, trailing `:` or `;` dropped and cut to
+    /// [`MAX_BLOCK_NAME_LENGTH`] characters, so that names stay short and
+    /// change only with the heading they come from.
+    #[allow(deprecated)]
+    fn block_symbol(
+        &self,
+        heading: &str,
+        kind: SymbolKind,
+        range: std::ops::Range<usize>,
+        selection_range: std::ops::Range<usize>,
+        children: Vec<DocumentSymbol>,
+    ) -> DocumentSymbol {
+        let heading = heading.split_whitespace().collect::<Vec<_>>().join(" ");
+        let heading = heading.trim_end_matches([':', ';']).trim_end();
+        let name = if heading.chars().count() > MAX_BLOCK_NAME_LENGTH {
+            let cut: String = heading.chars().take(MAX_BLOCK_NAME_LENGTH - 1).collect();
+            format!("{}…", cut.trim_end())
+        } else {
+            heading.to_string()
+        };
+        DocumentSymbol {
+            name,
+            detail: None,
+            kind,
+            tags: None,
+            deprecated: None,
+            range: self.line_index.byte_range_to_range(range),
+            selection_range: self.line_index.byte_range_to_range(selection_range),
+            children: Some(children),
+        }
+    }
+
+    pub fn get_hover_info(&self, position: Position) -> Result<Hover, AnalysisError> {
+        let node = self.node_at(position)?;
+
+        if let Some(hover) = self.get_directive_hover(node) {
+            return Ok(hover);
+        }
+        if let Some(hover) = self.get_string_chain_hover(node) {
+            return Ok(hover);
+        }
+
+        // Try to find the closest meaningful parent node
+        let hover_node = self.find_hover_node(node);
+
+        if let Some(implicit) = self.implicit_identifier(hover_node) {
+            let mut value = format!(
+                "```pascal\n{}: {}\n```\n",
+                implicit.name, implicit.type_name
+            );
+            if let Some(routine) = implicit.header.child_by_field_name("name") {
+                value.push_str(&format!("Implicit in `{}`", self.get_node_text(routine)));
+            }
+            return Ok(Hover {
+                contents: HoverContents::Markup(MarkupContent {
+                    kind: MarkupKind::Markdown,
+                    value,
+                }),
+                range: Some(self.node_to_range(hover_node)),
+            });
+        }
+        if let Some(hover) = self.get_loop_variable_hover(hover_node) {
+            return Ok(hover);
+        }
+        if let Some(hover) = self.get_member_chain_hover(position, &mut ChainResolver::local()) {
+            return Ok(hover);
+        }
+
+        let hover = match hover_node.kind() {
+            "identifier" => hover_node.parent().and_then(|parent| match parent.kind() {
+                "declProc" => {
+                    let mut content = self.get_node_text(parent);
+                    if let Some(import) = self.get_external_import(parent) {
+                        content.push_str("\n\n");
+                        content.push_str(&self.format_external_import(&import));
+                    } else if let Some(convention) = self.effective_calling_convention(parent) {
+                        content.push_str(&format!("\n\nCalling convention `{}`", convention));
+                    }
+                    Some(self.create_hover(
+                        content,
+                        Some("function".to_string()),
+                        self.node_to_range(hover_node),
+                    ))
+                }
+                // The method name of an implementation header shows its
+                // declaration, which carries the directives
+                "genericDot" if parent.child_by_field_name("rhs") == Some(hover_node) => parent
+                    .parent()
+                    .filter(|header| {
+                        header.kind() == "declProc"
+                            && header.child_by_field_name("assign").is_none()
+                    })
+                    .and_then(|header| self.implemented_method(header))
+                    .map(|method| {
+                        self.create_hover(
+                            method.detail.clone(),
+                            Some("function".to_string()),
+                            self.node_to_range(hover_node),
+                        )
+                    }),
+                "declType" => Some(self.create_hover(
+                    self.get_node_text(parent),
+                    Some("type".to_string()),
+                    self.node_to_range(hover_node),
+                )),
+                "declVar" => Some(self.create_hover(
+                    self.get_node_text(parent),
+                    Some("variable".to_string()),
+                    self.node_to_range(hover_node),
+                )),
+                "declConst" => Some(self.create_hover(
+                    self.get_node_text(parent),
+                    Some("constant".to_string()),
+                    self.node_to_range(hover_node),
+                )),
+                "declProp" => {
+                    let (content, kind) = match self.find_accessor_member(hover_node) {
+                        Some(member) => (member.detail.clone(), "accessor"),
+                        None => (self.get_node_text(parent), "property"),
+                    };
+                    Some(self.create_hover(
+                        content,
+                        Some(kind.to_string()),
+                        self.node_to_range(hover_node),
+                    ))
+                }
+                _ => None,
+            }),
+            _ => None,
+        };
+        hover.ok_or_else(|| self.unanswerable(hover_node))
+    }
+
+    fn format_external_import(&self, import: &ExternalImport) -> String {
+        let mut value = match &import.library {
+            Some(library) => format!("Imported from `{}`", library),
+            None => "Linked from an external object file".to_string(),
+        };
+        if let Some(name) = &import.name {
+            value.push_str(&format!(" as `{}`", name));
+        }
+        if let Some(index) = &import.index {
+            value.push_str(&format!(" at index {}", index));
+        }
+        if import.delayed {
+            value.push_str(" (delayed)");
+        }
+        if let Some(convention) = &import.calling_convention {
+            value.push_str(&format!(", calling convention `{}`", convention));
+        }
+        value
+    }
+
+    pub fn find_definition(&self, position: Position) -> Result<Location, AnalysisError> {
+        let node = self.node_at(position)?;
+        let hover_node = self.find_hover_node(node);
+
+        if let Some(implicit) = self.implicit_identifier(hover_node) {
+            return self.location(implicit.declaration);
+        }
+
+        if hover_node.kind() != "identifier" {
+            return Err(self.unanswerable(hover_node));
+        }
+        if let Some(found) = self.member_chain_at(position, &mut ChainResolver::local()) {
+            return Ok(found.location);
+        }
+        if let Some(member) = self.find_resolution_member(hover_node) {
+            return self.location(member.range);
+        }
+        if let Some(member) = self.find_accessor_member(hover_node) {
+            return self.location(member.range);
+        }
+        let name = self.get_name(hover_node);
+        let key = name.to_lowercase();
+        if let Some(symbol) = self.visible_declarations(&key, position).first() {
+            return self.location(symbol.range);
+        }
+        if self.symbol_map.contains_key(&key) {
+            Err(AnalysisError::NotInScope(name))
+        } else {
+            Err(AnalysisError::NotDeclared(name))
+        }
+    }
+
+    /// The declaration of the type of the identifier at `position`: of the
+    /// type a variable, field, parameter or property is declared with, or
+    /// that a `for..in` loop variable is inferred to have.
+    pub fn find_type_definition(&self, position: Position) -> Result<Location, AnalysisError> {
+        let node = self.find_hover_node(self.node_at(position)?);
+        if node.kind() != "identifier" {
+            return Err(self.unanswerable(node));
+        }
+        let type_name = self
+            .expression_type(node)
+            .ok_or_else(|| AnalysisError::NotDeclaration(self.get_name(node)))?;
+        if let Some((decl, _)) = self.type_table.get_instance(&type_name) {
+            return self.location(decl.name_range);
+        }
+        let (base, _) = split_generic(&type_name);
+        self.visible_declarations(&base.to_lowercase(), position)
+            .into_iter()
+            .find(|symbol| symbol.kind == SymbolKind::CLASS)
+            .map(|symbol| self.location(symbol.selection_range))
+            .unwrap_or_else(|| Err(AnalysisError::NotDeclared(base.to_string())))
+    }
+
+    /// The declarations named `name` (lowercase) in scope at `position`,
+    /// those of the innermost routine first.
+    fn visible_declarations(&self, name: &str, position: Position) -> Vec<&Symbol> {
+        let mut symbols: Vec<&Symbol> = self
+            .symbol_map
+            .get(name)
+            .into_iter()
+            .flatten()
+            .filter(|symbol| self.is_in_scope(symbol, position))
+            .collect();
+        symbols.sort_by_key(|symbol| std::cmp::Reverse(symbol.scope.map(|scope| scope.start)));
+        symbols
+    }
+
+    fn is_in_scope(&self, symbol: &Symbol, position: Position) -> bool {
+        symbol
+            .scope
+            .is_none_or(|scope| range_contains(scope, position))
+    }
+
+    /// What the identifier at `position` may name in the RTL or in another
+    /// unit, with its range, for when the document declares nothing by that
+    /// name. A member access `List.Add` resolves `List` to its declared
+    /// type.
+    pub fn rtl_query(&self, position: Position) -> Option<(RtlQuery, Range)> {
+        let identifier = self.find_hover_node(self.node_at(position).ok()?);
+        if identifier.kind() != "identifier" || self.implicit_identifier(identifier).is_some() {
+            return None;
+        }
+        let range = self.node_to_range(identifier);
+        let name = self.get_name(identifier);
+        let Some(dot) = identifier
+            .parent()
+            .filter(|parent| parent.kind() == "exprDot")
+            .filter(|dot| dot.child_by_field_name("rhs") == Some(identifier))
+        else {
+            return Some((RtlQuery::Name(name), range));
+        };
+        let lhs = dot
+            .child_by_field_name("lhs")
+            .filter(|lhs| lhs.kind() == "identifier")?;
+        let lhs_name = self.get_name(lhs);
+        let type_name = self
+            .resolve_identifier_type(&lhs_name, identifier)
+            .unwrap_or(lhs_name);
+        Some((
+            RtlQuery::Member {
+                type_name,
+                member: name,
+            },
+            range,
+        ))
+    }
+
+    /// Maps a symbol id back to the current location of its declaration.
+    /// Falls back to a declaration with the same path but another parameter
+    /// list when the signature was edited, except with `analysis.strict`;
+    /// `None` once the symbol is gone.
+    pub fn resolve_symbol_id(&self, id: &SymbolId) -> Option<Location> {
+        let mut candidates: Vec<&Symbol> = self
+            .symbol_map
+            .values()
+            .flatten()
+            .filter(|symbol| symbol.id.matches_name(id))
+            .collect();
+        candidates.sort_by_key(|symbol| symbol.range.start);
+        let resolution = match candidates.iter().find(|symbol| symbol.id.matches(id)) {
+            Some(symbol) => Resolution::declared(symbol),
+            None => Resolution::heuristic(
+                candidates.first()?,
+                "a declaration with another parameter list".to_string(),
+            ),
+        };
+        let symbol = self.accept(Some(resolution), || format!("the symbol {}", id))?;
+        Some(Location {
+            uri: self.document_uri.clone()?,
+            range: symbol.range,
+        })
+    }
+
+    /// The occurrences of the identifier at `position` referring to the
+    /// same declaration, as locations. `None` when the document declares
+    /// no such name.
+    pub fn find_references(
+        &self,
+        position: Position,
+        include_declaration: bool,
+    ) -> Option<Vec<Location>> {
+        let uri = self.document_uri.clone()?;
+        Some(
+            self.reference_occurrences(position)?
+                .into_iter()
+                .filter(|occurrence| include_declaration || !occurrence.declaration)
+                .map(|occurrence| Location {
+                    uri: uri.clone(),
+                    range: occurrence.range,
+                })
+                .collect(),
+        )
+    }
+
+    /// Handles `textDocument/documentHighlight`: the occurrences of the
+    /// identifier at `position`, declarations as writes and uses as reads.
+    /// A name the document does not declare is highlighted wherever it
+    /// appears.
+    pub fn get_document_highlights(&self, position: Position) -> Option<Vec<DocumentHighlight>> {
+        let occurrences = match self.reference_occurrences(position) {
+            Some(occurrences) => occurrences,
+            None => {
+                let identifier = self.renamable_identifier(position)?;
+                self.occurrences
+                    .get(&self.get_name(identifier).to_lowercase())?
+                    .clone()
+            }
+        };
+        Some(
+            occurrences
+                .into_iter()
+                .map(|occurrence| DocumentHighlight {
+                    range: occurrence.range,
+                    kind: Some(if occurrence.declaration {
+                        DocumentHighlightKind::WRITE
+                    } else {
+                        DocumentHighlightKind::READ
+                    }),
+                })
+                .collect(),
+        )
+    }
+
+    /// The occurrences of the identifier at `position` referring to the
+    /// same declaration, in source order: for the implicit `Result` and
+    /// `Self`, those of the routine; otherwise those whose innermost scope
+    /// declaring the name is the scope of the declaration visible at
+    /// `position`, so that a local and the unit-level name it hides are
+    /// told apart.
+    fn reference_occurrences(&self, position: Position) -> Option<Vec<Occurrence>> {
+        let identifier = self.find_hover_node(self.node_at(position).ok()?);
+        if identifier.kind() != "identifier" {
+            return None;
+        }
+
+        if let Some(implicit) = self.implicit_identifier(identifier) {
+            let routine = implicit.header.parent().unwrap_or(implicit.header);
+            let mut uses = Vec::new();
+            self.collect_identifiers(routine, &implicit.name, &mut uses);
+            return Some(
+                uses.into_iter()
+                    .filter(|identifier| self.implicit_identifier(*identifier).is_some())
+                    .map(|identifier| Occurrence {
+                        range: self.node_to_range(identifier),
+                        declaration: false,
+                        qualified: false,
+                        scope: None,
+                    })
+                    .collect(),
+            );
+        }
+
+        let name = self.get_name(identifier).to_lowercase();
+        let occurrences = self.occurrences.get(&name)?;
+        let range = self.node_to_range(identifier);
+        let scope = match occurrences
+            .iter()
+            .find(|occurrence| occurrence.range == range)
+        {
+            Some(Occurrence {
+                scope: Some(header),
+                ..
+            }) => Some(*header),
+            _ => self.visible_declarations(&name, position).first()?.scope,
+        };
+        let scopes: Vec<Range> = self
+            .symbol_map
+            .get(&name)
+            .into_iter()
+            .flatten()
+            .filter_map(|symbol| symbol.scope)
+            .chain(occurrences.iter().filter_map(|occurrence| occurrence.scope))
+            .collect();
+        let innermost_scope = |occurrence: &Occurrence| {
+            if occurrence.qualified {
+                return None;
+            }
+            scopes
+                .iter()
+                .filter(|scope| range_contains(**scope, occurrence.range.start))
+                .max_by_key(|scope| scope.start)
+                .copied()
+        };
+        Some(
+            occurrences
+                .iter()
+                .filter(|occurrence| innermost_scope(occurrence) == scope)
+                .copied()
+                .collect(),
+        )
+    }
+
+    /// The name of the identifier at `position` when the interface section
+    /// of the unit declares it, so other units may reference it too.
+    pub fn exported_name(&self, position: Position) -> Option<String> {
+        let identifier = self.find_hover_node(self.node_at(position).ok()?);
+        if identifier.kind() != "identifier" || self.implicit_identifier(identifier).is_some() {
+            return None;
+        }
+        let interface = self.interface_range()?;
+        let name = self.get_name(identifier);
+        self.symbol_map
+            .get(&name.to_lowercase())?
+            .iter()
+            .any(|symbol| range_contains(interface, symbol.selection_range.start))
+            .then_some(name)
+    }
+
+    /// The types, routines, variables and constants declared by the
+    /// interface section, by lowercase name, for units using this one to
+    /// resolve. Members of types are left out.
+    pub fn get_exported_declarations(&self) -> HashMap<String, Vec<Range>> {
+        let mut declarations: HashMap<String, Vec<Range>> = HashMap::new();
+        let Some(interface) = self.interface_range() else {
+            return declarations;
+        };
+        let symbols = self.symbols.iter().flat_map(|module| &module.children);
+        for symbol in symbols {
+            if range_contains(interface, symbol.selection_range.start) {
+                declarations
+                    .entry(symbol.name.to_lowercase())
+                    .or_default()
+                    .push(symbol.range);
+            }
+        }
+        declarations
+    }
+
+    /// The names a unit using this one can refer to: its exported
+    /// declarations, without type parameters, and the values of the
+    /// enumerations its interface declares. `None` when a using unit may
+    /// depend on it without naming anything, through its initialization
+    /// or finalization section or a helper, or when it is not a unit that
+    /// parsed without errors.
+    pub fn interface_names(&self) -> Option<Vec<String>> {
+        let tree = self.tree.as_ref()?;
+        if tree.root_node().has_error() {
+            return None;
+        }
+        let interface = self.interface_range()?;
+        let mut names: Vec<String> = self
+            .get_exported_declarations()
+            .into_keys()
+            .map(|name| match name.split_once('<') {
+                Some((name, _)) => name.to_string(),
+                None => name,
+            })
+            .collect();
+        let mut stack = vec![tree.root_node()];
+        while let Some(node) = stack.pop() {
+            match node.kind() {
+                "initialization" | "finalization" | "declHelper" => return None,
+                "declEnumValue" if range_contains(interface, self.node_to_range(node).start) => {
+                    if let Some(name) = node.child_by_field_name("name") {
+                        names.push(self.get_name(name).to_lowercase());
+                    }
+                }
+                _ => {}
+            }
+            let mut cursor = node.walk();
+            stack.extend(node.children(&mut cursor));
+        }
+        Some(names)
+    }
+
+    /// Whether the document refers to `name`, ignoring case, outside
+    /// declarations and uses clauses.
+    pub fn refers_to(&self, name: &str) -> bool {
+        let clauses: Vec<Range> = self
+            .uses_entries()
+            .into_iter()
+            .filter_map(|(unit, _)| unit.parent())
+            .map(|clause| self.node_to_range(clause))
+            .collect();
+        self.occurrences
+            .get(&name.to_lowercase())
+            .into_iter()
+            .flatten()
+            .any(|occurrence| {
+                !occurrence.declaration
+                    && !clauses
+                        .iter()
+                        .any(|clause| range_contains(*clause, occurrence.range.start))
+            })
+    }
+
+    /// The range of the interface section of a unit.
+    fn interface_range(&self) -> Option<Range> {
+        let tree = self.tree.as_ref()?;
+        let mut cursor = tree.root_node().walk();
+        let interface = tree
+            .root_node()
+            .named_children(&mut cursor)
+            .flat_map(|module| {
+                let mut cursor = module.walk();
+                let sections: Vec<Node> = module.named_children(&mut cursor).collect();
+                sections
+            })
+            .find(|section| section.kind() == "interface")?;
+        Some(self.node_to_range(interface))
+    }
+
+    /// Every identifier of the document spelled `name`, ignoring case,
+    /// leaving out the names of declarations unless `include_declaration`.
+    pub fn find_name_references(&self, name: &str, include_declaration: bool) -> Vec<Location> {
+        let Some(uri) = &self.document_uri else {
+            return Vec::new();
+        };
+        self.occurrences
+            .get(&name.to_lowercase())
+            .into_iter()
+            .flatten()
+            .filter(|occurrence| include_declaration || !occurrence.declaration)
+            .map(|occurrence| Location {
+                uri: uri.clone(),
+                range: occurrence.range,
+            })
+            .collect()
+    }
+
+    /// The range of the identifier at `position` if it can be renamed.
+    pub fn prepare_rename(&self, position: Position) -> Option<Range> {
+        let identifier = self.renamable_identifier(position)?;
+        Some(self.node_to_range(identifier))
+    }
+
+    /// Edits renaming every identifier of the document spelled like the one
+    /// at `position`, ignoring case, to `new_name` as given.
+    pub fn rename(&self, position: Position, new_name: &str) -> Option<Vec<TextEdit>> {
+        Some(
+            self.rename_occurrences(position, false)?
+                .into_iter()
+                .map(|occurrence| TextEdit::new(occurrence.range, new_name.to_string()))
+                .collect(),
+        )
+    }
+
+    /// The occurrences a rename of the identifier at `position` edits, in
+    /// document order: every identifier spelled like it, ignoring case,
+    /// and with `text_occurrences` the words spelled like it in strings and
+    /// comments.
+    pub fn rename_occurrences(
+        &self,
+        position: Position,
+        text_occurrences: bool,
+    ) -> Option<Vec<RenameOccurrence>> {
+        let name = self.get_name(self.renamable_identifier(position)?);
+        let mut occurrences: Vec<RenameOccurrence> = self
+            .occurrences
+            .get(&name.to_lowercase())
+            .into_iter()
+            .flatten()
+            .map(|occurrence| RenameOccurrence {
+                range: occurrence.range,
+                kind: if occurrence.declaration {
+                    RenameKind::Declaration
+                } else {
+                    RenameKind::Reference
+                },
+            })
+            .collect();
+        if let (Some(tree), true) = (&self.tree, text_occurrences) {
+            self.collect_text_occurrences(tree.root_node(), &name, &mut occurrences);
+        }
+        occurrences.sort_by_key(|occurrence| occurrence.range.start);
+        occurrences.dedup_by_key(|occurrence| occurrence.range);
+        Some(occurrences)
+    }
+
+    /// Adds the words spelled like `name`, ignoring case, in the strings
+    /// and comments under `node`.
+    fn collect_text_occurrences(
+        &self,
+        node: Node,
+        name: &str,
+        occurrences: &mut Vec<RenameOccurrence>,
+    ) {
+        if matches!(node.kind(), "comment" | "literalString") {
+            // ASCII lowercasing keeps byte offsets
+            let text = self.get_node_text(node).to_ascii_lowercase();
+            let name = name.to_ascii_lowercase();
+            let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+            let mut from = 0;
+            while let Some(found) = text[from..].find(&name) {
+                let start = from + found;
+                let end = start + name.len();
+                from = end;
+                if !text[..start].ends_with(is_ident) && !text[end..].starts_with(is_ident) {
+                    occurrences.push(RenameOccurrence {
+                        range: self.line_index.byte_range_to_range(
+                            node.start_byte() + start..node.start_byte() + end,
+                        ),
+                        kind: RenameKind::Text,
+                    });
+                }
+            }
+            return;
+        }
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            self.collect_text_occurrences(child, name, occurrences);
+        }
+    }
+
+    /// The name of the implicit `Result` or `Self` at `position`, which
+    /// rename rejects.
+    pub fn implicit_identifier_at(&self, position: Position) -> Option<String> {
+        let identifier = self.find_hover_node(self.node_at(position).ok()?);
+        Some(self.implicit_identifier(identifier)?.name)
+    }
+
+    /// The identifier at `position`, unless it is the implicit `Result` or
+    /// `Self`, which cannot be renamed.
+    fn renamable_identifier(&self, position: Position) -> Option<Node<'_>> {
+        let identifier = self.find_hover_node(self.node_at(position).ok()?);
+        if identifier.kind() != "identifier" || self.implicit_identifier(identifier).is_some() {
+            return None;
+        }
+        Some(identifier)
+    }
+
+    /// The symbols of the document whose name contains `query`, ignoring
+    /// case, in source order. Parameters and locals of routines are left
+    /// out.
+    pub fn get_workspace_symbols(&self, query: &str) -> Vec<SymbolInformation> {
+        let Some(uri) = &self.document_uri else {
+            return Vec::new();
+        };
+        let query = query.to_lowercase();
+        let mut symbols: Vec<&Symbol> = self
+            .symbol_map
+            .iter()
+            .filter(|(name, _)| name.contains(&query))
+            .flat_map(|(_, symbols)| symbols)
+            .filter(|symbol| symbol.scope.is_none())
+            .collect();
+        symbols.sort_by_key(|symbol| symbol.selection_range.start);
+        symbols
+            .into_iter()
+            .map(|symbol| {
+                #[allow(deprecated)]
+                SymbolInformation {
+                    name: symbol.name.clone(),
+                    kind: symbol.kind,
+                    tags: symbol.deprecated.then(|| vec![SymbolTag::DEPRECATED]),
+                    deprecated: None,
+                    location: Location {
+                        uri: uri.clone(),
+                        range: symbol.selection_range,
+                    },
+                    container_name: None,
+                }
+            })
+            .collect()
+    }
+
+    /// The symbols enclosing `position`, from the unit down to the innermost
+    /// one. Between declarations the path is the unit alone. The type of a
+    /// method implementation (`TFoo.Add`) gets its own segment.
+    pub fn get_symbol_path(&self, position: Position) -> Option<SymbolPath> {
+        let mut segments = Vec::new();
+        let mut level = &self.symbols;
+        while let Some(symbol) = level
+            .iter()
+            .find(|symbol| range_contains(symbol.range, position))
+        {
+            segments.extend(self.to_path_segments(symbol));
+            level = &symbol.children;
+        }
+        if segments.is_empty() {
+            let module = self
+                .symbols
+                .iter()
+                .find(|symbol| symbol.kind == SymbolKind::MODULE)?;
+            segments.extend(self.to_path_segments(module));
+        }
+        Some(SymbolPath {
+            display: segments
+                .iter()
+                .map(|segment| segment.label.as_str())
+                .collect::<Vec<_>>()
+                .join(" / "),
+            segments,
+        })
+    }
+
+    fn to_path_segments(&self, symbol: &Symbol) -> Vec<SymbolPathSegment> {
+        let is_routine = symbol.kind == SymbolKind::FUNCTION || symbol.kind == SymbolKind::METHOD;
+        if !is_routine {
+            return vec![SymbolPathSegment {
+                label: symbol.name.clone(),
+                kind: symbol.kind,
+                range: symbol.range,
+                selection_range: symbol.selection_range,
+            }];
+        }
+
+        let mut names: Vec<&str> = symbol.name.split('.').map(str::trim).collect();
+        let name = names.pop().unwrap_or_default();
+        let mut segments: Vec<SymbolPathSegment> = names
+            .into_iter()
+            .map(|qualifier| {
+                let declaration =
+                    self.symbol_map
+                        .get(&qualifier.to_lowercase())
+                        .and_then(|symbols| {
+                            symbols
+                                .iter()
+                                .find(|symbol| symbol.kind == SymbolKind::CLASS)
+                        });
+                SymbolPathSegment {
+                    label: qualifier.to_string(),
+                    kind: SymbolKind::CLASS,
+                    range: declaration.map_or(symbol.range, |declaration| declaration.range),
+                    selection_range: declaration.map_or(symbol.selection_range, |declaration| {
+                        declaration.selection_range
+                    }),
+                }
+            })
+            .collect();
+        let parameters = self
+            .node_at(symbol.selection_range.start)
+            .ok()
+            .and_then(|node| {
+                let mut current = Some(node);
+                while let Some(node) = current {
+                    if node.kind() == "declProc" {
+                        return node.child_by_field_name("args");
+                    }
+                    current = node.parent();
+                }
+                None
+            })
+            .map(|args| format_signature(&self.get_node_text(args)))
+            .unwrap_or_default();
+        segments.push(SymbolPathSegment {
+            label: format!("{}{}", name, parameters),
+            kind: symbol.kind,
+            range: symbol.range,
+            selection_range: symbol.selection_range,
+        });
+        segments
+    }
+
+    /// Returns the block constructs enclosing `position`, innermost first.
+    /// Expression and simple statement nodes are skipped.
+    pub fn get_enclosing_blocks(&self, position: Position) -> Option<Vec<EnclosingBlock>> {
+        self.tree.as_ref()?;
+        let mut current = self.node_at(position).ok();
+        let mut blocks = Vec::new();
+        while let Some(node) = current {
+            if let Some(block) = self.to_enclosing_block(node) {
+                blocks.push(block);
+            }
+            current = node.parent();
+        }
+        Some(blocks)
+    }
+
+    fn to_enclosing_block(&self, node: Node) -> Option<EnclosingBlock> {
+        let (kind, open, close) = match node.kind() {
+            "block" => (
+                "block",
+                self.find_child(node, "kBegin"),
+                self.find_child(node, "kEnd"),
+            ),
+            "repeat" => (
+                "repeat",
+                self.find_child(node, "kRepeat"),
+                self.find_child(node, "kUntil"),
+            ),
+            "case" => (
+                "case",
+                self.find_child(node, "kCase"),
+                self.find_child(node, "kEnd"),
+            ),
+            "caseCase" => ("caseBranch", None, None),
+            "try" => (
+                "try",
+                self.find_child(node, "kTry"),
+                self.find_child(node, "kEnd"),
+            ),
+            "asm" => (
+                "asm",
+                self.find_child(node, "kAsm"),
+                self.find_child(node, "kEnd"),
+            ),
+            "defProc" => {
+                let header = node.child_by_field_name("header");
+                let body = node.child_by_field_name("body");
+                (
+                    "routine",
+                    header.and_then(|header| header.child(0)),
+                    body.and_then(|body| self.find_child(body, "kEnd")),
+                )
+            }
+            "declClass" | "declIntf" | "declHelper" => {
+                ("type", node.child(0), self.find_child(node, "kEnd"))
+            }
+            _ => return None,
+        };
+
+        Some(EnclosingBlock {
+            kind: kind.to_string(),
+            range: self.node_to_range(node),
+            open_range: open.map(|open| self.node_to_range(open)),
+            close_range: close.map(|close| self.node_to_range(close)),
+        })
+    }
+
+    /// Folding ranges for multi-line blocks and string concatenations.
+    pub fn get_folding_ranges(&self) -> Option<Vec<FoldingRange>> {
+        let tree = self.tree.as_ref()?;
+        let mut ranges = Vec::new();
+        self.collect_folding_ranges(tree.root_node(), &mut ranges);
+        // `for ... do begin` on one line folds once, as the statement
+        let mut start_lines = HashSet::new();
+        ranges.retain(|range| start_lines.insert(range.start_line));
+        Some(ranges)
+    }
+
+    fn collect_folding_ranges(&self, node: Node, ranges: &mut Vec<FoldingRange>) {
+        let is_chain = strings::string_chain_root(node) == Some(node);
+        let is_loop = matches!(node.kind(), "for" | "foreach" | "while" | "with");
+        if is_chain || is_loop || self.to_enclosing_block(node).is_some() {
+            let range = self.node_to_range(node);
+            if range.start.line < range.end.line {
+                ranges.push(FoldingRange {
+                    start_line: range.start.line,
+                    start_character: None,
+                    end_line: range.end.line,
+                    end_character: None,
+                    kind: None,
+                    collapsed_text: None,
+                });
+            }
+        }
+        // A chain folds as a whole; its nested `+` expressions are not folds.
+        if is_chain {
+            return;
+        }
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            self.collect_folding_ranges(child, ranges);
+        }
+    }
+
+    /// The `{$R}`/`{$RESOURCE}` directives of the document with the file each
+    /// one names.
+    fn resource_directives(&self) -> Vec<(Node<'_>, String)> {
+        let mut directives = Vec::new();
+        let Some(tree) = &self.tree else {
+            return directives;
+        };
+        let mut stack = vec![tree.root_node()];
+        while let Some(node) = stack.pop() {
+            if node.kind() == "pp" {
+                if let Some(file) = directives::resource_file(&self.get_node_text(node)) {
+                    directives.push((node, file));
+                }
+            }
+            let mut cursor = node.walk();
+            stack.extend(node.children(&mut cursor));
+        }
+        directives.sort_by_key(|(node, _)| node.start_byte());
+        directives
+    }
+
+    fn unit_path(&self) -> Option<PathBuf> {
+        self.document_uri.as_ref()?.to_file_path().ok()
+    }
+
+    /// Reflows parameter lists and uses clauses on the lines of `range`, or
+    /// in the whole document, following the `format` settings.
+    pub fn format(&self, range: Option<Range>, options: &FormattingOptions) -> Vec<TextEdit> {
+        let Some(tree) = &self.tree else {
+            return Vec::new();
+        };
+        Formatter::new(
+            &self.source,
+            &self.line_index,
+            &self.settings.format,
+            options,
+        )
+        .format(tree.root_node(), range)
+    }
+
+    /// The edits of the `source.fixAll` code action, with the fixes
+    /// `fixAll` settings enable. `unused` names the units the uses
+    /// clauses may drop. Conflicting edits are resolved in the order
+    /// missing semicolons, uses clauses, keyword case and trailing
+    /// whitespace, leaving out the later edit.
+    pub fn fix_all(&self, unused: &[String]) -> Vec<TextEdit> {
+        let Some(tree) = &self.tree else {
+            return Vec::new();
+        };
+        let root = tree.root_node();
+        let settings = &self.settings.fix_all;
+        let mut edits = Vec::new();
+        if settings.missing_semicolons {
+            edits.extend(fixes::missing_semicolons(
+                &self.source,
+                &self.line_index,
+                root,
+            ));
+        }
+        if settings.remove_unused_uses || settings.sort_uses {
+            let unused = if settings.remove_unused_uses {
+                unused
+            } else {
+                &[]
+            };
+            edits.extend(self.organize_uses(root, unused, settings.sort_uses));
+        }
+        edits.extend(fixes::keyword_case(
+            &self.source,
+            &self.line_index,
+            root,
+            settings.keyword_case,
+            self.dialect,
+        ));
+        if settings.trailing_whitespace {
+            edits.extend(fixes::trailing_whitespace(
+                &self.source,
+                &self.line_index,
+                root,
+            ));
+        }
+        fixes::combine_edits(&self.line_index, edits)
+    }
+
+    /// The edits of the rules among `rules` of [`fixes::DOCUMENT_RULES`],
+    /// one fix each, by the precedence of the rules. `unused` names the
+    /// units the `unused-uses` rule drops; a uses clause both sorted and
+    /// cut down counts as a fix of that rule.
+    pub fn batch_fixes(&self, rules: &[&str], unused: &[String]) -> Vec<MechanicalFix> {
+        let Some(tree) = &self.tree else {
+            return Vec::new();
+        };
+        let root = tree.root_node();
+        let mut batch = Vec::new();
+        let mut add = |code: &'static str, title: &str, edits: Vec<TextEdit>| {
+            batch.extend(edits.into_iter().map(|edit| MechanicalFix {
+                code,
+                title: title.to_string(),
+                edits: vec![edit],
+            }));
+        };
+        let remove = rules.contains(&fixes::UNUSED_USES);
+        let sort = rules.contains(&fixes::SORT_USES);
+        if remove || sort {
+            let unused = if remove { unused } else { &[] };
+            let removing: Vec<Position> = self
+                .organize_uses(root, unused, false)
+                .into_iter()
+                .map(|edit| edit.range.start)
+                .collect();
+            for edit in self.organize_uses(root, unused, sort) {
+                if removing.contains(&edit.range.start) {
+                    add(fixes::UNUSED_USES, "Remove unused units", vec![edit]);
+                } else {
+                    add(fixes::SORT_USES, "Sort uses clause", vec![edit]);
+                }
+            }
+        }
+        if rules.contains(&fixes::KEYWORD_CASE) {
+            let edits = fixes::keyword_case(
+                &self.source,
+                &self.line_index,
+                root,
+                self.settings.fix_all.keyword_case,
+                self.dialect,
+            );
+            add(fixes::KEYWORD_CASE, "Change keyword case", edits);
+        }
+        if rules.contains(&fixes::TRAILING_WHITESPACE) {
+            let edits = fixes::trailing_whitespace(&self.source, &self.line_index, root);
+            add(
+                fixes::TRAILING_WHITESPACE,
+                "Remove trailing whitespace",
+                edits,
+            );
+        }
+        if rules.contains(&fixes::FINAL_NEWLINE) {
+            let edits = fixes::final_newline(&self.source, &self.line_index);
+            add(
+                fixes::FINAL_NEWLINE,
+                "Add final newline",
+                edits.into_iter().collect(),
+            );
+        }
+        batch
+    }
+
+    /// The edits of [`Formatter::organize_uses`] for the document.
+    fn organize_uses(&self, root: Node, unused: &[String], sort: bool) -> Vec<TextEdit> {
+        Formatter::new(
+            &self.source,
+            &self.line_index,
+            &self.settings.format,
+            // Code actions carry no formatting options; the Delphi style
+            // guide indents by two spaces
+            &FormattingOptions {
+                tab_size: 2,
+                insert_spaces: true,
+                ..FormattingOptions::default()
+            },
+        )
+        .organize_uses(root, unused, sort)
+    }
+
+    /// Shows the dialect a `{$MODE}` directive selects, or where a resource
+    /// directive resolves and whether the file exists.
+    fn get_directive_hover(&self, node: Node) -> Option<Hover> {
+        if node.kind() != "pp" {
+            return None;
+        }
+        let text = self.get_node_text(node);
+        if let Some(dialect) = directives::mode_directive(&text) {
+            let mut value = format!("Compiles as {}", dialect.name());
+            if dialect != self.dialect {
+                value.push_str(&format!(
+                    " (overridden by an earlier directive: {})",
+                    self.dialect.name()
+                ));
+            }
+            return Some(self.create_hover(value, Some(text), self.node_to_range(node)));
+        }
+        let file = directives::resource_file(&self.get_node_text(node))?;
+        let path = directives::resolve_resource(&file, &self.unit_path()?)?;
+        let status = if path.is_file() { "" } else { " (not found)" };
+        Some(self.create_hover(
+            format!("`{}`{}", path.display(), status),
+            Some(format!("resource {}", file)),
+            self.node_to_range(node),
+        ))
+    }
+
+    /// Links resource directives naming an existing text file, such as a
+    /// text form file, to that file.
+    pub fn get_document_links(&self) -> Vec<DocumentLink> {
+        let Some(unit_path) = self.unit_path() else {
+            return Vec::new();
+        };
+        self.resource_directives()
+            .into_iter()
+            .filter_map(|(node, file)| {
+                let path = directives::resolve_resource(&file, &unit_path)?;
+                if !directives::is_text_resource(&path) {
+                    return None;
+                }
+                Some(DocumentLink {
+                    range: self.node_to_range(node),
+                    target: Some(Url::from_file_path(&path).ok()?),
+                    tooltip: Some(path.display().to_string()),
+                    data: None,
+                })
+            })
+            .collect()
+    }
+
+    /// The conditional symbols the directives of the document name, with
+    /// their byte ranges in the document.
+    fn conditional_directives(&self) -> Vec<Conditional> {
+        let mut conditionals = Vec::new();
+        let Some(tree) = &self.tree else {
+            return conditionals;
+        };
+        let mut stack = vec![tree.root_node()];
+        while let Some(node) = stack.pop() {
+            if node.kind() == "pp" {
+                let start = node.start_byte();
+                let directive = conditionals::directive_conditionals(&self.get_node_text(node));
+                conditionals.extend(directive.into_iter().map(|conditional| Conditional {
+                    range: start + conditional.range.start..start + conditional.range.end,
+                    ..conditional
+                }));
+            }
+            let mut cursor = node.walk();
+            stack.extend(node.children(&mut cursor));
+        }
+        conditionals.sort_by_key(|conditional| conditional.range.start);
+        conditionals
+    }
+
+    /// The conditional symbols the directives of the document name.
+    pub fn get_conditional_sites(&self) -> Vec<ConditionalSite> {
+        let Some(uri) = &self.document_uri else {
+            return Vec::new();
+        };
+        self.conditional_directives()
+            .into_iter()
+            .map(|conditional| ConditionalSite {
+                location: Location {
+                    uri: uri.clone(),
+                    range: self.line_index.byte_range_to_range(conditional.range),
+                },
+                name: conditional.name,
+                usage: conditional.usage,
+            })
+            .collect()
+    }
+
+    /// The conditional symbols named by the files the document includes
+    /// with `{$I}`, and the files they include in turn. An include file
+    /// named without an extension is looked for with `.inc`. Files that
+    /// cannot be read are skipped.
+    pub fn get_included_conditionals(&self) -> Vec<ConditionalSite> {
+        let mut sites = Vec::new();
+        let Some(unit_path) = self.unit_path() else {
+            return sites;
+        };
+        let Some(tree) = &self.tree else {
+            return sites;
+        };
+        let mut includes = Vec::new();
+        let mut stack = vec![tree.root_node()];
+        while let Some(node) = stack.pop() {
+            if node.kind() == "pp" {
+                includes.extend(
+                    directives::include_file(&self.get_node_text(node))
+                        .map(|file| (file, unit_path.clone())),
+                );
+            }
+            let mut cursor = node.walk();
+            stack.extend(node.children(&mut cursor));
+        }
+
+        let mut visited = HashSet::new();
+        while let Some((file, including)) = includes.pop() {
+            let Some(mut path) = directives::resolve_resource(&file, &including) else {
+                continue;
+            };
+            if path.extension().is_none() {
+                path.set_extension("inc");
+            }
+            if !visited.insert(path.clone()) {
+                continue;
+            }
+            let (Ok(text), Ok(uri)) = (read_source(&path, true), Url::from_file_path(&path)) else {
+                continue;
+            };
+            let line_index = LineIndex::new(&text, self.position_encoding);
+            sites.extend(conditionals::scan(&text).into_iter().map(|conditional| {
+                ConditionalSite {
+                    location: Location {
+                        uri: uri.clone(),
+                        range: line_index.byte_range_to_range(conditional.range),
+                    },
+                    name: conditional.name,
+                    usage: conditional.usage,
+                }
+            }));
+            includes.extend(
+                directives::scan_directives(&text)
+                    .into_iter()
+                    .filter_map(|(_, directive)| directives::include_file(directive))
+                    .map(|file| (file, path.clone())),
+            );
+        }
+        sites
+    }
+
+    /// The conditional symbol a directive names at `position`, and its
+    /// range.
+    pub fn conditional_at(&self, position: Position) -> Option<(String, Range)> {
+        let offset = self.line_index.position_to_offset(position);
+        self.conditional_directives()
+            .into_iter()
+            .find(|conditional| {
+                conditional.range.start <= offset && offset <= conditional.range.end
+            })
+            .map(|conditional| {
+                let range = self.line_index.byte_range_to_range(conditional.range);
+                (conditional.name, range)
+            })
+    }
+
+    /// Reports the conditional symbols the document tests that are neither
+    /// in `known` nor predefined by the compilers, suggesting the closest
+    /// known name. The suggestion is the `suggestion` of the diagnostic
+    /// data.
+    pub fn get_undefined_conditional_diagnostics(&self, known: &[String]) -> Vec<Diagnostic> {
+        self.conditional_directives()
+            .into_iter()
+            .filter(|conditional| {
+                conditional.usage == ConditionalUse::Test
+                    && !conditionals::is_predefined(&conditional.name)
+                    && !known
+                        .iter()
+                        .any(|name| name.eq_ignore_ascii_case(&conditional.name))
+            })
+            .map(|conditional| {
+                let suggestion =
+                    conditionals::closest(&conditional.name, known.iter().map(String::as_str));
+                let mut message = format!(
+                    "Conditional symbol '{}' is not defined anywhere known",
+                    conditional.name
+                );
+                if let Some(suggestion) = suggestion {
+                    message.push_str(&format!("; did you mean '{}'?", suggestion));
+                }
+                Diagnostic {
+                    range: self.line_index.byte_range_to_range(conditional.range),
+                    severity: Some(DiagnosticSeverity::WARNING),
+                    code: Some(NumberOrString::String(UNDEFINED_CONDITIONAL.to_string())),
+                    source: Some("dls".to_string()),
+                    message,
+                    data: suggestion
+                        .map(|suggestion| serde_json::json!({ "suggestion": suggestion })),
+                    ..Diagnostic::default()
+                }
+            })
+            .collect()
+    }
+
+    /// Reports `{$R *.dfm}` directives whose form file is missing, and form
+    /// files next to the unit that no directive links. Either mismatch ends
+    /// in confusing linker or runtime errors.
+    fn collect_form_file_diagnostics(&self, diagnostics: &mut Vec<Diagnostic>) {
+        let Some(unit_path) = self.unit_path() else {
+            return;
+        };
+        let directives = self.resource_directives();
+        let mut linked = Vec::new();
+        for (node, file) in &directives {
+            let Some(path) = directives::resolve_resource(file, &unit_path) else {
+                continue;
+            };
+            let is_form = path
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| {
+                    directives::FORM_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+                });
+            if !is_form {
+                continue;
+            }
+            if !path.is_file() {
+                diagnostics.push(Diagnostic {
+                    range: self.node_to_range(*node),
+                    severity: Some(DiagnosticSeverity::WARNING),
+                    code: Some(NumberOrString::String(FORM_FILE_MISMATCH.to_string())),
+                    source: Some("dls".to_string()),
+                    message: format!("Form file '{}' not found", path.display()),
+                    ..Diagnostic::default()
+                });
+            }
+            linked.push(path);
+        }
+
+        let Some(module_name) = self
+            .tree
+            .as_ref()
+            .and_then(|tree| tree.root_node().child(0))
+            .and_then(|module| self.find_child(module, "moduleName"))
+        else {
+            return;
+        };
+        for extension in directives::FORM_EXTENSIONS {
+            let form = unit_path.with_extension(extension);
+            if form.is_file() && !linked.contains(&form) {
+                diagnostics.push(Diagnostic {
+                    range: self.node_to_range(module_name),
+                    severity: Some(DiagnosticSeverity::WARNING),
+                    code: Some(NumberOrString::String(FORM_FILE_MISMATCH.to_string())),
+                    source: Some("dls".to_string()),
+                    message: format!(
+                        "'{}' exists but the unit has no {{$R *.{}}} directive",
+                        form.file_name().unwrap_or_default().to_string_lossy(),
+                        extension
+                    ),
+                    ..Diagnostic::default()
+                });
+            }
+        }
+    }
+
+    /// Previews the value built by the string concatenation around a literal,
+    /// showing non-literal operands as `«name»` placeholders.
+    fn get_string_chain_hover(&self, node: Node) -> Option<Hover> {
+        let literal = match node.kind() {
+            "literalString" => node,
+            "literalChar" => node.parent()?,
+            _ => return None,
+        };
+        let root = strings::string_chain_root(literal)?;
+
+        let mut preview = String::new();
+        for operand in strings::chain_operands(root) {
+            let text = self.get_node_text(operand);
+            match operand.kind() {
+                "literalString" => preview.push_str(&strings::decode_literal(&text)?),
+                _ => preview.push_str(&format!("«{}»", text.trim())),
+            }
+        }
+        let preview = preview.replace("\r\n", "\n").replace('\r', "\n");
+
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: format!("```text\n{}\n```", preview),
+            }),
+            range: Some(self.node_to_range(root)),
+        })
+    }
+
+    /// An edit merging adjacent string literals of the concatenation at
+    /// `position`, split again where a literal would exceed 255 characters.
+    /// `None` when there is nothing to join or the chain contains comments,
+    /// which the rewrite would drop.
+    pub fn join_string_literals(&self, position: Position) -> Option<TextEdit> {
+        let root = strings::string_chain_root(self.node_at(position).ok()?)?;
+        let operands = strings::chain_operands(root);
+        let joinable = operands
+            .windows(2)
+            .any(|pair| pair.iter().all(|operand| operand.kind() == "literalString"));
+        if !joinable || self.contains_comment(root) {
+            return None;
+        }
+
+        let mut parts = Vec::new();
+        let mut run: Vec<String> = Vec::new();
+        for operand in &operands {
+            if operand.kind() == "literalString" {
+                run.push(self.get_node_text(*operand));
+                continue;
+            }
+            if !run.is_empty() {
+                let literals: Vec<&str> = run.iter().map(String::as_str).collect();
+                parts.extend(strings::join_literals(&literals)?);
+                run.clear();
+            }
+            parts.push(self.get_node_text(*operand));
+        }
+        if !run.is_empty() {
+            let literals: Vec<&str> = run.iter().map(String::as_str).collect();
+            parts.extend(strings::join_literals(&literals)?);
+        }
+
+        let range = self.node_to_range(root);
+        let separator = if range.start.line < range.end.line {
+            let ending = self
+                .line_index
+                .dominant_line_ending()
+                .unwrap_or(LineEnding::CrLf);
+            format!(
+                " +{}{}",
+                ending.as_str(),
+                " ".repeat(range.start.character as usize)
+            )
+        } else {
+            " + ".to_string()
+        };
+
+        Some(TextEdit {
+            range,
+            new_text: parts.join(&separator),
+        })
+    }
+
+    fn contains_comment(&self, node: Node) -> bool {
+        let mut cursor = node.walk();
+        let has_comment = node
+            .children(&mut cursor)
+            .any(|child| child.kind() == "comment" || self.contains_comment(child));
+        has_comment
+    }
+
+    fn find_hover_node<'a>(&self, mut node: Node<'a>) -> Node<'a> {
+        while node.kind() == "ERROR" || node.is_extra() {
+            if let Some(parent) = node.parent() {
+                node = parent;
+            } else {
+                break;
+            }
+        }
+        node
+    }
+
+    fn create_hover(&self, content: String, kind: Option<String>, range: Range) -> Hover {
+        let mut value = String::new();
+        if let Some(k) = kind {
+            value.push_str(&format!("```pascal\n{}\n```\n", k));
+        }
+        value.push_str(&content);
+
+        Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value,
+            }),
+            range: Some(range),
+        }
+    }
+
+    pub fn get_completion_items(
+        &self,
+        position: Position,
+        _trigger_char: Option<String>,
+    ) -> Option<Vec<CompletionItem>> {
+        let node = self.node_at(position).ok()?;
+        let mut items = Vec::new();
+
+        let offset = self.line_index.position_to_offset(position);
+        if let Some((kind, property)) = self.property_specifier_before(offset) {
+            // Handle `property X: T read |` and `write |`
+            if let Some(decl) = self.type_table.type_at(position) {
+                items.extend(self.get_accessor_candidates(&decl.name, &property, kind));
+            }
+        } else if let Some(qualifier) = self.qualifier_before(offset) {
+            // Handle member completion after dot
+            if let Some(type_name) = self.resolve_identifier_type(&qualifier, node) {
+                items.extend(self.get_type_members(&type_name, node));
+            }
+        } else {
+            // Handle general identifier completion
+            items.extend(self.get_visible_symbols(position));
+            items.extend(
+                completion_keywords(self.dialect)
+                    .into_iter()
+                    .map(|keyword| CompletionItem {
+                        label: keyword.to_string(),
+                        kind: Some(CompletionItemKind::KEYWORD),
+                        ..CompletionItem::default()
+                    }),
+            );
+        }
+
+        if self.settings.outline.group_overloads {
+            items = group_overload_items(items);
+        }
+        Some(items)
+    }
+
+    /// Returns the identifier before the dot preceding the word being typed
+    /// at `offset`, e.g. `Customer` for `Customer.Na|`.
+    fn qualifier_before(&self, offset: usize) -> Option<String> {
+        let before = self.get_text(0..offset);
+        let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+        let before = before.trim_end_matches(is_ident).strip_suffix('.')?;
+        let before = before.trim_end();
+        let start = before.trim_end_matches(is_ident).len();
+        let qualifier = &before[start..];
+        (!qualifier.is_empty()).then(|| qualifier.to_string())
+    }
+
+    /// Recognizes a cursor right after the `read` or `write` keyword of a
+    /// property declaration and parses the property header before it, e.g.
+    /// `property Items[Index: Integer]: string read |`. Works on the text
+    /// because the declaration is usually incomplete while typing.
+    fn property_specifier_before(
+        &self,
+        offset: usize,
+    ) -> Option<(AccessorKind, PropertySignature)> {
+        let before = self.get_text(0..offset);
+        let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+        let before = before.trim_end_matches(is_ident).trim_end();
+        let keyword_start = before.trim_end_matches(is_ident).len();
+        let kind = match before[keyword_start..].to_lowercase().as_str() {
+            "read" => AccessorKind::Read,
+            "write" => AccessorKind::Write,
+            _ => return None,
+        };
+
+        let lower = before[..keyword_start].to_lowercase();
+        let start = lower.rfind("property")?;
+        let header = &before[start + "property".len()..keyword_start];
+        let mut rest = header
+            .trim_start()
+            .trim_start_matches(is_ident)
+            .trim_start();
+
+        let mut index_params = Vec::new();
+        if let Some(args) = rest.strip_prefix('[') {
+            let end = args.find(']')?;
+            for group in args[..end].split(';') {
+                let (names, type_name) = group.split_once(':')?;
+                for name in names.split(',') {
+                    let name = name.trim().rsplit(' ').next().unwrap_or_default();
+                    index_params.push(Parameter {
+                        name: name.to_string(),
+                        type_name: Some(type_name.trim().to_string()),
+                    });
+                }
+            }
+            rest = args[end + 1..].trim_start();
+        }
+        // Any `;` outside the index brackets ends the property declaration
+        if rest.contains(';') {
+            return None;
+        }
+
+        let declared = rest.strip_prefix(':')?;
+        let mut words = declared.split_whitespace().peekable();
+        let mut type_words = Vec::new();
+        let mut index_specifier = false;
+        while let Some(word) = words.next() {
+            match word.to_lowercase().as_str() {
+                "index" => {
+                    index_specifier = true;
+                    words.next();
+                }
+                "read" | "write" | "stored" | "default" | "nodefault" => {
+                    words.next();
+                }
+                _ if !index_specifier => type_words.push(word),
+                _ => {}
+            }
+        }
+        if type_words.is_empty() {
+            return None;
+        }
+
+        Some((
+            kind,
+            PropertySignature {
+                type_name: type_words.join(" "),
+                index_params,
+                index_specifier,
+            },
+        ))
+    }
+
+    fn get_accessor_candidates(
+        &self,
+        type_name: &str,
+        property: &PropertySignature,
+        kind: AccessorKind,
+    ) -> Vec<CompletionItem> {
+        self.type_table
+            .accessor_candidates(type_name, property, kind)
+            .into_iter()
+            .map(|(declaring, member)| CompletionItem {
+                label: member.name.clone(),
+                label_details: Some(CompletionItemLabelDetails {
+                    detail: None,
+                    description: Some(declaring.name.clone()),
+                }),
+                kind: Some(match member.kind {
+                    MemberKind::Field => CompletionItemKind::FIELD,
+                    _ => CompletionItemKind::METHOD,
+                }),
+                detail: Some(member.detail.clone()),
+                ..CompletionItem::default()
+            })
+            .collect()
+    }
+
+    /// Resolves the identifier in a property's `read`/`write` specifier to
+    /// the member of the enclosing class it names.
+    fn find_accessor_member(&self, identifier: Node) -> Option<&crate::lsp::members::Member> {
+        let property = identifier.parent()?;
+        if property.kind() != "declProp" {
+            return None;
+        }
+        let is_specifier = ["getter", "setter"]
+            .iter()
+            .any(|field| property.child_by_field_name(field) == Some(identifier));
+        if !is_specifier {
+            return None;
+        }
+        let class_name = self.access_context(identifier).class_name?;
+        let (_, member) = self
+            .type_table
+            .find_member(&class_name, &self.get_name(identifier))?;
+        Some(member)
+    }
+
+    /// Lists the members of `type_name` accessible from the position of
+    /// `node`. With `visibility.relaxed` inaccessible members are listed too,
+    /// marked and sorted last.
+    fn get_type_members(&self, type_name: &str, node: Node) -> Vec<CompletionItem> {
+        let context = self.access_context(node);
+        let mut items = Vec::new();
+        for (declaring, member) in self.type_table.members(type_name) {
+            let accessible = self.type_table.is_accessible(declaring, member, &context);
+            if !accessible && !self.settings.visibility.relaxed {
+                continue;
+            }
+            let kind = match member.kind {
+                MemberKind::Field => CompletionItemKind::FIELD,
+                MemberKind::Method => CompletionItemKind::METHOD,
+                MemberKind::Property => CompletionItemKind::PROPERTY,
+            };
+            let description = if accessible {
+                format!("{} {}", member.visibility.as_str(), declaring.name)
+            } else {
+                format!(
+                    "{} {} (inaccessible)",
+                    member.visibility.as_str(),
+                    declaring.name
+                )
+            };
+            items.push(CompletionItem {
+                label: member.name.clone(),
+                label_details: Some(CompletionItemLabelDetails {
+                    detail: None,
+                    description: Some(description),
+                }),
+                kind: Some(kind),
+                detail: Some(member.detail.clone()),
+                sort_text: (!accessible).then(|| format!("~{}", member.name)),
+                ..CompletionItem::default()
+            });
+        }
+        items
+    }
+
+    /// The structured type at `position` with its members, for
+    /// `dls/typeMembers`: the type declared or named there, the declared
+    /// type of the variable, field, parameter or property there, or the
+    /// type of the expression there. Inside a type declaration, away from
+    /// any identifier, the declared type itself.
+    pub fn get_type_members_at(&self, position: Position) -> Option<TypeMembers> {
+        let node = self.find_hover_node(self.node_at(position).ok()?);
+        let decl = self
+            .expression_type(node)
+            .and_then(|type_name| self.type_table.get(&type_name))
+            .or_else(|| self.type_table.type_at(position))?;
+        let uri = self.document_uri.clone()?;
+        let location = |range| Location {
+            uri: uri.clone(),
+            range,
+        };
+        let members = self
+            .type_table
+            .all_members(&decl.name)
+            .into_iter()
+            .map(|(declaring, member)| TypeMember {
+                name: member.name.clone(),
+                kind: member.kind,
+                signature: member.detail.clone(),
+                type_name: member.type_name.clone(),
+                visibility: member.visibility,
+                declaring_type: declaring.name.clone(),
+                inherited: !std::ptr::eq(declaring, decl),
+                location: location(member.range),
+            })
+            .collect();
+        Some(TypeMembers {
+            name: decl.name.clone(),
+            location: location(decl.name_range),
+            ancestors: self
+                .type_table
+                .ancestors(&decl.name)
+                .iter()
+                .skip(1)
+                .map(|ancestor| ancestor.name.clone())
+                .collect(),
+            members,
+        })
+    }
+
+    /// The name of the type of `node`: the type a type declaration or
+    /// reference names, the declared type of a declaration, or the type of
+    /// an expression, following member accesses, calls, subscripts and
+    /// casts.
+    fn expression_type(&self, node: Node) -> Option<String> {
+        match node.kind() {
+            "identifier" => {
+                let parent = node.parent()?;
+                let is_name = parent.child_by_field_name("name") == Some(node);
+                match parent.kind() {
+                    "exprDot" if parent.child_by_field_name("rhs") == Some(node) => {
+                        self.expression_type(parent)
+                    }
+                    "declType" if is_name => Some(self.get_name(node)),
+                    "typeref" => Some(self.get_node_text(parent)),
+                    "declField" | "declVar" | "declArg" | "declProp" | "declConst" | "declProc"
+                        if is_name =>
+                    {
+                        let type_node = parent.child_by_field_name("type")?;
+                        Some(self.get_node_text(type_node))
+                    }
+                    _ => self.resolve_identifier_type(&self.get_name(node), node),
+                }
+            }
+            _ => self.chain_type(node, &mut ChainResolver::local()),
+        }
+    }
+
+    /// The member named by the identifier at `position` when it follows a
+    /// dot, resolving the chain before it left to right: `Address` in
+    /// `Order.Customer.Address.City` is looked up in the type of
+    /// `Order.Customer`. Variables, fields, properties, function results,
+    /// indexers and `as` casts are followed; the chain stops where a type
+    /// is unknown. A member of the document hidden by visibility is not
+    /// found, unless `visibility.relaxed` is set. The first identifier of
+    /// a chain follows no dot and is left to the plain lookup.
+    pub fn member_chain_at(
+        &self,
+        position: Position,
+        chain: &mut ChainResolver,
+    ) -> Option<ChainMember> {
+        let identifier = self.find_hover_node(self.node_at(position).ok()?);
+        let dot = identifier
+            .parent()
+            .filter(|dot| dot.child_by_field_name("rhs") == Some(identifier))?;
+        let (_, found) = self.member_access(dot, chain)?;
+        if found.unit.is_none() && !self.settings.visibility.relaxed {
+            let (declaring, member) = self
+                .type_table
+                .find_member(&found.declaring_type, &found.member.name)?;
+            let context = self.access_context(identifier);
+            if !self.type_table.is_accessible(declaring, member, &context) {
+                return None;
+            }
+        }
+        Some(found)
+    }
+
+    /// The hover of the member named at `position` after a dot: its
+    /// declaration and the type declaring it.
+    pub fn get_member_chain_hover(
+        &self,
+        position: Position,
+        chain: &mut ChainResolver,
+    ) -> Option<Hover> {
+        let found = self.member_chain_at(position, chain)?;
+        let identifier = self.find_hover_node(self.node_at(position).ok()?);
+        let kind = match found.member.kind {
+            MemberKind::Field => "Field",
+            MemberKind::Method => "Method",
+            MemberKind::Property => "Property",
+        };
+        let mut value = format!(
+            "```pascal\n{}\n```\n{} of `{}`",
+            found.member.detail, kind, found.declaring_type
+        );
+        if let Some(unit) = &found.unit {
+            value.push_str(&format!(", declared in unit `{}`", unit));
+        }
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value,
+            }),
+            range: Some(self.node_to_range(identifier)),
+        })
+    }
+
+    /// The owner type and the member of the member access `dot`, when
+    /// `dot` is an `exprDot` whose right-hand side is an identifier.
+    fn member_access(&self, dot: Node, chain: &mut ChainResolver) -> Option<(String, ChainMember)> {
+        let rhs = dot
+            .child_by_field_name("rhs")
+            .filter(|rhs| dot.kind() == "exprDot" && rhs.kind() == "identifier")?;
+        let owner = self.chain_type(dot.child_by_field_name("lhs")?, chain)?;
+        let found =
+            self.find_chain_member(&owner, MemberQuery::Named(&self.get_name(rhs)), chain)?;
+        Some((owner, found))
+    }
+
+    /// The type of the expression `node` of a member access chain.
+    fn chain_type(&self, node: Node, chain: &mut ChainResolver) -> Option<String> {
+        match node.kind() {
+            "identifier" => {
+                let name = self.get_name(node);
+                self.resolve_identifier_type(&name, node)
+                    .or_else(|| self.global_type(&name))
+                    .or_else(|| {
+                        let (_, unit) = chain.unit_declaring(self, &name)?;
+                        unit.global_type(&name)
+                    })
+            }
+            "exprDot" => {
+                let (owner, found) = self.member_access(node, chain)?;
+                if found.member.is_constructor() {
+                    Some(owner)
+                } else {
+                    found.member.type_name
+                }
+            }
+            "exprCall" => self.chain_type(node.child_by_field_name("entity")?, chain),
+            "exprSubscript" => {
+                let entity = node.child_by_field_name("entity")?;
+                // An array property takes the indexes itself
+                if let Some((_, found)) = self.member_access(entity, chain) {
+                    if found.member.kind == MemberKind::Property && !found.member.params.is_empty()
+                    {
+                        return found.member.type_name;
+                    }
+                }
+                let container = self.chain_type(entity, chain)?;
+                self.indexed_type(&container, chain)
+            }
+            "exprParens" => self.chain_type(node.named_child(0)?, chain),
+            "exprBinary" => {
+                let operator = node.child_by_field_name("operator")?;
+                let target = node.child_by_field_name("rhs")?;
+                (operator.kind() == "kAs").then(|| self.get_node_text(target))
+            }
+            _ => None,
+        }
+    }
+
+    /// The type of `Value[Index]` for a `Value` of type `container`: the
+    /// type of its default array property, or the element type of arrays,
+    /// strings and the RTL generic collections.
+    fn indexed_type(&self, container: &str, chain: &mut ChainResolver) -> Option<String> {
+        if let Some(found) = self.find_chain_member(container, MemberQuery::DefaultProperty, chain)
+        {
+            return found.member.type_name;
+        }
+        if self.type_table.get_instance(container).is_some() {
+            return None;
+        }
+        let (base, args) = split_generic(container);
+        let resolution = if base.eq_ignore_ascii_case("TArray") {
+            args.first()
+                .map(|arg| Resolution::declared(arg.to_string()))
+        } else if ENUMERABLE_DICTIONARIES
+            .iter()
+            .any(|dictionary| dictionary.eq_ignore_ascii_case(base))
+        {
+            args.get(1).map(|value| {
+                Resolution::heuristic(
+                    value.to_string(),
+                    format!(
+                        "{} assumed to be the System.Generics.Collections type",
+                        base
+                    ),
+                )
+            })
+        } else {
+            self.element_type(container, 0)
+        };
+        self.accept(resolution, || format!("the elements of {}", container))
+    }
+
+    /// Finds `query` in the type `type_name` or its ancestors, following
+    /// them into the units declaring them.
+    fn find_chain_member(
+        &self,
+        type_name: &str,
+        query: MemberQuery,
+        chain: &mut ChainResolver,
+    ) -> Option<ChainMember> {
+        if !chain.step() {
+            return None;
+        }
+        let Some((decl, bindings)) = self.type_table.get_instance(type_name) else {
+            let (base, _) = split_generic(type_name);
+            let (unit_name, unit) = chain.unit_declaring(self, base)?;
+            unit.type_table.get_instance(type_name)?;
+            let found = unit.find_chain_member(type_name, query, chain)?;
+            return Some(ChainMember {
+                unit: found.unit.or(Some(unit_name)),
+                ..found
+            });
+        };
+        let found = match query {
+            MemberQuery::Named(name) => self.type_table.find_member(&decl.name, name),
+            MemberQuery::DefaultProperty => self.type_table.default_property(&decl.name),
+        };
+        let Some((declaring, member)) = found else {
+            // Inherited from an ancestor declared in another unit
+            let ancestors = self.type_table.ancestors(&decl.name);
+            let parent = ancestors.last()?.parents.first()?;
+            return self.find_chain_member(parent, query, chain);
+        };
+        let mut member = member.clone();
+        member.type_name = member
+            .type_name
+            .map(|type_name| substitute_type_params(&type_name, &bindings));
+        Some(ChainMember {
+            declaring_type: declaring.name.clone(),
+            location: self.location(member.range).ok()?,
+            member,
+            unit: None,
+        })
+    }
+
+    /// The type of a unit-level variable or function result named `name`,
+    /// or `name` itself when it is a type the document declares.
+    fn global_type(&self, name: &str) -> Option<String> {
+        let root = self.tree.as_ref()?.root_node();
+        self.find_global_variable_type(root, name)
+            .or_else(|| self.find_function_result_type(root, name))
+            .or_else(|| self.type_table.get(name).map(|decl| decl.name.clone()))
+    }
+
+    /// Finds the result type of a unit- or program-level function, skipping
+    /// type declarations and routine bodies.
+    fn find_function_result_type(&self, node: Node, name: &str) -> Option<String> {
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            let header = match child.kind() {
+                "declProc" => Some(child),
+                "defProc" => child.child_by_field_name("header"),
+                "declType" => None,
+                _ => {
+                    if let Some(found) = self.find_function_result_type(child, name) {
+                        return Some(found);
+                    }
+                    None
+                }
+            };
+            let found = header.and_then(|header| {
+                let routine = header
+                    .child_by_field_name("name")
+                    .filter(|routine| routine.kind() == "identifier")?;
+                let result = header.child_by_field_name("type")?;
+                self.get_name(routine)
+                    .eq_ignore_ascii_case(name)
+                    .then(|| self.get_node_text(result))
+            });
+            if found.is_some() {
+                return found;
+            }
+        }
+        None
+    }
+
+    /// Resolves the method names of a method resolution clause,
+    /// `procedure IMyIntf.DoWork = InternalDoWork;`: `DoWork` to the method
+    /// of the interface, `InternalDoWork` to the method of the class.
+    fn find_resolution_member(&self, identifier: Node) -> Option<&Member> {
+        let parent = identifier.parent()?;
+        let (type_name, name) = match parent.kind() {
+            "genericDot" if parent.child_by_field_name("rhs") == Some(identifier) => {
+                let clause = parent.parent()?;
+                clause.child_by_field_name("assign")?;
+                let interface = parent.child_by_field_name("lhs")?;
+                (self.get_name(interface), self.get_name(identifier))
+            }
+            "defaultValue" => {
+                let clause = parent.parent()?;
+                if clause.kind() != "declProc" || clause.child_by_field_name("assign")? != parent {
+                    return None;
+                }
+                let class = self.type_table.type_at(self.node_to_range(clause).start)?;
+                (class.name.clone(), self.get_name(identifier))
+            }
+            _ => return None,
+        };
+        let (_, member) = self.type_table.find_member(&type_name, &name)?;
+        Some(member)
+    }
+
+    /// Handles `textDocument/signatureHelp`: the signatures of the routine
+    /// whose argument list contains `position`, overloads included, with
+    /// the argument being typed active. The active signature is the one
+    /// with the fewest parameters that still takes that argument. Works on
+    /// the text from the enclosing block, as a call being typed rarely
+    /// parses.
+    pub fn get_signature_help(&self, position: Position) -> Option<SignatureHelp> {
+        let node = self.node_at(position).ok()?;
+        let offset = self.line_index.position_to_offset(position);
+        let start = std::iter::successors(Some(node), |node| node.parent())
+            .find(|node| matches!(node.kind(), "block" | "blockTr"))
+            .map_or(0, |block| block.start_byte().min(offset));
+        let (signatures, active_parameter) = open_argument_lists(self.get_text(start..offset))
+            .into_iter()
+            .rev()
+            .find_map(|(paren, commas)| {
+                let signatures = self.call_signatures(start + paren, node, position);
+                (!signatures.is_empty()).then_some((signatures, commas))
+            })?;
+        let arity =
+            |signature: &SignatureInformation| signature.parameters.as_ref().map_or(0, Vec::len);
+        let active_signature = signatures
+            .iter()
+            .enumerate()
+            .filter(|(_, signature)| arity(signature) > active_parameter)
+            .min_by_key(|(_, signature)| arity(signature))
+            .or_else(|| {
+                signatures
+                    .iter()
+                    .enumerate()
+                    .max_by_key(|(_, signature)| arity(signature))
+            })
+            .map(|(index, _)| index as u32);
+        Some(SignatureHelp {
+            signatures,
+            active_signature,
+            active_parameter: Some(active_parameter as u32),
+        })
+    }
+
+    /// The signatures of the routine called with the parenthesis at offset
+    /// `paren`, as `Name(` or `Value.Method(`, ignoring case.
+    fn call_signatures(
+        &self,
+        paren: usize,
+        node: Node,
+        position: Position,
+    ) -> Vec<SignatureInformation> {
+        let before = self.get_text(0..paren).trim_end();
+        let is_ident = |c: char| c.is_alphanumeric() || c == '_' || c == '&';
+        let name = unescape_identifier(&before[before.trim_end_matches(is_ident).len()..]);
+        if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+            return Vec::new();
+        }
+        let declarations: Vec<Range> = match self.qualifier_before(before.len()) {
+            Some(qualifier) => {
+                let type_name = self
+                    .resolve_identifier_type(&qualifier, node)
+                    .unwrap_or(qualifier);
+                // Overloads in the nearest type declaring the method
+                self.type_table
+                    .ancestors(&type_name)
+                    .into_iter()
+                    .map(|decl| {
+                        decl.members
+                            .iter()
+                            .filter(|member| {
+                                member.kind == MemberKind::Method
+                                    && member.name.eq_ignore_ascii_case(&name)
+                            })
+                            .map(|member| member.range)
+                            .collect::<Vec<_>>()
+                    })
+                    .find(|methods| !methods.is_empty())
+                    .unwrap_or_default()
+            }
+            None => self
+                .visible_declarations(&name.to_lowercase(), position)
+                .into_iter()
+                .filter(|symbol| matches!(symbol.kind, SymbolKind::FUNCTION | SymbolKind::METHOD))
+                .map(|symbol| symbol.selection_range)
+                .collect(),
+        };
+        let mut signatures: Vec<SignatureInformation> = Vec::new();
+        for declaration in declarations {
+            // A routine of the interface section repeats in the
+            // implementation section
+            match self.signature_information(declaration) {
+                Some(signature)
+                    if !signatures
+                        .iter()
+                        .any(|other| other.label == signature.label) =>
+                {
+                    signatures.push(signature)
+                }
+                _ => {}
+            }
+        }
+        signatures
+    }
+
+    /// The signature of the routine header at `range`, labelled like
+    /// `function Add(A, B: Integer): Integer`, with one parameter per name
+    /// so that `A, B: Integer` gives two.
+    fn signature_information(&self, range: Range) -> Option<SignatureInformation> {
+        let header = std::iter::successors(self.node_at(range.start).ok(), |node| node.parent())
+            .find(|node| node.kind() == "declProc")?;
+        let name = header.child_by_field_name("name")?;
+        let mut label = format_signature(self.get_text(header.start_byte()..name.end_byte()));
+        // Byte ranges of the parameter names in the label
+        let mut names = Vec::new();
+        if let Some(args) = header.child_by_field_name("args") {
+            label.push('(');
+            let mut cursor = args.walk();
+            let args = args
+                .children(&mut cursor)
+                .filter(|arg| arg.kind() == "declArg");
+            for (index, arg) in args.enumerate() {
+                if index > 0 {
+                    label.push_str("; ");
+                }
+                let text = format_signature(&self.get_node_text(arg));
+                let mut searched = 0;
+                let mut name_nodes = arg.walk();
+                for name in arg
+                    .children_by_field_name("name", &mut name_nodes)
+                    .filter(|name| name.kind() == "identifier")
+                {
+                    let name = self.get_node_text(name);
+                    if let Some(found) = find_word(&text[searched..], &name) {
+                        let start = label.len() + searched + found;
+                        names.push(start..start + name.len());
+                        searched += found + name.len();
+                    }
+                }
+                label.push_str(&text);
+            }
+            label.push(')');
+        }
+        if let Some(result) = header.child_by_field_name("type") {
+            label.push_str(": ");
+            label.push_str(&format_signature(&self.get_node_text(result)));
+        }
+        if let Some(convention) = self.effective_calling_convention(header) {
+            label.push_str("; ");
+            label.push_str(&convention);
+        }
+        // Label offsets count UTF-16 code units
+        let utf16_offset = |offset: usize| label[..offset].encode_utf16().count() as u32;
+        let parameters = names
+            .into_iter()
+            .map(|name| ParameterInformation {
+                label: ParameterLabel::LabelOffsets([
+                    utf16_offset(name.start),
+                    utf16_offset(name.end),
+                ]),
+                documentation: None,
+            })
+            .collect();
+        Some(SignatureInformation {
+            label,
+            documentation: None,
+            parameters: Some(parameters),
+            active_parameter: None,
+        })
+    }
+
+    /// Reports semantic problems: property accessors and method resolution
+    /// clauses naming missing or incompatible members, interface methods a
+    /// class leaves unimplemented, and, when `diagnostics.visibility` is set,
+    /// dotted member accesses the compiler would reject because of the
+    /// member's visibility.
+    pub fn get_diagnostics(&self) -> Vec<Diagnostic> {
+        if self.mode == AnalysisMode::Outline {
+            return Vec::new();
+        }
+        let mut diagnostics: Vec<Diagnostic> = self
+            .type_table
+            .accessor_problems()
+            .into_iter()
+            .map(|problem| Diagnostic {
+                range: problem.range,
+                severity: Some(DiagnosticSeverity::ERROR),
+                code: Some(NumberOrString::String(
+                    INVALID_PROPERTY_ACCESSOR.to_string(),
+                )),
+                source: Some("dls".to_string()),
+                message: problem.message,
+                ..Diagnostic::default()
+            })
+            .collect();
+        let member_problems = [
+            (
+                self.type_table.resolution_problems(),
+                INVALID_METHOD_RESOLUTION,
+            ),
+            (
+                self.type_table.missing_interface_methods(),
+                MISSING_INTERFACE_METHOD,
+            ),
+            (self.type_table.override_problems(), INVALID_OVERRIDE),
+        ];
+        for (problems, code) in member_problems {
+            diagnostics.extend(problems.into_iter().map(|problem| Diagnostic {
+                range: problem.range,
+                severity: Some(DiagnosticSeverity::ERROR),
+                code: Some(NumberOrString::String(code.to_string())),
+                source: Some("dls".to_string()),
+                message: problem.message,
+                ..Diagnostic::default()
+            }));
+        }
+        if let (Some(tree), true) = (&self.tree, self.settings.diagnostics.visibility) {
+            self.collect_visibility_diagnostics(tree.root_node(), &mut diagnostics);
+        }
+        if let Some(tree) = &self.tree {
+            self.collect_reserved_identifiers(tree.root_node(), &mut diagnostics);
+            self.collect_message_handler_diagnostics(tree.root_node(), &mut diagnostics);
+            self.collect_declaration_mismatches(tree.root_node(), &mut diagnostics);
+            self.collect_overload_diagnostics(tree.root_node(), &mut diagnostics);
+            let mut procedural_types = HashMap::new();
+            self.procedural_types(tree.root_node(), &mut procedural_types);
+            self.collect_calling_convention_diagnostics(
+                tree.root_node(),
+                &procedural_types,
+                &mut diagnostics,
+            );
+            self.collect_dialect_diagnostics(tree.root_node(), &mut diagnostics);
+            self.collect_guid_diagnostics(&mut diagnostics);
+
+            let mut constants = ConstEvaluator::default();
+            constants.collect(tree.root_node(), &self.source);
+            self.collect_case_diagnostics(tree.root_node(), &constants, &mut diagnostics);
+        }
+        self.collect_form_file_diagnostics(&mut diagnostics);
+        if let (Some(tree), false) = (&self.tree, self.settings.naming.is_empty()) {
+            self.collect_naming_diagnostics(tree.root_node(), &mut diagnostics);
+        }
+        if self.settings.diagnostics.shadowed_intrinsics {
+            self.collect_shadowed_intrinsics(&mut diagnostics);
+        }
+        if let (Some(tree), true) = (
+            &self.tree,
+            self.settings.diagnostics.uninitialized_variables,
+        ) {
+            self.collect_uninitialized_reads(tree.root_node(), &mut diagnostics);
+        }
+        if self.settings.diagnostics.unused_private {
+            diagnostics.extend(
+                self.unused_declarations()
+                    .into_iter()
+                    .map(|unused| Diagnostic {
+                        range: self.node_to_range(unused.name),
+                        severity: Some(DiagnosticSeverity::HINT),
+                        code: Some(NumberOrString::String(UNUSED_PRIVATE_MEMBER.to_string())),
+                        source: Some("dls".to_string()),
+                        message: format!(
+                            "{} '{}' is never used",
+                            unused.kind,
+                            self.get_name(unused.name)
+                        ),
+                        tags: Some(vec![DiagnosticTag::UNNECESSARY]),
+                        ..Diagnostic::default()
+                    }),
+            );
+        }
+        diagnostics
+    }
+
+    /// Reports `uses` entries naming one of `missing_units`, the units whose
+    /// file was deleted or moved away while the workspace was open.
+    pub fn get_missing_unit_diagnostics(&self, missing_units: &[String]) -> Vec<Diagnostic> {
+        self.uses_entries()
+            .into_iter()
+            .filter(|(_, name)| {
+                missing_units
+                    .iter()
+                    .any(|missing| missing.eq_ignore_ascii_case(name))
+            })
+            .map(|(unit, name)| Diagnostic {
+                range: self.node_to_range(unit),
+                severity: Some(DiagnosticSeverity::INFORMATION),
+                code: Some(NumberOrString::String(MISSING_UNIT.to_string())),
+                source: Some("dls".to_string()),
+                message: format!("The file of unit '{}' was deleted or moved on disk", name),
+                ..Diagnostic::default()
+            })
+            .collect()
+    }
+
+    /// Reports `uses` entries naming one of `unresolved_units`, with the
+    /// folders searched for them in the message and, in full, as the
+    /// `searchedPaths` of the diagnostic data. A unit paired with the
+    /// folder of a file of that name off the search path carries it as the
+    /// `containingFolder` of the data.
+    pub fn get_unresolved_unit_diagnostics(
+        &self,
+        unresolved_units: &[(String, Option<PathBuf>)],
+        searched: &[PathBuf],
+    ) -> Vec<Diagnostic> {
+        let paths: Vec<String> = searched
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        let looked_in = match paths.len() {
+            0 => "no workspace folder is open".to_string(),
+            count if count > MAX_LISTED_SEARCH_PATHS => format!(
+                "looked in: {} and {} more",
+                paths[..MAX_LISTED_SEARCH_PATHS].join(", "),
+                count - MAX_LISTED_SEARCH_PATHS
+            ),
+            _ => format!("looked in: {}", paths.join(", ")),
+        };
+        self.uses_entries()
+            .into_iter()
+            .filter_map(|(unit, name)| {
+                let (_, folder) = unresolved_units
+                    .iter()
+                    .find(|(unresolved, _)| unresolved.eq_ignore_ascii_case(&name))?;
+                let mut data = serde_json::json!({ "unit": name, "searchedPaths": paths });
+                if let Some(folder) = folder {
+                    data["containingFolder"] = serde_json::json!(folder.display().to_string());
+                }
+                Some(Diagnostic {
+                    range: self.node_to_range(unit),
+                    severity: Some(DiagnosticSeverity::WARNING),
+                    code: Some(NumberOrString::String(UNRESOLVED_UNIT.to_string())),
+                    source: Some("dls".to_string()),
+                    message: format!("unit '{}' not found on search path; {}", name, looked_in),
+                    data: Some(data),
+                    ..Diagnostic::default()
+                })
+            })
+            .collect()
+    }
+
+    /// The names of the units in the `uses` clauses of the document.
+    pub fn get_used_units(&self) -> Vec<String> {
+        self.uses_entries()
+            .into_iter()
+            .map(|(_, name)| name)
+            .collect()
+    }
+
+    /// The `moduleName` entries of all `uses` clauses in source order, with
+    /// their dotted name without whitespace.
+    fn uses_entries(&self) -> Vec<(Node<'_>, String)> {
+        let mut entries = Vec::new();
+        let Some(tree) = &self.tree else {
+            return entries;
+        };
+        let mut stack = vec![tree.root_node()];
+        while let Some(node) = stack.pop() {
+            if node.kind() != "declUses" {
+                let mut cursor = node.walk();
+                stack.extend(node.children(&mut cursor));
+                continue;
+            }
+            let mut cursor = node.walk();
+            for unit in node.children(&mut cursor) {
+                if unit.kind() == "moduleName" {
+                    let name = self
+                        .get_name(unit)
+                        .chars()
+                        .filter(|c| !c.is_whitespace())
+                        .collect();
+                    entries.push((unit, name));
+                }
+            }
+        }
+        entries.sort_by_key(|(unit, _)| unit.start_byte());
+        entries
+    }
+
+    /// Finds private fields and methods, and routines declared only in the
+    /// implementation section, whose name occurs nowhere in the unit except
+    /// in declarations. Members the compiler or RTTI reach without naming
+    /// them are skipped: those with attributes, constructors and
+    /// destructors, `override` and `message` methods, and methods that may
+    /// implement an interface.
+    fn unused_declarations(&self) -> Vec<UnusedDeclaration<'_>> {
+        let mut unused = Vec::new();
+        let Some(tree) = &self.tree else {
+            return unused;
+        };
+        let root = tree.root_node();
+        let mut stack = vec![root];
+        while let Some(node) = stack.pop() {
+            match node.kind() {
+                "declSection"
+                    if matches!(
+                        Visibility::from_section(node),
+                        Some(Visibility::Private | Visibility::StrictPrivate)
+                    ) =>
+                {
+                    let type_name = node
+                        .parent()
+                        .and_then(|body| body.parent())
+                        .filter(|decl| decl.kind() == "declType")
+                        .and_then(|decl| decl.child_by_field_name("name"))
+                        .map(|name| self.get_name(name));
+                    let mut cursor = node.walk();
+                    for member in node.children(&mut cursor) {
+                        self.collect_unused_member(member, type_name.as_deref(), &mut unused);
+                    }
+                }
+                "implementation" => {
+                    let mut cursor = node.walk();
+                    for routine in node.children(&mut cursor) {
+                        self.collect_unused_routine(routine, &mut unused);
+                    }
+                }
+                _ => {}
+            }
+            let mut cursor = node.walk();
+            stack.extend(node.children(&mut cursor));
+        }
+        unused.sort_by_key(|unused| unused.name.start_byte());
+        unused
+    }
+
+    fn collect_unused_member<'a>(
+        &'a self,
+        member: Node<'a>,
+        type_name: Option<&str>,
+        unused: &mut Vec<UnusedDeclaration<'a>>,
+    ) {
+        if !matches!(member.kind(), "declField" | "declProc")
+            || self.find_child(member, "rttiAttributes").is_some()
+        {
+            return;
+        }
+        let mut cursor = member.walk();
+        let names: Vec<Node> = member
+            .children_by_field_name("name", &mut cursor)
+            .filter(|name| name.kind() == "identifier")
+            .collect();
+
+        if member.kind() == "declField" {
+            let removal = if names.len() == 1 {
+                vec![member]
+            } else {
+                Vec::new()
+            };
+            for name in names {
+                if !self.is_used(name) {
+                    unused.push(UnusedDeclaration {
+                        name,
+                        kind: "Private field",
+                        removal: removal.clone(),
+                    });
+                }
+            }
+            return;
+        }
+
+        let Some(&name) = names.first() else {
+            return;
+        };
+        let directives = self.get_directives(member);
+        let reached_indirectly = self.find_child(member, "kConstructor").is_some()
+            || self.find_child(member, "kDestructor").is_some()
+            || directives
+                .iter()
+                .any(|directive| matches!(directive.as_str(), "override" | "message" | "dynamic"));
+        let method = self.get_name(name);
+        if reached_indirectly
+            || type_name.is_some_and(|type_name| {
+                self.type_table.may_implement_interface(type_name, &method)
+            })
+            || self.is_used(name)
+        {
+            return;
+        }
+        let mut removal = vec![member];
+        if let Some(type_name) = type_name {
+            removal.extend(self.method_implementations(type_name, &method));
+        }
+        unused.push(UnusedDeclaration {
+            name,
+            kind: "Private method",
+            removal,
+        });
+    }
+
+    /// Reports a routine of the implementation section that has no
+    /// declaration in the interface section and is never used.
+    fn collect_unused_routine<'a>(
+        &'a self,
+        routine: Node<'a>,
+        unused: &mut Vec<UnusedDeclaration<'a>>,
+    ) {
+        if routine.kind() != "defProc" {
+            return;
+        }
+        let Some(name) = routine
+            .child_by_field_name("header")
+            .and_then(|header| header.child_by_field_name("name"))
+            .filter(|name| name.kind() == "identifier")
+        else {
+            return;
+        };
+        let routine_name = self.get_name(name);
+        let Some(implementation) = routine.parent() else {
+            return;
+        };
+        let declared_in_interface = implementation
+            .prev_sibling()
+            .filter(|section| section.kind() == "interface")
+            .is_some_and(|interface| {
+                let mut cursor = interface.walk();
+                let found = interface.children(&mut cursor).any(|child| {
+                    child.kind() == "declProc"
+                        && child
+                            .child_by_field_name("name")
+                            .is_some_and(|n| self.get_name(n).eq_ignore_ascii_case(&routine_name))
+                });
+                found
+            });
+        if declared_in_interface || self.is_used(name) {
+            return;
+        }
+        // Forward declarations in the implementation section go too
+        let mut cursor = implementation.walk();
+        let removal = implementation
+            .children(&mut cursor)
+            .filter(|child| {
+                let header = match child.kind() {
+                    "defProc" => child.child_by_field_name("header"),
+                    "declProc" => Some(*child),
+                    _ => None,
+                };
+                header
+                    .and_then(|header| header.child_by_field_name("name"))
+                    .is_some_and(|n| {
+                        n.kind() == "identifier"
+                            && self.get_name(n).eq_ignore_ascii_case(&routine_name)
+                    })
+            })
+            .collect();
+        unused.push(UnusedDeclaration {
+            name,
+            kind: "Routine",
+            removal,
+        });
+    }
+
+    /// The `defProc` nodes implementing `TypeName.Method`.
+    fn method_implementations<'a>(&'a self, type_name: &str, method: &str) -> Vec<Node<'a>> {
+        let mut implementations = Vec::new();
+        let Some(tree) = &self.tree else {
+            return implementations;
+        };
+        let mut stack = vec![tree.root_node()];
+        while let Some(node) = stack.pop() {
+            if node.kind() == "defProc" {
+                let qualified = node
+                    .child_by_field_name("header")
+                    .and_then(|header| header.child_by_field_name("name"))
+                    .filter(|name| name.kind() == "genericDot");
+                if let Some(qualified) = qualified {
+                    let lhs = qualified.child_by_field_name("lhs");
+                    let rhs = qualified.child_by_field_name("rhs");
+                    if lhs.is_some_and(|lhs| self.get_name(lhs).eq_ignore_ascii_case(type_name))
+                        && rhs.is_some_and(|rhs| self.get_name(rhs).eq_ignore_ascii_case(method))
+                    {
+                        implementations.push(node);
+                    }
+                }
+                continue;
+            }
+            let mut cursor = node.walk();
+            stack.extend(node.children(&mut cursor));
+        }
+        implementations
+    }
+
+    /// Whether the identifier declared by `name` occurs anywhere in the
+    /// document outside declarations. Occurrences are matched by name, so a
+    /// same-named declaration elsewhere counts as a use.
+    fn is_used(&self, name: Node) -> bool {
+        self.occurrences
+            .get(&self.get_name(name).to_lowercase())
+            .is_some_and(|occurrences| occurrences.iter().any(|occurrence| !occurrence.declaration))
+    }
+
+    /// Whether `identifier` is the name of a declaration or of a method
+    /// implementation header rather than a use.
+    fn is_declaration_name(&self, identifier: Node) -> bool {
+        let Some(mut parent) = identifier.parent() else {
+            return false;
+        };
+        let mut named = identifier;
+        if parent.kind() == "genericDot" {
+            if parent.child_by_field_name("rhs") != Some(identifier) {
+                return false;
+            }
+            named = parent;
+            let Some(grandparent) = parent.parent() else {
+                return false;
+            };
+            parent = grandparent;
+        }
+        matches!(
+            parent.kind(),
+            "declField"
+                | "declProc"
+                | "declVar"
+                | "declConst"
+                | "declType"
+                | "declArg"
+                | "declProp"
+                | "declEnumValue"
+        ) && {
+            let mut cursor = parent.walk();
+            let is_name = parent
+                .children_by_field_name("name", &mut cursor)
+                .any(|name| name == named);
+            is_name
+        }
+    }
+
+    /// The "Remove declaration" fix for an `unused-private-member`
+    /// diagnostic at `position`: deletes the declaration together with the
+    /// implementation of a method, each with the lines it occupies.
+    pub fn remove_unused_declaration(&self, position: Position) -> Option<Vec<TextEdit>> {
+        let unused = self
+            .unused_declarations()
+            .into_iter()
+            .find(|unused| range_contains(self.node_to_range(unused.name), position))?;
+        if unused.removal.is_empty() {
+            return None;
+        }
+        Some(
+            unused
+                .removal
+                .into_iter()
+                .map(|node| TextEdit {
+                    range: self
+                        .line_index
+                        .byte_range_to_range(self.line_removal_range(node)),
+                    new_text: String::new(),
+                })
+                .collect(),
+        )
+    }
+
+    /// The byte range removing `node`: whole lines when nothing else shares
+    /// them, plus one of two blank lines the removal would leave adjacent.
+    fn line_removal_range(&self, node: Node) -> std::ops::Range<usize> {
+        let source = self.source.as_str();
+        let mut start = node.start_byte().min(source.len());
+        let mut end = node.end_byte().min(source.len());
+        let line_start = self.get_text(0..start).rfind('\n').map_or(0, |i| i + 1);
+        if self.get_text(line_start..start).trim().is_empty() {
+            start = line_start;
+        }
+        let line_end = |from: usize| {
+            self.get_text(from..source.len())
+                .find('\n')
+                .map_or(source.len(), |i| from + i + 1)
+        };
+        if self.get_text(end..line_end(end)).trim().is_empty() {
+            end = line_end(end);
+            let blank_before = start == 0
+                || self
+                    .get_text(0..start - 1)
+                    .rsplit('\n')
+                    .next()
+                    .is_some_and(|line| line.trim().is_empty());
+            let next = line_end(end);
+            if blank_before && end < next && self.get_text(end..next).trim().is_empty() {
+                end = next;
+            }
+        }
+        start..end
+    }
+
+    /// Reports FreePascal's `generic` and `specialize` keywords in dialects
+    /// that lack them; in objfpc and fpc modes they are ordinary syntax.
+    fn collect_dialect_diagnostics(&self, node: Node, diagnostics: &mut Vec<Diagnostic>) {
+        if self.dialect.has_fpc_generics() {
+            return;
+        }
+        if matches!(node.kind(), "kGeneric" | "kSpecialize") {
+            diagnostics.push(Diagnostic {
+                range: self.node_to_range(node),
+                severity: Some(DiagnosticSeverity::ERROR),
+                code: Some(NumberOrString::String(DIALECT_SYNTAX.to_string())),
+                source: Some("dls".to_string()),
+                message: format!(
+                    "'{}' is not available in {}; it needs {{$MODE objfpc}} or {{$MODE fpc}}",
+                    self.get_node_text(node),
+                    self.dialect.name()
+                ),
+                ..Diagnostic::default()
+            });
+        }
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            self.collect_dialect_diagnostics(child, diagnostics);
+        }
+    }
+
+    /// The interface types of the document: the name and `declIntf` node of
+    /// each declaration, forward declarations included.
+    fn interface_types(&self) -> Vec<(Node<'_>, Node<'_>)> {
+        let mut interfaces = Vec::new();
+        let Some(tree) = &self.tree else {
+            return interfaces;
+        };
+        let mut stack = vec![tree.root_node()];
+        while let Some(node) = stack.pop() {
+            if node.kind() == "declType" {
+                let name = node.child_by_field_name("name");
+                let interface = node
+                    .child_by_field_name("type")
+                    .filter(|interface| interface.kind() == "declIntf");
+                if let (Some(name), Some(interface)) = (name, interface) {
+                    interfaces.push((name, interface));
+                    continue;
+                }
+            }
+            let mut cursor = node.walk();
+            stack.extend(node.children(&mut cursor));
+        }
+        interfaces.sort_by_key(|(name, _)| name.start_byte());
+        interfaces
+    }
+
+    /// The GUID string literal of an interface with its value. GUIDs given
+    /// by a constant, such as `[SID_IFoo]`, have no literal.
+    fn guid_literal<'a>(&self, interface: Node<'a>) -> Option<(Node<'a>, String)> {
+        let literal = interface
+            .child_by_field_name("guid")?
+            .named_child(0)
+            .filter(|literal| literal.kind() == "literalString")?;
+        let value = strings::decode_literal(&self.get_node_text(literal))?;
+        Some((literal, value))
+    }
+
+    /// The valid GUIDs of the interfaces of the document. Dispinterfaces are
+    /// left out: they share the GUID of their dual interface by design.
+    pub fn get_interface_guids(&self) -> Vec<InterfaceGuid> {
+        self.interface_types()
+            .into_iter()
+            .filter(|(_, interface)| !is_dispinterface(*interface))
+            .filter_map(|(name, interface)| {
+                let (_, value) = self.guid_literal(interface)?;
+                guid::is_valid(&value).then(|| InterfaceGuid {
+                    interface: self.get_name(name),
+                    value: value.to_uppercase(),
+                })
+            })
+            .collect()
+    }
+
+    /// Reports malformed interface GUIDs and GUIDs used by an earlier
+    /// interface of the document.
+    fn collect_guid_diagnostics(&self, diagnostics: &mut Vec<Diagnostic>) {
+        let mut seen: HashMap<String, String> = HashMap::new();
+        for (name, interface) in self.interface_types() {
+            let Some((literal, value)) = self.guid_literal(interface) else {
+                continue;
+            };
+            if !guid::is_valid(&value) {
+                diagnostics.push(Diagnostic {
+                    range: self.node_to_range(literal),
+                    severity: Some(DiagnosticSeverity::ERROR),
+                    code: Some(NumberOrString::String(INVALID_GUID.to_string())),
+                    source: Some("dls".to_string()),
+                    message: format!(
+                        "Malformed GUID '{}'; expected {{XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX}}",
+                        value
+                    ),
+                    ..Diagnostic::default()
+                });
+                continue;
+            }
+            if is_dispinterface(interface) {
+                continue;
+            }
+            let name = self.get_name(name);
+            match seen.get(&value.to_uppercase()) {
+                Some(first) => diagnostics.push(self.duplicate_guid_diagnostic(
+                    literal,
+                    &name,
+                    &format!("'{}'", first),
+                )),
+                None => {
+                    seen.insert(value.to_uppercase(), name);
+                }
+            }
+        }
+    }
+
+    /// Reports interface GUIDs also used by interfaces of other documents,
+    /// given as the file name of the document and the GUID.
+    pub fn get_shared_guid_diagnostics(
+        &self,
+        other_guids: &[(String, InterfaceGuid)],
+    ) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        for (name, interface) in self.interface_types() {
+            if is_dispinterface(interface) {
+                continue;
+            }
+            let Some((literal, value)) = self.guid_literal(interface) else {
+                continue;
+            };
+            let value = value.to_uppercase();
+            let Some((file, other)) = other_guids.iter().find(|(_, other)| other.value == value)
+            else {
+                continue;
+            };
+            diagnostics.push(self.duplicate_guid_diagnostic(
+                literal,
+                &self.get_name(name),
+                &format!("'{}' in {}", other.interface, file),
+            ));
+        }
+        diagnostics
+    }
+
+    fn duplicate_guid_diagnostic(&self, literal: Node, name: &str, other: &str) -> Diagnostic {
+        Diagnostic {
+            range: self.node_to_range(literal),
+            severity: Some(DiagnosticSeverity::WARNING),
+            code: Some(NumberOrString::String(DUPLICATE_GUID.to_string())),
+            source: Some("dls".to_string()),
+            message: format!("The GUID of '{}' is already used by {}", name, other),
+            ..Diagnostic::default()
+        }
+    }
+
+    /// The "Replace with new GUID" fix: replaces the GUID literal at
+    /// `position` with a freshly generated GUID.
+    pub fn replace_guid(&self, position: Position) -> Option<TextEdit> {
+        let literal = self
+            .interface_types()
+            .into_iter()
+            .filter_map(|(_, interface)| self.guid_literal(interface))
+            .map(|(literal, _)| literal)
+            .find(|literal| range_contains(self.node_to_range(*literal), position))?;
+        Some(TextEdit {
+            range: self.node_to_range(literal),
+            new_text: format!("'{}'", guid::generate()),
+        })
+    }
+
+    /// The "Generate new GUID" action for the interface declared at
+    /// `position` when it has no GUID: inserts a GUID line below the
+    /// `interface(...)` header. Forward declarations get none.
+    pub fn generate_guid(&self, position: Position) -> Option<TextEdit> {
+        let (name, interface) =
+            self.interface_types()
+                .into_iter()
+                .rev()
+                .find(|(name, interface)| {
+                    let start = self.node_to_range(*name).start;
+                    let end = self.node_to_range(*interface).end;
+                    range_contains(Range::new(start, end), position)
+                })?;
+        let mut cursor = interface.walk();
+        let children: Vec<Node> = interface.children(&mut cursor).collect();
+        if interface.child_by_field_name("guid").is_some()
+            || !children.iter().any(|child| child.kind() == "kEnd")
+        {
+            return None;
+        }
+        let header = children
+            .iter()
+            .take_while(|child| {
+                matches!(
+                    child.kind(),
+                    "kInterface" | "kDispInterface" | "(" | ")" | "typeref"
+                )
+            })
+            .last()?;
+
+        let line_start = self
+            .get_text(0..name.start_byte())
+            .rfind(['\n', '\r'])
+            .map_or(0, |i| i + 1);
+        let prefix = self.get_text(line_start..name.start_byte());
+        let indent = &prefix[..prefix.len() - prefix.trim_start().len()];
+        let ending = self
+            .line_index
+            .dominant_line_ending()
+            .unwrap_or(LineEnding::CrLf);
+        let position = self.node_to_range(*header).end;
+        Some(TextEdit {
+            range: Range::new(position, position),
+            new_text: format!("{}{}  ['{}']", ending.as_str(), indent, guid::generate()),
+        })
+    }
+
+    /// Reports case labels duplicating or overlapping an earlier label of the
+    /// same case statement. Labels without a constant value are skipped.
+    fn collect_case_diagnostics(
+        &self,
+        node: Node,
+        constants: &ConstEvaluator,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) {
+        if node.kind() == "defProc" {
+            let mut locals = constants.clone();
+            locals.collect_locals(node, &self.source);
+            let mut cursor = node.walk();
+            for child in node.children(&mut cursor) {
+                self.collect_case_diagnostics(child, &locals, diagnostics);
+            }
+            return;
+        }
+
+        if node.kind() == "case" {
+            let mut seen: Vec<(i64, i64, Node)> = Vec::new();
+            let mut branches = node.walk();
+            for branch in node.children(&mut branches) {
+                let Some(label) = branch.child_by_field_name("label") else {
+                    continue;
+                };
+                let mut items = label.walk();
+                for item in label.named_children(&mut items) {
+                    let bounds = if item.kind() == "range" {
+                        item.named_child(0)
+                            .zip(item.named_child(1))
+                            .and_then(|(low, high)| {
+                                Some((
+                                    constants.evaluate(low, &self.source)?,
+                                    constants.evaluate(high, &self.source)?,
+                                ))
+                            })
+                    } else {
+                        constants
+                            .evaluate(item, &self.source)
+                            .map(|value| (value, value))
+                    };
+                    let Some((low, high)) = bounds else {
+                        continue;
+                    };
+
+                    let earlier = seen.iter().find(|(l, h, _)| low.max(*l) <= high.min(*h));
+                    if let Some((l, h, earlier)) = earlier {
+                        let message = if low == high && l == h {
+                            format!("Duplicate case label '{}'", self.get_node_text(item))
+                        } else {
+                            format!(
+                                "Case label '{}' overlaps '{}'",
+                                self.get_node_text(item),
+                                self.get_node_text(*earlier)
+                            )
+                        };
+                        diagnostics.push(Diagnostic {
+                            range: self.node_to_range(item),
+                            severity: Some(DiagnosticSeverity::ERROR),
+                            code: Some(NumberOrString::String(DUPLICATE_CASE_LABEL.to_string())),
+                            source: Some("dls".to_string()),
+                            message,
+                            related_information: self.document_uri.clone().map(|uri| {
+                                vec![DiagnosticRelatedInformation {
+                                    location: Location {
+                                        uri,
+                                        range: self.node_to_range(*earlier),
+                                    },
+                                    message: "Earlier label".to_string(),
+                                }]
+                            }),
+                            ..Diagnostic::default()
+                        });
+                    }
+                    seen.push((low, high, item));
+                }
+            }
+        }
+
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            self.collect_case_diagnostics(child, constants, diagnostics);
+        }
+    }
+
+    /// Reports `message` methods that do not take a single `var` parameter,
+    /// which the compiler rejects.
+    fn collect_message_handler_diagnostics(&self, node: Node, diagnostics: &mut Vec<Diagnostic>) {
+        let is_handler = node.kind() == "declProc"
+            && self
+                .get_directives(node)
+                .iter()
+                .any(|directive| directive == "message");
+        if is_handler {
+            let mut params = Vec::new();
+            if let Some(args) = node.child_by_field_name("args") {
+                let mut cursor = args.walk();
+                for arg in args.children(&mut cursor) {
+                    if arg.kind() != "declArg" {
+                        continue;
+                    }
+                    let mut names = arg.walk();
+                    let by_reference = self.find_child(arg, "kVar").is_some();
+                    params.extend(
+                        arg.children_by_field_name("name", &mut names)
+                            .filter(|name| name.kind() == "identifier")
+                            .map(|name| (name, by_reference)),
+                    );
+                }
+            }
+            let problem = match params.as_slice() {
+                [(_, true)] => None,
+                [(param, false)] => Some((
+                    *param,
+                    format!(
+                        "The parameter '{}' of a message handler must be a var parameter",
+                        self.get_name(*param)
+                    ),
+                )),
+                _ => node.child_by_field_name("name").map(|name| {
+                    (
+                        name,
+                        format!(
+                            "Message handler '{}' must take exactly one var parameter",
+                            self.get_name(name)
+                        ),
+                    )
+                }),
+            };
+            if let Some((node, message)) = problem {
+                diagnostics.push(Diagnostic {
+                    range: self.node_to_range(node),
+                    severity: Some(DiagnosticSeverity::ERROR),
+                    code: Some(NumberOrString::String(INVALID_MESSAGE_HANDLER.to_string())),
+                    source: Some("dls".to_string()),
+                    message,
+                    ..Diagnostic::default()
+                });
+            }
+            return;
+        }
+
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            self.collect_message_handler_diagnostics(child, diagnostics);
+        }
+    }
+
+    /// Reports routine implementations naming another calling convention
+    /// than their declaration, and method implementations adding or
+    /// leaving out the `class` keyword of their declaration. An
+    /// implementation may leave the calling convention out.
+    fn collect_declaration_mismatches(&self, node: Node, diagnostics: &mut Vec<Diagnostic>) {
+        if node.kind() == "defProc" {
+            if let Some(header) = node.child_by_field_name("header") {
+                self.check_declaration_match(header, diagnostics);
+            }
+        }
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            self.collect_declaration_mismatches(child, diagnostics);
+        }
+    }
+
+    fn check_declaration_match(&self, header: Node, diagnostics: &mut Vec<Diagnostic>) {
+        let Some((directives, declaration)) = self.routine_declaration(header) else {
+            return;
+        };
+        let Some(name) = header.child_by_field_name("name") else {
+            return;
+        };
+        let mut problems = Vec::new();
+        if let Some(keyword) = self.calling_convention_node(header) {
+            let convention = self.get_node_text(keyword).to_lowercase();
+            let declared = calling_convention(&directives);
+            if !same_calling_convention(Some(&convention), declared.as_deref()) {
+                problems.push((
+                    keyword,
+                    format!(
+                        "Calling convention '{}' differs from the declaration of '{}', which is {}",
+                        convention,
+                        self.get_name(name),
+                        convention_description(declared.as_deref())
+                    ),
+                    "Declaration".to_string(),
+                ));
+            }
+        }
+        if self.routine_class(header).is_some() {
+            let declared_class = directives.iter().any(|directive| directive == "class");
+            if self.find_child(header, "kClass").is_some() != declared_class {
+                let message = if declared_class {
+                    format!("'{}' is declared as a class method", self.get_name(name))
+                } else {
+                    format!(
+                        "'{}' is not declared as a class method",
+                        self.get_name(name)
+                    )
+                };
+                problems.push((name, message, "Declaration".to_string()));
+            }
+        }
+        for (node, message, related) in problems {
+            diagnostics.push(Diagnostic {
+                range: self.node_to_range(node),
+                severity: Some(DiagnosticSeverity::ERROR),
+                code: Some(NumberOrString::String(DECLARATION_MISMATCH.to_string())),
+                source: Some("dls".to_string()),
+                message,
+                related_information: self.document_uri.clone().map(|uri| {
+                    vec![DiagnosticRelatedInformation {
+                        location: Location {
+                            uri,
+                            range: declaration,
+                        },
+                        message: related,
+                    }]
+                }),
+                ..Diagnostic::default()
+            });
+        }
+    }
+
+    /// Reports same-named routines of one scope, the unit, a type or the
+    /// local routines of a routine, where one lacks the `overload`
+    /// directive, and overloads whose parameter lists are identical or
+    /// accept the same arguments once default values are left out.
+    fn collect_overload_diagnostics(&self, root: Node, diagnostics: &mut Vec<Diagnostic>) {
+        let mut scopes = Vec::new();
+        self.collect_routine_scopes(root, &mut scopes);
+        let related = |node: Node, message: &str| {
+            self.document_uri.clone().map(|uri| {
+                vec![DiagnosticRelatedInformation {
+                    location: Location {
+                        uri,
+                        range: self.node_to_range(node),
+                    },
+                    message: message.to_string(),
+                }]
+            })
+        };
+        for routines in scopes {
+            for (i, routine) in routines.iter().enumerate() {
+                let name = self.get_name(routine.name);
+                let same_named = |other: &&RoutineSignature| {
+                    self.get_name(other.name).eq_ignore_ascii_case(&name)
+                };
+                let Some(other) = routines
+                    .iter()
+                    .enumerate()
+                    .filter(|(j, _)| *j != i)
+                    .map(|(_, other)| other)
+                    .find(same_named)
+                else {
+                    continue;
+                };
+                if !self
+                    .get_directives(routine.header)
+                    .iter()
+                    .any(|directive| directive == "overload")
+                {
+                    diagnostics.push(Diagnostic {
+                        range: self.node_to_range(routine.name),
+                        severity: Some(DiagnosticSeverity::ERROR),
+                        code: Some(NumberOrString::String(MISSING_OVERLOAD.to_string())),
+                        source: Some("dls".to_string()),
+                        message: format!(
+                            "'{}' is declared more than once but not marked with the 'overload' directive",
+                            name
+                        ),
+                        related_information: related(other.name, "Other declaration"),
+                        ..Diagnostic::default()
+                    });
+                }
+                // Each pair is reported once, on the later declaration
+                for earlier in routines[..i].iter().filter(same_named) {
+                    let Some(arguments) = ambiguous_arguments(earlier, routine) else {
+                        continue;
+                    };
+                    let message = if earlier.params == routine.params {
+                        format!("'{}' has the same parameter list as another overload", name)
+                    } else {
+                        format!(
+                            "Overloads of '{}' are ambiguous when called with {} argument{}, because of default parameters",
+                            name,
+                            arguments,
+                            if arguments == 1 { "" } else { "s" }
+                        )
+                    };
+                    diagnostics.push(Diagnostic {
+                        range: self.node_to_range(routine.name),
+                        severity: Some(DiagnosticSeverity::ERROR),
+                        code: Some(NumberOrString::String(AMBIGUOUS_OVERLOAD.to_string())),
+                        source: Some("dls".to_string()),
+                        message,
+                        related_information: related(earlier.name, "Conflicting overload"),
+                        ..Diagnostic::default()
+                    });
+                }
+            }
+        }
+    }
+
+    /// Collects the routines declared in each scope under `node`: the
+    /// unit or program, each type body and the local declarations of each
+    /// routine.
+    fn collect_routine_scopes<'a>(
+        &'a self,
+        node: Node<'a>,
+        scopes: &mut Vec<Vec<RoutineSignature<'a>>>,
+    ) {
+        let mut cursor = node.walk();
+        let children: Vec<Node> = node.children(&mut cursor).collect();
+        let sections = |kinds: &[&str]| -> Vec<Node<'a>> {
+            children
+                .iter()
+                .flat_map(|child| {
+                    if kinds.contains(&child.kind()) {
+                        let mut cursor = child.walk();
+                        child.children(&mut cursor).collect()
+                    } else {
+                        vec![*child]
+                    }
+                })
+                .collect()
+        };
+        match node.kind() {
+            "unit" | "program" | "library" => {
+                scopes.push(self.scope_routines(sections(&["interface", "implementation"])));
+            }
+            "declClass" | "declIntf" | "declHelper" => {
+                let members = sections(&["declSection"]);
+                let routines = self.scope_routines(members).into_iter().map(|mut routine| {
+                    routine.implementation = self.method_implementation(node, &routine);
+                    routine
+                });
+                scopes.push(routines.collect());
+            }
+            "defProc" => {
+                let mut cursor = node.walk();
+                let locals = node.children_by_field_name("local", &mut cursor).collect();
+                scopes.push(self.scope_routines(locals));
+            }
+            _ => {}
+        }
+        for child in children {
+            self.collect_routine_scopes(child, scopes);
+        }
+    }
+
+    /// The routines declared by `nodes`, in source order. A routine body
+    /// implementing an earlier declaration of the same scope is taken as
+    /// that declaration's implementation rather than as another routine.
+    fn scope_routines<'a>(&'a self, nodes: Vec<Node<'a>>) -> Vec<RoutineSignature<'a>> {
+        let mut routines: Vec<RoutineSignature> = Vec::new();
+        for node in nodes {
+            let header = match node.kind() {
+                "declProc" if node.child_by_field_name("assign").is_none() => node,
+                "defProc" => match node.child_by_field_name("header") {
+                    Some(header) => header,
+                    None => continue,
+                },
+                _ => continue,
+            };
+            let Some(name) = header
+                .child_by_field_name("name")
+                .filter(|name| name.kind() == "identifier")
+            else {
+                continue;
+            };
+            let routine = self.routine_signature(header, name);
+            if node.kind() == "defProc" {
+                let declarations: Vec<usize> = (0..routines.len())
+                    .filter(|i| {
+                        let declaration = &routines[*i];
+                        declaration
+                            .header
+                            .parent()
+                            .is_some_and(|p| p.kind() != "defProc")
+                            && declaration.implementation.is_none()
+                            && self
+                                .get_name(declaration.name)
+                                .eq_ignore_ascii_case(&self.get_name(name))
+                    })
+                    .collect();
+                // The body of a routine that is not overloaded may leave
+                // out the parameter list
+                let declaration = declarations
+                    .iter()
+                    .find(|i| routines[**i].params == routine.params)
+                    .or_else(|| {
+                        (header.child_by_field_name("args").is_none() && declarations.len() == 1)
+                            .then(|| &declarations[0])
+                    });
+                if let Some(i) = declaration {
+                    routines[*i].implementation = Some(header);
+                    continue;
+                }
+            }
+            routines.push(routine);
+        }
+        routines
+    }
+
+    /// The header of the body implementing a method declared in the type
+    /// body `type_node`.
+    fn method_implementation<'a>(
+        &'a self,
+        type_node: Node,
+        method: &RoutineSignature,
+    ) -> Option<Node<'a>> {
+        let type_name = type_node
+            .parent()
+            .filter(|parent| parent.kind() == "declType")?
+            .child_by_field_name("name")?;
+        self.method_implementations(&self.get_name(type_name), &self.get_name(method.name))
+            .into_iter()
+            .filter_map(|implementation| implementation.child_by_field_name("header"))
+            .find(|header| {
+                let rhs = header
+                    .child_by_field_name("name")
+                    .and_then(|name| name.child_by_field_name("rhs"));
+                rhs.is_some_and(|rhs| self.routine_signature(*header, rhs).params == method.params)
+            })
+    }
+
+    /// The parameters of a routine header, as compared between overloads.
+    /// `const` parameters are passed like value parameters; `var` and `out`
+    /// parameters by reference.
+    fn routine_signature<'a>(&self, header: Node<'a>, name: Node<'a>) -> RoutineSignature<'a> {
+        let mut params = Vec::new();
+        let mut required = None;
+        if let Some(args) = header.child_by_field_name("args") {
+            let mut cursor = args.walk();
+            for arg in args.children(&mut cursor) {
+                if arg.kind() != "declArg" {
+                    continue;
+                }
+                let type_name = arg
+                    .child_by_field_name("type")
+                    .map(|type_node| self.signature_type(type_node))
+                    .unwrap_or_default();
+                let by_reference = self.find_child(arg, "kVar").is_some()
+                    || self.find_child(arg, "kOut").is_some();
+                if arg.child_by_field_name("defaultValue").is_some() && required.is_none() {
+                    required = Some(params.len());
+                }
+                let mut names = arg.walk();
+                let count = arg
+                    .children_by_field_name("name", &mut names)
+                    .filter(|name| name.kind() == "identifier")
+                    .count();
+                params.extend(std::iter::repeat_n((type_name, by_reference), count));
+            }
+        }
+        RoutineSignature {
+            header,
+            name,
+            implementation: None,
+            required: required.unwrap_or(params.len()),
+            params,
+        }
+    }
+
+    /// The type of a parameter as compared between overloads: lowercase,
+    /// without whitespace, and with the type aliases declared in the
+    /// document resolved.
+    fn signature_type(&self, type_node: Node) -> String {
+        let normalize = |text: &str| {
+            text.chars()
+                .filter(|c| !c.is_whitespace())
+                .collect::<String>()
+                .to_lowercase()
+        };
+        let mut type_name = normalize(&self.get_node_text(type_node));
+        if let Some(tree) = &self.tree {
+            for _ in 0..MAX_ALIAS_DEPTH {
+                match self.find_type_alias(tree.root_node(), &type_name) {
+                    Some(alias) => type_name = normalize(&alias),
+                    None => break,
+                }
+            }
+        }
+        type_name
+    }
+
+    /// The quick fix of a `missing-overload` diagnostic at `position`: adds
+    /// the `overload` directive to the declaration and to the header of
+    /// its implementation.
+    pub fn overload_fix(&self, position: Position) -> Option<Vec<TextEdit>> {
+        let mut scopes = Vec::new();
+        self.collect_routine_scopes(self.tree.as_ref()?.root_node(), &mut scopes);
+        let routine = scopes.into_iter().flatten().find(|routine| {
+            let range = self.node_to_range(routine.name);
+            range.start <= position && position <= range.end
+        })?;
+        let edits: Vec<TextEdit> = std::iter::once(routine.header)
+            .chain(routine.implementation)
+            .filter(|header| {
+                !self
+                    .get_directives(*header)
+                    .iter()
+                    .any(|directive| directive == "overload")
+            })
+            .filter_map(|header| {
+                // After the semicolon ending the signature, before any
+                // other directive
+                let mut cursor = header.walk();
+                let semicolon = header
+                    .children(&mut cursor)
+                    .find(|child| child.kind() == ";")?;
+                let end = self.node_to_range(semicolon).end;
+                Some(TextEdit {
+                    range: Range { start: end, end },
+                    new_text: " overload;".to_string(),
+                })
+            })
+            .collect();
+        (!edits.is_empty()).then_some(edits)
+    }
+
+    /// The procedural types of the document by lowercase name, with the
+    /// calling convention they declare and the range of their name.
+    fn procedural_types(&self, node: Node, types: &mut HashMap<String, (Option<String>, Range)>) {
+        if node.kind() == "declType" {
+            let is_procedural = node
+                .child_by_field_name("type")
+                .and_then(|type_node| type_node.named_child(0))
+                .is_some_and(|type_node| type_node.kind() == "declProcRef");
+            if let (true, Some(name)) = (is_procedural, node.child_by_field_name("name")) {
+                let convention = self
+                    .calling_convention_node(node)
+                    .map(|keyword| self.get_node_text(keyword).to_lowercase());
+                types.insert(
+                    self.get_name(name).to_lowercase(),
+                    (convention, self.node_to_range(name)),
+                );
+            }
+            return;
+        }
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            self.procedural_types(child, types);
+        }
+    }
+
+    /// Reports routines assigned, with or without `@`, to a variable, field
+    /// or property of a procedural type declaring another calling
+    /// convention.
+    fn collect_calling_convention_diagnostics(
+        &self,
+        node: Node,
+        types: &HashMap<String, (Option<String>, Range)>,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) {
+        if node.kind() == "assignment" {
+            self.check_assigned_routine(node, types, diagnostics);
+        }
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            self.collect_calling_convention_diagnostics(child, types, diagnostics);
+        }
+    }
+
+    fn check_assigned_routine(
+        &self,
+        assignment: Node,
+        types: &HashMap<String, (Option<String>, Range)>,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) {
+        let (Some(lhs), Some(rhs)) = (
+            assignment.child_by_field_name("lhs"),
+            assignment.child_by_field_name("rhs"),
+        ) else {
+            return;
+        };
+        let Some(type_name) = self.expression_type(lhs) else {
+            return;
+        };
+        let Some((expected, type_range)) = types.get(&type_name.trim().to_lowercase()) else {
+            return;
+        };
+        let routine = match rhs.kind() {
+            "exprUnary" if self.find_child(rhs, "kAt").is_some() => {
+                rhs.child_by_field_name("operand")
+            }
+            _ => Some(rhs),
+        };
+        let Some((directives, declaration)) = routine.and_then(|routine| {
+            self.accept(self.routine_at(routine), || {
+                format!("the routine '{}'", self.get_node_text(routine))
+            })
+        }) else {
+            return;
+        };
+        let convention = calling_convention(&directives);
+        if same_calling_convention(convention.as_deref(), expected.as_deref()) {
+            return;
+        }
+        let name = self.get_node_text(routine.unwrap_or(rhs));
+        diagnostics.push(Diagnostic {
+            range: self.node_to_range(rhs),
+            severity: Some(DiagnosticSeverity::ERROR),
+            code: Some(NumberOrString::String(
+                INCOMPATIBLE_CALLING_CONVENTION.to_string(),
+            )),
+            source: Some("dls".to_string()),
+            message: format!(
+                "'{}' is {}, but {} is {}",
+                name,
+                convention_description(convention.as_deref()),
+                type_name.trim(),
+                convention_description(expected.as_deref())
+            ),
+            related_information: self.document_uri.clone().map(|uri| {
+                vec![
+                    DiagnosticRelatedInformation {
+                        location: Location {
+                            uri: uri.clone(),
+                            range: *type_range,
+                        },
+                        message: "Procedural type".to_string(),
+                    },
+                    DiagnosticRelatedInformation {
+                        location: Location {
+                            uri,
+                            range: declaration,
+                        },
+                        message: "Routine declaration".to_string(),
+                    },
+                ]
+            }),
+            ..Diagnostic::default()
+        });
+    }
+
+    /// The directive names and declaration range of the routine an
+    /// expression names: a method of the enclosing class, a method through
+    /// `Value.Method`, or a routine in scope. `None` for anything else, such
+    /// as a procedural variable. Of overloads, which the expression does not
+    /// tell apart, the first is a guess.
+    fn routine_at(&self, node: Node) -> Option<Resolution<(Vec<String>, Range)>> {
+        let method = |type_name: &str, name: &str| {
+            let (_, member) = self
+                .type_table
+                .find_member(type_name, name)
+                .filter(|(_, member)| member.kind == MemberKind::Method)?;
+            let directives = member.directives.iter().map(|d| d.name.clone()).collect();
+            let overloads = self
+                .type_table
+                .all_members(type_name)
+                .into_iter()
+                .filter(|(_, other)| {
+                    other.kind == MemberKind::Method && other.name.eq_ignore_ascii_case(name)
+                })
+                .count();
+            Some(if overloads > 1 {
+                Resolution::heuristic(
+                    (directives, member.range),
+                    format!("the first of {} overloads of {}", overloads, member.name),
+                )
+            } else {
+                Resolution::declared((directives, member.range))
+            })
+        };
+        match node.kind() {
+            "identifier" => {
+                let name = self.get_name(node);
+                if let Some(found) = self
+                    .enclosing_routine_header(node)
+                    .and_then(|header| self.routine_class(header))
+                    .and_then(|class_name| method(&class_name, &name))
+                {
+                    return Some(found);
+                }
+                let routines: Vec<&Symbol> = self
+                    .visible_declarations(&name.to_lowercase(), self.node_to_range(node).start)
+                    .into_iter()
+                    .filter(|symbol| symbol.kind == SymbolKind::FUNCTION)
+                    .collect();
+                // The interface-section declaration decides the calling
+                // convention, which the implementation may leave out
+                let routine = routines
+                    .iter()
+                    .find(|symbol| {
+                        self.section_at(symbol.selection_range.start) == Some(Section::Interface)
+                    })
+                    .or_else(|| {
+                        routines
+                            .iter()
+                            .find(|symbol| calling_convention(&symbol.directives).is_some())
+                    })
+                    .or(routines.first())?;
+                let found = (routine.directives.clone(), routine.selection_range);
+                let overloaded = routines
+                    .iter()
+                    .any(|symbol| symbol.directives.iter().any(|d| d == "overload"));
+                Some(if overloaded {
+                    Resolution::heuristic(
+                        found,
+                        format!("the first of the overloads of {}", routine.name),
+                    )
+                } else {
+                    Resolution::declared(found)
+                })
+            }
+            "exprDot" => {
+                let owner = self.expression_type(node.child_by_field_name("lhs")?)?;
+                let rhs = node.child_by_field_name("rhs")?;
+                method(&owner, &self.get_name(rhs))
+            }
+            _ => None,
+        }
+    }
+
+    /// Reports declarations whose name is a keyword or directive in the
+    /// configured `languageVersion` but was a plain identifier in Delphi 7.
+    fn collect_reserved_identifiers(&self, node: Node, diagnostics: &mut Vec<Diagnostic>) {
+        if matches!(
+            node.kind(),
+            "declType"
+                | "declProc"
+                | "declVar"
+                | "declConst"
+                | "declField"
+                | "declProp"
+                | "declArg"
+        ) {
+            let mut cursor = node.walk();
+            for name in node.children_by_field_name("name", &mut cursor) {
+                let text = self.get_node_text(name);
+                if name.kind() != "identifier"
+                    || !collides_with_keyword(&text, self.settings.language_version)
+                {
+                    continue;
+                }
+                diagnostics.push(Diagnostic {
+                    range: self.node_to_range(name),
+                    severity: Some(DiagnosticSeverity::HINT),
+                    code: Some(NumberOrString::String(RESERVED_IDENTIFIER.to_string())),
+                    source: Some("dls".to_string()),
+                    message: format!(
+                        "'{}' is a reserved word or directive in newer Delphi versions",
+                        text
+                    ),
+                    ..Diagnostic::default()
+                });
+            }
+        }
+
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            self.collect_reserved_identifiers(child, diagnostics);
+        }
+    }
+
+    /// Fixes for a `reserved-identifier` diagnostic at `position`: renaming
+    /// every occurrence of the identifier in the document with a trailing
+    /// underscore, or escaping it with `&`. Returns `(title, edits)` pairs.
+    pub fn reserved_identifier_fixes(&self, position: Position) -> Vec<(String, Vec<TextEdit>)> {
+        let (Some(tree), Some(node)) = (&self.tree, self.node_at(position).ok()) else {
+            return Vec::new();
+        };
+        if node.kind() != "identifier" {
+            return Vec::new();
+        }
+        let name = self.get_name(node);
+        let mut occurrences = Vec::new();
+        self.collect_identifiers(tree.root_node(), &name, &mut occurrences);
+
+        let edits = |f: &dyn Fn(&str) -> String| -> Vec<TextEdit> {
+            occurrences
+                .iter()
+                .map(|occurrence| TextEdit {
+                    range: self.node_to_range(*occurrence),
+                    new_text: f(&self.get_name(*occurrence)),
+                })
+                .collect()
+        };
+        vec![
+            (
+                format!("Rename '{}' to '{}_'", name, name),
+                edits(&|name| format!("{}_", name)),
+            ),
+            (
+                format!("Escape '{}' as '&{}'", name, name),
+                edits(&|name| format!("&{}", name)),
+            ),
+        ]
+    }
+
+    fn is_intrinsic(&self, name: &str) -> bool {
+        match &self.settings.diagnostics.intrinsics {
+            Some(intrinsics) => intrinsics
+                .iter()
+                .any(|intrinsic| intrinsic.eq_ignore_ascii_case(name)),
+            None => INTRINSICS
+                .iter()
+                .any(|intrinsic| intrinsic.eq_ignore_ascii_case(name)),
+        }
+    }
+
+    /// The unqualified uses that resolve to the declaration at `position`
+    /// instead of the intrinsic routine it is named after, or `None` when
+    /// there is no such declaration.
+    fn shadowed_intrinsic_uses(&self, position: Position) -> Option<Vec<Range>> {
+        let identifier = self.node_at(position).ok()?;
+        let name = self.get_name(identifier);
+        if identifier.kind() != "identifier" || !self.is_intrinsic(&name) {
+            return None;
+        }
+        let occurrences = self.reference_occurrences(position)?;
+        let range = self.node_to_range(identifier);
+        if !occurrences
+            .iter()
+            .any(|occurrence| occurrence.declaration && occurrence.range == range)
+        {
+            return None;
+        }
+        // A member only hides the intrinsic in the methods of its type
+        let owner = std::iter::successors(identifier.parent(), |node| node.parent())
+            .take_while(|node| node.kind() != "defProc")
+            .find(|node| node.kind() == "declType")
+            .and_then(|declaration| declaration.child_by_field_name("name"))
+            .map(|name| self.get_name(name));
+        let in_owner_method = |range: &Range| {
+            let Some(owner) = &owner else {
+                return true;
+            };
+            self.node_at(range.start)
+                .ok()
+                .and_then(|node| self.enclosing_routine_header(node))
+                .and_then(|header| self.routine_class(header))
+                .is_some_and(|class| self.type_table.is_same_or_descendant(&class, owner))
+        };
+        Some(
+            occurrences
+                .iter()
+                .filter(|occurrence| !occurrence.declaration && !occurrence.qualified)
+                .map(|occurrence| occurrence.range)
+                .filter(in_owner_method)
+                .collect(),
+        )
+    }
+
+    /// Reports the first read of each local variable that some path
+    /// reaches before any assignment, pointing at its declaration.
+    fn collect_uninitialized_reads(&self, root: Node, diagnostics: &mut Vec<Diagnostic>) {
+        let reads = flow::uninitialized_reads(
+            root,
+            &self.source,
+            self.settings.diagnostics.uninitialized_managed_types,
+        );
+        for read in reads {
+            let name = self.get_name(read.declaration);
+            let uri = self.document_uri.clone();
+            diagnostics.push(Diagnostic {
+                range: self.node_to_range(read.read),
+                severity: Some(DiagnosticSeverity::WARNING),
+                code: Some(NumberOrString::String(UNINITIALIZED_VARIABLE.to_string())),
+                source: Some("dls".to_string()),
+                message: format!("Variable '{}' might not have been initialized", name),
+                related_information: uri.map(|uri| {
+                    vec![DiagnosticRelatedInformation {
+                        location: Location {
+                            uri,
+                            range: self.node_to_range(read.declaration),
+                        },
+                        message: format!("'{}' is declared here", name),
+                    }]
+                }),
+                ..Diagnostic::default()
+            });
+        }
+    }
+
+    /// Reports declarations named after an intrinsic routine, such as a
+    /// local `Length`, whose name the unit uses, pointing at the uses that
+    /// now resolve to the declaration.
+    fn collect_shadowed_intrinsics(&self, diagnostics: &mut Vec<Diagnostic>) {
+        let mut declarations: Vec<Range> = self
+            .occurrences
+            .iter()
+            .filter(|(name, _)| self.is_intrinsic(name))
+            .flat_map(|(_, occurrences)| occurrences)
+            .filter(|occurrence| occurrence.declaration && !occurrence.qualified)
+            .map(|occurrence| occurrence.range)
+            .collect();
+        declarations.sort_by_key(|range| range.start);
+        for range in declarations {
+            let Some(uses) = self
+                .shadowed_intrinsic_uses(range.start)
+                .filter(|uses| !uses.is_empty())
+            else {
+                continue;
+            };
+            let name = self.get_text(self.line_index.range_to_byte_range(range));
+            let uri = self.document_uri.clone();
+            diagnostics.push(Diagnostic {
+                range,
+                severity: Some(DiagnosticSeverity::WARNING),
+                code: Some(NumberOrString::String(SHADOWED_INTRINSIC.to_string())),
+                source: Some("dls".to_string()),
+                message: format!(
+                    "'{}' shadows the intrinsic System.{} used in this unit",
+                    name, name
+                ),
+                related_information: uri.map(|uri| {
+                    uses.iter()
+                        .map(|range| DiagnosticRelatedInformation {
+                            location: Location {
+                                uri: uri.clone(),
+                                range: *range,
+                            },
+                            message: format!("Resolves to this '{}', not System.{}", name, name),
+                        })
+                        .collect()
+                }),
+                ..Diagnostic::default()
+            });
+        }
+    }
+
+    /// Fixes for a `shadowed-intrinsic` diagnostic at `position`: renaming
+    /// the declaration, or qualifying the uses resolving to it with
+    /// `System.` so that they call the intrinsic again. Returns `(title,
+    /// edits)` pairs.
+    pub fn shadowed_intrinsic_fixes(&self, position: Position) -> Vec<(String, Vec<TextEdit>)> {
+        let Some(uses) = self.shadowed_intrinsic_uses(position) else {
+            return Vec::new();
+        };
+        let Ok(identifier) = self.node_at(position) else {
+            return Vec::new();
+        };
+        let name = self.get_name(identifier);
+        let prefix = match identifier.parent().map(|parent| parent.kind()) {
+            Some("declField") => "F",
+            Some("declArg") => "A",
+            Some("declVar") => match self.enclosing_routine_header(identifier) {
+                Some(_) => "L",
+                None => "G",
+            },
+            _ => "My",
         };
+        let new_name = format!("{}{}", prefix, name);
+        let mut fixes = Vec::new();
+        if let Some(mut edits) = self.rename(position, &new_name) {
+            // Besides the declaration and its uses, only accesses to a
+            // member like `Item.Length`: unqualified names elsewhere and
+            // `System.Length` name the intrinsic
+            let declaration = self.node_to_range(identifier);
+            let is_member = std::iter::successors(identifier.parent(), |node| node.parent())
+                .take_while(|node| node.kind() != "defProc")
+                .any(|node| node.kind() == "declClass");
+            edits.retain(|edit| {
+                edit.range == declaration
+                    || uses.contains(&edit.range)
+                    || is_member
+                        && self
+                            .qualifier(edit.range.start)
+                            .is_some_and(|qualifier| !qualifier.eq_ignore_ascii_case("System"))
+            });
+            fixes.push((format!("Rename '{}' to '{}'", name, new_name), edits));
+        }
+        let count = uses.len();
+        fixes.push((
+            format!(
+                "Qualify {} with 'System.'",
+                if count == 1 {
+                    "the use".to_string()
+                } else {
+                    format!("the {} uses", count)
+                }
+            ),
+            uses.into_iter()
+                .map(|range| TextEdit {
+                    range: Range::new(range.start, range.start),
+                    new_text: "System.".to_string(),
+                })
+                .collect(),
+        ));
+        fixes
+    }
 
-        let node = tree.root_node().descendant_for_point_range(point, point)?;
-        let hover_node = self.find_hover_node(node);
+    /// The name before the dot when the identifier at `position` is
+    /// qualified, as `System` in `System.Copy`.
+    fn qualifier(&self, position: Position) -> Option<String> {
+        let identifier = self.node_at(position).ok()?;
+        let dot = identifier.parent().filter(|dot| dot.kind() == "exprDot")?;
+        if dot.child_by_field_name("rhs")? != identifier {
+            return None;
+        }
+        Some(self.get_name(dot.child_by_field_name("lhs")?))
+    }
+
+    /// Checks declared names against the naming rule of their category.
+    fn collect_naming_diagnostics(&self, node: Node, diagnostics: &mut Vec<Diagnostic>) {
+        if let Some((category, rule)) = self.naming_rule(node) {
+            let mut cursor = node.walk();
+            let names = node
+                .children_by_field_name("name", &mut cursor)
+                .filter(|name| name.kind() == "identifier");
+            for name_node in names {
+                let name = self.get_name(name_node);
+                if rule.accepts(&name) {
+                    continue;
+                }
+                let message = match self.naming_suggestion(rule, &name) {
+                    Some(suggestion) => format!(
+                        "{} '{}' does not follow the naming convention; expected '{}'",
+                        category.name(),
+                        name,
+                        suggestion
+                    ),
+                    None => format!(
+                        "{} '{}' does not follow the naming convention",
+                        category.name(),
+                        name
+                    ),
+                };
+                diagnostics.push(Diagnostic {
+                    range: self.node_to_range(name_node),
+                    severity: Some(rule.severity.to_diagnostic_severity()),
+                    code: Some(NumberOrString::String(NAMING_CONVENTION.to_string())),
+                    source: Some("dls".to_string()),
+                    message,
+                    ..Diagnostic::default()
+                });
+            }
+        }
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            self.collect_naming_diagnostics(child, diagnostics);
+        }
+    }
 
-        if hover_node.kind() == "identifier" {
-            let name = self.get_node_text(hover_node);
-            if let Some(symbols) = self.symbol_map.get(&name) {
-                // Get document URI once before the map
-                let uri = self.document_uri.clone()?;
-                let locations: Vec<Location> = symbols
+    /// The category of the names `declaration` declares and the rule
+    /// configured for it. Overrides, message handlers and methods that may
+    /// implement an interface method have no rule, as their name is chosen
+    /// where they are first declared.
+    fn naming_rule(&self, declaration: Node) -> Option<(NameCategory, &NamingRule)> {
+        let container = std::iter::successors(declaration.parent(), |node| node.parent())
+            .find(|node| matches!(node.kind(), "declClass" | "declHelper" | "defProc"));
+        let category = match declaration.kind() {
+            "declType" => match declaration.child_by_field_name("type")?.kind() {
+                "declIntf" => NameCategory::Interface,
+                _ => NameCategory::Type,
+            },
+            "declField" => NameCategory::Field,
+            "declVar" => match container.map(|container| container.kind()) {
+                Some("defProc") => NameCategory::Local,
+                Some(_) => NameCategory::Field,
+                None => NameCategory::Variable,
+            },
+            "declConst" => NameCategory::Constant,
+            "declArg" => NameCategory::Parameter,
+            // `property Caption;` only changes the visibility of an
+            // inherited property
+            "declProp" if declaration.child_by_field_name("type").is_some() => {
+                NameCategory::Property
+            }
+            "declProc" if declaration.child_by_field_name("assign").is_none() => {
+                let inherited = self
+                    .get_directives(declaration)
                     .iter()
-                    .map(|symbol| Location {
-                        uri: uri.clone(),
-                        range: symbol.range,
-                    })
-                    .collect();
-                return Some(locations);
+                    .any(|directive| directive == "override" || directive == "message");
+                let implements_interface =
+                    || {
+                        let name = declaration.child_by_field_name("name")?;
+                        let type_name =
+                            std::iter::successors(declaration.parent(), |node| node.parent())
+                                .find(|node| node.kind() == "declType")?
+                                .child_by_field_name("name")?;
+                        Some(self.type_table.may_implement_interface(
+                            &self.get_name(type_name),
+                            &self.get_name(name),
+                        ))
+                    };
+                if inherited || implements_interface().unwrap_or(false) {
+                    return None;
+                }
+                NameCategory::Routine
             }
+            _ => return None,
+        };
+        Some((category, self.settings.naming.rule(category)?))
+    }
+
+    /// The name `rule` suggests for `name`, unless it is a reserved word.
+    fn naming_suggestion(&self, rule: &NamingRule, name: &str) -> Option<String> {
+        rule.suggest(name)
+            .filter(|suggestion| !is_reserved_word(suggestion, self.dialect))
+    }
+
+    /// The quick fix of a `naming-convention` diagnostic at `position`:
+    /// renames the declaration and its references to the suggested name.
+    pub fn naming_fix(&self, position: Position) -> Option<(String, Vec<TextEdit>)> {
+        let identifier = self.node_at(position).ok()?;
+        let (_, rule) = self.naming_rule(identifier.parent()?)?;
+        let name = self.get_name(identifier);
+        let suggestion = self.naming_suggestion(rule, &name)?;
+        let edits = self.rename(position, &suggestion)?;
+        Some((format!("Rename '{}' to '{}'", name, suggestion), edits))
+    }
+
+    fn collect_identifiers<'a>(&self, node: Node<'a>, name: &str, found: &mut Vec<Node<'a>>) {
+        if node.kind() == "identifier" && self.get_name(node).eq_ignore_ascii_case(name) {
+            found.push(node);
+        }
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            self.collect_identifiers(child, name, found);
         }
-        None
     }
 
-    fn find_hover_node<'a>(&self, mut node: Node<'a>) -> Node<'a> {
-        while node.kind() == "ERROR" || node.is_extra() {
-            if let Some(parent) = node.parent() {
-                node = parent;
-            } else {
-                break;
+    fn collect_visibility_diagnostics(&self, node: Node, diagnostics: &mut Vec<Diagnostic>) {
+        if node.kind() == "exprDot" {
+            let lhs = node.child_by_field_name("lhs");
+            let rhs = node.child_by_field_name("rhs");
+            if let (Some(lhs), Some(rhs)) = (lhs, rhs) {
+                if lhs.kind() == "identifier" && rhs.kind() == "identifier" {
+                    let member_name = self.get_name(rhs);
+                    let type_name = self.resolve_identifier_type(&self.get_name(lhs), node);
+                    let found = type_name
+                        .as_deref()
+                        .and_then(|type_name| self.type_table.find_member(type_name, &member_name));
+                    if let Some((declaring, member)) = found {
+                        let context = self.access_context(node);
+                        if !self.type_table.is_accessible(declaring, member, &context) {
+                            diagnostics.push(Diagnostic {
+                                range: self.node_to_range(rhs),
+                                severity: Some(DiagnosticSeverity::WARNING),
+                                code: Some(NumberOrString::String(INACCESSIBLE_MEMBER.to_string())),
+                                source: Some("dls".to_string()),
+                                message: format!(
+                                    "'{}' is {} in {} and cannot be accessed here",
+                                    member.name,
+                                    member.visibility.as_str(),
+                                    declaring.name
+                                ),
+                                ..Diagnostic::default()
+                            });
+                        }
+                    }
+                }
             }
         }
-        node
+
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            self.collect_visibility_diagnostics(child, diagnostics);
+        }
     }
 
-    fn create_hover(&self, content: String, kind: Option<String>, range: Range) -> Hover {
-        let mut value = String::new();
-        if let Some(k) = kind {
-            value.push_str(&format!("```pascal\n{}\n```\n", k));
+    /// Resolves `Result` inside a function and `Self` inside a method, which
+    /// are declared implicitly and have no declaration node of their own.
+    /// Qualified uses such as `Obj.Result` are ordinary members.
+    fn implicit_identifier<'a>(&self, identifier: Node<'a>) -> Option<ImplicitIdentifier<'a>> {
+        if identifier.kind() != "identifier" {
+            return None;
+        }
+        let parent = identifier.parent()?;
+        if parent.kind() == "exprDot" && parent.child_by_field_name("rhs") == Some(identifier) {
+            return None;
+        }
+        let header = self.enclosing_routine_header(identifier)?;
+        if header
+            .child_by_field_name("name")?
+            .byte_range()
+            .contains(&identifier.start_byte())
+        {
+            return None;
         }
-        value.push_str(&content);
 
-        Hover {
+        let name = self.get_name(identifier);
+        if name.eq_ignore_ascii_case("result") {
+            let return_type = header.child_by_field_name("type")?;
+            Some(ImplicitIdentifier {
+                name: "Result".to_string(),
+                type_name: self.get_node_text(return_type),
+                declaration: self.node_to_range(return_type),
+                header,
+            })
+        } else if name.eq_ignore_ascii_case("self") {
+            let class_name = self.routine_class(header)?;
+            let declaration = match self.type_table.get(&class_name) {
+                Some(decl) => decl.range,
+                None => self.node_to_range(header.child_by_field_name("name")?),
+            };
+            Some(ImplicitIdentifier {
+                name: "Self".to_string(),
+                type_name: class_name,
+                declaration,
+                header,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// The hover of the variable of a `for..in` loop, on the variable or
+    /// inside the loop body: its type and what the loop iterates over.
+    fn get_loop_variable_hover(&self, identifier: Node) -> Option<Hover> {
+        if identifier.kind() != "identifier" {
+            return None;
+        }
+        let parent = identifier.parent()?;
+        if parent.kind() == "exprDot" && parent.child_by_field_name("rhs") == Some(identifier) {
+            return None;
+        }
+        let name = self.get_name(identifier);
+        let foreach = self.enclosing_loop(&name, identifier)?;
+        let resolution = self.resolve_type(&name, identifier)?;
+        let type_name = match resolution.provenance {
+            Provenance::Heuristic(reason) if self.settings.analysis.strict => {
+                log::trace!(
+                    "Strict analysis left the type of '{}' unresolved; the heuristic gave {:?} ({})",
+                    name,
+                    resolution.value,
+                    reason
+                );
+                format!("type not resolved ({})", reason)
+            }
+            _ => resolution.value,
+        };
+        let iterable = foreach.child_by_field_name("iterable")?;
+        Some(Hover {
             contents: HoverContents::Markup(MarkupContent {
                 kind: MarkupKind::Markdown,
-                value,
+                value: format!(
+                    "```pascal\n{}: {}\n```\nElement of `{}`",
+                    self.get_node_text(foreach.child_by_field_name("iterator")?),
+                    type_name,
+                    self.get_node_text(iterable)
+                ),
             }),
-            range: Some(range),
+            range: Some(self.node_to_range(identifier)),
+        })
+    }
+
+    /// Returns the header (`declProc`) of the routine implementation
+    /// containing `node`. When error recovery broke the `defProc` apart, the
+    /// nearest preceding method header among the ancestors' siblings is used.
+    /// Statements of an implicit routine (see `is_implicit_routine`) have no
+    /// header: only globals are in scope there.
+    fn enclosing_routine_header<'a>(&self, node: Node<'a>) -> Option<Node<'a>> {
+        let mut current = Some(node);
+        while let Some(n) = current {
+            if n.kind() == "defProc" {
+                return n.child_by_field_name("header");
+            }
+            if is_implicit_routine(n) {
+                return None;
+            }
+            current = n.parent();
+        }
+
+        let mut current = Some(node);
+        while let Some(n) = current {
+            let mut sibling = n.prev_named_sibling();
+            while let Some(s) = sibling {
+                match s.kind() {
+                    // External and forward declarations have no body that
+                    // the statements could belong to
+                    "declProc"
+                        if self.find_child(s, "procExternal").is_some()
+                            || self.find_child(s, "kForward").is_some() =>
+                    {
+                        return None
+                    }
+                    "declProc" => return Some(s),
+                    "defProc" | "declTypes" => break,
+                    _ if is_implicit_routine(s) => return None,
+                    _ => sibling = s.prev_named_sibling(),
+                }
+            }
+            current = n.parent();
         }
+        None
     }
 
-    pub fn get_completion_items(
-        &self,
-        position: Position,
-        trigger_char: Option<String>,
-    ) -> Option<Vec<CompletionItem>> {
-        let tree = self.tree.as_ref()?;
-        let point = tree_sitter::Point {
-            row: position.line as usize,
-            column: position.character as usize,
-        };
+    /// The declaration of the method a routine header implements, `Bar` of
+    /// `TFoo` for `TFoo.Bar`.
+    fn implemented_method(&self, header: Node) -> Option<&Member> {
+        let type_name = self.routine_class(header)?;
+        let name = header
+            .child_by_field_name("name")?
+            .child_by_field_name("rhs")?;
+        self.type_table
+            .find_member(&type_name, &self.get_name(name))
+            .map(|(_, member)| member)
+            .filter(|member| member.kind == MemberKind::Method)
+    }
 
-        let node = tree.root_node().descendant_for_point_range(point, point)?;
-        let mut items = Vec::new();
+    /// The declaration of the routine a header with a body implements: the
+    /// method of its class, or the interface-section declaration of a
+    /// unit routine. Returns its directive names and the range of its name.
+    fn routine_declaration(&self, header: Node) -> Option<(Vec<String>, Range)> {
+        if self.routine_class(header).is_some() {
+            let method = self.implemented_method(header)?;
+            let directives = method.directives.iter().map(|d| d.name.clone()).collect();
+            return Some((directives, method.range));
+        }
+        let name = header.child_by_field_name("name")?;
+        let range = self.node_to_range(name);
+        let symbols = self.symbol_map.get(&self.get_name(name).to_lowercase())?;
+        let implementation = symbols
+            .iter()
+            .find(|symbol| symbol.selection_range == range)?;
+        symbols
+            .iter()
+            .find(|symbol| {
+                symbol.selection_range != range
+                    && symbol.kind == SymbolKind::FUNCTION
+                    && symbol.id.matches(&implementation.id)
+                    && self.section_at(symbol.selection_range.start) == Some(Section::Interface)
+            })
+            .map(|symbol| (symbol.directives.clone(), symbol.selection_range))
+    }
+
+    /// The calling convention keyword of a routine header or procedural
+    /// type declaration.
+    fn calling_convention_node<'a>(&self, node: Node<'a>) -> Option<Node<'a>> {
+        let mut cursor = node.walk();
+        let keyword = node
+            .children(&mut cursor)
+            .filter(|child| child.kind() == "procAttribute")
+            .filter_map(|attribute| attribute.child(0))
+            .find(|keyword| CALLING_CONVENTIONS.contains(&keyword.kind()));
+        keyword
+    }
+
+    /// The calling convention a routine header declares, or its declaration
+    /// declares when the implementation leaves it out.
+    fn effective_calling_convention(&self, header: Node) -> Option<String> {
+        match self.calling_convention_node(header) {
+            Some(keyword) => Some(self.get_node_text(keyword).to_lowercase()),
+            None => self
+                .routine_declaration(header)
+                .and_then(|(directives, _)| calling_convention(&directives)),
+        }
+    }
+
+    /// The class a routine header belongs to, e.g. `TFoo` for `TFoo.Bar`.
+    fn routine_class(&self, header: Node) -> Option<String> {
+        let name = header.child_by_field_name("name")?;
+        if name.kind() != "genericDot" {
+            return None;
+        }
+        Some(self.get_name(name.child_by_field_name("lhs")?))
+    }
+
+    fn access_context(&self, node: Node) -> AccessContext {
+        let mut class_name = self
+            .enclosing_routine_header(node)
+            .and_then(|header| self.routine_class(header));
 
-        if let Some(trigger) = trigger_char {
-            if trigger == "." {
-                // Handle member completion after dot
-                if let Some(scope) = self.find_completion_scope(node) {
-                    items.extend(self.get_scope_members(scope));
+        if class_name.is_none() {
+            let mut current = node.parent();
+            while let Some(n) = current {
+                if n.kind() == "declType" {
+                    class_name = n
+                        .child_by_field_name("name")
+                        .map(|name| self.get_name(name));
+                    break;
                 }
+                current = n.parent();
             }
-        } else {
-            // Handle general identifier completion
-            items.extend(self.get_visible_symbols(node));
         }
 
-        Some(items)
+        AccessContext {
+            class_name,
+            // Only the current document is analyzed, so every member comes
+            // from the unit containing the access.
+            same_unit: true,
+        }
+    }
+
+    /// Resolves the declared type of an identifier used at `node`. With
+    /// `analysis.strict`, types resolved by a heuristic are unknown.
+    fn resolve_identifier_type(&self, name: &str, node: Node) -> Option<String> {
+        self.accept(self.resolve_type(name, node), || {
+            format!("the type of '{}'", name)
+        })
+    }
+
+    /// Resolves the type of an identifier used at `node`: `Self`, routine
+    /// parameters and locals, the variables of enclosing `for..in` loops,
+    /// fields of the routine's class, global variables, or the name of a
+    /// type itself for class-level access.
+    fn resolve_type(&self, name: &str, node: Node) -> Option<Resolution<String>> {
+        let header = self.enclosing_routine_header(node);
+        let class_name = header.and_then(|header| self.routine_class(header));
+
+        if name.eq_ignore_ascii_case("self") {
+            return class_name.map(Resolution::declared);
+        }
+
+        if let Some(header) = header {
+            if let Some(type_name) = self.find_declared_type(header, name) {
+                return Some(Resolution::declared(type_name));
+            }
+            // Locals of a defProc, or the declarations that follow a header
+            // when error recovery separated them
+            let locals: Vec<Node> = match header.parent() {
+                Some(def) if def.kind() == "defProc" => {
+                    let mut cursor = def.walk();
+                    let locals = def.children_by_field_name("local", &mut cursor).collect();
+                    locals
+                }
+                _ => {
+                    let mut locals = Vec::new();
+                    let mut sibling = header.next_named_sibling();
+                    while let Some(s) = sibling.filter(|s| s.kind() == "declVars") {
+                        locals.push(s);
+                        sibling = s.next_named_sibling();
+                    }
+                    locals
+                }
+            };
+            for local in locals {
+                if let Some(type_name) = self.find_declared_type(local, name) {
+                    return Some(Resolution::declared(type_name));
+                }
+            }
+        }
+
+        if let Some(resolution) = self.loop_element_type(name, node) {
+            return Some(resolution);
+        }
+
+        if let Some(class_name) = &class_name {
+            if let Some((_, member)) = self.type_table.find_member(class_name, name) {
+                return member.type_name.clone().map(Resolution::declared);
+            }
+        }
+
+        let tree = self.tree.as_ref()?;
+        if let Some(type_name) = self.find_global_variable_type(tree.root_node(), name) {
+            return Some(Resolution::declared(type_name));
+        }
+
+        self.type_table
+            .get(name)
+            .map(|decl| Resolution::declared(decl.name.clone()))
+    }
+
+    /// The value of `resolution`, or `None` with `analysis.strict` when it
+    /// relied on a heuristic, logging at trace level what `what` would have
+    /// resolved to.
+    pub fn accept<T: fmt::Debug>(
+        &self,
+        resolution: Option<Resolution<T>>,
+        what: impl FnOnce() -> String,
+    ) -> Option<T> {
+        let resolution = resolution?;
+        match &resolution.provenance {
+            Provenance::Heuristic(reason) if self.settings.analysis.strict => {
+                log::trace!(
+                    "Strict analysis left {} unresolved; the heuristic gave {:?} ({})",
+                    what(),
+                    resolution.value,
+                    reason
+                );
+                None
+            }
+            _ => Some(resolution.value),
+        }
     }
 
-    fn find_completion_scope(&self, node: Node) -> Option<String> {
-        // For now, just return the type name if we can find it
-        // TODO: Implement proper scope resolution
-        let mut current = node;
-        while let Some(parent) = current.parent() {
-            if parent.kind() == "type_declaration" {
-                if let Some(name_node) = self.find_identifier(parent) {
-                    return Some(self.get_node_text(name_node));
+    /// Searches the parameters or var/const declarations directly under
+    /// `node` for `name` and returns its declared type.
+    fn find_declared_type(&self, node: Node, name: &str) -> Option<String> {
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            match child.kind() {
+                "declArgs" | "declVars" | "declConsts" => {
+                    if let Some(type_name) = self.find_declared_type(child, name) {
+                        return Some(type_name);
+                    }
+                }
+                "declArg" | "declVar" | "declConst" => {
+                    let mut names = child.walk();
+                    let matches = child
+                        .children_by_field_name("name", &mut names)
+                        .any(|n| self.get_name(n).eq_ignore_ascii_case(name));
+                    if matches {
+                        return child
+                            .child_by_field_name("type")
+                            .map(|t| self.get_node_text(t));
+                    }
                 }
+                _ => {}
             }
-            current = parent;
         }
         None
     }
 
-    fn get_scope_members(&self, scope: String) -> Vec<CompletionItem> {
-        let mut items = Vec::new();
-        // TODO: Implement proper member lookup based on type/scope
-        if let Some(symbols) = self.symbol_map.get(&scope) {
-            for symbol in symbols {
-                items.push(CompletionItem {
-                    label: symbol.name.clone(),
-                    kind: Some(self.symbol_kind_to_completion_kind(symbol.kind)),
-                    detail: symbol.detail.clone(),
-                    documentation: None,
-                    ..CompletionItem::default()
-                });
+    /// The `for..in` loop enclosing `node` whose variable is `name`, when
+    /// `node` is that variable or inside the loop body.
+    fn enclosing_loop<'a>(&self, name: &str, node: Node<'a>) -> Option<Node<'a>> {
+        std::iter::successors(Some(node), |node| node.parent())
+            .take_while(|ancestor| ancestor.kind() != "defProc")
+            .filter(|ancestor| ancestor.kind() == "foreach")
+            .find(|foreach| {
+                let Some(iterator) = foreach.child_by_field_name("iterator") else {
+                    return false;
+                };
+                let inside = iterator == node
+                    || foreach
+                        .child_by_field_name("body")
+                        .is_some_and(|body| body.byte_range().contains(&node.start_byte()));
+                inside && self.get_name(iterator).eq_ignore_ascii_case(name)
+            })
+    }
+
+    /// The type of the variable `name` of a `for..in` loop enclosing
+    /// `node`, inferred from what the loop iterates over.
+    fn loop_element_type(&self, name: &str, node: Node) -> Option<Resolution<String>> {
+        let iterable = self
+            .enclosing_loop(name, node)?
+            .child_by_field_name("iterable")?;
+        self.element_type(&self.expression_type(iterable)?, 0)
+    }
+
+    /// The type of the elements a `for..in` loop over a value of type
+    /// `container` yields: the element type of arrays and sets, `Char` for
+    /// strings, the type argument of the RTL generic collections, and the
+    /// type of the `Current` property of the enumerator `GetEnumerator`
+    /// returns. Aliases declared in the document are followed. The RTL
+    /// collections are recognized by name alone, a heuristic.
+    fn element_type(&self, container: &str, depth: usize) -> Option<Resolution<String>> {
+        let container = container.trim();
+        let lower = container.to_lowercase();
+        let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+        let first_word = lower
+            .split(|c: char| !is_ident(c))
+            .next()
+            .unwrap_or_default();
+        if matches!(first_word, "array" | "set" | "packed") {
+            let of = lower.match_indices("of").find(|(offset, _)| {
+                let before = lower[..*offset].chars().next_back();
+                let after = lower[offset + 2..].chars().next();
+                !before.is_some_and(is_ident) && !after.is_some_and(is_ident)
+            })?;
+            return Some(Resolution::declared(
+                container[of.0 + 2..].trim().to_string(),
+            ));
+        }
+        match lower.as_str() {
+            "string" | "unicodestring" | "widestring" => {
+                return Some(Resolution::declared("Char".to_string()))
             }
+            "ansistring" | "rawbytestring" | "utf8string" | "shortstring" => {
+                return Some(Resolution::declared("AnsiChar".to_string()))
+            }
+            _ => {}
         }
-        items
+
+        if let Some((decl, bindings)) = self.type_table.get_instance(container) {
+            let (_, get_enumerator) = self.type_table.find_member(&decl.name, "GetEnumerator")?;
+            let enumerator =
+                substitute_type_params(get_enumerator.type_name.as_deref()?, &bindings);
+            let (decl, bindings) = self.type_table.get_instance(&enumerator)?;
+            let (_, current) = self.type_table.find_member(&decl.name, "Current")?;
+            return Some(Resolution::declared(substitute_type_params(
+                current.type_name.as_deref()?,
+                &bindings,
+            )));
+        }
+
+        let (base, args) = split_generic(container);
+        let assumed = || {
+            format!(
+                "{} assumed to be the System.Generics.Collections type",
+                base
+            )
+        };
+        if ENUMERABLE_COLLECTIONS
+            .iter()
+            .any(|collection| collection.eq_ignore_ascii_case(base))
+        {
+            return args
+                .first()
+                .map(|arg| Resolution::heuristic(arg.to_string(), assumed()));
+        }
+        if ENUMERABLE_DICTIONARIES
+            .iter()
+            .any(|dictionary| dictionary.eq_ignore_ascii_case(base))
+        {
+            return Some(Resolution::heuristic(
+                format!("TPair<{}>", args.join(", ")),
+                assumed(),
+            ));
+        }
+        if depth < MAX_ALIAS_DEPTH && args.is_empty() {
+            let tree = self.tree.as_ref()?;
+            let aliased = self.find_type_alias(tree.root_node(), container)?;
+            return self.element_type(&aliased, depth + 1);
+        }
+        None
+    }
+
+    /// The type a type alias or array, set or string type declaration named
+    /// `name` stands for, as written.
+    fn find_type_alias(&self, node: Node, name: &str) -> Option<String> {
+        if node.kind() == "declType" {
+            let type_node = node
+                .child_by_field_name("type")
+                .filter(|type_node| type_node.kind() == "type")?;
+            let declared = node
+                .child_by_field_name("name")
+                .filter(|declared| declared.kind() == "identifier")?;
+            return self
+                .get_name(declared)
+                .eq_ignore_ascii_case(name)
+                .then(|| self.get_node_text(type_node));
+        }
+        let mut cursor = node.walk();
+        let found = node
+            .children(&mut cursor)
+            .find_map(|child| self.find_type_alias(child, name));
+        found
+    }
+
+    /// Finds a unit- or program-level variable, skipping routine bodies.
+    fn find_global_variable_type(&self, node: Node, name: &str) -> Option<String> {
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            let found = match child.kind() {
+                "defProc" | "declType" => None,
+                "declVars" => self.find_declared_type(child, name),
+                _ => self.find_global_variable_type(child, name),
+            };
+            if found.is_some() {
+                return found;
+            }
+        }
+        None
     }
 
-    fn get_visible_symbols(&self, node: Node) -> Vec<CompletionItem> {
+    /// The symbols in scope at `position`: those of the unit, and the
+    /// parameters and locals of the routines enclosing it.
+    fn get_visible_symbols(&self, position: Position) -> Vec<CompletionItem> {
         let mut items = Vec::new();
 
-        // Add all symbols in the current scope
         for symbols in self.symbol_map.values() {
             for symbol in symbols {
+                if !self.is_in_scope(symbol, position) {
+                    continue;
+                }
                 items.push(CompletionItem {
                     label: symbol.name.clone(),
                     kind: Some(self.symbol_kind_to_completion_kind(symbol.kind)),
                     detail: symbol.detail.clone(),
                     documentation: None,
+                    data: Some(serde_json::json!({ "symbolId": symbol.id })),
                     ..CompletionItem::default()
                 });
             }
@@ -397,7 +5927,456 @@ impl SymbolAnalyzer {
             SymbolKind::CLASS => CompletionItemKind::CLASS,
             SymbolKind::VARIABLE => CompletionItemKind::VARIABLE,
             SymbolKind::MODULE => CompletionItemKind::MODULE,
+            SymbolKind::CONSTANT => CompletionItemKind::CONSTANT,
+            SymbolKind::FIELD => CompletionItemKind::FIELD,
+            SymbolKind::METHOD => CompletionItemKind::METHOD,
+            SymbolKind::PROPERTY => CompletionItemKind::PROPERTY,
             _ => CompletionItemKind::TEXT,
         }
     }
 }
+
+/// The parentheses left open at the end of `text`, outermost first, as
+/// their offset and the number of commas after them so far. Commas inside
+/// inner parentheses or brackets, strings and comments are not counted.
+fn open_argument_lists(text: &str) -> Vec<(usize, usize)> {
+    let mut open: Vec<(char, usize, usize)> = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some((offset, c)) = chars.next() {
+        match c {
+            '\'' => {
+                chars.find(|(_, c)| *c == '\'' || *c == '\n');
+            }
+            '{' => {
+                chars.find(|(_, c)| *c == '}');
+            }
+            '/' if chars.next_if(|(_, next)| *next == '/').is_some() => {
+                chars.find(|(_, c)| *c == '\n');
+            }
+            '(' if chars.next_if(|(_, next)| *next == '*').is_some() => {
+                while let Some((_, c)) = chars.next() {
+                    if c == '*' && chars.next_if(|(_, next)| *next == ')').is_some() {
+                        break;
+                    }
+                }
+            }
+            '(' | '[' => open.push((c, offset, 0)),
+            ')' | ']' => {
+                open.pop();
+            }
+            ',' => {
+                if let Some((_, _, commas)) = open.last_mut() {
+                    *commas += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    open.into_iter()
+        .filter(|(c, _, _)| *c == '(')
+        .map(|(_, offset, commas)| (offset, commas))
+        .collect()
+}
+
+/// The offset of the first occurrence of `word` in `text` not part of a
+/// longer identifier.
+fn find_word(text: &str, word: &str) -> Option<usize> {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    text.match_indices(word)
+        .map(|(offset, _)| offset)
+        .find(|&offset| {
+            !text[..offset].ends_with(is_ident)
+                && !text[offset + word.len()..].starts_with(is_ident)
+        })
+}
+
+/// Whether `node` starts code that runs like a parameterless routine without
+/// being declared as one: the main block of a program or library, or the
+/// initialization or finalization section of a unit. The keywords count too,
+/// as error recovery may leave them without their section.
+fn is_implicit_routine(node: Node) -> bool {
+    match node.kind() {
+        "initialization" | "finalization" | "kInitialization" | "kFinalization" => true,
+        "block" => node
+            .parent()
+            .is_some_and(|parent| matches!(parent.kind(), "program" | "library")),
+        _ => false,
+    }
+}
+
+fn is_dispinterface(interface: Node) -> bool {
+    interface
+        .child(0)
+        .is_some_and(|keyword| keyword.kind() == "kDispInterface")
+}
+
+/// The calling convention among directive names, lowercase.
+fn calling_convention(directives: &[String]) -> Option<String> {
+    directives
+        .iter()
+        .find(|directive| {
+            CALLING_CONVENTIONS
+                .iter()
+                .any(|kind| kind[1..].eq_ignore_ascii_case(directive))
+        })
+        .cloned()
+}
+
+/// Whether two calling conventions are the same, `None` standing for the
+/// default `register` and `winapi` for `stdcall`.
+fn same_calling_convention(a: Option<&str>, b: Option<&str>) -> bool {
+    fn normalize(convention: Option<&str>) -> &str {
+        match convention {
+            None => "register",
+            Some("winapi") => "stdcall",
+            Some(convention) => convention,
+        }
+    }
+    normalize(a) == normalize(b)
+}
+
+/// The number of arguments with which a call matches both overloads, if
+/// any: the fewest both accept, when the parameters up to there agree.
+fn ambiguous_arguments(a: &RoutineSignature, b: &RoutineSignature) -> Option<usize> {
+    let count = a.required.max(b.required);
+    if count > a.params.len().min(b.params.len()) {
+        return None;
+    }
+    (a.params[..count] == b.params[..count]).then_some(count)
+}
+
+/// How a message names a calling convention, `None` being the default.
+fn convention_description(convention: Option<&str>) -> String {
+    match convention {
+        Some(convention) => convention.to_string(),
+        None => "register (the default)".to_string(),
+    }
+}
+
+/// Merges the completion items of overloaded routines into one per name,
+/// placed where the first overload was. A routine and its forward or
+/// implementation declaration count once.
+fn group_overload_items(items: Vec<CompletionItem>) -> Vec<CompletionItem> {
+    let is_routine = |item: &CompletionItem| {
+        matches!(
+            item.kind,
+            Some(CompletionItemKind::FUNCTION | CompletionItemKind::METHOD)
+        )
+    };
+    let symbol_id = |item: &CompletionItem| {
+        let id = item.data.as_ref()?.get("symbolId")?.clone();
+        serde_json::from_value::<SymbolId>(id).ok()
+    };
+    let same_overload = |a: &CompletionItem, b: &CompletionItem| match (symbol_id(a), symbol_id(b))
+    {
+        (Some(a), Some(b)) => a.matches(&b),
+        _ => a.detail == b.detail,
+    };
+
+    let mut groups: Vec<Vec<CompletionItem>> = Vec::new();
+    for item in items {
+        let group = groups.iter_mut().find(|group| {
+            is_routine(&group[0])
+                && group[0].kind == item.kind
+                && group[0].label.eq_ignore_ascii_case(&item.label)
+        });
+        match group {
+            Some(group) if group.iter().any(|other| same_overload(other, &item)) => {}
+            Some(group) => group.push(item),
+            None => groups.push(vec![item]),
+        }
+    }
+    groups.into_iter().map(merge_overloads).collect()
+}
+
+/// Keeps the first of the items of overloads, its detail counting the
+/// others and its data listing all signatures for
+/// [`resolve_completion_item`].
+fn merge_overloads(mut overloads: Vec<CompletionItem>) -> CompletionItem {
+    let signatures: Vec<String> = overloads
+        .iter()
+        .filter_map(|item| item.detail.clone())
+        .collect();
+    let others = overloads.len() - 1;
+    let mut item = overloads.swap_remove(0);
+    if others == 0 {
+        return item;
+    }
+    item.detail = Some(format!(
+        "{} (+{} overload{})",
+        item.detail.unwrap_or_default(),
+        others,
+        if others == 1 { "" } else { "s" }
+    ));
+    let mut data = item.data.take().unwrap_or_else(|| serde_json::json!({}));
+    data["overloads"] = serde_json::json!(signatures);
+    item.data = Some(data);
+    item
+}
+
+/// Handles `completionItem/resolve`: lists the signatures of all overloads
+/// of a merged item in its documentation.
+pub fn resolve_completion_item(mut item: CompletionItem) -> CompletionItem {
+    let signatures: Option<Vec<String>> = item
+        .data
+        .as_ref()
+        .and_then(|data| data.get("overloads"))
+        .and_then(|overloads| serde_json::from_value(overloads.clone()).ok());
+    if let Some(signatures) = signatures {
+        item.documentation = Some(Documentation::MarkupContent(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: format!("```pascal\n{}\n```", signatures.join("\n")),
+        }));
+    }
+    item
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsp::parser::DelphiParser;
+
+    /// The types the for-in tests iterate over: a class, an array alias,
+    /// and classes enumerable through `GetEnumerator`, one of them generic.
+    const FOR_IN_TYPES: &str = "\
+type
+  TCustomer = class
+    Name: string;
+  end;
+  TCustomers = array of TCustomer;
+  TCustomerEnumerator = class
+    function GetCurrent: TCustomer;
+    property Current: TCustomer read GetCurrent;
+  end;
+  TCustomerList = class
+    function GetEnumerator: TCustomerEnumerator;
+  end;
+  TBoxEnumerator<T> = class
+    function GetCurrent: T;
+    property Current: T read GetCurrent;
+  end;
+  TBox<T> = class
+    function GetEnumerator: TBoxEnumerator<T>;
+  end;
+";
+
+    fn analyze(source: &str) -> SymbolAnalyzer {
+        let mut parser = DelphiParser::new();
+        let tree = parser.parse(source).unwrap();
+        let mut analyzer = SymbolAnalyzer::new();
+        analyzer.set_content(
+            tree,
+            source.to_string(),
+            Url::parse("file:///test/U.pas").unwrap(),
+            None,
+        );
+        analyzer
+    }
+
+    /// An analysis of a unit iterating over `Items` of type `container`
+    /// with the undeclared variable `Item`, and the position of `Item` in
+    /// the loop body, followed by `.Name`.
+    fn for_in(container: &str) -> (SymbolAnalyzer, Position) {
+        let source = format!(
+            "unit U;\ninterface\n{}implementation\nprocedure P;\nvar\n  Items: {};\n\
+             begin\n  for Item in Items do\n    Item.Name;\nend;\nend.\n",
+            FOR_IN_TYPES, container
+        );
+        let line = source.lines().position(|line| line.contains("Item.Name"));
+        (analyze(&source), Position::new(line.unwrap() as u32, 4))
+    }
+
+    /// The type the hover shows for the loop variable over `container`.
+    fn element_type(container: &str) -> Option<String> {
+        let (analyzer, position) = for_in(container);
+        let HoverContents::Markup(markup) = analyzer.get_hover_info(position).ok()?.contents else {
+            panic!("hover is not markup");
+        };
+        let declaration = markup.value.lines().nth(1)?.to_string();
+        declaration.strip_prefix("Item: ").map(str::to_string)
+    }
+
+    #[test]
+    fn arrays_yield_their_element_type() {
+        assert_eq!(element_type("array of TCustomer").unwrap(), "TCustomer");
+        assert_eq!(element_type("array[0..9] of Integer").unwrap(), "Integer");
+        assert_eq!(element_type("TCustomers").unwrap(), "TCustomer");
+    }
+
+    #[test]
+    fn generic_collections_yield_their_type_argument() {
+        assert_eq!(element_type("TList<TCustomer>").unwrap(), "TCustomer");
+        assert_eq!(element_type("TObjectList<TCustomer>").unwrap(), "TCustomer");
+    }
+
+    #[test]
+    fn enumerators_yield_the_type_of_current() {
+        assert_eq!(element_type("TCustomerList").unwrap(), "TCustomer");
+        assert_eq!(element_type("TBox<TCustomer>").unwrap(), "TCustomer");
+        assert_eq!(element_type("TBox<Integer>").unwrap(), "Integer");
+    }
+
+    #[test]
+    fn strings_yield_characters() {
+        assert_eq!(element_type("string").unwrap(), "Char");
+        assert_eq!(element_type("AnsiString").unwrap(), "AnsiChar");
+    }
+
+    #[test]
+    fn resolves_loop_variables_over_workspace_types() {
+        for container in [
+            "TCustomers",
+            "TObjectList<TCustomer>",
+            "TCustomerList",
+            "TBox<TCustomer>",
+        ] {
+            let (analyzer, position) = for_in(container);
+            let after_dot = Position::new(position.line, position.character + 5);
+            let members = analyzer.get_completion_items(after_dot, None).unwrap();
+            assert!(
+                members.iter().any(|item| item.label == "Name"),
+                "no members completed over {}",
+                container
+            );
+            let definition = analyzer.find_type_definition(position).unwrap();
+            assert_eq!(
+                definition.range.start,
+                Position::new(3, 2),
+                "over {}",
+                container
+            );
+        }
+    }
+
+    #[test]
+    fn leaves_loop_variables_over_unknown_types_untyped() {
+        assert_eq!(element_type("TUnknown"), None);
+        let (analyzer, position) = for_in("TUnknown");
+        assert!(analyzer.find_type_definition(position).is_err());
+    }
+
+    /// A unit accessing members through chains, `Cust.Address.City` on
+    /// line 18 and `Self.FValue` on line 19.
+    const MEMBER_CHAINS: &str = "\
+unit U;
+interface
+type
+  TAddress = class
+    City: string;
+  end;
+  TCustomer = class
+    Address: TAddress;
+  end;
+  TForm = class
+    FValue: Integer;
+    procedure Run;
+  end;
+implementation
+procedure TForm.Run;
+var
+  Cust: TCustomer;
+begin
+  Cust.Address.City := 'x';
+  Self.FValue := 1;
+end;
+end.
+";
+
+    fn hover_text(analyzer: &SymbolAnalyzer, position: Position) -> Option<String> {
+        match analyzer.get_hover_info(position).ok()?.contents {
+            HoverContents::Markup(markup) => Some(markup.value),
+            contents => panic!("hover is not markup: {:?}", contents),
+        }
+    }
+
+    fn definition_line(analyzer: &SymbolAnalyzer, position: Position) -> u32 {
+        analyzer.find_definition(position).unwrap().range.start.line
+    }
+
+    #[test]
+    fn resolves_each_segment_of_a_member_chain() {
+        let analyzer = analyze(MEMBER_CHAINS);
+        let (cust, address, city) = (
+            Position::new(18, 3),
+            Position::new(18, 8),
+            Position::new(18, 16),
+        );
+
+        let mut chain = ChainResolver::local();
+        assert!(analyzer.member_chain_at(cust, &mut chain).is_none());
+        assert!(analyzer.get_member_chain_hover(cust, &mut chain).is_none());
+        assert_eq!(hover_text(&analyzer, cust), None);
+        assert_eq!(definition_line(&analyzer, cust), 16);
+
+        let hover = hover_text(&analyzer, address).unwrap();
+        assert!(hover.contains("Address: TAddress;") && hover.ends_with("Field of `TCustomer`"));
+        assert_eq!(definition_line(&analyzer, address), 7);
+
+        let hover = hover_text(&analyzer, city).unwrap();
+        assert!(hover.contains("City: string;") && hover.ends_with("Field of `TAddress`"));
+        assert_eq!(definition_line(&analyzer, city), 4);
+    }
+
+    #[test]
+    fn resolves_self_before_a_member_access() {
+        let analyzer = analyze(MEMBER_CHAINS);
+        let (this, value) = (Position::new(19, 3), Position::new(19, 8));
+
+        let mut chain = ChainResolver::local();
+        assert!(analyzer.get_member_chain_hover(this, &mut chain).is_none());
+        let hover = hover_text(&analyzer, this).unwrap();
+        assert!(hover.contains("Self: TForm") && hover.ends_with("Implicit in `TForm.Run`"));
+        assert_eq!(definition_line(&analyzer, this), 9);
+
+        let hover = hover_text(&analyzer, value).unwrap();
+        assert!(hover.contains("FValue: Integer;") && hover.ends_with("Field of `TForm`"));
+        assert_eq!(definition_line(&analyzer, value), 10);
+    }
+
+    /// The text of `source` after the batch fixes of `rules`, and the
+    /// rule of each fix applied.
+    fn batch_fix(source: &str, rules: &[&str], unused: &[String]) -> (String, Vec<&'static str>) {
+        let analyzer = analyze(source);
+        let candidates = analyzer.batch_fixes(rules, unused);
+        let (fixed, applied) = fixes::apply_fixes(source, &analyzer.line_index, candidates);
+        (fixed, applied.iter().map(|fix| fix.code).collect())
+    }
+
+    #[test]
+    fn batch_fixes_apply_the_document_rules() {
+        let source = "unit U;  \nINTERFACE\nuses SysUtils, Classes;\nimplementation\nEND.";
+        let (fixed, applied) = batch_fix(source, fixes::DOCUMENT_RULES, &[]);
+        assert_eq!(
+            fixed,
+            "unit U;\ninterface\nuses Classes, SysUtils;\nimplementation\nend.\n"
+        );
+        assert_eq!(
+            applied,
+            [
+                fixes::SORT_USES,
+                fixes::KEYWORD_CASE,
+                fixes::KEYWORD_CASE,
+                fixes::TRAILING_WHITESPACE,
+                fixes::FINAL_NEWLINE
+            ]
+        );
+
+        let (fixed, applied) = batch_fix(source, &[fixes::FINAL_NEWLINE], &[]);
+        assert_eq!(fixed, format!("{}\n", source));
+        assert_eq!(applied, [fixes::FINAL_NEWLINE]);
+    }
+
+    #[test]
+    fn batch_fixes_count_a_clause_cut_down_as_unused_uses() {
+        let source =
+            "unit U;\r\ninterface\r\nuses SysUtils, Classes, Types;\r\nimplementation\r\nend.";
+        let unused = ["types".to_string()];
+        let (fixed, applied) = batch_fix(source, &[fixes::UNUSED_USES, fixes::SORT_USES], &unused);
+        assert!(fixed.contains("uses Classes, SysUtils;"));
+        assert_eq!(applied, [fixes::UNUSED_USES]);
+
+        let rules = [fixes::UNUSED_USES, fixes::FINAL_NEWLINE];
+        let (fixed, _) = batch_fix(source, &rules, &unused);
+        assert!(fixed.ends_with("uses SysUtils, Classes;\r\nimplementation\r\nend.\r\n"));
+    }
+}
@@ -0,0 +1,305 @@
+use crate::lsp::document::Document;
+use crate::lsp::text_position::LineEnding;
+use tower_lsp::lsp_types::*;
+
+/// A word or punctuation character of the source, with comments, compiler
+/// directives and string literals skipped.
+#[derive(Debug, Clone)]
+struct Token {
+    /// Lowercase text of a word, or a single punctuation character.
+    text: String,
+    /// Byte offset of the token in the source.
+    offset: usize,
+}
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let bytes = text.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        let skip_to = |from: usize, end: &str| {
+            text[from..]
+                .find(end)
+                .map_or(bytes.len(), |found| from + found + end.len())
+        };
+        if c.is_ascii_whitespace() {
+            i += 1;
+        } else if c == b'{' {
+            i = skip_to(i + 1, "}");
+        } else if text[i..].starts_with("(*") {
+            i = skip_to(i + 2, "*)");
+        } else if text[i..].starts_with("//") {
+            i = text[i..]
+                .find(['\r', '\n'])
+                .map_or(bytes.len(), |found| i + found);
+        } else if c == b'\'' {
+            // '' inside a literal reads as two adjacent literals
+            i = skip_to(i + 1, "'");
+        } else if c.is_ascii_alphabetic() || c == b'_' || c == b'&' {
+            let start = i;
+            i += 1;
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            tokens.push(Token {
+                text: text[start..i].trim_start_matches('&').to_lowercase(),
+                offset: start,
+            });
+        } else {
+            let len = text[i..].chars().next().map_or(1, char::len_utf8);
+            tokens.push(Token {
+                text: text[i..i + len].to_string(),
+                offset: i,
+            });
+            i += len;
+        }
+    }
+    tokens
+}
+
+/// Counts block openers left without a closer in `text`. Statement blocks
+/// (`begin`, `try`, `case`, `asm`, `repeat`) and type bodies (`class`,
+/// `record`, `interface`, `object`) are matched with their `end` or
+/// `until`; forward declarations and `class of` open nothing. The final
+/// `end.` closes the main block of programs and libraries and stops the scan.
+pub fn unclosed_blocks(text: &str) -> usize {
+    let tokens = tokenize(text);
+    let is_program = is_program(&tokens);
+    let mut stack = Vec::new();
+    for i in 0..tokens.len() {
+        if is_final_end(&tokens, i) {
+            if is_program && !stack.is_empty() {
+                stack.remove(0);
+            }
+            break;
+        }
+        match_token(&tokens, i, &mut stack);
+    }
+    stack.len()
+}
+
+fn is_program(tokens: &[Token]) -> bool {
+    tokens
+        .first()
+        .is_some_and(|token| token.text == "program" || token.text == "library")
+}
+
+/// Whether the token at `i` is the `end` of the final `end.`.
+fn is_final_end(tokens: &[Token], i: usize) -> bool {
+    tokens[i].text == "end" && tokens.get(i + 1).is_some_and(|next| next.text == ".")
+}
+
+/// Updates `stack`, the closers the blocks open before the token at `i`
+/// wait for, innermost last, with that token.
+fn match_token(tokens: &[Token], i: usize, stack: &mut Vec<&'static str>) {
+    let previous = i.checked_sub(1).map(|p| tokens[p].text.as_str());
+    match tokens[i].text.as_str() {
+        "begin" | "try" | "asm" => stack.push("end"),
+        "repeat" => stack.push("until"),
+        // The variant part of a record has no `end` of its own
+        "case" if stack.last() != Some(&"record") => stack.push("end"),
+        "record" => stack.push("record"),
+        "class" | "interface" | "dispinterface" | "object"
+            if matches!(previous, Some("=") | Some("packed"))
+                && opens_type_body(&tokens[i + 1..]) =>
+        {
+            stack.push("end")
+        }
+        "until" => {
+            if let Some(position) = stack.iter().rposition(|closer| *closer == "until") {
+                stack.truncate(position);
+            }
+        }
+        "end" => {
+            stack.pop();
+        }
+        _ => {}
+    }
+}
+
+/// Whether the block opened by the token at `opener` has a closer of its
+/// own further down: the closer matching it, unless that closer is
+/// indented less than the line of the opener, when it rather closes an
+/// enclosing block left without one.
+fn has_closer(text: &str, tokens: &[Token], opener: usize) -> bool {
+    let indented =
+        |i: usize| indentation(text, tokens[i].offset) >= indentation(text, tokens[opener].offset);
+    let mut stack = Vec::new();
+    match_token(tokens, opener, &mut stack);
+    for i in opener + 1..tokens.len() {
+        if is_final_end(tokens, i) {
+            // The main block of a program ends with the final `end.`
+            return is_program(tokens) && stack.len() == 1 && indented(i);
+        }
+        match_token(tokens, i, &mut stack);
+        if stack.is_empty() {
+            return indented(i);
+        }
+    }
+    false
+}
+
+/// The width of the leading whitespace of the line containing `offset`.
+fn indentation(text: &str, offset: usize) -> usize {
+    let start = text[..offset]
+        .rfind(['\r', '\n'])
+        .map_or(0, |found| found + 1);
+    text[start..]
+        .chars()
+        .take_while(|c| *c == ' ' || *c == '\t')
+        .count()
+}
+
+/// Whether the tokens after `class`/`interface` start a body rather than a
+/// forward declaration (`TFoo = class;`, `TFoo = class(TBase);`) or a
+/// metaclass (`class of TFoo`).
+fn opens_type_body(rest: &[Token]) -> bool {
+    let mut rest = rest.iter().map(|token| token.text.as_str());
+    match rest.next() {
+        Some(";") | Some("of") | None => false,
+        Some("(") => {
+            let mut depth = 1;
+            for text in rest.by_ref() {
+                match text {
+                    "(" => depth += 1,
+                    ")" => depth -= 1,
+                    _ => {}
+                }
+                if depth == 0 {
+                    break;
+                }
+            }
+            rest.next() != Some(";")
+        }
+        _ => true,
+    }
+}
+
+/// The closer inserted for a line of `tokens` ending in a block opener,
+/// and the index of the opener among them.
+fn closer_for_line(tokens: &[Token]) -> Option<(&'static str, usize)> {
+    let last = tokens.len().checked_sub(1)?;
+    match tokens[last].text.as_str() {
+        "begin" | "try" | "asm" => Some(("end;", last)),
+        "repeat" => Some(("until ;", last)),
+        "of" => {
+            let case = tokens.iter().rposition(|token| token.text == "case")?;
+            Some(("end;", case))
+        }
+        _ => None,
+    }
+}
+
+/// On-type formatting for a newline typed at `position`: when the previous
+/// line ends in a block opener that has no closer of its own further down,
+/// inserts the closer on the line after the cursor, indented like the line
+/// of the opener. Returns `None` when the block is already balanced.
+pub fn close_block_on_newline(document: &Document, position: Position) -> Option<TextEdit> {
+    let line_index = document.line_index();
+    let text = document.text();
+    let previous = position.line.checked_sub(1)? as usize;
+
+    let opener_span = line_index.line_span(previous)?;
+    let opener_line = &text[opener_span.clone()];
+    let tokens = tokenize(text);
+    let line_start = tokens.partition_point(|token| token.offset < opener_span.start);
+    let line_end = tokens.partition_point(|token| token.offset < opener_span.end);
+    let (closer, opener) = closer_for_line(&tokens[line_start..line_end])?;
+
+    let cursor_line = line_index.line_span(position.line as usize)?;
+    let cursor = line_index.position_to_offset(position);
+    if !text[cursor..cursor_line.end].trim().is_empty() {
+        return None;
+    }
+    // Nothing is missing in a balanced document, however it is indented
+    if unclosed_blocks(text) == 0 || has_closer(text, &tokens, line_start + opener) {
+        return None;
+    }
+
+    let indent: String = opener_line
+        .chars()
+        .take_while(|c| c.is_whitespace())
+        .collect();
+    let ending = line_index.line_ending(previous).unwrap_or(LineEnding::CrLf);
+    let end = line_index.offset_to_position(cursor_line.end);
+    Some(TextEdit {
+        range: Range { start: end, end },
+        new_text: format!("{}{}{}", ending.as_str(), indent, closer),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsp::text_position::PositionEncoding;
+
+    /// The closer inserted for a newline typed before `|` in `text`, at the
+    /// start of an empty line.
+    fn closer(text: &str) -> Option<String> {
+        let cursor = text.find('|').unwrap();
+        let line = text[..cursor].matches('\n').count() as u32;
+        let document = Document::new(text.replace('|', ""), 1, PositionEncoding::Utf16);
+        let edit = close_block_on_newline(&document, Position::new(line, 0))?;
+        Some(edit.new_text)
+    }
+
+    /// A unit whose routine `A` holds `block`, followed by a routine `B`
+    /// whose `begin` has no `end`.
+    fn unbalanced_after(block: &str) -> String {
+        format!(
+            "unit U;\ninterface\nimplementation\nprocedure A;\nbegin\n{}end;\n\
+             procedure B;\nbegin\n  if X then\n  begin\n    Y;\nend;\nend.\n",
+            block
+        )
+    }
+
+    #[test]
+    fn leaves_blocks_balanced_further_down() {
+        for block in [
+            "  if X then\n  begin\n|\n  end;\n",
+            "  try\n|\n  finally\n    Free;\n  end;\n",
+            "  repeat\n|\n  until Done;\n",
+            "  case X of\n|\n    1: Y;\n  end;\n",
+        ] {
+            let text = unbalanced_after(block);
+            assert!(unclosed_blocks(&text.replace('|', "")) > 0);
+            assert_eq!(closer(&text), None, "in {}", block);
+        }
+    }
+
+    #[test]
+    fn closes_blocks_missing_their_closer() {
+        assert_eq!(
+            closer(&unbalanced_after("  if X then\n  begin\n|\n")).as_deref(),
+            Some("\n  end;")
+        );
+        assert_eq!(
+            closer(&unbalanced_after("  repeat\n|\n")).as_deref(),
+            Some("\n  until ;")
+        );
+        assert_eq!(
+            closer(&unbalanced_after("  case X of\n|\n")).as_deref(),
+            Some("\n  end;")
+        );
+        // The routine `end;` closes the routine, not the typed block
+        let text =
+            "unit U;\ninterface\nimplementation\nprocedure A;\nbegin\n  try\n|\nend;\nend.\n";
+        assert_eq!(closer(text).as_deref(), Some("\n  end;"));
+    }
+
+    #[test]
+    fn leaves_balanced_documents_however_indented() {
+        let text = "unit U;\ninterface\nimplementation\nprocedure A;\nbegin\n  if X then\n  begin\n|\nend;\nend;\nend.\n";
+        assert_eq!(closer(text), None);
+    }
+
+    #[test]
+    fn closes_the_main_block_of_programs_with_the_final_end() {
+        assert_eq!(closer("program P;\nbegin\n|\nend.\n"), None);
+        assert_eq!(
+            closer("program P;\nbegin\n  while X do\n  begin\n|\nend.\n").as_deref(),
+            Some("\n  end;")
+        );
+    }
+}
@@ -0,0 +1,143 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+use std::{env, process, thread};
+
+/// How long a writer waits for another process to release the lock of a
+/// cache file.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The age past which a lock is taken to be left behind by a process that
+/// died while writing. Writes take milliseconds.
+const STALE_LOCK_AGE: Duration = Duration::from_secs(30);
+
+/// The directory of the files the server keeps across runs, shared by the
+/// server processes of a user: the session of each workspace and the
+/// extracted RTL stubs. `None` without a cache directory.
+pub fn cache_dir() -> Option<PathBuf> {
+    let cache = env::var_os("XDG_CACHE_HOME")
+        .or_else(|| env::var_os("LOCALAPPDATA"))
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+    Some(cache.join("delphi-language-server"))
+}
+
+/// The lock of a cache file, held by creating `<file>.lock`, which only
+/// one process can do. Released on drop.
+struct CacheLock {
+    path: PathBuf,
+}
+
+impl CacheLock {
+    /// Takes the lock of `file`, waiting up to [`LOCK_TIMEOUT`] for another
+    /// process to release it. A lock older than [`STALE_LOCK_AGE`] is
+    /// removed.
+    fn acquire(file: &Path) -> io::Result<Self> {
+        let path = sibling(file, "lock");
+        let started = Instant::now();
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut lock) => {
+                    // The owner, for whoever finds the lock left behind
+                    let _ = write!(lock, "{}", process::id());
+                    return Ok(Self { path });
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    if is_stale(&path) {
+                        log::warn!("Removing the stale cache lock {}", path.display());
+                        let _ = fs::remove_file(&path);
+                        continue;
+                    }
+                    if started.elapsed() > LOCK_TIMEOUT {
+                        return Err(io::Error::new(
+                            io::ErrorKind::WouldBlock,
+                            format!("{} is locked by another process", file.display()),
+                        ));
+                    }
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for CacheLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn is_stale(lock: &Path) -> bool {
+    fs::metadata(lock)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|age| age > STALE_LOCK_AGE)
+}
+
+/// `file` with `suffix` appended to its name.
+fn sibling(file: &Path, suffix: &str) -> PathBuf {
+    let mut name = file.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    file.with_file_name(name)
+}
+
+/// Writes a cache file so that other server processes see either its old
+/// or its new content: under the lock of the file, to a temporary file
+/// renamed over it.
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let _lock = CacheLock::acquire(path)?;
+    let temporary = sibling(
+        path,
+        &format!(
+            "{}-{}.tmp",
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ),
+    );
+    let written = fs::write(&temporary, bytes).and_then(|()| fs::rename(&temporary, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    written
+}
+
+/// Deletes the files of the cache directory, except the locks other
+/// processes hold. Returns how many files were deleted.
+pub fn clear() -> io::Result<usize> {
+    let Some(dir) = cache_dir() else {
+        return Ok(0);
+    };
+    match remove_files(&dir) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+        removed => removed,
+    }
+}
+
+fn remove_files(dir: &Path) -> io::Result<usize> {
+    let mut removed = 0;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            removed += remove_files(&path)?;
+            // Kept when a lock remains inside
+            let _ = fs::remove_dir(&path);
+        } else if path.extension().is_none_or(|extension| extension != "lock") || is_stale(&path) {
+            match fs::remove_file(&path) {
+                Ok(()) => removed += 1,
+                // Renamed away by another process meanwhile
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    Ok(removed)
+}
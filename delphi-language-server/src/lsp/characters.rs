@@ -0,0 +1,203 @@
+use crate::lsp::text_position::LineIndex;
+use serde::Deserialize;
+use tower_lsp::lsp_types::*;
+
+/// Code of the diagnostic reporting an invisible, look-alike or non-ASCII
+/// character, typically brought in by text pasted from chat tools or PDFs.
+pub const AMBIGUOUS_CHARACTER: &str = "ambiguous-character";
+
+/// Where the ambiguous character pass looks, configured by the
+/// `diagnostics.ambiguousCharacters` setting.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CharacterScan {
+    #[default]
+    Off,
+    /// Code only: every non-ASCII character outside comments and strings.
+    Code,
+    /// Code, plus the invisible and look-alike characters of comments and
+    /// strings.
+    All,
+}
+
+/// Invisible characters and their names.
+const INVISIBLE: &[(char, &str)] = &[
+    ('\u{00A0}', "non-breaking space"),
+    ('\u{2007}', "figure space"),
+    ('\u{2009}', "thin space"),
+    ('\u{200A}', "hair space"),
+    ('\u{202F}', "narrow non-breaking space"),
+    ('\u{3000}', "ideographic space"),
+    ('\u{200B}', "zero-width space"),
+    ('\u{200C}', "zero-width non-joiner"),
+    ('\u{200D}', "zero-width joiner"),
+    ('\u{2060}', "word joiner"),
+    ('\u{FEFF}', "zero-width no-break space"),
+    ('\u{00AD}', "soft hyphen"),
+];
+
+/// Characters with an ASCII look-alike that can replace them without
+/// changing what the author meant: spaces, quotes, dashes, and Cyrillic and
+/// Greek letters identical to Latin ones.
+const LOOKALIKES: &[(char, &str)] = &[
+    ('\u{00A0}', " "),
+    ('\u{2007}', " "),
+    ('\u{2009}', " "),
+    ('\u{200A}', " "),
+    ('\u{202F}', " "),
+    ('\u{3000}', " "),
+    ('\u{200B}', ""),
+    ('\u{200C}', ""),
+    ('\u{200D}', ""),
+    ('\u{2060}', ""),
+    ('\u{FEFF}', ""),
+    ('\u{00AD}', ""),
+    ('\u{2018}', "'"),
+    ('\u{2019}', "'"),
+    ('\u{201A}', "'"),
+    ('\u{2032}', "'"),
+    ('\u{201C}', "\""),
+    ('\u{201D}', "\""),
+    ('\u{201E}', "\""),
+    ('\u{2013}', "-"),
+    ('\u{2014}', "-"),
+    ('\u{2212}', "-"),
+    ('\u{2044}', "/"),
+    ('\u{0410}', "A"),
+    ('\u{0412}', "B"),
+    ('\u{0415}', "E"),
+    ('\u{041A}', "K"),
+    ('\u{041C}', "M"),
+    ('\u{041D}', "H"),
+    ('\u{041E}', "O"),
+    ('\u{0420}', "P"),
+    ('\u{0421}', "C"),
+    ('\u{0422}', "T"),
+    ('\u{0425}', "X"),
+    ('\u{0430}', "a"),
+    ('\u{0435}', "e"),
+    ('\u{043E}', "o"),
+    ('\u{0440}', "p"),
+    ('\u{0441}', "c"),
+    ('\u{0443}', "y"),
+    ('\u{0445}', "x"),
+    ('\u{0455}', "s"),
+    ('\u{0456}', "i"),
+    ('\u{0458}', "j"),
+    ('\u{0391}', "A"),
+    ('\u{0392}', "B"),
+    ('\u{0395}', "E"),
+    ('\u{0396}', "Z"),
+    ('\u{0397}', "H"),
+    ('\u{0399}', "I"),
+    ('\u{039A}', "K"),
+    ('\u{039C}', "M"),
+    ('\u{039D}', "N"),
+    ('\u{039F}', "O"),
+    ('\u{03A1}', "P"),
+    ('\u{03A4}', "T"),
+    ('\u{03A5}', "Y"),
+    ('\u{03A7}', "X"),
+    ('\u{03BF}', "o"),
+];
+
+/// The ASCII text that safely replaces `c`, empty for zero-width
+/// characters. `None` when there is no such text.
+pub fn ascii_equivalent(c: char) -> Option<&'static str> {
+    LOOKALIKES
+        .iter()
+        .find(|(lookalike, _)| *lookalike == c)
+        .map(|(_, replacement)| *replacement)
+}
+
+fn invisible_name(c: char) -> Option<&'static str> {
+    INVISIBLE
+        .iter()
+        .find(|(invisible, _)| *invisible == c)
+        .map(|(_, name)| *name)
+}
+
+/// The title of the quick fix replacing `c`.
+pub fn fix_title(c: char) -> Option<String> {
+    let replacement = ascii_equivalent(c)?;
+    Some(match (invisible_name(c), replacement) {
+        (Some(name), "") => format!("Remove {}", name),
+        (Some(name), _) => format!("Replace {} with a space", name),
+        (None, replacement) => format!("Replace `{}` with `{}`", c, replacement),
+    })
+}
+
+/// The lexical region the scanner is in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Region {
+    Code,
+    String,
+    LineComment,
+    BraceComment,
+    ParenComment,
+}
+
+/// Reports the characters `mode` selects in a single pass over `text`,
+/// telling code from comments and strings with a minimal lexer rather than
+/// the syntax tree.
+pub fn scan(text: &str, line_index: &LineIndex, mode: CharacterScan) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    if mode == CharacterScan::Off {
+        return diagnostics;
+    }
+    let mut region = Region::Code;
+    let mut chars = text.char_indices().peekable();
+    while let Some((offset, c)) = chars.next() {
+        let next = chars.peek().map(|(_, next)| *next);
+        region = match (region, c) {
+            (Region::Code, '\'') => Region::String,
+            (Region::Code, '{') => Region::BraceComment,
+            (Region::Code, '/') if next == Some('/') => Region::LineComment,
+            (Region::Code, '(') if next == Some('*') => {
+                chars.next();
+                Region::ParenComment
+            }
+            (Region::String, '\'' | '\r' | '\n') => Region::Code,
+            (Region::LineComment, '\r' | '\n') => Region::Code,
+            (Region::BraceComment, '}') => Region::Code,
+            (Region::ParenComment, '*') if next == Some(')') => {
+                chars.next();
+                Region::Code
+            }
+            (region, _) => region,
+        };
+        if c.is_ascii() {
+            continue;
+        }
+        let in_code = region == Region::Code;
+        if !in_code && (mode != CharacterScan::All || ascii_equivalent(c).is_none()) {
+            continue;
+        }
+        diagnostics.push(Diagnostic {
+            range: line_index.byte_range_to_range(offset..offset + c.len_utf8()),
+            severity: Some(DiagnosticSeverity::WARNING),
+            code: Some(NumberOrString::String(AMBIGUOUS_CHARACTER.to_string())),
+            message: describe(c, in_code),
+            source: Some("dls".to_string()),
+            ..Diagnostic::default()
+        });
+    }
+    diagnostics
+}
+
+fn describe(c: char, in_code: bool) -> String {
+    let codepoint = format!("U+{:04X}", c as u32);
+    if let Some(name) = invisible_name(c) {
+        return format!("Invisible character {} ({})", codepoint, name);
+    }
+    match ascii_equivalent(c) {
+        Some(replacement) => format!(
+            "Character `{}` ({}) looks like `{}` but is not",
+            c, codepoint, replacement
+        ),
+        None if in_code && c.is_alphabetic() => {
+            format!("Non-ASCII letter `{}` ({}) in an identifier", c, codepoint)
+        }
+        None => format!("Non-ASCII character `{}` ({}) in code", c, codepoint),
+    }
+}
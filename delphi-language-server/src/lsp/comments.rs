@@ -0,0 +1,228 @@
+use crate::lsp::document::Document;
+use std::ops;
+use tower_lsp::lsp_types::*;
+use tree_sitter::Node;
+
+/// The innermost syntax node at byte `offset`, found in the parsed tree.
+fn node_at(document: &Document, offset: usize) -> Option<Node<'_>> {
+    document
+        .tree()?
+        .root_node()
+        .descendant_for_byte_range(offset, offset + 1)
+}
+
+/// The comment at byte `offset`, if the offset lies inside one.
+fn comment_at(document: &Document, offset: usize) -> Option<Node<'_>> {
+    node_at(document, offset)
+        .filter(|node| node.kind() == "comment" && node.byte_range().contains(&offset))
+}
+
+/// Whether byte `offset` lies inside a string literal or a comment, past
+/// its first character.
+fn inside_literal(document: &Document, offset: usize) -> bool {
+    node_at(document, offset).is_some_and(|node| {
+        matches!(node.kind(), "comment" | "literalString")
+            && node.start_byte() < offset
+            && offset < node.end_byte()
+    })
+}
+
+/// The number of bytes of leading whitespace of `line`.
+fn indent_length(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+/// On-type formatting for a newline typed at `position`: when the previous
+/// line starts with a `///` or `//` comment, continues it on the new line
+/// with the same indentation. The text of the comment keeps its own
+/// alignment, hanging under the text of a `-` or `*` list item; after a
+/// blank comment line, which separates paragraphs, it starts one space
+/// after the prefix. Returns `None` when the cursor has code before it.
+pub fn continue_comment_on_newline(document: &Document, position: Position) -> Option<TextEdit> {
+    let line_index = document.line_index();
+    let text = document.text();
+    let previous = position.line.checked_sub(1)? as usize;
+
+    let previous_span = line_index.line_span(previous)?;
+    let previous_line = &text[previous_span.clone()];
+    let indent = indent_length(previous_line);
+    // Only a comment token counts, not `//` inside a string or a block comment
+    let comment = comment_at(document, previous_span.start + indent)?;
+    if comment.start_byte() != previous_span.start + indent {
+        return None;
+    }
+    let content = &previous_line[indent..];
+    let prefix = ["///", "//"]
+        .into_iter()
+        .find(|prefix| content.starts_with(prefix))?;
+
+    let cursor_line = line_index.line_span(position.line as usize)?;
+    let cursor = line_index.position_to_offset(position);
+    if cursor < cursor_line.start || !text[cursor_line.start..cursor].trim().is_empty() {
+        return None;
+    }
+
+    let body = &content[prefix.len()..];
+    let words = body.trim_start_matches([' ', '\t']);
+    let mut padding = if words.is_empty() {
+        " ".to_string()
+    } else {
+        body[..body.len() - words.len()].to_string()
+    };
+    if words.starts_with("- ") || words.starts_with("* ") {
+        padding.push_str("  ");
+    }
+    // The text moved down by the newline brings its own spacing
+    let rest = &text[cursor..cursor_line.end];
+    if rest.starts_with([' ', '\t']) {
+        padding.clear();
+    }
+    Some(TextEdit {
+        range: line_index.byte_range_to_range(cursor_line.start..cursor),
+        new_text: format!("{}{}{}", &previous_line[..indent], prefix, padding),
+    })
+}
+
+/// Edits commenting out `range`, or uncommenting it when it is already
+/// commented. A range covering whole lines, or an empty one, toggles `//`
+/// at the start of each line; lines beginning inside a block comment are
+/// left alone. A range within lines is wrapped in `{ }`, or in `(* *)` when
+/// it contains a `}`. Returns no edits when the range starts or ends
+/// inside a string or a comment.
+pub fn toggle_comment(document: &Document, range: Range) -> Vec<TextEdit> {
+    let line_index = document.line_index();
+    let text = document.text();
+    let bytes = line_index.range_to_byte_range(range);
+    let first = range.start.line as usize;
+    let mut last = range.end.line as usize;
+    // A selection ending at the start of a line does not include that line
+    if last > first && line_index.line_span(last).map(|span| span.start) == Some(bytes.end) {
+        last -= 1;
+    }
+    let (Some(first_span), Some(last_span)) =
+        (line_index.line_span(first), line_index.line_span(last))
+    else {
+        return Vec::new();
+    };
+    let code_start = first_span.start + indent_length(&text[first_span.clone()]);
+    let code_end = last_span.start + text[last_span].trim_end().len();
+    if bytes.is_empty() || (bytes.start <= code_start && bytes.end >= code_end) {
+        toggle_line_comments(document, first..=last)
+    } else {
+        toggle_block_comment(document, bytes)
+    }
+}
+
+fn toggle_line_comments(document: &Document, lines: ops::RangeInclusive<usize>) -> Vec<TextEdit> {
+    let line_index = document.line_index();
+    let text = document.text();
+    // The start of the code of each line to toggle, with its indentation
+    let starts: Vec<(usize, usize)> = lines
+        .filter_map(|line| line_index.line_span(line))
+        .filter(|span| !text[span.clone()].trim().is_empty())
+        .map(|span| (span.start, span.start + indent_length(&text[span])))
+        .filter(|(_, start)| {
+            comment_at(document, *start).is_none_or(|comment| comment.start_byte() == *start)
+        })
+        .collect();
+
+    let commented = !starts.is_empty()
+        && starts
+            .iter()
+            .all(|(_, start)| text[*start..].starts_with("//"));
+    if commented {
+        starts
+            .iter()
+            .map(|(_, start)| {
+                let end = if text[start + 2..].starts_with(' ') {
+                    start + 3
+                } else {
+                    start + 2
+                };
+                TextEdit {
+                    range: line_index.byte_range_to_range(*start..end),
+                    new_text: String::new(),
+                }
+            })
+            .collect()
+    } else {
+        let indent = starts
+            .iter()
+            .map(|(line_start, start)| start - line_start)
+            .min()
+            .unwrap_or_default();
+        starts
+            .iter()
+            .map(|(line_start, _)| {
+                let position = line_index.offset_to_position(line_start + indent);
+                TextEdit {
+                    range: Range {
+                        start: position,
+                        end: position,
+                    },
+                    new_text: "// ".to_string(),
+                }
+            })
+            .collect()
+    }
+}
+
+fn toggle_block_comment(document: &Document, bytes: ops::Range<usize>) -> Vec<TextEdit> {
+    let line_index = document.line_index();
+    let selected = &document.text()[bytes.clone()];
+    let delete = |range: ops::Range<usize>| TextEdit {
+        range: line_index.byte_range_to_range(range),
+        new_text: String::new(),
+    };
+
+    // A selection of exactly one block comment is uncommented
+    if let Some(comment) = comment_at(document, bytes.start)
+        .filter(|comment| comment.start_byte() == bytes.start && comment.end_byte() == bytes.end)
+    {
+        let (open, close) = if selected.starts_with("(*") {
+            ("(*", "*)")
+        } else if selected.starts_with('{') && !selected.starts_with("{$") {
+            ("{", "}")
+        } else {
+            return Vec::new();
+        };
+        let inner = &selected[open.len()..selected.len() - close.len()];
+        let open_end = comment.start_byte() + open.len() + usize::from(inner.starts_with(' '));
+        let close_start =
+            comment.end_byte() - close.len() - usize::from(inner.len() > 1 && inner.ends_with(' '));
+        return vec![
+            delete(comment.start_byte()..open_end),
+            delete(close_start..comment.end_byte()),
+        ];
+    }
+
+    if inside_literal(document, bytes.start) || inside_literal(document, bytes.end) {
+        return Vec::new();
+    }
+    // A line comment inside the braces would comment out the closer
+    let line_comment = selected.match_indices("//").any(|(index, _)| {
+        comment_at(document, bytes.start + index)
+            .is_some_and(|comment| comment.start_byte() == bytes.start + index)
+    });
+    if line_comment {
+        return Vec::new();
+    }
+    let (open, close) = if !selected.contains('}') {
+        ("{ ", " }")
+    } else if !selected.contains("*)") {
+        ("(* ", " *)")
+    } else {
+        return Vec::new();
+    };
+    let insert = |offset: usize, new_text: &str| {
+        let position = line_index.offset_to_position(offset);
+        TextEdit {
+            range: Range {
+                start: position,
+                end: position,
+            },
+            new_text: new_text.to_string(),
+        }
+    };
+    vec![insert(bytes.start, open), insert(bytes.end, close)]
+}
@@ -0,0 +1,264 @@
+use crate::lsp::directives;
+use std::ops;
+use tower_lsp::lsp_types::Location;
+
+/// Conditional symbols the compilers define for the target, beyond the
+/// `VER*`, `CPU*` and `FPC_*` families.
+const PREDEFINED: &[&str] = &[
+    "ALIGN_STACK",
+    "ANDROID",
+    "ANDROID32",
+    "ANDROID64",
+    "ASSEMBLER",
+    "AUTOREFCOUNT",
+    "BCB",
+    "BSD",
+    "CONDITIONALEXPRESSIONS",
+    "CONSOLE",
+    "DARWIN",
+    "DCC",
+    "ELF",
+    "ENDIAN_BIG",
+    "ENDIAN_LITTLE",
+    "EXTERNALLINKER",
+    "FPC",
+    "FREEBSD",
+    "IOS",
+    "IOS32",
+    "IOS64",
+    "LINUX",
+    "LINUX32",
+    "LINUX64",
+    "MACOS",
+    "MACOS32",
+    "MACOS64",
+    "MSWINDOWS",
+    "NATIVECODE",
+    "NEXTGEN",
+    "OSX",
+    "OSX32",
+    "OSX64",
+    "PIC",
+    "POSIX",
+    "POSIX32",
+    "POSIX64",
+    "UNDERSCOREIMPORTNAME",
+    "UNICODE",
+    "UNIX",
+    "WEAKINSTREF",
+    "WEAKINTFREF",
+    "WEAKREF",
+    "WIN32",
+    "WIN64",
+    "WINDOWS",
+];
+
+/// What a compiler directive does with a conditional symbol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConditionalUse {
+    /// `{$DEFINE NAME}`
+    Define,
+    /// `{$UNDEF NAME}`
+    Undefine,
+    /// `{$IFDEF NAME}`, `{$IFNDEF NAME}`, or `Defined(NAME)` in an `{$IF}`
+    /// or `{$ELSEIF}` expression.
+    Test,
+}
+
+/// A conditional symbol named by a compiler directive.
+#[derive(Debug, Clone)]
+pub struct Conditional {
+    pub name: String,
+    pub usage: ConditionalUse,
+    /// The bytes of the name in the scanned text.
+    pub range: ops::Range<usize>,
+}
+
+/// A conditional symbol named in a file of the workspace.
+#[derive(Debug, Clone)]
+pub struct ConditionalSite {
+    pub name: String,
+    pub usage: ConditionalUse,
+    pub location: Location,
+}
+
+/// Where a conditional symbol tested by a document gets defined.
+#[derive(Debug, Clone)]
+pub enum Definition {
+    /// A `{$DEFINE}` of the document or of a file it includes.
+    Directive(Location),
+    /// The `defines` setting, standing for the project's `-D` options.
+    Settings,
+    /// A `<DCC_Define>` element of a Delphi project file.
+    Project(Location),
+    /// The compiler, for the target platform or its version.
+    Compiler,
+}
+
+impl Definition {
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            Definition::Directive(location) | Definition::Project(location) => Some(location),
+            Definition::Settings | Definition::Compiler => None,
+        }
+    }
+}
+
+/// The conditional symbols named by the directive `text`, with ranges
+/// relative to its start.
+pub fn directive_conditionals(text: &str) -> Vec<Conditional> {
+    let Some((name, arguments)) = directives::parse_directive(text) else {
+        return Vec::new();
+    };
+    // The arguments are a slice of `text`
+    let start = arguments.as_ptr() as usize - text.as_ptr() as usize;
+    let usage = match name.as_str() {
+        "DEFINE" => ConditionalUse::Define,
+        "UNDEF" => ConditionalUse::Undefine,
+        "IFDEF" | "IFNDEF" => ConditionalUse::Test,
+        "IF" | "ELSEIF" => return defined_calls(text, start),
+        _ => return Vec::new(),
+    };
+    identifier_at(text, start)
+        .map(|range| Conditional {
+            name: text[range.clone()].to_string(),
+            usage,
+            range,
+        })
+        .into_iter()
+        .collect()
+}
+
+/// The symbols of the `Defined(NAME)` calls of an `{$IF}` expression
+/// starting at byte `start` of `text`.
+fn defined_calls(text: &str, start: usize) -> Vec<Conditional> {
+    // ASCII lowercasing keeps byte offsets
+    let lower = text.to_ascii_lowercase();
+    let is_ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut conditionals = Vec::new();
+    let mut from = start;
+    while let Some(found) = lower[from..].find("defined") {
+        let call = from + found;
+        from = call + "defined".len();
+        if lower[..call].ends_with(is_ident) {
+            continue;
+        }
+        let rest = &text[from..];
+        let Some(argument) = rest.trim_start().strip_prefix('(') else {
+            continue;
+        };
+        let argument_start = text.len() - argument.len();
+        if let Some(range) = identifier_at(text, argument_start) {
+            conditionals.push(Conditional {
+                name: text[range.clone()].to_string(),
+                usage: ConditionalUse::Test,
+                range,
+            });
+        }
+    }
+    conditionals
+}
+
+/// The identifier starting at byte `start` of `text` after whitespace.
+fn identifier_at(text: &str, start: usize) -> Option<ops::Range<usize>> {
+    let rest = &text[start..];
+    let start = start + rest.len() - rest.trim_start().len();
+    let rest = &text[start..];
+    if !rest.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        return None;
+    }
+    let length = rest
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .unwrap_or(rest.len());
+    Some(start..start + length)
+}
+
+/// The conditional symbols named by the directives of `text`, such as an
+/// include file, which need not parse.
+pub fn scan(text: &str) -> Vec<Conditional> {
+    directives::scan_directives(text)
+        .into_iter()
+        .flat_map(|(offset, directive)| {
+            directive_conditionals(directive)
+                .into_iter()
+                .map(move |conditional| Conditional {
+                    range: offset + conditional.range.start..offset + conditional.range.end,
+                    ..conditional
+                })
+        })
+        .collect()
+}
+
+/// The symbols the `<DCC_Define>` elements of a Delphi project file
+/// define, with their byte ranges. References to other properties, such as
+/// the inherited `$(DCC_Define)`, are left out.
+pub fn project_defines(text: &str) -> Vec<(String, ops::Range<usize>)> {
+    const OPEN: &str = "<DCC_Define>";
+    const CLOSE: &str = "</DCC_Define>";
+    let mut defines = Vec::new();
+    let mut from = 0;
+    while let Some(found) = text[from..].find(OPEN) {
+        let start = from + found + OPEN.len();
+        let Some(length) = text[start..].find(CLOSE) else {
+            break;
+        };
+        let mut offset = start;
+        for define in text[start..start + length].split(';') {
+            let name = define.trim();
+            let name_start = offset + define.len() - define.trim_start().len();
+            offset += define.len() + 1;
+            if !name.is_empty() && !name.starts_with("$(") {
+                defines.push((name.to_string(), name_start..name_start + name.len()));
+            }
+        }
+        from = start + length + CLOSE.len();
+    }
+    defines
+}
+
+/// Whether the compilers define `name` themselves, ignoring case.
+pub fn is_predefined(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    let numbered = |prefix: &str| {
+        name.strip_prefix(prefix)
+            .is_some_and(|rest| !rest.is_empty() && rest.chars().all(|c| c.is_ascii_digit()))
+    };
+    numbered("VER")
+        || name.starts_with("CPU")
+        || name.starts_with("FPC_")
+        || PREDEFINED.contains(&name.as_str())
+}
+
+/// The name of `known` closest to `name`, ignoring case, when it is close
+/// enough to be a misspelling: at most two edits, and fewer than half its
+/// characters.
+pub fn closest<'a>(name: &str, known: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let name = name.to_ascii_uppercase();
+    known
+        .into_iter()
+        .map(|candidate| {
+            (
+                edit_distance(&name, &candidate.to_ascii_uppercase()),
+                candidate,
+            )
+        })
+        .filter(|(distance, _)| *distance > 0 && *distance <= 2 && distance * 2 < name.len())
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// The Levenshtein distance between `a` and `b`, in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let substituted = diagonal + usize::from(a != *b);
+            diagonal = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
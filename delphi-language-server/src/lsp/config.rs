@@ -0,0 +1,573 @@
+use crate::lsp::characters::CharacterScan;
+use crate::lsp::fixes::KeywordCase;
+use crate::lsp::format::{ContinuationIndent, WrapParameters, WrapUses};
+use crate::lsp::keywords::LanguageVersion;
+use crate::lsp::naming::NamingSettings;
+use serde::Deserialize;
+use serde_json::Value;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// Server settings, read from `initializationOptions` and refreshed by
+/// `workspace/didChangeConfiguration`. Every field has a default so clients
+/// only need to send what they change.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Settings {
+    pub diagnostics: DiagnosticSettings,
+    pub visibility: VisibilitySettings,
+    /// The compiler version whose reserved words and directives apply.
+    pub language_version: LanguageVersion,
+    pub format: FormatSettings,
+    pub companions: CompanionSettings,
+    pub outline: OutlineSettings,
+    pub naming: NamingSettings,
+    pub rename: RenameSettings,
+    pub performance: PerformanceSettings,
+    pub analysis: AnalysisSettings,
+    pub fix_all: FixAllSettings,
+    /// The conditional symbols the project defines for the compiler, as
+    /// its `-D` option does.
+    pub defines: Vec<String>,
+}
+
+/// Toggles for the opt-in diagnostic passes.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DiagnosticSettings {
+    /// Report documents mixing CRLF, LF and CR line endings.
+    pub line_endings: bool,
+    /// Report member accesses the compiler rejects because of visibility.
+    pub visibility: bool,
+    /// Report private members and implementation-section routines never
+    /// used in their unit.
+    pub unused_private: bool,
+    /// Report invisible, look-alike and non-ASCII characters in code, and
+    /// optionally in comments and strings.
+    pub ambiguous_characters: CharacterScan,
+    /// Report declarations named after an intrinsic routine the unit
+    /// calls, which the calls now resolve to instead.
+    pub shadowed_intrinsics: bool,
+    /// The names `shadowedIntrinsics` checks, replacing the built-in list
+    /// of System routines.
+    pub intrinsics: Option<Vec<String>>,
+    /// Report uses clause entries naming a unit found neither on the
+    /// search path, the `search_paths` of the `dls.toml` governing the
+    /// document or else the workspace folders, nor among the RTL stubs.
+    pub unresolved_units: bool,
+    /// Report conditional symbols tested by `{$IFDEF}` or `Defined()`
+    /// that no directive, project file or `defines` entry defines.
+    pub undefined_conditionals: bool,
+    /// Report reads of local variables that some path reaches before any
+    /// assignment.
+    pub uninitialized_variables: bool,
+    /// Include the locals of managed types in `uninitializedVariables`,
+    /// which Delphi initializes to empty.
+    pub uninitialized_managed_types: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct VisibilitySettings {
+    /// Offer and resolve inaccessible members anyway, marking them in
+    /// completion instead of hiding them.
+    pub relaxed: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct OutlineSettings {
+    /// Collapse overloaded routines into one node of the document symbol
+    /// tree and one completion item.
+    pub group_overloads: bool,
+    /// Show the top-level `with`, `for`, `while`, `case`, `try` and
+    /// `repeat` statements and labels of routine bodies as children of the
+    /// routine, and the branches of those `case` statements as their
+    /// children.
+    pub show_blocks: bool,
+    pub sort: OutlineSort,
+    /// Show the variables declared outside routines and types.
+    pub include_variables: bool,
+    /// Show the constants declared outside routines and types.
+    pub include_constants: bool,
+}
+
+impl Default for OutlineSettings {
+    fn default() -> Self {
+        Self {
+            group_overloads: false,
+            show_blocks: false,
+            sort: OutlineSort::default(),
+            include_variables: true,
+            include_constants: true,
+        }
+    }
+}
+
+/// The order of sibling symbols in the outline, set by `outline.sort`.
+/// Symbols are only reordered among the siblings of the same unit section
+/// and visibility, so sections and visibility blocks keep their order.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum OutlineSort {
+    /// The order of the declarations in the source.
+    #[default]
+    Position,
+    /// By name, ignoring case.
+    Name,
+    /// Constants, types, variables, fields, properties, then routines, by
+    /// name within each kind.
+    KindThenName,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RenameSettings {
+    /// Also rename the name where it appears as a word in strings and
+    /// comments, as edits the client asks to confirm. Only offered to
+    /// clients supporting change annotations.
+    pub text_occurrences: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AnalysisSettings {
+    /// Treat resolutions relying on a heuristic as unknown: an assumed RTL
+    /// type, the first of several overloads, a name from a unit the
+    /// document does not use. Hovers say what is unresolved, completion
+    /// and diagnostics leave it out, and the guess is logged at trace
+    /// level.
+    pub strict: bool,
+}
+
+/// How the server prepares, once initialized, to answer the first requests
+/// quickly.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PerformanceSettings {
+    /// How many idle parsers are kept for requests to share.
+    pub parser_pool_size: usize,
+    /// How many levels of the uses clauses of the open documents are
+    /// indexed before the rest of the workspace, 0 for none.
+    pub warm_up_depth: usize,
+    /// The size in bytes above which documents are only analyzed for the
+    /// outline, folding and syntax errors.
+    pub large_file_size: usize,
+    /// Remember the open documents at shutdown, and on the next start
+    /// analyze them and index the units they use before the rest.
+    pub restore_session: bool,
+}
+
+impl Default for PerformanceSettings {
+    fn default() -> Self {
+        Self {
+            parser_pool_size: 2,
+            warm_up_depth: 1,
+            large_file_size: 4 * 1024 * 1024,
+            restore_session: true,
+        }
+    }
+}
+
+/// The fixes the `source.fixAll` code action applies, which clients run
+/// on save.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct FixAllSettings {
+    /// Insert the semicolons the parser found missing, away from other
+    /// syntax errors.
+    pub missing_semicolons: bool,
+    /// Drop the uses clause entries naming workspace units none of whose
+    /// interface names the document refers to.
+    pub remove_unused_uses: bool,
+    /// Sort uses clauses by unit name. Off by default, since the order
+    /// decides which unit wins when two declare the same name.
+    pub sort_uses: bool,
+    pub keyword_case: KeywordCase,
+    pub trailing_whitespace: bool,
+}
+
+impl Default for FixAllSettings {
+    fn default() -> Self {
+        Self {
+            missing_semicolons: true,
+            remove_unused_uses: true,
+            sort_uses: false,
+            keyword_case: KeywordCase::default(),
+            trailing_whitespace: true,
+        }
+    }
+}
+
+/// Wrapping policies of the formatter.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct FormatSettings {
+    pub max_line_length: usize,
+    pub wrap_parameters: WrapParameters,
+    pub wrap_uses: WrapUses,
+    pub continuation_indent: ContinuationIndent,
+}
+
+impl Default for FormatSettings {
+    fn default() -> Self {
+        Self {
+            max_line_length: 100,
+            wrap_parameters: WrapParameters::default(),
+            wrap_uses: WrapUses::default(),
+            continuation_indent: ContinuationIndent::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CompanionSettings {
+    /// Names of test units, with `{name}` standing for the tested unit.
+    pub test_patterns: Vec<String>,
+}
+
+impl Default for CompanionSettings {
+    fn default() -> Self {
+        Self {
+            test_patterns: vec!["{name}.Tests".to_string(), "Test{name}".to_string()],
+        }
+    }
+}
+
+impl Settings {
+    /// Reads settings from a client payload. Accepts both the bare settings
+    /// object and one nested under a `delphi` section, as sent by clients
+    /// synchronizing a configuration section. Invalid payloads fall back to
+    /// the defaults.
+    pub fn from_value(value: Value) -> Self {
+        let value = match value {
+            Value::Object(mut map) if map.contains_key("delphi") => map.remove("delphi").unwrap(),
+            value => value,
+        };
+        serde_json::from_value(value).unwrap_or_else(|e| {
+            log::warn!("Ignoring invalid settings: {}", e);
+            Settings::default()
+        })
+    }
+}
+
+/// The name of the file configuring the unit search paths of a project.
+pub const PROJECT_FILE: &str = "dls.toml";
+
+/// The settings of a `dls.toml`. Only a flat subset of TOML is read:
+/// top-level keys whose values are strings or arrays of strings.
+#[derive(Debug, Clone, Default)]
+pub struct ProjectConfig {
+    /// The `dls.toml` read.
+    pub file: PathBuf,
+    /// The directories searched for the units a file uses, besides its own
+    /// directory. Relative paths are taken from the directory of the file.
+    pub search_paths: Vec<PathBuf>,
+}
+
+impl ProjectConfig {
+    /// Reads the `dls.toml` of the directory of `file` or of the nearest
+    /// directory above it. `None` when there is none.
+    pub fn find(file: &Path) -> Result<Option<Self>, String> {
+        let Some(config) = file
+            .ancestors()
+            .skip(1)
+            .map(|dir| dir.join(PROJECT_FILE))
+            .find(|config| config.is_file())
+        else {
+            return Ok(None);
+        };
+        let text = fs::read_to_string(&config)
+            .map_err(|e| format!("Error reading {}: {}", config.display(), e))?;
+        let dir = config.parent().unwrap_or(Path::new(""));
+        Self::parse(&text, dir)
+            .map(Some)
+            .map_err(|e| format!("{}: {}", config.display(), e))
+    }
+
+    /// Parses the text of a `dls.toml` in `dir`. Unknown keys and tables
+    /// are skipped.
+    pub fn parse(text: &str, dir: &Path) -> Result<Self, String> {
+        let mut config = ProjectConfig {
+            file: dir.join(PROJECT_FILE),
+            ..ProjectConfig::default()
+        };
+        let mut lines = text.lines().enumerate();
+        let mut in_table = false;
+        while let Some((number, line)) = lines.next() {
+            let line = strip_comment(line).trim();
+            if line.starts_with('[') {
+                in_table = true;
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                if line.is_empty() {
+                    continue;
+                }
+                return Err(format!("line {}: expected `key = value`", number + 1));
+            };
+            let mut value = value.trim().to_string();
+            // An array may continue over the following lines
+            if value.starts_with('[') {
+                while !strip_comment(&value).trim_end().ends_with(']') {
+                    let Some((_, next)) = lines.next() else {
+                        return Err(format!("line {}: unterminated array", number + 1));
+                    };
+                    value.push(' ');
+                    value.push_str(strip_comment(next));
+                }
+            }
+            if in_table || key.trim() != "search_paths" {
+                continue;
+            }
+            config.search_paths = parse_strings(strip_comment(&value).trim())
+                .ok_or_else(|| {
+                    format!(
+                        "line {}: search_paths must be an array of strings",
+                        number + 1
+                    )
+                })?
+                .into_iter()
+                .map(|path| dir.join(path))
+                .collect();
+        }
+        Ok(config)
+    }
+}
+
+/// The edit adding `path` to the `search_paths` of the `dls.toml` text
+/// `text`: the range of the text to replace and its replacement. The path
+/// goes after the last entry of the array, a lone string becomes an array,
+/// and a `search_paths` line is added at the top when there is none.
+/// `None` when the array is not terminated.
+pub fn add_search_path(text: &str, path: &str) -> Option<(Range<usize>, String)> {
+    let entry = format!("\"{}\"", path.replace('\\', "\\\\").replace('"', "\\\""));
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let code = strip_comment(line);
+        if code.trim_start().starts_with('[') {
+            break;
+        }
+        if let Some((key, value)) = code.split_once('=') {
+            if key.trim() == "search_paths" {
+                let value_start = offset + key.len() + 1;
+                let trimmed = value.trim();
+                if trimmed.starts_with('[') {
+                    let open = value_start + value.find('[').unwrap();
+                    let end = last_array_item_end(text, open)?;
+                    let separator = match text[..end].chars().next_back() {
+                        Some('[') => "",
+                        Some(',') => " ",
+                        _ => ", ",
+                    };
+                    return Some((end..end, format!("{}{}", separator, entry)));
+                }
+                let start = value_start + value.find(trimmed).unwrap_or(0);
+                let replaced = start..start + trimmed.len();
+                return Some((replaced, format!("[{}, {}]", trimmed, entry)));
+            }
+        }
+        offset += line.len();
+    }
+    Some((0..0, format!("search_paths = [{}]\n", entry)))
+}
+
+/// The offset after the last character of the array opening at `open`
+/// that is neither whitespace nor part of a comment, before its `]`.
+fn last_array_item_end(text: &str, open: usize) -> Option<usize> {
+    let start = open + 1;
+    let mut end = start;
+    let mut chars = text[start..].char_indices().map(|(i, c)| (start + i, c));
+    while let Some((i, c)) = chars.next() {
+        match c {
+            ']' => return Some(end),
+            '#' => {
+                chars.find(|(_, c)| *c == '\n');
+            }
+            '"' | '\'' => {
+                let mut escaped = false;
+                let (close, _) = chars.find(|(_, next)| {
+                    let closes = *next == c && !escaped;
+                    escaped = c == '"' && *next == '\\' && !escaped;
+                    closes
+                })?;
+                end = close + 1;
+            }
+            _ if c.is_whitespace() => {}
+            _ => end = i + c.len_utf8(),
+        }
+    }
+    None
+}
+
+/// `line` up to a `#` outside of strings.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some('"') if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' => return &line[..i],
+            None => {}
+        }
+    }
+    line
+}
+
+/// The strings of an array like `["src", 'lib\rtl']`, or of a lone string.
+fn parse_strings(value: &str) -> Option<Vec<String>> {
+    let Some(items) = value.strip_prefix('[') else {
+        let (string, rest) = parse_string(value)?;
+        return rest.trim().is_empty().then(|| vec![string]);
+    };
+    let mut rest = items.strip_suffix(']')?.trim();
+    let mut strings = Vec::new();
+    while !rest.is_empty() {
+        let (string, after) = parse_string(rest)?;
+        strings.push(string);
+        rest = after.trim_start();
+        match rest.strip_prefix(',') {
+            Some(after) => rest = after.trim_start(),
+            None if rest.is_empty() => {}
+            None => return None,
+        }
+    }
+    Some(strings)
+}
+
+/// Splits a leading basic (`"..."`, with escapes) or literal (`'...'`)
+/// string off `text`.
+fn parse_string(text: &str) -> Option<(String, &str)> {
+    let mut chars = text.char_indices();
+    let (_, quote) = chars.next().filter(|(_, c)| *c == '"' || *c == '\'')?;
+    let mut string = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            _ if c == quote => return Some((string, &text[i + 1..])),
+            '\\' if quote == '"' => string.push(match chars.next()?.1 {
+                'n' => '\n',
+                't' => '\t',
+                escaped @ ('"' | '\\') => escaped,
+                _ => return None,
+            }),
+            _ => string.push(c),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn search_paths(text: &str) -> Result<Vec<PathBuf>, String> {
+        ProjectConfig::parse(text, Path::new("/project")).map(|config| config.search_paths)
+    }
+
+    #[test]
+    fn reads_search_paths_relative_to_the_project() {
+        assert_eq!(
+            search_paths("search_paths = [\"src\", '/opt/rtl']"),
+            Ok(vec![
+                PathBuf::from("/project/src"),
+                PathBuf::from("/opt/rtl")
+            ])
+        );
+        assert_eq!(
+            search_paths("search_paths = \"lib\""),
+            Ok(vec![PathBuf::from("/project/lib")])
+        );
+    }
+
+    #[test]
+    fn reads_arrays_over_several_lines_with_comments() {
+        let text = "# units\nsearch_paths = [\n  \"src\", # ours\n  'lib#1',\n]\n";
+        assert_eq!(
+            search_paths(text),
+            Ok(vec![
+                PathBuf::from("/project/src"),
+                PathBuf::from("/project/lib#1")
+            ])
+        );
+    }
+
+    #[test]
+    fn unescapes_basic_strings_only() {
+        assert_eq!(
+            search_paths(r#"search_paths = ["a\\b\"c", 'd\e']"#),
+            Ok(vec![
+                PathBuf::from("/project/a\\b\"c"),
+                PathBuf::from("/project/d\\e")
+            ])
+        );
+    }
+
+    #[test]
+    fn skips_other_keys_and_tables() {
+        let text = "name = 'app'\nsearch_paths = ['src']\n[tool]\nsearch_paths = ['other']\n";
+        assert_eq!(search_paths(text), Ok(vec![PathBuf::from("/project/src")]));
+        assert_eq!(search_paths(""), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn rejects_malformed_values() {
+        assert!(search_paths("search_paths = [1]").is_err());
+        assert!(search_paths("search_paths = ['a' 'b']").is_err());
+        assert!(search_paths("search_paths = ['a'").is_err());
+        assert!(search_paths("search_paths").is_err());
+    }
+
+    /// `text` with `path` added to its search paths.
+    fn with_search_path(text: &str, path: &str) -> String {
+        let (range, replacement) = add_search_path(text, path).unwrap();
+        let mut text = text.to_string();
+        text.replace_range(range, &replacement);
+        text
+    }
+
+    #[test]
+    fn adds_search_paths_after_the_last_entry() {
+        assert_eq!(
+            with_search_path("search_paths = ['src'] # ours\n", "lib"),
+            "search_paths = ['src', \"lib\"] # ours\n"
+        );
+        assert_eq!(
+            with_search_path("search_paths = []", "lib"),
+            "search_paths = [\"lib\"]"
+        );
+        let text = "search_paths = [\n  \"a]\", # b]\n  'c',\n]\n";
+        let added = with_search_path(text, "d\\e");
+        assert_eq!(
+            added,
+            "search_paths = [\n  \"a]\", # b]\n  'c', \"d\\\\e\"\n]\n"
+        );
+        assert_eq!(
+            search_paths(&added).unwrap().last(),
+            Some(&PathBuf::from("/project/d\\e"))
+        );
+    }
+
+    #[test]
+    fn adds_search_paths_where_there_were_none() {
+        assert_eq!(
+            with_search_path("search_paths = 'src'\n", "lib"),
+            "search_paths = ['src', \"lib\"]\n"
+        );
+        let added = with_search_path("name = 'app'\n[tool]\nsearch_paths = []\n", "lib");
+        assert_eq!(
+            added,
+            "search_paths = [\"lib\"]\nname = 'app'\n[tool]\nsearch_paths = []\n"
+        );
+        assert_eq!(
+            search_paths(&added),
+            Ok(vec![PathBuf::from("/project/lib")])
+        );
+        assert_eq!(add_search_path("search_paths = ['a'", "b"), None);
+    }
+}
@@ -0,0 +1,174 @@
+use crate::lsp::document::slice_text;
+use crate::lsp::keywords::unescape_identifier;
+use crate::lsp::strings;
+use std::collections::HashMap;
+use tree_sitter::Node;
+
+/// Evaluates constant ordinal expressions: integer and character literals,
+/// constants and enum members declared in scope, and integer arithmetic on
+/// them (`MAX_ITEMS - 1`, `Ord('a')`). Characters evaluate to their code,
+/// enum members to their ordinal value. Anything else is not constant and
+/// evaluates to `None`.
+#[derive(Debug, Clone, Default)]
+pub struct ConstEvaluator {
+    /// Known constant values by lowercase name.
+    values: HashMap<String, i64>,
+}
+
+impl ConstEvaluator {
+    /// Collects the constants and enum members declared under `node`,
+    /// skipping routine bodies, whose declarations are local.
+    pub fn collect(&mut self, node: Node, source: &str) {
+        match node.kind() {
+            "defProc" => return,
+            "declConst" => {
+                let name = node.child_by_field_name("name");
+                let value = node
+                    .child_by_field_name("defaultValue")
+                    .and_then(default_value)
+                    .and_then(|value| self.evaluate(value, source));
+                if let (Some(name), Some(value)) = (name, value) {
+                    self.define(node_text(source, name), value);
+                }
+                return;
+            }
+            "declEnum" => {
+                self.collect_enum(node, source);
+                return;
+            }
+            _ => {}
+        }
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            self.collect(child, source);
+        }
+    }
+
+    /// Collects the local constants of a routine, which shadow outer ones.
+    pub fn collect_locals(&mut self, routine: Node, source: &str) {
+        let mut cursor = routine.walk();
+        for local in routine.children_by_field_name("local", &mut cursor) {
+            self.collect(local, source);
+        }
+    }
+
+    /// Enum members count up from zero, continuing from the last explicit
+    /// value: in `(A, B = 5, C)` C is 6.
+    fn collect_enum(&mut self, node: Node, source: &str) {
+        let mut next = 0;
+        let mut cursor = node.walk();
+        for member in node.children(&mut cursor) {
+            if member.kind() != "declEnumValue" {
+                continue;
+            }
+            let explicit = member
+                .child_by_field_name("value")
+                .and_then(default_value)
+                .and_then(|value| self.evaluate(value, source));
+            let value = explicit.unwrap_or(next);
+            if let Some(name) = member.child_by_field_name("name") {
+                self.define(node_text(source, name), value);
+            }
+            next = value + 1;
+        }
+    }
+
+    fn define(&mut self, name: &str, value: i64) {
+        self.values
+            .insert(unescape_identifier(name).to_lowercase(), value);
+    }
+
+    pub fn evaluate(&self, node: Node, source: &str) -> Option<i64> {
+        let text = node_text(source, node);
+        match node.kind() {
+            "literalNumber" => parse_integer(text),
+            "literalString" => {
+                let value = strings::decode_literal(text)?;
+                let mut chars = value.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Some(c as i64),
+                    _ => None,
+                }
+            }
+            "identifier" => self
+                .values
+                .get(&unescape_identifier(text).to_lowercase())
+                .copied(),
+            // Qualified enum members such as `TColor.clRed`
+            "exprDot" => self.evaluate(node.child_by_field_name("rhs")?, source),
+            "exprParens" => self.evaluate(node.named_child(0)?, source),
+            "exprUnary" => {
+                let operand = self.evaluate(node.child_by_field_name("operand")?, source)?;
+                match node.child_by_field_name("operator")?.kind() {
+                    "kSub" => operand.checked_neg(),
+                    "kAdd" => Some(operand),
+                    "kNot" => Some(!operand),
+                    _ => None,
+                }
+            }
+            "exprBinary" => {
+                let lhs = self.evaluate(node.child_by_field_name("lhs")?, source)?;
+                let rhs = self.evaluate(node.child_by_field_name("rhs")?, source)?;
+                match node.child_by_field_name("operator")?.kind() {
+                    "kAdd" => lhs.checked_add(rhs),
+                    "kSub" => lhs.checked_sub(rhs),
+                    "kMul" => lhs.checked_mul(rhs),
+                    "kDiv" => lhs.checked_div(rhs),
+                    "kMod" => lhs.checked_rem(rhs),
+                    "kShl" => lhs.checked_shl(u32::try_from(rhs).ok()?),
+                    "kShr" => lhs.checked_shr(u32::try_from(rhs).ok()?),
+                    "kAnd" => Some(lhs & rhs),
+                    "kOr" => Some(lhs | rhs),
+                    "kXor" => Some(lhs ^ rhs),
+                    _ => None,
+                }
+            }
+            // Ordinal conversions keep the value
+            "exprCall" => {
+                let entity = node_text(source, node.child_by_field_name("entity")?);
+                let args = node.child_by_field_name("args")?;
+                if args.named_child_count() != 1
+                    || !["ord", "chr"].contains(&entity.to_lowercase().as_str())
+                {
+                    return None;
+                }
+                self.evaluate(args.named_child(0)?, source)
+            }
+            _ => None,
+        }
+    }
+}
+
+fn node_text<'a>(source: &'a str, node: Node) -> &'a str {
+    slice_text(source, node.byte_range(), || {
+        "a constant expression".to_string()
+    })
+}
+
+/// The expression of a `defaultValue` node, after its `=`.
+fn default_value(node: Node) -> Option<Node> {
+    let mut cursor = node.walk();
+    let value = node
+        .named_children(&mut cursor)
+        .find(|child| child.kind() != "kEq");
+    value
+}
+
+/// Parses decimal, `$` hexadecimal, `%` binary and `&` octal integers.
+fn parse_integer(text: &str) -> Option<i64> {
+    let text = text.replace('_', "");
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(text) => (true, text),
+        None => (false, text.as_str()),
+    };
+    let value = if let Some(hex) = text.strip_prefix('$') {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = text.strip_prefix('%') {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if let Some(octal) = text.strip_prefix('&') {
+        i64::from_str_radix(octal, 8).ok()?
+    } else {
+        text.parse().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
@@ -0,0 +1,182 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Extensions of form files linked with `{$R *.dfm}`-style directives.
+pub const FORM_EXTENSIONS: &[&str] = &["dfm", "fmx"];
+
+/// Extensions of unit sources: Delphi's `.pas` and FreePascal's `.pp`.
+/// Programs (`.dpr`, `.lpr`) are not units.
+pub const UNIT_EXTENSIONS: &[&str] = &["pas", "pp"];
+
+/// Extensions of all Pascal sources: units, programs and packages.
+pub const SOURCE_EXTENSIONS: &[&str] = &["pas", "pp", "dpr", "lpr", "dpk"];
+
+/// Extensions of Delphi project files, which hold the conditional symbols
+/// the project defines.
+pub const PROJECT_EXTENSIONS: &[&str] = &["dproj"];
+
+/// The language dialect a file is compiled in: Delphi, or one of the
+/// FreePascal compiler modes selected with `{$MODE ...}`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum Dialect {
+    #[default]
+    Delphi,
+    /// FreePascal in `{$MODE delphi}` or `{$MODE delphiunicode}`.
+    FpcDelphi,
+    ObjFpc,
+    Fpc,
+    Tp,
+    MacPas,
+}
+
+impl Dialect {
+    /// The dialect selected by a `{$MODE name}` argument.
+    pub fn from_mode(mode: &str) -> Option<Self> {
+        match mode.to_lowercase().as_str() {
+            "delphi" | "delphiunicode" => Some(Dialect::FpcDelphi),
+            "objfpc" => Some(Dialect::ObjFpc),
+            "fpc" => Some(Dialect::Fpc),
+            "tp" => Some(Dialect::Tp),
+            "macpas" => Some(Dialect::MacPas),
+            _ => None,
+        }
+    }
+
+    /// The dialect of a file without a `{$MODE}` directive: FreePascal's
+    /// default mode for `.pp` and `.lpr` files, Delphi otherwise.
+    pub fn from_path(path: &Path) -> Self {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_lowercase);
+        match extension.as_deref() {
+            Some("pp" | "lpr") => Dialect::Fpc,
+            _ => Dialect::Delphi,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Dialect::Delphi => "Delphi",
+            Dialect::FpcDelphi => "FreePascal (Delphi mode)",
+            Dialect::ObjFpc => "FreePascal (ObjFPC mode)",
+            Dialect::Fpc => "FreePascal (FPC mode)",
+            Dialect::Tp => "FreePascal (Turbo Pascal mode)",
+            Dialect::MacPas => "FreePascal (MacPas mode)",
+        }
+    }
+
+    /// Whether the `generic` and `specialize` keywords are available.
+    pub fn has_fpc_generics(self) -> bool {
+        matches!(self, Dialect::ObjFpc | Dialect::Fpc)
+    }
+}
+
+/// The dialect selected by a `{$MODE name}` directive, if `text` is one.
+pub fn mode_directive(text: &str) -> Option<Dialect> {
+    let (name, arguments) = parse_directive(text)?;
+    if name != "MODE" {
+        return None;
+    }
+    Dialect::from_mode(arguments.split_whitespace().next()?)
+}
+
+/// Splits a compiler directive such as `{$R *.dfm}` or `(*$IFDEF X*)` into
+/// its uppercase name and the argument text. Returns `None` for comments.
+pub fn parse_directive(text: &str) -> Option<(String, &str)> {
+    let body = text
+        .strip_prefix("{$")
+        .and_then(|body| body.strip_suffix('}'))
+        .or_else(|| {
+            text.strip_prefix("(*$")
+                .and_then(|body| body.strip_suffix("*)"))
+        })?;
+    let name_end = body
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .unwrap_or(body.len());
+    Some((body[..name_end].to_uppercase(), body[name_end..].trim()))
+}
+
+/// The compiler directives of `text`, which need not parse, with their
+/// byte offsets. Strings and comments are skipped, so a `{$` inside them
+/// starts no directive.
+pub fn scan_directives(text: &str) -> Vec<(usize, &str)> {
+    let mut directives = Vec::new();
+    let mut offset = 0;
+    while offset < text.len() {
+        let rest = &text[offset..];
+        let length = if rest.starts_with("//") {
+            rest.find('\n').unwrap_or(rest.len())
+        } else if rest.starts_with('{') {
+            rest.find('}').map_or(rest.len(), |end| end + 1)
+        } else if rest.starts_with("(*") {
+            rest.find("*)").map_or(rest.len(), |end| end + 2)
+        } else if let Some(string) = rest.strip_prefix('\'') {
+            string.find('\'').map_or(rest.len(), |end| end + 2)
+        } else {
+            rest.chars().next().map_or(1, char::len_utf8)
+        };
+        if rest.starts_with("{$") || rest.starts_with("(*$") {
+            directives.push((offset, &rest[..length]));
+        }
+        offset += length;
+    }
+    directives
+}
+
+/// The file named by a `{$R file}` or `{$RESOURCE file}` directive, without
+/// quotes. `{$R+}` and `{$R-}` toggle range checking and name no file.
+pub fn resource_file(text: &str) -> Option<String> {
+    directive_file(text, &["R", "RESOURCE"])
+}
+
+/// The file named by an `{$I file}` or `{$INCLUDE file}` directive, without
+/// quotes. `{$I+}` and `{$I-}` toggle I/O checking and name no file.
+pub fn include_file(text: &str) -> Option<String> {
+    directive_file(text, &["I", "INCLUDE"])
+}
+
+/// The file argument of a directive named one of `names`.
+fn directive_file(text: &str, names: &[&str]) -> Option<String> {
+    let (name, arguments) = parse_directive(text)?;
+    if !names.contains(&name.as_str()) {
+        return None;
+    }
+    let file = match arguments.strip_prefix(['\'', '"']) {
+        Some(quoted) => quoted.split(['\'', '"']).next()?,
+        None => arguments.split_whitespace().next()?,
+    };
+    (!file.is_empty() && !file.starts_with(['+', '-'])).then(|| file.to_string())
+}
+
+/// Resolves a resource file name relative to the directory of the unit.
+/// `*` stands for the file name of the unit without its extension.
+pub fn resolve_resource(file: &str, unit_path: &Path) -> Option<PathBuf> {
+    let stem = unit_path.file_stem()?.to_str()?;
+    let file = file.replace('*', stem).replace('\\', "/");
+    Some(unit_path.parent()?.join(file))
+}
+
+/// Whether a resource is a text file worth opening in the editor: text
+/// form files and resource scripts, but not compiled `.res` files or binary
+/// form files.
+pub fn is_text_resource(path: &Path) -> bool {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_lowercase);
+    if !matches!(
+        extension.as_deref(),
+        Some("dfm" | "fmx" | "lfm" | "xfm" | "rc" | "txt")
+    ) {
+        return false;
+    }
+    let Ok(bytes) = fs::read(path) else {
+        return false;
+    };
+    let head = &bytes[..bytes.len().min(512)];
+    !head.starts_with(b"TPF0") && !head.contains(&0)
+}
@@ -0,0 +1,395 @@
+use crate::lsp::document::slice_text;
+use crate::lsp::keywords::unescape_identifier;
+use crate::lsp::members::{MemberKind, TypeTable, Visibility};
+use crate::lsp::parser::DelphiParser;
+use crate::lsp::text_position::{LineIndex, PositionEncoding};
+use tree_sitter::Node;
+
+/// A generated Markdown page documenting the interface of one unit.
+pub struct UnitPage {
+    pub name: String,
+    /// The first line of the unit's doc comment, used in the index.
+    pub summary: Option<String>,
+    pub markdown: String,
+}
+
+/// Generates the API page of a unit from its interface section. Units with
+/// syntax errors still produce a page covering whatever parsed, with a note
+/// listing the lines of the errors. Returns `None` when the text has no
+/// module header at all.
+pub fn generate_unit_page(parser: &mut DelphiParser, source: &str) -> Option<UnitPage> {
+    let tree = parser.parse(source)?;
+    let line_index = LineIndex::new(source, PositionEncoding::default());
+    let generator = Generator {
+        source,
+        line_index: &line_index,
+        comments: collect_comments(tree.root_node()),
+        type_table: TypeTable::build(tree.root_node(), source, &line_index),
+    };
+
+    // Error recovery may leave a flat ERROR root holding the module tokens
+    let module =
+        find_child(tree.root_node(), &["unit", "program", "library"]).unwrap_or(tree.root_node());
+    let name = unescape_identifier(&generator.text(find_child(module, &["moduleName"])?));
+    let doc = generator.doc_comment(module.start_byte());
+
+    let mut markdown = format!("# Unit {}\n", name);
+    if let Some(doc) = &doc {
+        markdown.push_str(&format!("\n{}\n", doc));
+    }
+
+    let mut error_lines = Vec::new();
+    collect_error_lines(tree.root_node(), &line_index, &mut error_lines);
+    if !error_lines.is_empty() {
+        error_lines.dedup();
+        markdown.push_str(&format!(
+            "\n> **Note:** the unit has syntax errors on line{} {}; declarations may be missing.\n",
+            if error_lines.len() == 1 { "" } else { "s" },
+            error_lines
+                .iter()
+                .map(|line| line.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+
+    match find_child(module, &["interface"]) {
+        Some(interface) => generator.write_interface(interface, &mut markdown),
+        None if find_child(module, &["kInterface"]).is_some() => {
+            generator.write_interface(module, &mut markdown)
+        }
+        None => markdown.push_str("\nThis module has no interface section.\n"),
+    }
+
+    Some(UnitPage {
+        name,
+        summary: doc.and_then(|doc| doc.lines().next().map(str::to_string)),
+        markdown,
+    })
+}
+
+/// Generates an index page linking the pages of `pages`, which are expected
+/// to be written next to it as `<unit name>.md`.
+pub fn generate_index(pages: &[UnitPage]) -> String {
+    let mut markdown = String::from("# API Reference\n\n");
+    for page in pages {
+        markdown.push_str(&format!("- [{}]({}.md)", page.name, page.name));
+        if let Some(summary) = &page.summary {
+            markdown.push_str(&format!(" — {}", summary));
+        }
+        markdown.push('\n');
+    }
+    markdown
+}
+
+/// Collapses a declaration to a single line with normalized whitespace,
+/// e.g. `function Add(A,\n    B: Integer): Integer;` to
+/// `function Add(A, B: Integer): Integer;`.
+pub fn format_signature(text: &str) -> String {
+    let signature = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    signature
+        .replace("( ", "(")
+        .replace(" )", ")")
+        .replace(" ;", ";")
+}
+
+struct Generator<'a> {
+    source: &'a str,
+    line_index: &'a LineIndex,
+    comments: Vec<Node<'a>>,
+    type_table: TypeTable,
+}
+
+impl Generator<'_> {
+    fn text(&self, node: Node) -> String {
+        self.slice(node.byte_range()).to_string()
+    }
+
+    fn slice(&self, range: std::ops::Range<usize>) -> &str {
+        slice_text(self.source, range, || "the documented unit".to_string())
+    }
+
+    fn write_interface(&self, interface: Node, markdown: &mut String) {
+        let mut types = Vec::new();
+        let mut routines = Vec::new();
+        let mut constants = Vec::new();
+        let mut variables = Vec::new();
+
+        // In a flattened ERROR tree the interface section is the run of
+        // children between the `interface` and `implementation` keywords
+        let flattened = interface.kind() != "interface";
+        let mut in_interface = !flattened;
+        let mut cursor = interface.walk();
+        for child in interface.children(&mut cursor) {
+            if flattened {
+                match child.kind() {
+                    "kInterface" => in_interface = true,
+                    "kImplementation" => break,
+                    _ => {}
+                }
+            }
+            if !in_interface {
+                continue;
+            }
+            let mut declarations = child.walk();
+            match child.kind() {
+                "declTypes" => types.extend(
+                    child
+                        .children(&mut declarations)
+                        .filter(|n| n.kind() == "declType"),
+                ),
+                "declClass" | "declIntf" | "declHelper" => types.push(child),
+                "declConsts" => constants.extend(
+                    child
+                        .children(&mut declarations)
+                        .filter(|n| n.kind() == "declConst"),
+                ),
+                "declVars" => variables.extend(
+                    child
+                        .children(&mut declarations)
+                        .filter(|n| n.kind() == "declVar"),
+                ),
+                "declProc" => routines.push(child),
+                _ => {}
+            }
+        }
+
+        if !types.is_empty() {
+            markdown.push_str("\n## Types\n");
+            for declaration in types {
+                self.write_type(declaration, markdown);
+            }
+        }
+        if !routines.is_empty() {
+            markdown.push_str("\n## Routines\n");
+            for routine in routines {
+                let Some(name) = routine.child_by_field_name("name") else {
+                    continue;
+                };
+                markdown.push_str(&format!(
+                    "\n### {}\n\n```pascal\n{}\n```\n",
+                    unescape_identifier(&self.text(name)),
+                    format_signature(&self.text(routine))
+                ));
+                if let Some(doc) = self.doc_comment(routine.start_byte()) {
+                    markdown.push_str(&format!("\n{}\n", doc));
+                }
+            }
+        }
+        self.write_table("Constants", &constants, markdown);
+        self.write_table("Variables", &variables, markdown);
+    }
+
+    /// Documents a `declType`, or a type body whose `declType` was lost to
+    /// error recovery and which follows its `Name =` tokens.
+    fn write_type(&self, declaration: Node, markdown: &mut String) {
+        let (name, start) = match declaration.child_by_field_name("name") {
+            Some(name) => (name, declaration.start_byte()),
+            None => {
+                let Some(name) = declaration
+                    .prev_sibling()
+                    .filter(|n| n.kind() == "kEq")
+                    .and_then(|eq| eq.prev_sibling())
+                    .filter(|n| n.kind() == "identifier")
+                else {
+                    return;
+                };
+                (name, name.start_byte())
+            }
+        };
+        let name = unescape_identifier(&self.text(name));
+        markdown.push_str(&format!(
+            "\n### {}\n\n```pascal\n{}\n```\n",
+            name,
+            self.type_signature(start, declaration)
+        ));
+        if let Some(doc) = self.doc_comment(start) {
+            markdown.push_str(&format!("\n{}\n", doc));
+        }
+
+        let Some(decl) = self.type_table.get(&name) else {
+            return;
+        };
+        let members: Vec<_> = decl
+            .members
+            .iter()
+            .filter(|member| {
+                !matches!(
+                    member.visibility,
+                    Visibility::Private | Visibility::StrictPrivate
+                )
+            })
+            .collect();
+        if members.is_empty() {
+            return;
+        }
+
+        markdown.push_str("\n| Member | Kind | Visibility | Description |\n");
+        markdown.push_str("| --- | --- | --- | --- |\n");
+        for member in members {
+            let kind = match member.kind {
+                MemberKind::Field => "field",
+                MemberKind::Method => "method",
+                MemberKind::Property => "property",
+            };
+            let offset = self.line_index.position_to_offset(member.range.start);
+            markdown.push_str(&format!(
+                "| `{}` | {} | {} | {} |\n",
+                escape_cell(&format_signature(&member.detail)),
+                kind,
+                member.visibility.as_str(),
+                self.doc_comment(offset)
+                    .map(|doc| escape_cell(&doc))
+                    .unwrap_or_default()
+            ));
+        }
+    }
+
+    /// The declaration of a type without its body: `TFoo = class(TBase)`
+    /// for classes, interfaces and records, the whole declaration otherwise.
+    fn type_signature(&self, start: usize, declaration: Node) -> String {
+        let body = declaration
+            .child_by_field_name("type")
+            .or(Some(declaration))
+            .filter(|body| matches!(body.kind(), "declClass" | "declIntf" | "declHelper"));
+        let Some(body) = body else {
+            return format_signature(&self.text(declaration));
+        };
+
+        let mut end = body.start_byte();
+        let mut cursor = body.walk();
+        for child in body.children(&mut cursor) {
+            if matches!(
+                child.kind(),
+                "declSection" | "declField" | "declProc" | "declProp" | "kEnd" | "comment"
+            ) {
+                break;
+            }
+            end = child.end_byte();
+        }
+        format_signature(self.slice(start..end))
+    }
+
+    fn write_table(&self, title: &str, declarations: &[Node], markdown: &mut String) {
+        if declarations.is_empty() {
+            return;
+        }
+        markdown.push_str(&format!("\n## {}\n\n", title));
+        markdown.push_str("| Declaration | Description |\n");
+        markdown.push_str("| --- | --- |\n");
+        for declaration in declarations {
+            markdown.push_str(&format!(
+                "| `{}` | {} |\n",
+                escape_cell(&format_signature(&self.text(*declaration))),
+                self.doc_comment(declaration.start_byte())
+                    .map(|doc| escape_cell(&doc))
+                    .unwrap_or_default()
+            ));
+        }
+    }
+
+    /// Extracts the doc comment of the declaration starting at `offset`:
+    /// the comments directly above it, each on a line of its own, without
+    /// blank lines in between. `//`, `///`, `{ }` and `(* *)` comments are
+    /// accepted; XMLDoc `<summary>` tags are dropped.
+    fn doc_comment(&self, offset: usize) -> Option<String> {
+        let mut lines = Vec::new();
+        let mut next_start = offset;
+        for comment in self.comments.iter().rev() {
+            if comment.end_byte() > next_start {
+                continue;
+            }
+            let gap = self.slice(comment.end_byte()..next_start);
+            let start = self.line_index.offset_to_position(comment.start_byte());
+            let line_start = self
+                .line_index
+                .position_to_offset(tower_lsp::lsp_types::Position {
+                    line: start.line,
+                    character: 0,
+                });
+            let own_line = self
+                .slice(line_start..comment.start_byte())
+                .trim()
+                .is_empty();
+            let gap_lines = self.line_index.offset_to_position(next_start).line
+                - self.line_index.offset_to_position(comment.end_byte()).line;
+            if !gap.trim().is_empty() || gap_lines > 1 || !own_line {
+                break;
+            }
+            lines.splice(0..0, comment_lines(&self.text(*comment)));
+            next_start = comment.start_byte();
+        }
+
+        while lines.first().is_some_and(|line| line.is_empty()) {
+            lines.remove(0);
+        }
+        while lines.last().is_some_and(|line| line.is_empty()) {
+            lines.pop();
+        }
+        (!lines.is_empty()).then(|| lines.join("\n"))
+    }
+}
+
+/// The text lines of a comment without its delimiters.
+fn comment_lines(comment: &str) -> Vec<String> {
+    let body = if let Some(body) = comment.strip_prefix("//") {
+        body.trim_start_matches('/')
+    } else if let Some(body) = comment.strip_prefix("(*") {
+        body.strip_suffix("*)").unwrap_or(body)
+    } else if let Some(body) = comment.strip_prefix('{') {
+        body.strip_suffix('}').unwrap_or(body)
+    } else {
+        comment
+    };
+    body.replace("<summary>", "")
+        .replace("</summary>", "")
+        .lines()
+        .map(|line| line.trim().to_string())
+        .collect()
+}
+
+/// Makes text safe for a single Markdown table cell.
+fn escape_cell(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .replace('|', "\\|")
+}
+
+fn collect_comments(node: Node) -> Vec<Node> {
+    let mut comments = Vec::new();
+    let mut stack = vec![node];
+    while let Some(node) = stack.pop() {
+        if node.kind() == "comment" {
+            comments.push(node);
+        }
+        let mut cursor = node.walk();
+        stack.extend(node.children(&mut cursor));
+    }
+    comments.sort_by_key(|comment| comment.start_byte());
+    comments
+}
+
+/// Collects the 1-based lines of the innermost error nodes, so that an ERROR
+/// root wrapping a whole unit reports where parsing actually failed.
+fn collect_error_lines(node: Node, line_index: &LineIndex, lines: &mut Vec<u32>) {
+    let mut cursor = node.walk();
+    let nested: Vec<Node> = node
+        .children(&mut cursor)
+        .filter(|child| child.has_error())
+        .collect();
+    if (node.is_error() && nested.is_empty()) || node.is_missing() {
+        lines.push(line_index.offset_to_position(node.start_byte()).line + 1);
+    }
+    for child in nested {
+        collect_error_lines(child, line_index, lines);
+    }
+}
+
+fn find_child<'a>(node: Node<'a>, kinds: &[&str]) -> Option<Node<'a>> {
+    let mut cursor = node.walk();
+    let child = node
+        .children(&mut cursor)
+        .find(|child| kinds.contains(&child.kind()));
+    child
+}
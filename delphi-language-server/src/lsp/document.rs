@@ -0,0 +1,523 @@
+use crate::lsp::analyzer::{AnalysisMode, AnalysisSnapshot};
+use crate::lsp::text_position::{offset_to_point, LineEnding, LineIndex, PositionEncoding};
+use std::path::Path;
+use std::{fs, io, ops};
+use tower_lsp::lsp_types::*;
+use tree_sitter::{InputEdit, Tree};
+
+/// Code of the diagnostic reporting a document mixing line terminators.
+pub const INCONSISTENT_LINE_ENDINGS: &str = "inconsistent-line-endings";
+
+/// Code of the diagnostic reporting an open document whose file was deleted
+/// or moved away on disk.
+pub const DETACHED_DOCUMENT: &str = "detached-document";
+
+/// The UTF-8 byte order mark some Windows editors write at the start of a
+/// file.
+const BYTE_ORDER_MARK: char = '\u{FEFF}';
+
+/// Strips the byte order mark from the start of `text`. Editors never
+/// count it in positions, so text stored without it yields the same
+/// offsets whether it came from the editor or from disk. Returns whether
+/// there was one.
+pub fn strip_byte_order_mark(text: &mut String) -> bool {
+    let present = text.starts_with(BYTE_ORDER_MARK);
+    if present {
+        text.drain(..BYTE_ORDER_MARK.len_utf8());
+    }
+    present
+}
+
+/// Reads a source file without its byte order mark. Bytes that are not
+/// UTF-8 are replaced when `lossy`, and an `InvalidData` error otherwise.
+pub fn read_source(path: &Path, lossy: bool) -> io::Result<String> {
+    let mut text = read_text(path, lossy)?;
+    strip_byte_order_mark(&mut text);
+    Ok(text)
+}
+
+/// Reads a source file to be rewritten by [`write_source`]: the text
+/// without its byte order mark, and whether there was one. Bytes that are
+/// not UTF-8 are an `InvalidData` error, since replacing them would
+/// corrupt the file once written back.
+pub fn read_source_for_rewrite(path: &Path) -> io::Result<(String, bool)> {
+    let mut text = read_text(path, false)?;
+    let byte_order_mark = strip_byte_order_mark(&mut text);
+    Ok((text, byte_order_mark))
+}
+
+/// Writes `text` back to a file read by [`read_source_for_rewrite`],
+/// restoring its byte order mark. Line endings are written as `text` has
+/// them.
+pub fn write_source(path: &Path, text: &str, byte_order_mark: bool) -> io::Result<()> {
+    let mut bytes = Vec::with_capacity(text.len() + BYTE_ORDER_MARK.len_utf8());
+    if byte_order_mark {
+        bytes.extend_from_slice(BYTE_ORDER_MARK.to_string().as_bytes());
+    }
+    bytes.extend_from_slice(text.as_bytes());
+    fs::write(path, bytes)
+}
+
+fn read_text(path: &Path, lossy: bool) -> io::Result<String> {
+    let bytes = fs::read(path)?;
+    match String::from_utf8(bytes) {
+        Ok(text) => Ok(text),
+        Err(error) if lossy => Ok(String::from_utf8_lossy(error.as_bytes()).into_owned()),
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not valid UTF-8",
+        )),
+    }
+}
+
+/// Slices `text` by the byte range of a syntax node without panicking. A
+/// tree parsed from another version of the text can yield ranges past its
+/// end or inside a UTF-8 sequence; such ranges are clamped to the text and
+/// widened to the nearest character boundaries, and the mismatch is logged
+/// with `context` naming the document. Inverted ranges yield `""`.
+pub fn slice_text(text: &str, range: ops::Range<usize>, context: impl FnOnce() -> String) -> &str {
+    if let Some(slice) = text.get(range.clone()) {
+        return slice;
+    }
+    log::warn!(
+        "Syntax tree out of sync with the text of {}: byte range {:?} does not fit {} bytes",
+        context(),
+        range,
+        text.len()
+    );
+    let mut start = range.start.min(text.len());
+    while !text.is_char_boundary(start) {
+        start -= 1;
+    }
+    let mut end = range.end.min(text.len());
+    while !text.is_char_boundary(end) {
+        end += 1;
+    }
+    text.get(start..end).unwrap_or_default()
+}
+
+/// An open text document together with its line index and syntax tree.
+#[derive(Debug, Clone)]
+pub struct Document {
+    text: String,
+    line_index: LineIndex,
+    version: i32,
+    /// The tree of the last parse, edited along with the text so that the
+    /// next parse can reuse its unchanged nodes. `None` before the first
+    /// parse and after the whole text was replaced.
+    tree: Option<Tree>,
+    /// Whether the text arrived with a byte order mark, which is stripped.
+    byte_order_mark: bool,
+    /// The analysis of the current version, once a request needed it.
+    /// Dropped by every change.
+    analysis: Option<AnalysisSnapshot>,
+    /// How much of the document is analyzed, chosen when it is opened and
+    /// kept across changes.
+    mode: AnalysisMode,
+}
+
+impl Document {
+    pub fn new(mut text: String, version: i32, encoding: PositionEncoding) -> Self {
+        let byte_order_mark = strip_byte_order_mark(&mut text);
+        let line_index = LineIndex::new(&text, encoding);
+        Self {
+            text,
+            line_index,
+            version,
+            tree: None,
+            byte_order_mark,
+            analysis: None,
+            mode: AnalysisMode::default(),
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn byte_order_mark(&self) -> bool {
+        self.byte_order_mark
+    }
+
+    pub fn version(&self) -> i32 {
+        self.version
+    }
+
+    pub fn set_version(&mut self, version: i32) {
+        self.version = version;
+    }
+
+    pub fn line_index(&self) -> &LineIndex {
+        &self.line_index
+    }
+
+    /// The syntax tree, once parsed. After a change it still needs to be
+    /// passed to the parser to match the text.
+    pub fn tree(&self) -> Option<&Tree> {
+        self.tree.as_ref()
+    }
+
+    pub fn set_tree(&mut self, tree: Option<Tree>) {
+        self.tree = tree;
+    }
+
+    pub fn analysis(&self) -> Option<&AnalysisSnapshot> {
+        self.analysis.as_ref()
+    }
+
+    pub fn set_analysis(&mut self, analysis: Option<AnalysisSnapshot>) {
+        self.analysis = analysis;
+    }
+
+    pub fn mode(&self) -> AnalysisMode {
+        self.mode
+    }
+
+    /// Changes how much of the document is analyzed, dropping the analysis.
+    pub fn set_mode(&mut self, mode: AnalysisMode) {
+        self.mode = mode;
+        self.analysis = None;
+    }
+
+    /// Applies a `didChange` content change, editing the syntax tree to
+    /// match. Changes without a range replace the whole text and drop the
+    /// tree.
+    pub fn apply_change(&mut self, range: Option<Range>, text: &str) {
+        self.analysis = None;
+        match range {
+            Some(range) => {
+                let range = self.line_index.range_to_byte_range(range);
+                let start_position = offset_to_point(&self.text, range.start);
+                let old_end_position = offset_to_point(&self.text, range.end);
+                let new_end_byte = range.start + text.len();
+                self.text.replace_range(range.clone(), text);
+                if let Some(tree) = &mut self.tree {
+                    tree.edit(&InputEdit {
+                        start_byte: range.start,
+                        old_end_byte: range.end,
+                        new_end_byte,
+                        start_position,
+                        old_end_position,
+                        new_end_position: offset_to_point(&self.text, new_end_byte),
+                    });
+                }
+            }
+            None => {
+                self.text = text.to_string();
+                self.byte_order_mark = strip_byte_order_mark(&mut self.text);
+                self.tree = None;
+            }
+        }
+        self.line_index = LineIndex::new(&self.text, self.line_index.encoding());
+    }
+
+    /// Reports the first line break that differs from the dominant line
+    /// ending of the document, if any.
+    pub fn inconsistent_line_endings(&self) -> Option<Diagnostic> {
+        let dominant = self.line_index.dominant_line_ending()?;
+        let mut counts = Vec::new();
+        for ending in [LineEnding::CrLf, LineEnding::Lf, LineEnding::Cr] {
+            let count = (0..self.line_index.line_count())
+                .filter(|line| self.line_index.line_ending(*line) == Some(ending))
+                .count();
+            if count > 0 {
+                counts.push(format!("{} {}", count, ending.name()));
+            }
+        }
+        if counts.len() < 2 {
+            return None;
+        }
+
+        let line = (0..self.line_index.line_count()).find(|line| {
+            self.line_index
+                .line_ending(*line)
+                .is_some_and(|ending| ending != dominant)
+        })?;
+        let position = Position {
+            line: line as u32,
+            character: 0,
+        };
+        Some(Diagnostic {
+            range: Range {
+                start: position,
+                end: position,
+            },
+            severity: Some(DiagnosticSeverity::INFORMATION),
+            code: Some(NumberOrString::String(
+                INCONSISTENT_LINE_ENDINGS.to_string(),
+            )),
+            message: format!("Inconsistent line endings: {}", counts.join(", ")),
+            source: Some("dls".to_string()),
+            ..Diagnostic::default()
+        })
+    }
+
+    /// Marks the first line of a document whose file no longer exists on
+    /// disk. Features keep working on the editor buffer.
+    pub fn detached_diagnostic(&self) -> Diagnostic {
+        let end = self.line_index.line_span(0).map_or(0, |span| span.end);
+        Diagnostic {
+            range: self.line_index.byte_range_to_range(0..end),
+            severity: Some(DiagnosticSeverity::INFORMATION),
+            code: Some(NumberOrString::String(DETACHED_DOCUMENT.to_string())),
+            message: "The file was deleted or moved on disk; only the editor buffer remains"
+                .to_string(),
+            source: Some("dls".to_string()),
+            ..Diagnostic::default()
+        }
+    }
+
+    /// Edits replacing every line break that differs from the dominant line
+    /// ending of the document.
+    pub fn normalize_line_endings(&self) -> Vec<TextEdit> {
+        let Some(dominant) = self.line_index.dominant_line_ending() else {
+            return Vec::new();
+        };
+        (0..self.line_index.line_count())
+            .filter_map(|line| {
+                let ending = self.line_index.line_ending(line)?;
+                if ending == dominant {
+                    return None;
+                }
+                let start = self.line_index.line_span(line)?.end;
+                Some(TextEdit {
+                    range: self
+                        .line_index
+                        .byte_range_to_range(start..start + ending.as_str().len()),
+                    new_text: dominant.as_str().to_string(),
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsp::parser::DelphiParser;
+
+    fn document(text: &str) -> Document {
+        Document::new(text.to_string(), 1, PositionEncoding::Utf16)
+    }
+
+    /// The text of `document` after applying `edits`, last first so that
+    /// the ranges of the others stay valid.
+    fn apply_edits(mut document: Document, edits: &[TextEdit]) -> String {
+        for edit in edits.iter().rev() {
+            document.apply_change(Some(edit.range), &edit.new_text);
+        }
+        document.text().to_string()
+    }
+
+    #[test]
+    fn reports_the_first_line_break_off_the_dominant_ending() {
+        let diagnostic = document("a\r\nb\nc\rd\r\ne")
+            .inconsistent_line_endings()
+            .unwrap();
+        assert_eq!(diagnostic.range.start, Position::new(1, 0));
+        assert_eq!(
+            diagnostic.message,
+            "Inconsistent line endings: 2 CRLF, 1 LF, 1 CR"
+        );
+        assert_eq!(
+            diagnostic.code,
+            Some(NumberOrString::String(
+                INCONSISTENT_LINE_ENDINGS.to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn accepts_consistent_line_endings() {
+        for text in ["", "a", "a\nb\n", "a\r\nb\r\n", "a\rb\r"] {
+            assert!(
+                document(text).inconsistent_line_endings().is_none(),
+                "{:?}",
+                text
+            );
+            assert!(
+                document(text).normalize_line_endings().is_empty(),
+                "{:?}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn normalizes_to_the_dominant_line_ending() {
+        let cases = [
+            ("a\r\nb\nc\rd\r\ne", "a\r\nb\r\nc\r\nd\r\ne"),
+            ("a\nb\r\nc\nd\re\n", "a\nb\nc\nd\ne\n"),
+            ("a\rb\rc\nd", "a\rb\rc\rd"),
+            // A lone CR at the end of the text
+            ("a\nb\nc\r", "a\nb\nc\n"),
+        ];
+        for (text, normalized) in cases {
+            let document = document(text);
+            let edits = document.normalize_line_endings();
+            assert_eq!(apply_edits(document, &edits), normalized, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn normalizes_only_the_differing_line_breaks() {
+        let edits = document("a\r\nb\nc\r\nd\r").normalize_line_endings();
+        let ranges: Vec<_> = edits.iter().map(|edit| edit.range).collect();
+        assert_eq!(
+            ranges,
+            [
+                Range::new(Position::new(1, 1), Position::new(2, 0)),
+                Range::new(Position::new(3, 1), Position::new(4, 0)),
+            ]
+        );
+        assert!(edits.iter().all(|edit| edit.new_text == "\r\n"));
+    }
+
+    #[test]
+    fn slices_ranges_off_the_text_without_panicking() {
+        // `é` spans bytes 1..3
+        let text = "x\u{e9}y";
+        assert_eq!(slice_text(text, 0..4, String::new), "x\u{e9}y");
+        assert_eq!(slice_text(text, 2..4, String::new), "\u{e9}y");
+        assert_eq!(slice_text(text, 0..2, String::new), "x\u{e9}");
+        assert_eq!(slice_text(text, 3..40, String::new), "y");
+        assert_eq!(slice_text(text, 10..40, String::new), "");
+        assert_eq!(
+            slice_text(text, ops::Range { start: 3, end: 1 }, String::new),
+            ""
+        );
+    }
+
+    #[test]
+    fn applies_stale_changes_keeping_text_and_tree_in_sync() {
+        let source = "unit U; // \u{1f600}\ninterface\nimplementation\nend.\n";
+        let changes = [
+            // Between the surrogates of the emoji
+            (Range::new(Position::new(0, 12), Position::new(0, 13)), "!"),
+            // Past the end of the text
+            (
+                Range::new(Position::new(9, 0), Position::new(12, 4)),
+                "var X: Integer;\n",
+            ),
+            // Inverted, and past the end of a line
+            (Range::new(Position::new(1, 40), Position::new(0, 2)), ";"),
+        ];
+        let mut parser = DelphiParser::new();
+        let mut document = document(source);
+        document.set_tree(parser.parse(document.text()));
+        for (range, text) in changes {
+            document.apply_change(Some(range), text);
+            let tree = parser.parse_incremental(document.text(), document.tree());
+            let fresh = parser.parse(document.text()).unwrap();
+            let tree = tree.unwrap();
+            assert_eq!(tree.root_node().to_sexp(), fresh.root_node().to_sexp());
+            let mut nodes = vec![tree.root_node()];
+            while let Some(node) = nodes.pop() {
+                assert!(document.text().get(node.byte_range()).is_some());
+                nodes.extend(node.children(&mut node.walk()));
+            }
+            document.set_tree(Some(tree));
+        }
+        assert_eq!(
+            document.text(),
+            "unit U; // !\ninterface;\nimplementation\nend.\nvar X: Integer;\n"
+        );
+    }
+
+    /// A file in the temporary directory, removed on drop.
+    struct TempFile(std::path::PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, bytes: &[u8]) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("dls-document-{}-{}", std::process::id(), name));
+            fs::write(&path, bytes).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn strips_only_a_leading_byte_order_mark() {
+        let mut text = "\u{feff}unit U;".to_string();
+        assert!(strip_byte_order_mark(&mut text));
+        assert_eq!(text, "unit U;");
+        assert!(!strip_byte_order_mark(&mut text));
+        assert_eq!(text, "unit U;");
+
+        let mut text = "unit\u{feff} U;".to_string();
+        assert!(!strip_byte_order_mark(&mut text));
+        assert_eq!(text, "unit\u{feff} U;");
+
+        let mut text = "\u{feff}\u{feff}x".to_string();
+        assert!(strip_byte_order_mark(&mut text));
+        assert_eq!(text, "\u{feff}x");
+    }
+
+    #[test]
+    fn reads_sources_without_the_byte_order_mark() {
+        let file = TempFile::new("read.pas", b"\xef\xbb\xbfunit U;\r\n");
+        assert_eq!(read_source(&file.0, false).unwrap(), "unit U;\r\n");
+        assert_eq!(
+            read_source_for_rewrite(&file.0).unwrap(),
+            ("unit U;\r\n".to_string(), true)
+        );
+
+        let invalid = TempFile::new("invalid.pas", b"\xef\xbb\xbfx := '\xe9';");
+        assert_eq!(read_source(&invalid.0, true).unwrap(), "x := '\u{fffd}';");
+        assert_eq!(
+            read_source(&invalid.0, false).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert!(read_source_for_rewrite(&invalid.0).is_err());
+    }
+
+    #[test]
+    fn round_trips_the_byte_order_mark_through_rewrites() {
+        for bytes in [
+            &b"\xef\xbb\xbfunit U;\r\nend.\r\n"[..],
+            &b"unit U;\nend.\n"[..],
+            &b"\xef\xbb\xbf"[..],
+        ] {
+            let file = TempFile::new("rewrite.pas", bytes);
+            let (text, byte_order_mark) = read_source_for_rewrite(&file.0).unwrap();
+            write_source(&file.0, &text, byte_order_mark).unwrap();
+            assert_eq!(fs::read(&file.0).unwrap(), bytes);
+
+            write_source(&file.0, &text.replace("unit", "program"), byte_order_mark).unwrap();
+            let (rewritten, kept) = read_source_for_rewrite(&file.0).unwrap();
+            assert_eq!(kept, byte_order_mark);
+            assert_eq!(rewritten, text.replace("unit", "program"));
+        }
+    }
+
+    #[test]
+    fn counts_positions_after_the_byte_order_mark() {
+        let mut document = document("\u{feff}unit U;\nend.");
+        assert!(document.byte_order_mark());
+        assert_eq!(document.text(), "unit U;\nend.");
+        assert_eq!(
+            document
+                .line_index()
+                .position_to_offset(Position::new(0, 5)),
+            5
+        );
+
+        let mut parser = DelphiParser::new();
+        let tree = parser.parse(document.text()).unwrap();
+        assert!(parser
+            .get_diagnostics(&tree, document.text(), document.line_index())
+            .is_empty());
+
+        document.apply_change(None, "unit V;\nend.");
+        assert!(!document.byte_order_mark());
+        document.apply_change(None, "\u{feff}unit W;\nend.");
+        assert!(document.byte_order_mark());
+        assert_eq!(document.text(), "unit W;\nend.");
+    }
+}
@@ -0,0 +1,273 @@
+use crate::lsp::characters::{self, AMBIGUOUS_CHARACTER};
+use crate::lsp::directives::Dialect;
+use crate::lsp::document::{slice_text, Document, INCONSISTENT_LINE_ENDINGS};
+use crate::lsp::keywords::is_reserved_word;
+use crate::lsp::text_position::{LineEnding, LineIndex};
+use serde::Deserialize;
+use std::cmp::Reverse;
+use std::ops;
+use tower_lsp::lsp_types::{Diagnostic, NumberOrString, TextEdit};
+use tree_sitter::Node;
+
+/// Codes of the diagnostics whose quick fix depends on the text alone and
+/// never changes what the code means, so that `--check --fix` applies it
+/// to many files unattended.
+pub const BATCH_SAFE_CODES: &[&str] = &[INCONSISTENT_LINE_ENDINGS, AMBIGUOUS_CHARACTER];
+
+/// Drops the uses clause entries of units none of whose interface names
+/// the document refers to.
+pub const UNUSED_USES: &str = "unused-uses";
+/// Sorts uses clauses by unit name.
+pub const SORT_USES: &str = "sort-uses";
+/// Spells reserved words as `fixAll.keywordCase` asks.
+pub const KEYWORD_CASE: &str = "keyword-case";
+/// Strips the spaces and tabs at the end of lines.
+pub const TRAILING_WHITESPACE: &str = "trailing-whitespace";
+/// Ends the last line with the dominant line ending.
+pub const FINAL_NEWLINE: &str = "final-newline";
+
+/// The rules `--fix` applies to whole documents rather than to
+/// diagnostics, in the order their edits take precedence.
+pub const DOCUMENT_RULES: &[&str] = &[
+    UNUSED_USES,
+    SORT_USES,
+    KEYWORD_CASE,
+    TRAILING_WHITESPACE,
+    FINAL_NEWLINE,
+];
+
+/// The quick fix of a diagnostic in [`BATCH_SAFE_CODES`], or an edit of a
+/// rule in [`DOCUMENT_RULES`].
+#[derive(Debug, Clone)]
+pub struct MechanicalFix {
+    pub code: &'static str,
+    pub title: String,
+    pub edits: Vec<TextEdit>,
+}
+
+/// The fix of `diagnostic`, `None` when its code is not batch safe or
+/// the diagnostic no longer matches the text.
+pub fn mechanical_fix(document: &Document, diagnostic: &Diagnostic) -> Option<MechanicalFix> {
+    let code = match &diagnostic.code {
+        Some(NumberOrString::String(code)) => code.as_str(),
+        _ => return None,
+    };
+    if code == INCONSISTENT_LINE_ENDINGS {
+        let ending = document.line_index().dominant_line_ending()?;
+        Some(MechanicalFix {
+            code: INCONSISTENT_LINE_ENDINGS,
+            title: format!("Normalize line endings to {}", ending.name()),
+            edits: document.normalize_line_endings(),
+        })
+    } else if code == AMBIGUOUS_CHARACTER {
+        let range = document.line_index().range_to_byte_range(diagnostic.range);
+        let c = document.text()[range].chars().next()?;
+        Some(MechanicalFix {
+            code: AMBIGUOUS_CHARACTER,
+            title: characters::fix_title(c)?,
+            edits: vec![TextEdit::new(
+                diagnostic.range,
+                characters::ascii_equivalent(c)?.to_string(),
+            )],
+        })
+    } else {
+        None
+    }
+}
+
+/// The text after applying `fixes`, and the fixes applied. A fix with an
+/// edit overlapping one of an earlier fix is left out whole, and the edits
+/// kept are applied from the end of the text backwards so that each one
+/// leaves the offsets of the others valid.
+pub fn apply_fixes(
+    text: &str,
+    line_index: &LineIndex,
+    fixes: Vec<MechanicalFix>,
+) -> (String, Vec<MechanicalFix>) {
+    let mut accepted: Vec<ops::Range<usize>> = Vec::new();
+    let mut edits: Vec<(ops::Range<usize>, String)> = Vec::new();
+    let mut applied = Vec::new();
+    for fix in fixes {
+        let ranges: Vec<ops::Range<usize>> = fix
+            .edits
+            .iter()
+            .map(|edit| line_index.range_to_byte_range(edit.range))
+            .collect();
+        if conflicts(&ranges, &accepted) {
+            continue;
+        }
+        for (range, edit) in ranges.iter().zip(&fix.edits) {
+            edits.push((range.clone(), edit.new_text.clone()));
+        }
+        accepted.extend(ranges);
+        applied.push(fix);
+    }
+
+    edits.sort_by_key(|(range, _)| Reverse((range.start, range.end)));
+    let mut fixed = text.to_string();
+    for (range, new_text) in edits {
+        fixed.replace_range(range, &new_text);
+    }
+    (fixed, applied)
+}
+
+/// The edits of `edits` that can be returned together, in document order.
+/// The edits are given by priority: one overlapping an edit kept before it
+/// is left out.
+pub fn combine_edits(line_index: &LineIndex, edits: Vec<TextEdit>) -> Vec<TextEdit> {
+    let mut accepted: Vec<ops::Range<usize>> = Vec::new();
+    let mut combined: Vec<(ops::Range<usize>, TextEdit)> = Vec::new();
+    for edit in edits {
+        let range = line_index.range_to_byte_range(edit.range);
+        if conflicts(std::slice::from_ref(&range), &accepted) {
+            continue;
+        }
+        accepted.push(range.clone());
+        combined.push((range, edit));
+    }
+    combined.sort_by_key(|(range, _)| (range.start, range.end));
+    combined.into_iter().map(|(_, edit)| edit).collect()
+}
+
+/// Whether one of `ranges` overlaps one of `accepted`. Two insertions at
+/// the same offset conflict too, since their order would be arbitrary.
+fn conflicts(ranges: &[ops::Range<usize>], accepted: &[ops::Range<usize>]) -> bool {
+    ranges.iter().any(|range| {
+        accepted
+            .iter()
+            .any(|other| overlap(range, other) || (range.is_empty() && range == other))
+    })
+}
+
+fn overlap(a: &ops::Range<usize>, b: &ops::Range<usize>) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
+/// How `fixAll.keywordCase` spells reserved words.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum KeywordCase {
+    /// Leave reserved words as written.
+    Preserve,
+    #[default]
+    Lower,
+    Upper,
+}
+
+/// Edits inserting the semicolons the parser found missing, right after
+/// the previous token. Semicolons missing next to a syntax error are left
+/// out, since where the statement ends is then a guess.
+pub fn missing_semicolons(source: &str, line_index: &LineIndex, root: Node) -> Vec<TextEdit> {
+    let mut edits = Vec::new();
+    let mut stack = vec![root];
+    while let Some(node) = stack.pop() {
+        if node.is_missing() && node.kind() == ";" && !near_error(node) {
+            let offset = source
+                .get(..node.start_byte())
+                .map_or(node.start_byte(), |before| before.trim_end().len());
+            edits.push(TextEdit {
+                range: line_index.byte_range_to_range(offset..offset),
+                new_text: ";".to_string(),
+            });
+        }
+        if node.has_error() {
+            let mut cursor = node.walk();
+            stack.extend(node.children(&mut cursor));
+        }
+    }
+    edits
+}
+
+/// Whether `node` lies in an ERROR node or next to one.
+fn near_error(node: Node) -> bool {
+    let mut ancestor = node.parent();
+    while let Some(parent) = ancestor {
+        if parent.is_error() {
+            return true;
+        }
+        ancestor = parent.parent();
+    }
+    node.parent().is_some_and(|parent| {
+        let mut cursor = parent.walk();
+        let mut siblings = parent.children(&mut cursor);
+        siblings.any(|sibling| sibling.is_error())
+    })
+}
+
+/// Edits spelling the reserved words of the document in `case`. Directives
+/// such as `override`, which are identifiers elsewhere, are left alone.
+pub fn keyword_case(
+    source: &str,
+    line_index: &LineIndex,
+    root: Node,
+    case: KeywordCase,
+    dialect: Dialect,
+) -> Vec<TextEdit> {
+    let mut edits = Vec::new();
+    if case == KeywordCase::Preserve {
+        return edits;
+    }
+    let mut stack = vec![root];
+    while let Some(node) = stack.pop() {
+        let is_keyword = node.kind().starts_with('k')
+            && node.kind()[1..].starts_with(|c: char| c.is_ascii_uppercase());
+        if is_keyword && node.child_count() == 0 && !node.is_missing() {
+            let text = slice_text(source, node.byte_range(), || {
+                "the fixed document".to_string()
+            });
+            let spelled = match case {
+                KeywordCase::Lower => text.to_ascii_lowercase(),
+                _ => text.to_ascii_uppercase(),
+            };
+            if spelled != text && is_reserved_word(text, dialect) {
+                edits.push(TextEdit {
+                    range: line_index.byte_range_to_range(node.byte_range()),
+                    new_text: spelled,
+                });
+            }
+        }
+        let mut cursor = node.walk();
+        stack.extend(node.children(&mut cursor));
+    }
+    edits
+}
+
+/// Edits removing the spaces and tabs at the end of lines, except inside
+/// string literals spanning lines.
+pub fn trailing_whitespace(source: &str, line_index: &LineIndex, root: Node) -> Vec<TextEdit> {
+    (0..line_index.line_count())
+        .filter_map(|line| {
+            let span = line_index.line_span(line)?;
+            let end = span.start + source[span.clone()].trim_end_matches([' ', '\t']).len();
+            if end == span.end {
+                return None;
+            }
+            let in_string = root
+                .descendant_for_byte_range(end, end + 1)
+                .is_some_and(|node| {
+                    node.kind() == "literalString"
+                        && node.start_byte() <= end
+                        && span.end < node.end_byte()
+                });
+            (!in_string).then(|| TextEdit {
+                range: line_index.byte_range_to_range(end..span.end),
+                new_text: String::new(),
+            })
+        })
+        .collect()
+}
+
+/// The edit ending the last line of `source` with the dominant line
+/// ending, LF when there is none. `None` when the source is empty or
+/// already ends with a line ending.
+pub fn final_newline(source: &str, line_index: &LineIndex) -> Option<TextEdit> {
+    if source.is_empty() || source.ends_with(['\n', '\r']) {
+        return None;
+    }
+    let ending = line_index.dominant_line_ending().unwrap_or(LineEnding::Lf);
+    let end = source.len();
+    Some(TextEdit {
+        range: line_index.byte_range_to_range(end..end),
+        new_text: ending.as_str().to_string(),
+    })
+}
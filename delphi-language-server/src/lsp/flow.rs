@@ -0,0 +1,867 @@
+use crate::lsp::document::slice_text;
+use crate::lsp::keywords::unescape_identifier;
+use std::collections::{HashMap, HashSet};
+use tree_sitter::Node;
+
+/// Code of the diagnostic reporting a read of a local variable that some
+/// path reaches before any assignment.
+pub const UNINITIALIZED_VARIABLE: &str = "uninitialized-variable";
+
+/// Types whose variables Delphi initializes to empty, lowercase.
+const MANAGED_TYPES: &[&str] = &[
+    "string",
+    "ansistring",
+    "unicodestring",
+    "widestring",
+    "rawbytestring",
+    "utf8string",
+    "variant",
+    "olevariant",
+    "tbytes",
+    "tarray",
+    "tproc",
+    "tfunc",
+    "tpredicate",
+];
+
+/// Routines and typecasts reading every argument, lowercase. Other calls
+/// outside the unit may take a variable by reference to assign it, so
+/// their arguments count as assignments.
+const VALUE_ROUTINES: &[&str] = &[
+    "abs",
+    "assert",
+    "assigned",
+    "boolean",
+    "byte",
+    "cardinal",
+    "char",
+    "chr",
+    "copy",
+    "dec",
+    "exit",
+    "floattostr",
+    "format",
+    "inc",
+    "int64",
+    "integer",
+    "inttostr",
+    "length",
+    "lowercase",
+    "max",
+    "min",
+    "odd",
+    "ord",
+    "pointer",
+    "pos",
+    "pred",
+    "round",
+    "sqr",
+    "sqrt",
+    "succ",
+    "trim",
+    "trunc",
+    "uppercase",
+    "word",
+    "write",
+    "writeln",
+];
+
+/// Compiler intrinsics looking only at the type of their argument.
+const TYPE_ROUTINES: &[&str] = &["default", "high", "low", "sizeof", "typeinfo", "typeof"];
+
+/// Routines leaving the statements after them unreachable.
+const JUMPS: &[&str] = &["abort", "break", "continue", "exit", "halt"];
+
+/// A read of a local variable that some path reaches before any
+/// assignment.
+#[derive(Debug, Clone)]
+pub struct UninitializedRead<'tree> {
+    /// The name in the declaration of the variable.
+    pub declaration: Node<'tree>,
+    /// The first such read of the variable.
+    pub read: Node<'tree>,
+}
+
+/// The reads of the local variables of the routines under `root` that a
+/// path reaches before the variable is definitely assigned. The pass is
+/// conservative: loops may run zero times, calls outside the unit may
+/// assign the variables they are given, and routines using `goto`, `asm`,
+/// nested `try` statements or not parsing cleanly are skipped. Variables
+/// of managed types, which Delphi initializes, are left out unless
+/// `managed_types`.
+pub fn uninitialized_reads<'tree>(
+    root: Node<'tree>,
+    source: &str,
+    managed_types: bool,
+) -> Vec<UninitializedRead<'tree>> {
+    let unit = UnitInfo::collect(root, source);
+    let mut routines = Vec::new();
+    collect_routines(root, &mut routines);
+    routines
+        .into_iter()
+        .flat_map(|routine| RoutineFlow::analyze(routine, source, &unit, managed_types))
+        .collect()
+}
+
+fn collect_routines<'tree>(node: Node<'tree>, routines: &mut Vec<Node<'tree>>) {
+    if node.kind() == "defProc" {
+        routines.push(node);
+    }
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        collect_routines(child, routines);
+    }
+}
+
+fn name_of(source: &str, node: Node) -> String {
+    let text = slice_text(source, node.byte_range(), || "an identifier".to_string());
+    unescape_identifier(text).to_lowercase()
+}
+
+/// How a routine takes an argument.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArgumentMode {
+    Value,
+    /// `var` or `out`, which the routine may assign.
+    Reference,
+}
+
+/// What the flow pass needs to know of the unit around a routine.
+#[derive(Default)]
+struct UnitInfo {
+    /// The argument modes of the routines declared in the unit, by
+    /// lowercase name; `None` when overloads disagree.
+    signatures: HashMap<String, Option<Vec<ArgumentMode>>>,
+    /// The types declared in the unit whose variables are managed.
+    managed_types: HashSet<String>,
+}
+
+impl UnitInfo {
+    fn collect(root: Node, source: &str) -> Self {
+        let mut unit = UnitInfo::default();
+        let mut declarations = Vec::new();
+        unit.collect_declarations(root, source, &mut declarations);
+        // Twice, for aliases of managed types declared before them
+        for _ in 0..2 {
+            for (name, declared) in &declarations {
+                if unit.is_managed(*declared, source) {
+                    unit.managed_types.insert(name.clone());
+                }
+            }
+        }
+        unit
+    }
+
+    fn collect_declarations<'tree>(
+        &mut self,
+        node: Node<'tree>,
+        source: &str,
+        types: &mut Vec<(String, Node<'tree>)>,
+    ) {
+        match node.kind() {
+            "declProc" => {
+                if let Some(name) = node.child_by_field_name("name") {
+                    let modes = argument_modes(node);
+                    self.signatures
+                        .entry(name_of(source, name))
+                        .and_modify(|known| {
+                            if known.as_ref() != Some(&modes) {
+                                *known = None;
+                            }
+                        })
+                        .or_insert(Some(modes));
+                }
+            }
+            "declType" => {
+                if let (Some(name), Some(declared)) = (
+                    node.child_by_field_name("name"),
+                    node.child_by_field_name("type"),
+                ) {
+                    types.push((name_of(source, name), declared));
+                }
+            }
+            _ => {}
+        }
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            self.collect_declarations(child, source, types);
+        }
+    }
+
+    /// Whether variables of the type `node` are managed: strings,
+    /// interfaces, dynamic arrays, variants and anonymous method types.
+    /// Interfaces declared elsewhere are recognized by their `I` prefix.
+    fn is_managed(&self, node: Node, source: &str) -> bool {
+        match node.kind() {
+            "type" => node
+                .named_child(0)
+                .is_some_and(|declared| self.is_managed(declared, source)),
+            "declIntf" | "declProcRef" => true,
+            // `string[20]` is a short string
+            "declString" => node.named_child_count() == 1,
+            // Static arrays have an index range or type before `of`
+            "declArray" => {
+                let mut cursor = node.walk();
+                let indexed = node
+                    .named_children(&mut cursor)
+                    .take_while(|child| child.kind() != "kOf")
+                    .any(|child| child.kind() != "kArray");
+                !indexed
+            }
+            "typeref" => {
+                let entity = node.named_child(0).map(|entity| match entity.kind() {
+                    "typerefTpl" => entity.child_by_field_name("entity").unwrap_or(entity),
+                    _ => entity,
+                });
+                entity.is_some_and(|entity| {
+                    if entity.kind() != "identifier" {
+                        return false;
+                    }
+                    let text = slice_text(source, entity.byte_range(), || "a type".to_string());
+                    let name = name_of(source, entity);
+                    let mut chars = text.chars();
+                    let interface_like = chars.next() == Some('I')
+                        && chars.next().is_some_and(|c| c.is_ascii_uppercase());
+                    interface_like
+                        || MANAGED_TYPES.contains(&name.as_str())
+                        || self.managed_types.contains(&name)
+                })
+            }
+            _ => false,
+        }
+    }
+}
+
+fn argument_modes(declaration: Node) -> Vec<ArgumentMode> {
+    let mut modes = Vec::new();
+    let Some(arguments) = declaration.child_by_field_name("args") else {
+        return modes;
+    };
+    let mut cursor = arguments.walk();
+    for argument in arguments.named_children(&mut cursor) {
+        if argument.kind() != "declArg" {
+            continue;
+        }
+        let mut argument_cursor = argument.walk();
+        let mode = match argument
+            .children(&mut argument_cursor)
+            .any(|child| matches!(child.kind(), "kVar" | "kOut"))
+        {
+            true => ArgumentMode::Reference,
+            false => ArgumentMode::Value,
+        };
+        let mut names_cursor = argument.walk();
+        let names = argument.children_by_field_name("name", &mut names_cursor);
+        modes.extend(names.map(|_| mode));
+    }
+    modes
+}
+
+/// The local variables definitely assigned at a point of a routine, by
+/// index; `None` where no path reaches.
+type State = Option<Vec<bool>>;
+
+/// The common part of two paths joining.
+fn join(a: State, b: State) -> State {
+    match (a, b) {
+        (None, state) | (state, None) => state,
+        (Some(a), Some(b)) => Some(a.iter().zip(&b).map(|(a, b)| *a && *b).collect()),
+    }
+}
+
+/// The part of two paths both taken, one after the other, such as a `try`
+/// block and its `finally` block.
+fn combine(a: State, b: State) -> State {
+    Some(a?.iter().zip(&b?).map(|(a, b)| *a || *b).collect())
+}
+
+/// Whether a loop statement collects the paths leaving it through `Break`
+/// and `Continue`, which only matters for `repeat`, whose body runs at
+/// least once.
+struct LoopFrame {
+    exits: Option<Vec<State>>,
+}
+
+/// The flow pass over the body of one routine.
+struct RoutineFlow<'a, 'tree> {
+    source: &'a str,
+    unit: &'a UnitInfo,
+    /// The declarations of the variables tracked, by index.
+    locals: Vec<Node<'tree>>,
+    by_name: HashMap<String, usize>,
+    reported: Vec<bool>,
+    reads: Vec<UninitializedRead<'tree>>,
+    loops: Vec<LoopFrame>,
+    /// The `with` statements around the current statement, whose bodies
+    /// may name members rather than variables.
+    with_depth: usize,
+    try_depth: usize,
+    /// Passes computing the state leaving a loop report nothing.
+    silent: usize,
+    /// Set on flow the pass does not model.
+    bail: bool,
+}
+
+impl<'a, 'tree> RoutineFlow<'a, 'tree> {
+    fn analyze(
+        routine: Node<'tree>,
+        source: &'a str,
+        unit: &'a UnitInfo,
+        managed_types: bool,
+    ) -> Vec<UninitializedRead<'tree>> {
+        let Some(body) = routine.child_by_field_name("body") else {
+            return Vec::new();
+        };
+        if body.kind() != "block" || routine.has_error() {
+            return Vec::new();
+        }
+        let mut flow = RoutineFlow {
+            source,
+            unit,
+            locals: Vec::new(),
+            by_name: HashMap::new(),
+            reported: Vec::new(),
+            reads: Vec::new(),
+            loops: Vec::new(),
+            with_depth: 0,
+            try_depth: 0,
+            silent: 0,
+            bail: false,
+        };
+        let mut untracked = HashSet::new();
+        let mut cursor = routine.walk();
+        for local in routine.children_by_field_name("local", &mut cursor) {
+            match local.kind() {
+                "declVars" => flow.declare_locals(local, managed_types, &mut untracked),
+                "declLabels" => return Vec::new(),
+                // Nested routines may assign the variables they name at
+                // any call
+                "defProc" => collect_names(local, source, &mut untracked),
+                _ => {}
+            }
+        }
+        flow.declare_inline(body, managed_types, &mut untracked);
+        for name in &untracked {
+            flow.by_name.remove(name);
+        }
+        if flow.by_name.is_empty() {
+            return Vec::new();
+        }
+        flow.reported = vec![false; flow.locals.len()];
+        flow.statement(body, Some(vec![false; flow.locals.len()]));
+        match flow.bail {
+            true => Vec::new(),
+            false => flow.reads,
+        }
+    }
+
+    fn track(&mut self, name: Node<'tree>) {
+        self.by_name
+            .insert(name_of(self.source, name), self.locals.len());
+        self.locals.push(name);
+    }
+
+    fn declare_locals(
+        &mut self,
+        declarations: Node<'tree>,
+        managed_types: bool,
+        untracked: &mut HashSet<String>,
+    ) {
+        let mut cursor = declarations.walk();
+        for declaration in declarations.named_children(&mut cursor) {
+            if declaration.kind() != "declVar" {
+                continue;
+            }
+            let mut names_cursor = declaration.walk();
+            let names: Vec<Node> = declaration
+                .children_by_field_name("name", &mut names_cursor)
+                .collect();
+            let mut children_cursor = declaration.walk();
+            let absolute = declaration
+                .children(&mut children_cursor)
+                .any(|child| child.kind() == "kAbsolute");
+            // A variable overlaying another is assigned through either
+            if absolute {
+                collect_names(declaration, self.source, untracked);
+                continue;
+            }
+            let managed = declaration
+                .child_by_field_name("type")
+                .is_some_and(|declared| self.unit.is_managed(declared, self.source));
+            let initialized = declaration.child_by_field_name("defaultValue").is_some();
+            if (managed && !managed_types) || initialized {
+                continue;
+            }
+            for name in names {
+                self.track(name);
+            }
+        }
+    }
+
+    /// Tracks the inline variables declared without a value under `node`,
+    /// and leaves out those anonymous methods capture, which may be
+    /// assigned whenever they run.
+    fn declare_inline(
+        &mut self,
+        node: Node<'tree>,
+        managed_types: bool,
+        untracked: &mut HashSet<String>,
+    ) {
+        match node.kind() {
+            "lambda" => {
+                collect_names(node, self.source, untracked);
+                return;
+            }
+            "varDef" => {
+                let managed = node
+                    .child_by_field_name("type")
+                    .is_some_and(|declared| self.unit.is_managed(declared, self.source));
+                if !managed || managed_types {
+                    let mut cursor = node.walk();
+                    let names: Vec<Node> = node
+                        .named_children(&mut cursor)
+                        .filter(|child| child.kind() == "identifier")
+                        .collect();
+                    for name in names {
+                        self.track(name);
+                    }
+                }
+                return;
+            }
+            _ => {}
+        }
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            self.declare_inline(child, managed_types, untracked);
+        }
+    }
+
+    fn local(&self, node: Node) -> Option<usize> {
+        match node.kind() {
+            "identifier" => self.by_name.get(&name_of(self.source, node)).copied(),
+            _ => None,
+        }
+    }
+
+    fn assign(&self, node: Node, state: &mut [bool]) {
+        if let Some(index) = self.local(node) {
+            state[index] = true;
+        }
+    }
+
+    fn read(&mut self, node: Node<'tree>, state: &[bool]) {
+        let Some(index) = self.local(node) else {
+            return;
+        };
+        if state[index] || self.reported[index] || self.with_depth > 0 || self.silent > 0 {
+            return;
+        }
+        self.reported[index] = true;
+        self.reads.push(UninitializedRead {
+            declaration: self.locals[index],
+            read: node,
+        });
+    }
+
+    /// The state after `node` runs from `state`.
+    fn statement(&mut self, node: Node<'tree>, state: State) -> State {
+        let mut current = state?;
+        if self.bail {
+            return None;
+        }
+        match node.kind() {
+            "block" | "statements" => {
+                let mut state = Some(current);
+                let mut cursor = node.walk();
+                for child in node.named_children(&mut cursor) {
+                    state = self.statement(child, state);
+                }
+                state
+            }
+            "statement" => {
+                let Some(expression) = node.named_child(0) else {
+                    return Some(current);
+                };
+                let (callee, arguments) = match expression.kind() {
+                    "identifier" => (Some(expression), None),
+                    "exprCall" => (
+                        expression.child_by_field_name("entity"),
+                        expression.child_by_field_name("args"),
+                    ),
+                    _ => (None, None),
+                };
+                let jump = callee
+                    .filter(|callee| callee.kind() == "identifier")
+                    .map(|callee| name_of(self.source, callee))
+                    .filter(|name| JUMPS.contains(&name.as_str()));
+                let Some(jump) = jump else {
+                    self.expression(expression, &mut current);
+                    return Some(current);
+                };
+                if let Some(arguments) = arguments {
+                    self.expression(arguments, &mut current);
+                }
+                if matches!(jump.as_str(), "break" | "continue") {
+                    if let Some(exits) =
+                        self.loops.last_mut().and_then(|frame| frame.exits.as_mut())
+                    {
+                        exits.push(Some(current));
+                    }
+                }
+                None
+            }
+            "assignment" => {
+                let target = node.child_by_field_name("lhs")?;
+                let compound = node
+                    .child_by_field_name("operator")
+                    .is_some_and(|operator| operator.kind() != "kAssign");
+                if compound {
+                    self.expression(target, &mut current);
+                }
+                if let Some(value) = node.child_by_field_name("rhs") {
+                    self.expression(value, &mut current);
+                }
+                self.target(target, &mut current);
+                Some(current)
+            }
+            "if" | "ifElse" => {
+                if let Some(condition) = node.child_by_field_name("condition") {
+                    self.expression(condition, &mut current);
+                }
+                let branch = |flow: &mut Self, field: &str| match node.child_by_field_name(field) {
+                    Some(branch) => flow.statement(branch, Some(current.clone())),
+                    None => Some(current.clone()),
+                };
+                let then = branch(self, "then");
+                let otherwise = branch(self, "else");
+                join(then, otherwise)
+            }
+            "case" => self.case(node, current),
+            "while" => {
+                if let Some(condition) = node.child_by_field_name("condition") {
+                    self.expression(condition, &mut current);
+                }
+                self.loop_body(node, &current);
+                Some(current)
+            }
+            "for" | "foreach" => {
+                if let Some(start) = node.child_by_field_name("start") {
+                    current = self.statement(start, Some(current))?;
+                }
+                for field in ["end", "iterable"] {
+                    if let Some(bound) = node.child_by_field_name(field) {
+                        self.expression(bound, &mut current);
+                    }
+                }
+                if let Some(iterator) = node.child_by_field_name("iterator") {
+                    self.target(iterator, &mut current);
+                }
+                self.loop_body(node, &current);
+                Some(current)
+            }
+            "repeat" => self.repeat(node, current),
+            "try" => self.try_statement(node, current),
+            "with" => {
+                if let Some(entity) = node.child_by_field_name("entity") {
+                    self.expression(entity, &mut current);
+                }
+                self.with_depth += 1;
+                let after = match node.child_by_field_name("body") {
+                    Some(body) => self.statement(body, Some(current)),
+                    None => Some(current),
+                };
+                self.with_depth -= 1;
+                after
+            }
+            "raise" => {
+                if let Some(exception) = node.child_by_field_name("exception") {
+                    self.expression(exception, &mut current);
+                }
+                None
+            }
+            // Tracked from the start of the routine, unassigned
+            "varDef" | "comment" | "pp" | ";" => Some(current),
+            kind if kind.starts_with('k') => Some(current),
+            _ => {
+                self.bail = true;
+                None
+            }
+        }
+    }
+
+    fn case(&mut self, node: Node<'tree>, mut current: Vec<bool>) -> State {
+        let mut cursor = node.walk();
+        let children: Vec<Node> = node.children(&mut cursor).collect();
+        if let Some(selector) = children.iter().find(|child| {
+            child.is_named() && !child.kind().starts_with('k') && child.kind() != "caseCase"
+        }) {
+            self.expression(*selector, &mut current);
+        }
+        let mut after = None;
+        for branch in children.iter().filter(|child| child.kind() == "caseCase") {
+            let end = match branch.child_by_field_name("body") {
+                Some(body) => self.statement(body, Some(current.clone())),
+                None => Some(current.clone()),
+            };
+            after = join(after, end);
+        }
+        let otherwise = children
+            .iter()
+            .position(|child| child.kind() == "kElse")
+            .map(|start| {
+                let mut state = Some(current.clone());
+                for statement in &children[start + 1..] {
+                    if statement.kind() != "kEnd" && statement.is_named() {
+                        state = self.statement(*statement, state);
+                    }
+                }
+                state
+            });
+        join(after, otherwise.unwrap_or(Some(current)))
+    }
+
+    /// Runs the body of a loop that may run zero times, with the variables
+    /// it assigns taken as assigned by a previous iteration.
+    fn loop_body(&mut self, node: Node<'tree>, current: &[bool]) {
+        let Some(body) = node.child_by_field_name("body") else {
+            return;
+        };
+        let mut carried = current.to_vec();
+        self.assigned_in(node, &mut carried);
+        self.loops.push(LoopFrame { exits: None });
+        self.statement(body, Some(carried));
+        self.loops.pop();
+    }
+
+    /// A `repeat` body runs at least once: the state leaving the loop is
+    /// that of its end or of a `Break` or `Continue`, computed in a silent
+    /// pass without what later iterations assign.
+    fn repeat(&mut self, node: Node<'tree>, current: Vec<bool>) -> State {
+        let body = node.child_by_field_name("body");
+        let condition = node.child_by_field_name("condition");
+        let mut carried = current.clone();
+        self.assigned_in(node, &mut carried);
+        self.loops.push(LoopFrame { exits: None });
+        let end = match body {
+            Some(body) => self.statement(body, Some(carried)),
+            None => Some(carried),
+        };
+        self.loops.pop();
+        if let (Some(condition), Some(mut end)) = (condition, end) {
+            self.expression(condition, &mut end);
+        }
+
+        self.silent += 1;
+        self.loops.push(LoopFrame {
+            exits: Some(Vec::new()),
+        });
+        let end = match body {
+            Some(body) => self.statement(body, Some(current)),
+            None => Some(current),
+        };
+        let exits = self.loops.pop().and_then(|frame| frame.exits);
+        self.silent -= 1;
+        let mut after = exits.into_iter().flatten().fold(end, join)?;
+        if let Some(condition) = condition {
+            self.expression(condition, &mut after);
+        }
+        Some(after)
+    }
+
+    /// The handlers of `except` may run after any statement of the `try`
+    /// block, and so does `finally`, which the pass checks from the state
+    /// entering the block.
+    fn try_statement(&mut self, node: Node<'tree>, current: Vec<bool>) -> State {
+        if self.try_depth > 0 {
+            self.bail = true;
+            return None;
+        }
+        self.try_depth += 1;
+        let guarded = match node.child_by_field_name("try") {
+            Some(block) => self.statement(block, Some(current.clone())),
+            None => Some(current.clone()),
+        };
+        let mut after = guarded.clone();
+        let mut cursor = node.walk();
+        for handler in node.children_by_field_name("except", &mut cursor) {
+            let end = match handler.kind() {
+                "statements" => self.statement(handler, Some(current.clone())),
+                "exceptionHandler" => match handler.child_by_field_name("body") {
+                    Some(body) => self.statement(body, Some(current.clone())),
+                    None => Some(current.clone()),
+                },
+                "exceptionElse" => {
+                    let mut state = Some(current.clone());
+                    let mut statements_cursor = handler.walk();
+                    for statement in handler.named_children(&mut statements_cursor) {
+                        state = self.statement(statement, state);
+                    }
+                    state
+                }
+                _ => continue,
+            };
+            after = join(after, end);
+        }
+        let mut cursor = node.walk();
+        let finally = node
+            .children_by_field_name("finally", &mut cursor)
+            .find(|child| child.kind() == "statements");
+        if let Some(finally) = finally {
+            let end = self.statement(finally, Some(current));
+            after = combine(guarded, end);
+        }
+        self.try_depth -= 1;
+        after
+    }
+
+    /// Marks the variables assigned anywhere under `node`, for the reads of
+    /// a loop body following them in a previous iteration.
+    fn assigned_in(&self, node: Node, state: &mut [bool]) {
+        match node.kind() {
+            "assignment" => {
+                if let Some(target) = node.child_by_field_name("lhs") {
+                    self.assign(root_variable(target), state);
+                }
+            }
+            "foreach" => {
+                if let Some(iterator) = node.child_by_field_name("iterator") {
+                    self.assign(root_variable(iterator), state);
+                }
+            }
+            // Arguments possibly passed by reference
+            "exprArgs" => {
+                let mut cursor = node.walk();
+                for argument in node.named_children(&mut cursor) {
+                    self.assign(root_variable(argument), state);
+                }
+            }
+            "exprUnary" => self.assign(root_variable(node), state),
+            _ => {}
+        }
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            self.assigned_in(child, state);
+        }
+    }
+
+    /// Assigns the variable `node` stores to, reading what it needs to get
+    /// there, such as indexes.
+    fn target(&mut self, node: Node<'tree>, state: &mut [bool]) {
+        match node.kind() {
+            "identifier" => self.assign(node, state),
+            // Assigning a field counts as assigning the record
+            "exprDot" => match node.child_by_field_name("lhs") {
+                Some(record) if record.kind() != "exprCall" => self.target(record, state),
+                Some(record) => self.expression(record, state),
+                None => {}
+            },
+            "exprSubscript" => {
+                if let Some(entity) = node.child_by_field_name("entity") {
+                    self.target(entity, state);
+                }
+                if let Some(arguments) = node.child_by_field_name("args") {
+                    self.expression(arguments, state);
+                }
+            }
+            _ => self.expression(node, state),
+        }
+    }
+
+    fn expression(&mut self, node: Node<'tree>, state: &mut [bool]) {
+        match node.kind() {
+            "identifier" => self.read(node, state),
+            // The right-hand side names a member
+            "exprDot" => {
+                if let Some(object) = node.child_by_field_name("lhs") {
+                    self.expression(object, state);
+                }
+            }
+            "exprCall" => self.call(node, state),
+            "exprUnary" => {
+                let address = node
+                    .child_by_field_name("operator")
+                    .is_some_and(|operator| operator.kind() == "kAt");
+                match (address, node.child_by_field_name("operand")) {
+                    // A variable whose address is taken may be assigned
+                    // through it
+                    (true, Some(operand)) => self.target(operand, state),
+                    (false, Some(operand)) => self.expression(operand, state),
+                    _ => {}
+                }
+            }
+            "lambda" => {}
+            _ => {
+                let mut cursor = node.walk();
+                let children: Vec<Node> = node.named_children(&mut cursor).collect();
+                for child in children {
+                    self.expression(child, state);
+                }
+            }
+        }
+    }
+
+    fn call(&mut self, node: Node<'tree>, state: &mut [bool]) {
+        let entity = node.child_by_field_name("entity");
+        let name = entity
+            .filter(|entity| entity.kind() == "identifier")
+            .map(|entity| name_of(self.source, entity));
+        if let Some(entity) = entity {
+            self.expression(entity, state);
+        }
+        let Some(arguments) = node.child_by_field_name("args") else {
+            return;
+        };
+        let mut cursor = arguments.walk();
+        let arguments: Vec<Node> = arguments.named_children(&mut cursor).collect();
+        let name = name.unwrap_or_default();
+        if TYPE_ROUTINES.contains(&name.as_str()) {
+            return;
+        }
+        // Unqualified calls resolve to the unit's routines or intrinsics
+        let modes = match self.unit.signatures.get(&name) {
+            Some(modes) => modes.clone(),
+            None if VALUE_ROUTINES.contains(&name.as_str()) => Some(Vec::new()),
+            None => None,
+        };
+        for (position, argument) in arguments.into_iter().enumerate() {
+            // `Inc` and `Dec` read the variable they assign
+            if position == 0 && matches!(name.as_str(), "inc" | "dec") {
+                self.expression(argument, state);
+                self.target(argument, state);
+                continue;
+            }
+            let mode = match &modes {
+                Some(modes) => modes.get(position).copied().unwrap_or(ArgumentMode::Value),
+                None => ArgumentMode::Reference,
+            };
+            match mode {
+                ArgumentMode::Value => self.expression(argument, state),
+                ArgumentMode::Reference => self.target(argument, state),
+            }
+        }
+    }
+}
+
+/// The identifier at the root of `node`, such as `R` in `R.Items[I]` or
+/// `@R`, or `node` itself.
+fn root_variable(node: Node) -> Node {
+    let inner = match node.kind() {
+        "exprDot" => node.child_by_field_name("lhs"),
+        "exprSubscript" => node.child_by_field_name("entity"),
+        "exprUnary" => node.child_by_field_name("operand"),
+        _ => None,
+    };
+    inner.map_or(node, root_variable)
+}
+
+/// Adds the lowercase identifiers under `node`.
+fn collect_names(node: Node, source: &str, names: &mut HashSet<String>) {
+    if node.kind() == "identifier" {
+        names.insert(name_of(source, node));
+        return;
+    }
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        collect_names(child, source, names);
+    }
+}
@@ -0,0 +1,437 @@
+use crate::lsp::config::FormatSettings;
+use crate::lsp::document::slice_text;
+use crate::lsp::text_position::{lines_overlap, LineEnding, LineIndex};
+use serde::Deserialize;
+use tower_lsp::lsp_types::*;
+use tree_sitter::Node;
+
+/// How `format.wrapParameters` lays out the parameter list of a routine
+/// header.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum WrapParameters {
+    /// Leave parameter lists as written.
+    #[default]
+    None,
+    /// Always join the parameters on the line of the header.
+    OneLine,
+    /// Keep headers that fit on one line, otherwise one parameter per line.
+    OnePerLine,
+    /// Keep headers that fit on one line, otherwise pack as many
+    /// parameters per line as fit.
+    Fill,
+}
+
+/// How `format.wrapUses` lays out uses clauses.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum WrapUses {
+    /// Keep clauses that fit on one line, otherwise pack as many units per
+    /// line as fit below the `uses` keyword.
+    #[default]
+    Fill,
+    /// One unit per line below the `uses` keyword.
+    OnePerLine,
+}
+
+/// Where continuation lines of a wrapped parameter list start.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ContinuationIndent {
+    /// Under the first parameter, right after the opening parenthesis.
+    #[default]
+    Parenthesis,
+    /// One indentation level deeper than the header.
+    Indent,
+}
+
+/// An entry of a parameter list or uses clause, with its separator and the
+/// comments that follow it rendered after the separator.
+struct Piece {
+    text: String,
+    /// Ends in a `//` comment, so nothing may follow on its line.
+    breaks_line: bool,
+}
+
+/// Reflows the parameter lists of routine headers and the uses clauses of
+/// a document. Lists containing compiler directives or syntax errors are
+/// left alone, so code never moves across `{$IFDEF}` boundaries.
+pub struct Formatter<'a> {
+    source: &'a str,
+    line_index: &'a LineIndex,
+    settings: &'a FormatSettings,
+    /// One indentation level, from the client's formatting options.
+    indent: String,
+}
+
+impl<'a> Formatter<'a> {
+    pub fn new(
+        source: &'a str,
+        line_index: &'a LineIndex,
+        settings: &'a FormatSettings,
+        options: &FormattingOptions,
+    ) -> Self {
+        let indent = if options.insert_spaces {
+            " ".repeat(options.tab_size as usize)
+        } else {
+            "\t".to_string()
+        };
+        Self {
+            source,
+            line_index,
+            settings,
+            indent,
+        }
+    }
+
+    /// Edits reflowing the lists under `root` on the lines of `range`, or
+    /// all of them when no range is given.
+    pub fn format(&self, root: Node, range: Option<Range>) -> Vec<TextEdit> {
+        let mut edits = Vec::new();
+        self.collect(root, range, &mut edits);
+        edits
+    }
+
+    fn collect(&self, node: Node, range: Option<Range>, edits: &mut Vec<TextEdit>) {
+        let node_range = self.line_index.byte_range_to_range(node.byte_range());
+        if range.is_some_and(|range| !lines_overlap(node_range, range)) {
+            return;
+        }
+        let edit = match node.kind() {
+            "declUses" => self.reflow_uses(node),
+            "declArgs" => self.reflow_parameters(node),
+            _ => None,
+        };
+        if let Some(edit) = edit {
+            edits.push(edit);
+            return;
+        }
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            self.collect(child, range, edits);
+        }
+    }
+
+    /// Edits removing the entries of uses clauses naming one of `unused`,
+    /// ignoring case, and sorting the others by name when `sort`. The
+    /// clauses are laid out as `format` lays them out, and a clause left
+    /// empty is removed with its line. Clauses with nothing to remove or
+    /// reorder are left as written.
+    pub fn organize_uses(&self, root: Node, unused: &[String], sort: bool) -> Vec<TextEdit> {
+        let mut edits = Vec::new();
+        let mut stack = vec![root];
+        while let Some(node) = stack.pop() {
+            if node.kind() == "declUses" {
+                edits.extend(self.organize_clause(node, unused, sort));
+                continue;
+            }
+            let mut cursor = node.walk();
+            stack.extend(node.children(&mut cursor));
+        }
+        edits
+    }
+
+    fn organize_clause(&self, uses: Node, unused: &[String], sort: bool) -> Option<TextEdit> {
+        let mut entries = self.entries(uses, "moduleName")?;
+        let written: Vec<usize> = entries.iter().map(|(unit, _)| unit.start_byte()).collect();
+        let name = |unit: Node| -> String {
+            self.text(unit)
+                .chars()
+                .filter(|c| !c.is_whitespace())
+                .collect()
+        };
+        entries.retain(|(unit, _)| {
+            let name = name(*unit);
+            !unused
+                .iter()
+                .any(|unused| unused.eq_ignore_ascii_case(&name))
+        });
+        if sort {
+            entries.sort_by_cached_key(|(unit, _)| name(*unit).to_lowercase());
+        }
+        if entries
+            .iter()
+            .map(|(unit, _)| unit.start_byte())
+            .eq(written)
+        {
+            return None;
+        }
+        if entries.is_empty() {
+            return Some(self.delete_with_line(uses));
+        }
+        self.layout_uses(uses, self.pieces(entries, ",", ";")?)
+    }
+
+    fn reflow_uses(&self, uses: Node) -> Option<TextEdit> {
+        self.layout_uses(
+            uses,
+            self.pieces(self.entries(uses, "moduleName")?, ",", ";")?,
+        )
+    }
+
+    fn layout_uses(&self, uses: Node, pieces: Vec<Piece>) -> Option<TextEdit> {
+        let keyword = uses.child(0).filter(|child| child.kind() == "kUses")?;
+        let prefix = self.line_prefix(uses);
+        let continuation = format!("{}{}", leading_whitespace(prefix), self.indent);
+        let keyword = self.text(keyword);
+
+        let single = format!("{} {}", keyword, join(&pieces));
+        let fits = width(prefix) + width(&single) + width(self.line_suffix(uses))
+            <= self.settings.max_line_length;
+        let lines = if self.settings.wrap_uses == WrapUses::Fill
+            && fits
+            && !pieces.iter().any(|piece| piece.breaks_line)
+        {
+            vec![single]
+        } else {
+            let mut lines = vec![keyword.to_string()];
+            lines.extend(self.fill(
+                &pieces,
+                &continuation,
+                0,
+                &continuation,
+                self.settings.wrap_uses == WrapUses::OnePerLine,
+            ));
+            lines
+        };
+        self.replace(uses, lines)
+    }
+
+    fn reflow_parameters(&self, args: Node) -> Option<TextEdit> {
+        let wrap = self.settings.wrap_parameters;
+        if wrap == WrapParameters::None || args.child(0).map(|open| open.kind()) != Some("(") {
+            return None;
+        }
+        let pieces = self.pieces(self.entries(args, "declArg")?, ";", "")?;
+        // A closing parenthesis cannot follow a trailing `//` comment
+        if pieces.last()?.breaks_line {
+            return None;
+        }
+        let prefix = self.line_prefix(args);
+        let continuation = match self.settings.continuation_indent {
+            ContinuationIndent::Parenthesis => " ".repeat(width(prefix) + 1),
+            ContinuationIndent::Indent => format!("{}{}", leading_whitespace(prefix), self.indent),
+        };
+
+        let single = format!("({}", join(&pieces));
+        let breaks_line = pieces.iter().any(|piece| piece.breaks_line);
+        let fits = width(prefix) + width(&single) + 1 + width(self.line_suffix(args))
+            <= self.settings.max_line_length;
+        let mut lines = match wrap {
+            WrapParameters::OneLine if breaks_line => return None,
+            WrapParameters::OneLine => vec![single],
+            _ if fits && !breaks_line => vec![single],
+            _ => self.fill(
+                &pieces,
+                "(",
+                width(prefix),
+                &continuation,
+                wrap == WrapParameters::OnePerLine,
+            ),
+        };
+        lines.last_mut()?.push(')');
+        self.replace(args, lines)
+    }
+
+    /// The entries of `list` of kind `item_kind` with the comments
+    /// following each. Returns `None` when the list cannot be reflowed
+    /// safely: it contains compiler directives or errors, or a comment that
+    /// follows no entry.
+    fn entries<'t>(
+        &self,
+        list: Node<'t>,
+        item_kind: &str,
+    ) -> Option<Vec<(Node<'t>, Vec<Node<'t>>)>> {
+        if list.has_error() {
+            return None;
+        }
+        let mut items: Vec<(Node, Vec<Node>)> = Vec::new();
+        let mut cursor = list.walk();
+        for child in list.children(&mut cursor) {
+            match child.kind() {
+                "pp" => return None,
+                "comment" => items.last_mut()?.1.push(child),
+                kind if kind == item_kind => {
+                    if contains_directive(child) {
+                        return None;
+                    }
+                    items.push((child, Vec::new()));
+                }
+                _ => {}
+            }
+        }
+        Some(items)
+    }
+
+    /// Renders entries with their separators, the last one followed by
+    /// `terminator`, and their comments.
+    fn pieces(
+        &self,
+        items: Vec<(Node, Vec<Node>)>,
+        separator: &str,
+        terminator: &str,
+    ) -> Option<Vec<Piece>> {
+        let count = items.len();
+        let mut pieces = Vec::with_capacity(count);
+        for (i, (item, comments)) in items.into_iter().enumerate() {
+            let mut text = collapse_line_breaks(self.text(item))?;
+            text.push_str(if i + 1 < count { separator } else { terminator });
+            let mut breaks_line = false;
+            for comment in comments {
+                // Only the last comment may run to the end of the line
+                if breaks_line {
+                    return None;
+                }
+                let comment = self.text(comment);
+                breaks_line = comment.starts_with("//");
+                text.push(' ');
+                text.push_str(comment);
+            }
+            pieces.push(Piece { text, breaks_line });
+        }
+        (!pieces.is_empty()).then_some(pieces)
+    }
+
+    /// Packs pieces into lines. The first line starts with `first` after
+    /// `offset` columns of text that stays in place; later lines start with
+    /// `continuation`. With `one_per_line` every piece gets its own line.
+    fn fill(
+        &self,
+        pieces: &[Piece],
+        first: &str,
+        offset: usize,
+        continuation: &str,
+        one_per_line: bool,
+    ) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut line = first.to_string();
+        let mut line_offset = offset;
+        let mut empty = true;
+        for piece in pieces {
+            let candidate = if empty {
+                format!("{}{}", line, piece.text)
+            } else {
+                format!("{} {}", line, piece.text)
+            };
+            let too_long = line_offset + width(&candidate) > self.settings.max_line_length;
+            if !empty && (one_per_line || too_long) {
+                lines.push(line);
+                line = format!("{}{}", continuation, piece.text);
+                line_offset = 0;
+            } else {
+                line = candidate;
+            }
+            empty = false;
+            if piece.breaks_line {
+                lines.push(line);
+                line = continuation.to_string();
+                line_offset = 0;
+                empty = true;
+            }
+        }
+        if !empty {
+            lines.push(line);
+        }
+        lines
+    }
+
+    /// Replaces `node` with `lines`, unless that changes nothing.
+    fn replace(&self, node: Node, lines: Vec<String>) -> Option<TextEdit> {
+        let ending = self
+            .line_index
+            .dominant_line_ending()
+            .unwrap_or(LineEnding::CrLf);
+        let new_text = lines.join(ending.as_str());
+        if new_text == self.text(node) {
+            return None;
+        }
+        Some(TextEdit {
+            range: self.line_index.byte_range_to_range(node.byte_range()),
+            new_text,
+        })
+    }
+
+    /// Deletes `node`, and its line when nothing else is on it.
+    fn delete_with_line(&self, node: Node) -> TextEdit {
+        let mut range = node.byte_range();
+        let prefix = self.line_prefix(node);
+        let suffix = self.line_suffix(node);
+        if prefix.trim().is_empty() && suffix.trim().is_empty() {
+            range.start -= prefix.len();
+            range.end += suffix.len();
+            let rest = &self.source[range.end.min(self.source.len())..];
+            range.end += if rest.starts_with("\r\n") {
+                2
+            } else {
+                usize::from(rest.starts_with(['\n', '\r']))
+            };
+        }
+        TextEdit {
+            range: self.line_index.byte_range_to_range(range),
+            new_text: String::new(),
+        }
+    }
+
+    fn text(&self, node: Node) -> &'a str {
+        slice_text(self.source, node.byte_range(), || {
+            format!("a {} being formatted", node.kind())
+        })
+    }
+
+    /// The text between the start of the line of `node` and the node.
+    fn line_prefix(&self, node: Node) -> &'a str {
+        let start = node.start_byte().min(self.source.len());
+        let before = slice_text(self.source, 0..start, || "a line prefix".to_string());
+        let line_start = before.rfind(['\n', '\r']).map_or(0, |i| i + 1);
+        &before[line_start..]
+    }
+
+    /// The text between the end of `node` and the end of its line.
+    fn line_suffix(&self, node: Node) -> &'a str {
+        let end = node.end_byte().min(self.source.len());
+        let after = slice_text(self.source, end..self.source.len(), || {
+            "a line suffix".to_string()
+        });
+        &after[..after.find(['\n', '\r']).unwrap_or(after.len())]
+    }
+}
+
+fn join(pieces: &[Piece]) -> String {
+    pieces
+        .iter()
+        .map(|piece| piece.text.as_str())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn width(text: &str) -> usize {
+    text.chars().count()
+}
+
+fn leading_whitespace(text: &str) -> &str {
+    &text[..text.len() - text.trim_start().len()]
+}
+
+/// Joins the lines of an entry written across several lines. Returns `None`
+/// when a `//` comment inside the entry needs its line break.
+fn collapse_line_breaks(text: &str) -> Option<String> {
+    let mut lines = text.lines();
+    let mut collapsed = lines.next()?.trim_end().to_string();
+    for line in lines {
+        if collapsed.contains("//") {
+            return None;
+        }
+        collapsed.push(' ');
+        collapsed.push_str(line.trim());
+    }
+    Some(collapsed)
+}
+
+fn contains_directive(node: Node) -> bool {
+    if node.kind() == "pp" {
+        return true;
+    }
+    let mut cursor = node.walk();
+    let found = node.children(&mut cursor).any(contains_directive);
+    found
+}
@@ -0,0 +1,200 @@
+use crate::lsp::directives::Dialect;
+use serde::Deserialize;
+
+/// The Delphi version whose reserved words and directives apply, configured
+/// by the `languageVersion` setting. Ordered from oldest to newest.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LanguageVersion {
+    Delphi7,
+    Delphi2005,
+    Delphi2006,
+    Delphi2009,
+    Delphi2010,
+    #[serde(rename = "delphiXE2")]
+    DelphiXe2,
+    #[default]
+    Latest,
+}
+
+/// Words that became keywords or directives after Delphi 7, with the version
+/// that introduced them. Code written for older compilers commonly uses them
+/// as identifiers.
+const NEWER_KEYWORDS: &[(&str, LanguageVersion)] = &[
+    ("final", LanguageVersion::Delphi2005),
+    ("inline", LanguageVersion::Delphi2005),
+    ("sealed", LanguageVersion::Delphi2005),
+    ("static", LanguageVersion::Delphi2005),
+    ("strict", LanguageVersion::Delphi2005),
+    ("helper", LanguageVersion::Delphi2006),
+    ("operator", LanguageVersion::Delphi2006),
+    ("reference", LanguageVersion::Delphi2009),
+    ("delayed", LanguageVersion::Delphi2010),
+    ("winapi", LanguageVersion::DelphiXe2),
+];
+
+/// Reserved words offered by keyword completion in every dialect.
+const KEYWORDS: &[&str] = &[
+    "and",
+    "array",
+    "as",
+    "asm",
+    "begin",
+    "case",
+    "class",
+    "const",
+    "constructor",
+    "destructor",
+    "div",
+    "do",
+    "downto",
+    "else",
+    "end",
+    "except",
+    "exports",
+    "file",
+    "finalization",
+    "finally",
+    "for",
+    "function",
+    "goto",
+    "if",
+    "implementation",
+    "in",
+    "inherited",
+    "initialization",
+    "interface",
+    "is",
+    "label",
+    "library",
+    "mod",
+    "nil",
+    "not",
+    "object",
+    "of",
+    "or",
+    "packed",
+    "procedure",
+    "program",
+    "property",
+    "raise",
+    "record",
+    "repeat",
+    "resourcestring",
+    "set",
+    "shl",
+    "shr",
+    "string",
+    "then",
+    "threadvar",
+    "to",
+    "try",
+    "type",
+    "unit",
+    "until",
+    "uses",
+    "var",
+    "while",
+    "with",
+    "xor",
+];
+
+/// Routines of the System unit that are easy to shadow by accident with a
+/// declaration of the same name.
+pub const INTRINSICS: &[&str] = &[
+    "Abs",
+    "Assert",
+    "Assigned",
+    "Break",
+    "Chr",
+    "Concat",
+    "Continue",
+    "Copy",
+    "Dec",
+    "Default",
+    "Delete",
+    "Dispose",
+    "Exclude",
+    "Exit",
+    "FillChar",
+    "Frac",
+    "Halt",
+    "High",
+    "Inc",
+    "Include",
+    "Insert",
+    "Int",
+    "Length",
+    "Low",
+    "Move",
+    "New",
+    "Odd",
+    "Ord",
+    "Pos",
+    "Pred",
+    "Random",
+    "Round",
+    "SetLength",
+    "SizeOf",
+    "Sqr",
+    "Sqrt",
+    "Str",
+    "Succ",
+    "Trunc",
+    "TypeInfo",
+    "Val",
+];
+
+/// Keywords only FreePascal's objfpc and fpc modes have.
+const FPC_GENERIC_KEYWORDS: &[&str] = &["generic", "specialize"];
+
+/// The keywords to complete in `dialect`.
+pub fn completion_keywords(dialect: Dialect) -> Vec<&'static str> {
+    let mut keywords = KEYWORDS.to_vec();
+    if dialect.has_fpc_generics() {
+        keywords.extend(FPC_GENERIC_KEYWORDS);
+    }
+    keywords
+}
+
+/// Whether `name` is a reserved word in `dialect`, usable as an identifier
+/// only when escaped with `&`.
+pub fn is_reserved_word(name: &str, dialect: Dialect) -> bool {
+    completion_keywords(dialect)
+        .iter()
+        .any(|keyword| keyword.eq_ignore_ascii_case(name))
+}
+
+/// Whether `name` is a valid identifier, optionally `&`-escaped: a letter or
+/// underscore followed by letters, digits and underscores.
+pub fn is_identifier(name: &str) -> bool {
+    let mut chars = name.strip_prefix('&').unwrap_or(name).chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_alphabetic() || first == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Whether `name` is a keyword or directive in `version` that was not one in
+/// Delphi 7. `&`-escaped names never collide.
+pub fn collides_with_keyword(name: &str, version: LanguageVersion) -> bool {
+    !name.starts_with('&')
+        && NEWER_KEYWORDS
+            .iter()
+            .any(|(word, since)| *since <= version && word.eq_ignore_ascii_case(name))
+}
+
+/// Strips the `&` escape from an identifier or dotted name, so that `&begin`
+/// and `TFoo.&operator` yield `begin` and `TFoo.operator`.
+pub fn unescape_identifier(text: &str) -> String {
+    let mut name = String::with_capacity(text.len());
+    let mut segment_start = true;
+    for c in text.chars() {
+        if c == '&' && segment_start {
+            continue;
+        }
+        segment_start = c == '.' || (segment_start && c.is_whitespace());
+        name.push(c);
+    }
+    name
+}
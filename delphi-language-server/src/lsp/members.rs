@@ -0,0 +1,1103 @@
+use crate::lsp::document::slice_text;
+use crate::lsp::keywords::unescape_identifier;
+use crate::lsp::text_position::{range_contains, LineIndex};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use tower_lsp::lsp_types::*;
+use tree_sitter::Node;
+
+/// Member visibility, ordered from most to least restrictive.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(rename_all = "camelCase")]
+pub enum Visibility {
+    StrictPrivate,
+    Private,
+    StrictProtected,
+    Protected,
+    Public,
+    Published,
+}
+
+impl Visibility {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Visibility::StrictPrivate => "strict private",
+            Visibility::Private => "private",
+            Visibility::StrictProtected => "strict protected",
+            Visibility::Protected => "protected",
+            Visibility::Public => "public",
+            Visibility::Published => "published",
+        }
+    }
+
+    /// Reads the visibility of a `declSection` from its leading keywords.
+    pub fn from_section(section: Node) -> Option<Self> {
+        let mut strict = false;
+        let mut cursor = section.walk();
+        for child in section.children(&mut cursor) {
+            let visibility = match child.kind() {
+                "kStrict" => {
+                    strict = true;
+                    continue;
+                }
+                "kPrivate" if strict => Visibility::StrictPrivate,
+                "kPrivate" => Visibility::Private,
+                "kProtected" if strict => Visibility::StrictProtected,
+                "kProtected" => Visibility::Protected,
+                "kPublic" => Visibility::Public,
+                "kPublished" => Visibility::Published,
+                _ => return None,
+            };
+            return Some(visibility);
+        }
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum MemberKind {
+    Field,
+    Method,
+    Property,
+}
+
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub name: String,
+    pub kind: MemberKind,
+    pub visibility: Visibility,
+    /// The declared type of a field or property, or the result type of a
+    /// function, as written in the source.
+    pub type_name: Option<String>,
+    /// The declaration text, used for completion details and hover.
+    pub detail: String,
+    pub range: Range,
+    /// Routine parameters, or the index parameters of an array property.
+    pub params: Vec<Parameter>,
+    /// The read/write specifiers of a property.
+    pub accessors: Option<PropertyAccessors>,
+    /// The directives of a method.
+    pub directives: Vec<Directive>,
+}
+
+impl Member {
+    fn has_directive(&self, name: &str) -> bool {
+        self.directives
+            .iter()
+            .any(|directive| directive.name == name)
+    }
+
+    /// Whether the member is a constructor, whose calls give an instance
+    /// of its type.
+    pub fn is_constructor(&self) -> bool {
+        self.kind == MemberKind::Method
+            && self
+                .detail
+                .get(.."constructor".len())
+                .is_some_and(|keyword| keyword.eq_ignore_ascii_case("constructor"))
+    }
+
+    /// Whether descendants can override the method.
+    fn is_virtual(&self) -> bool {
+        ["virtual", "dynamic", "abstract", "override"]
+            .iter()
+            .any(|name| self.has_directive(name))
+    }
+}
+
+/// A directive of a routine header, such as `virtual` or `message
+/// WM_PAINT`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Directive {
+    /// The keyword, lowercase.
+    pub name: String,
+    /// What follows the keyword, as written: the message of `message`, the
+    /// number of `dispid`, the text of `deprecated`.
+    pub argument: Option<String>,
+}
+
+impl fmt::Display for Directive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.argument {
+            Some(argument) => write!(f, "{} {}", self.name, argument),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// The directives of a routine header in source order, `class` of class
+/// methods included, e.g. `class` and `static` for `class function Make:
+/// TFoo; static;`. Every feature reads directives through this function,
+/// so they all see the same set.
+pub fn routine_directives(header: Node, source: &str) -> Vec<Directive> {
+    let text = |start: usize, end: usize| {
+        slice_text(source, start..end, || "a routine header".to_string()).to_string()
+    };
+    let mut directives = Vec::new();
+    let mut cursor = header.walk();
+    for child in header.children(&mut cursor) {
+        let (keyword, argument) = match child.kind() {
+            "kClass" => (child, None),
+            "procAttribute" => {
+                let Some(keyword) = child.child(0) else {
+                    continue;
+                };
+                let mut arguments = child.walk();
+                let last = child
+                    .named_children(&mut arguments)
+                    .skip(1)
+                    .last()
+                    .map(|last| last.end_byte());
+                let argument = last.and_then(|end| {
+                    keyword
+                        .next_named_sibling()
+                        .map(|first| text(first.start_byte(), end))
+                });
+                (keyword, argument)
+            }
+            _ => continue,
+        };
+        directives.push(Directive {
+            name: text(keyword.start_byte(), keyword.end_byte()).to_lowercase(),
+            argument,
+        });
+    }
+    directives
+}
+
+#[derive(Debug, Clone)]
+pub struct Parameter {
+    pub name: String,
+    pub type_name: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PropertyAccessors {
+    /// The `read` specifier and the range of its identifier.
+    pub getter: Option<(String, Range)>,
+    /// The `write` specifier and the range of its identifier.
+    pub setter: Option<(String, Range)>,
+    /// Whether the property has an `index N` specifier, which passes an
+    /// extra Integer argument to its accessor methods.
+    pub index_specifier: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessorKind {
+    Read,
+    Write,
+}
+
+/// The shape of a property, as needed to check its accessors.
+#[derive(Debug, Clone)]
+pub struct PropertySignature {
+    pub type_name: String,
+    pub index_params: Vec<Parameter>,
+    pub index_specifier: bool,
+}
+
+impl PropertySignature {
+    fn from_member(member: &Member) -> Option<Self> {
+        Some(PropertySignature {
+            type_name: member.type_name.clone()?,
+            index_params: member.params.clone(),
+            index_specifier: member.accessors.as_ref()?.index_specifier,
+        })
+    }
+
+    /// Whether `member` can serve as the `kind` accessor of this property:
+    /// a field of the property type (plain properties only), a function
+    /// taking the index parameters and returning the property type, or a
+    /// procedure taking the index parameters plus a value of that type.
+    pub fn accepts(&self, member: &Member, kind: AccessorKind) -> bool {
+        let mut expected: Vec<Option<&str>> = self
+            .index_params
+            .iter()
+            .map(|param| param.type_name.as_deref())
+            .collect();
+        if self.index_specifier {
+            expected.push(Some("Integer"));
+        }
+
+        match (member.kind, kind) {
+            (MemberKind::Field, _) => {
+                expected.is_empty() && same_type(member.type_name.as_deref(), Some(&self.type_name))
+            }
+            (MemberKind::Method, AccessorKind::Read) => {
+                same_type(member.type_name.as_deref(), Some(&self.type_name))
+                    && same_params(&member.params, &expected)
+            }
+            (MemberKind::Method, AccessorKind::Write) => {
+                expected.push(Some(&self.type_name));
+                member.type_name.is_none() && same_params(&member.params, &expected)
+            }
+            (MemberKind::Property, _) => false,
+        }
+    }
+
+    pub fn describe(&self, kind: AccessorKind) -> String {
+        let mut args: Vec<String> = self
+            .index_params
+            .iter()
+            .map(|param| {
+                format!(
+                    "{}: {}",
+                    param.name,
+                    param.type_name.as_deref().unwrap_or("?")
+                )
+            })
+            .collect();
+        if self.index_specifier {
+            args.push("Index: Integer".to_string());
+        }
+        match kind {
+            AccessorKind::Read if args.is_empty() => format!(
+                "a field of type {0} or a parameterless function returning {0}",
+                self.type_name
+            ),
+            AccessorKind::Read => {
+                format!("a function ({}): {}", args.join("; "), self.type_name)
+            }
+            AccessorKind::Write => {
+                let field = args.is_empty();
+                args.push(format!("Value: {}", self.type_name));
+                let procedure = format!("a procedure ({})", args.join("; "));
+                if field {
+                    format!("a field of type {} or {}", self.type_name, procedure)
+                } else {
+                    procedure
+                }
+            }
+        }
+    }
+}
+
+/// Compares type names the way the compiler does for identical types:
+/// case-insensitively and ignoring whitespace.
+fn same_type(a: Option<&str>, b: Option<&str>) -> bool {
+    let normalize = |t: &str| {
+        t.chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>()
+            .to_lowercase()
+    };
+    match (a, b) {
+        (Some(a), Some(b)) => normalize(a) == normalize(b),
+        _ => false,
+    }
+}
+
+/// Splits a type name into its base name and type arguments, e.g.
+/// `TDictionary<string, TList<Integer>>` into `TDictionary` and `string`,
+/// `TList<Integer>`. Names without arguments have none.
+pub fn split_generic(type_name: &str) -> (&str, Vec<&str>) {
+    let type_name = type_name.trim();
+    let (Some(open), Some(close)) = (type_name.find('<'), type_name.rfind('>')) else {
+        return (type_name, Vec::new());
+    };
+    if close < open {
+        return (type_name, Vec::new());
+    }
+    let mut args = Vec::new();
+    let mut depth = 0;
+    let mut start = open + 1;
+    for (offset, c) in type_name[..close]
+        .char_indices()
+        .skip_while(|(i, _)| *i <= open)
+    {
+        match c {
+            '<' => depth += 1,
+            '>' => depth -= 1,
+            ',' if depth == 0 => {
+                args.push(type_name[start..offset].trim());
+                start = offset + 1;
+            }
+            _ => {}
+        }
+    }
+    args.push(type_name[start..close].trim());
+    (type_name[..open].trim(), args)
+}
+
+/// Replaces the type parameters in `type_name` by the arguments bound to
+/// them, e.g. `TEnumerator<T>` with `T` bound to `TCustomer`.
+pub fn substitute_type_params(type_name: &str, bindings: &[(String, String)]) -> String {
+    let mut result = String::new();
+    let mut word = String::new();
+    let flush = |word: &mut String, result: &mut String| {
+        let bound = bindings
+            .iter()
+            .find(|(param, _)| param.eq_ignore_ascii_case(word));
+        result.push_str(bound.map_or(word.as_str(), |(_, arg)| arg.as_str()));
+        word.clear();
+    };
+    for c in type_name.chars() {
+        if c.is_alphanumeric() || c == '_' {
+            word.push(c);
+        } else {
+            flush(&mut word, &mut result);
+            result.push(c);
+        }
+    }
+    flush(&mut word, &mut result);
+    result
+}
+
+fn same_params(params: &[Parameter], expected: &[Option<&str>]) -> bool {
+    params.len() == expected.len()
+        && params
+            .iter()
+            .zip(expected)
+            .all(|(param, expected)| same_type(param.type_name.as_deref(), *expected))
+}
+
+/// Whether two methods take the same parameter types and return the same
+/// type, as required of a method implementing an interface method.
+fn same_signature(a: &Member, b: &Member) -> bool {
+    let expected: Vec<Option<&str>> = b
+        .params
+        .iter()
+        .map(|param| param.type_name.as_deref())
+        .collect();
+    same_params(&a.params, &expected)
+        && match (&a.type_name, &b.type_name) {
+            (None, None) => true,
+            (a, b) => same_type(a.as_deref(), b.as_deref()),
+        }
+}
+
+/// The signature of a method as `(A: Integer): string`.
+fn describe_signature(method: &Member) -> String {
+    let params: Vec<String> = method
+        .params
+        .iter()
+        .map(|param| {
+            format!(
+                "{}: {}",
+                param.name,
+                param.type_name.as_deref().unwrap_or("?")
+            )
+        })
+        .collect();
+    match &method.type_name {
+        Some(result) => format!("({}): {}", params.join("; "), result),
+        None => format!("({})", params.join("; ")),
+    }
+}
+
+/// Base classes of the RTL that implement no interface methods but
+/// `IInterface`'s, so a class descending from them implements the methods
+/// of its own interfaces itself.
+const INTERFACE_BASE_CLASSES: &[&str] = &[
+    "TObject",
+    "TInterfacedObject",
+    "TAggregatedObject",
+    "TContainedObject",
+];
+
+/// The virtual methods of `TObject`, which classes without a known
+/// ancestor override.
+const TOBJECT_VIRTUAL_METHODS: &[&str] = &[
+    "AfterConstruction",
+    "BeforeDestruction",
+    "DefaultHandler",
+    "Destroy",
+    "Equals",
+    "FreeInstance",
+    "GetHashCode",
+    "NewInstance",
+    "SafeCallException",
+    "ToString",
+];
+
+/// A method resolution clause, `procedure IMyIntf.DoWork = InternalDoWork;`,
+/// which implements an interface method with a method of another name.
+#[derive(Debug, Clone)]
+pub struct MethodResolution {
+    pub interface: String,
+    pub method: String,
+    pub target: String,
+    /// The ranges of the interface method and target identifiers.
+    pub method_range: Range,
+    pub target_range: Range,
+}
+
+/// A class, record, interface or helper declaration with its own members.
+#[derive(Debug, Clone)]
+pub struct TypeDecl {
+    pub name: String,
+    pub parents: Vec<String>,
+    pub members: Vec<Member>,
+    pub resolutions: Vec<MethodResolution>,
+    pub range: Range,
+    pub name_range: Range,
+    pub is_interface: bool,
+}
+
+impl TypeDecl {
+    /// Whether every ancestor of this type is known, i.e. a member missing
+    /// from the table is really missing rather than declared in an ancestor
+    /// from another unit.
+    fn ancestry_complete(&self, table: &TypeTable) -> bool {
+        let chain = table.ancestors(&self.name);
+        chain.last().is_some_and(|last| last.parents.is_empty())
+    }
+
+    /// Like `ancestry_complete`, but also accepting ancestries ending in
+    /// `TObject`, whose virtual methods are `TOBJECT_VIRTUAL_METHODS`.
+    fn ancestry_known(&self, table: &TypeTable) -> bool {
+        let chain = table.ancestors(&self.name);
+        chain.last().is_some_and(|last| {
+            last.parents
+                .first()
+                .is_none_or(|parent| parent.eq_ignore_ascii_case("TObject"))
+        })
+    }
+
+    /// Like `ancestry_complete`, but also accepting ancestries ending in
+    /// one of the `INTERFACE_BASE_CLASSES`.
+    fn implementers_known(&self, table: &TypeTable) -> bool {
+        let chain = table.ancestors(&self.name);
+        chain.last().is_some_and(|last| {
+            last.parents.first().is_none_or(|parent| {
+                INTERFACE_BASE_CLASSES
+                    .iter()
+                    .any(|base| base.eq_ignore_ascii_case(parent))
+            })
+        })
+    }
+}
+
+/// A member declaration that the compiler would reject: a property accessor
+/// or method resolution naming a missing or incompatible member, or an
+/// interface method without implementation.
+#[derive(Debug, Clone)]
+pub struct MemberProblem {
+    pub range: Range,
+    pub message: String,
+}
+
+/// Where a member is accessed from, for visibility checks.
+#[derive(Debug, Clone, Default)]
+pub struct AccessContext {
+    /// The class whose method or declaration contains the access.
+    pub class_name: Option<String>,
+    /// Whether the access is in the unit declaring the member.
+    pub same_unit: bool,
+}
+
+/// The structured types of a document keyed by lowercased name.
+#[derive(Debug, Default)]
+pub struct TypeTable {
+    types: HashMap<String, TypeDecl>,
+}
+
+impl TypeTable {
+    pub fn build(root: Node, source: &str, line_index: &LineIndex) -> Self {
+        let mut table = TypeTable::default();
+        let builder = Builder { source, line_index };
+        builder.collect(root, &mut table);
+        table
+    }
+
+    pub fn get(&self, name: &str) -> Option<&TypeDecl> {
+        self.types.get(&name.to_lowercase())
+    }
+
+    /// Looks up a type by a possibly instantiated name, `TMyList<TCustomer>`
+    /// finding the declaration of `TMyList<T>`, with the type arguments
+    /// bound to its parameters.
+    pub fn get_instance(&self, type_name: &str) -> Option<(&TypeDecl, Vec<(String, String)>)> {
+        if let Some(decl) = self.get(type_name) {
+            return Some((decl, Vec::new()));
+        }
+        let (base, args) = split_generic(type_name);
+        if args.is_empty() {
+            return None;
+        }
+        self.types.values().find_map(|decl| {
+            let (name, params) = split_generic(&decl.name);
+            if !name.eq_ignore_ascii_case(base) || params.len() != args.len() {
+                return None;
+            }
+            let bindings = params
+                .into_iter()
+                .zip(&args)
+                .map(|(param, arg)| (param.to_string(), arg.to_string()))
+                .collect();
+            Some((decl, bindings))
+        })
+    }
+
+    /// Returns the type and its resolvable ancestors, nearest first.
+    /// Guards against inheritance cycles in broken code.
+    pub fn ancestors(&self, name: &str) -> Vec<&TypeDecl> {
+        let mut chain = Vec::new();
+        let mut seen = HashSet::new();
+        let mut current = self.get(name);
+        while let Some(decl) = current {
+            if !seen.insert(decl.name.to_lowercase()) {
+                break;
+            }
+            chain.push(decl);
+            current = decl.parents.first().and_then(|parent| self.get(parent));
+        }
+        chain
+    }
+
+    pub fn is_same_or_descendant(&self, name: &str, ancestor: &str) -> bool {
+        self.ancestors(name)
+            .iter()
+            .any(|decl| decl.name.eq_ignore_ascii_case(ancestor))
+    }
+
+    /// Returns the members of a type including inherited ones, paired with
+    /// their declaring type. Members hidden by a same-named member of a
+    /// descendant are omitted.
+    pub fn members(&self, name: &str) -> Vec<(&TypeDecl, &Member)> {
+        let mut seen = HashSet::new();
+        let mut members = Vec::new();
+        for decl in self.ancestors(name) {
+            for member in &decl.members {
+                if seen.insert(member.name.to_lowercase()) {
+                    members.push((decl, member));
+                }
+            }
+        }
+        members
+    }
+
+    /// Like `members`, but keeping every overload: a member is only hidden
+    /// by a same-named member of a descendant, not of its own type.
+    pub fn all_members(&self, name: &str) -> Vec<(&TypeDecl, &Member)> {
+        let mut hidden: HashSet<String> = HashSet::new();
+        let mut members = Vec::new();
+        for decl in self.ancestors(name) {
+            let declared: Vec<String> = decl
+                .members
+                .iter()
+                .map(|member| member.name.to_lowercase())
+                .collect();
+            for member in &decl.members {
+                if !hidden.contains(&member.name.to_lowercase()) {
+                    members.push((decl, member));
+                }
+            }
+            hidden.extend(declared);
+        }
+        members
+    }
+
+    /// Returns the type whose declaration contains `position`.
+    pub fn type_at(&self, position: Position) -> Option<&TypeDecl> {
+        self.types
+            .values()
+            .filter(|decl| range_contains(decl.range, position))
+            .min_by_key(|decl| {
+                (
+                    decl.range.end.line - decl.range.start.line,
+                    decl.range.start,
+                )
+            })
+    }
+
+    /// Members of `type_name` usable as the `kind` accessor of a property.
+    pub fn accessor_candidates(
+        &self,
+        type_name: &str,
+        property: &PropertySignature,
+        kind: AccessorKind,
+    ) -> Vec<(&TypeDecl, &Member)> {
+        self.members(type_name)
+            .into_iter()
+            .filter(|(_, member)| property.accepts(member, kind))
+            .collect()
+    }
+
+    /// Checks the read and write specifiers of every property against the
+    /// members of its class.
+    pub fn accessor_problems(&self) -> Vec<MemberProblem> {
+        let mut problems = Vec::new();
+        for decl in self.types.values() {
+            for member in &decl.members {
+                let (Some(accessors), Some(property)) =
+                    (&member.accessors, PropertySignature::from_member(member))
+                else {
+                    continue;
+                };
+                let specifiers = [
+                    (AccessorKind::Read, &accessors.getter),
+                    (AccessorKind::Write, &accessors.setter),
+                ];
+                for (kind, specifier) in specifiers {
+                    let Some((name, range)) = specifier else {
+                        continue;
+                    };
+                    let message = match self.find_member(&decl.name, name) {
+                        Some((_, target)) if property.accepts(target, kind) => continue,
+                        Some(_) => format!(
+                            "'{}' is not a valid {} accessor for property {}: expected {}",
+                            name,
+                            if kind == AccessorKind::Read {
+                                "read"
+                            } else {
+                                "write"
+                            },
+                            member.name,
+                            property.describe(kind)
+                        ),
+                        None if decl.ancestry_complete(self) => {
+                            format!("'{}' is not declared in {}", name, decl.name)
+                        }
+                        None => continue,
+                    };
+                    problems.push(MemberProblem {
+                        range: *range,
+                        message,
+                    });
+                }
+            }
+        }
+        problems.sort_by_key(|problem| problem.range.start);
+        problems
+    }
+
+    /// Checks every method resolution clause: the interface method must
+    /// exist, and so must a method of the class with its signature.
+    pub fn resolution_problems(&self) -> Vec<MemberProblem> {
+        let mut problems = Vec::new();
+        for decl in self.types.values() {
+            for resolution in &decl.resolutions {
+                let interface = self.get(&resolution.interface);
+                let methods = interface
+                    .map(|interface| self.methods(&interface.name, &resolution.method))
+                    .unwrap_or_default();
+                if interface.is_some() && methods.is_empty() {
+                    problems.push(MemberProblem {
+                        range: resolution.method_range,
+                        message: format!(
+                            "'{}' is not a method of {}",
+                            resolution.method, resolution.interface
+                        ),
+                    });
+                    continue;
+                }
+                let targets = self.methods(&decl.name, &resolution.target);
+                let message = if targets.is_empty() {
+                    if !decl.implementers_known(self) {
+                        continue;
+                    }
+                    format!("'{}' is not declared in {}", resolution.target, decl.name)
+                } else if methods.is_empty()
+                    || methods
+                        .iter()
+                        .any(|method| targets.iter().any(|target| same_signature(target, method)))
+                {
+                    continue;
+                } else {
+                    format!(
+                        "'{}' does not match the signature of {}.{}: expected {}",
+                        resolution.target,
+                        resolution.interface,
+                        resolution.method,
+                        describe_signature(methods[0])
+                    )
+                };
+                problems.push(MemberProblem {
+                    range: resolution.target_range,
+                    message,
+                });
+            }
+        }
+        problems.sort_by_key(|problem| problem.range.start);
+        problems
+    }
+
+    /// Reports the methods of the interfaces a class lists that neither the
+    /// class nor its ancestors implement, by name or through a method
+    /// resolution clause. Classes with ancestors from other units, which may
+    /// implement them, are skipped.
+    pub fn missing_interface_methods(&self) -> Vec<MemberProblem> {
+        let mut problems = Vec::new();
+        for decl in self.types.values() {
+            if decl.is_interface || !decl.implementers_known(self) {
+                continue;
+            }
+            let chain = self.ancestors(&decl.name);
+            for interface in decl.parents.iter().filter_map(|parent| self.get(parent)) {
+                if !interface.is_interface {
+                    continue;
+                }
+                let methods = self
+                    .members(&interface.name)
+                    .into_iter()
+                    .filter(|(_, member)| member.kind == MemberKind::Method);
+                for (declaring, method) in methods {
+                    let resolved =
+                        chain
+                            .iter()
+                            .flat_map(|class| &class.resolutions)
+                            .any(|resolution| {
+                                resolution.interface.eq_ignore_ascii_case(&declaring.name)
+                                    && resolution.method.eq_ignore_ascii_case(&method.name)
+                            });
+                    if resolved || !self.methods(&decl.name, &method.name).is_empty() {
+                        continue;
+                    }
+                    problems.push(MemberProblem {
+                        range: decl.name_range,
+                        message: format!(
+                            "{} does not implement {}.{}",
+                            decl.name, declaring.name, method.name
+                        ),
+                    });
+                }
+            }
+        }
+        problems.sort_by_key(|problem| problem.range.start);
+        problems
+    }
+
+    /// Reports `override` methods of classes with no virtual or dynamic
+    /// method of that name in an ancestor. Classes with ancestors from
+    /// other units, which may declare it, are skipped.
+    pub fn override_problems(&self) -> Vec<MemberProblem> {
+        let mut problems = Vec::new();
+        for decl in self.types.values() {
+            if decl.is_interface || !decl.ancestry_known(self) {
+                continue;
+            }
+            let chain = self.ancestors(&decl.name);
+            let overrides = decl.members.iter().filter(|member| {
+                member.kind == MemberKind::Method && member.has_directive("override")
+            });
+            for method in overrides {
+                if TOBJECT_VIRTUAL_METHODS
+                    .iter()
+                    .any(|name| name.eq_ignore_ascii_case(&method.name))
+                {
+                    continue;
+                }
+                let inherited: Vec<(&TypeDecl, &Member)> = chain
+                    .iter()
+                    .skip(1)
+                    .flat_map(|ancestor| {
+                        ancestor
+                            .members
+                            .iter()
+                            .map(move |member| (*ancestor, member))
+                    })
+                    .filter(|(_, member)| {
+                        member.kind == MemberKind::Method
+                            && member.name.eq_ignore_ascii_case(&method.name)
+                    })
+                    .collect();
+                let message = match inherited.first() {
+                    None => format!(
+                        "'{}' overrides nothing: no ancestor of {} declares it",
+                        method.name, decl.name
+                    ),
+                    Some(_) if inherited.iter().any(|(_, member)| member.is_virtual()) => continue,
+                    Some((ancestor, _)) => format!(
+                        "'{}' cannot override the static method {}.{}",
+                        method.name, ancestor.name, method.name
+                    ),
+                };
+                problems.push(MemberProblem {
+                    range: method.range,
+                    message,
+                });
+            }
+        }
+        problems.sort_by_key(|problem| problem.range.start);
+        problems
+    }
+
+    /// The methods named `name` of a type and its ancestors, overloads
+    /// included.
+    fn methods(&self, type_name: &str, name: &str) -> Vec<&Member> {
+        self.ancestors(type_name)
+            .into_iter()
+            .flat_map(|decl| &decl.members)
+            .filter(|member| {
+                member.kind == MemberKind::Method && member.name.eq_ignore_ascii_case(name)
+            })
+            .collect()
+    }
+
+    pub fn find_member(&self, type_name: &str, member_name: &str) -> Option<(&TypeDecl, &Member)> {
+        self.members(type_name)
+            .into_iter()
+            .find(|(_, member)| member.name.eq_ignore_ascii_case(member_name))
+    }
+
+    /// The default array property of `type_name`, which `Value[Index]`
+    /// reads on an instance, declared by the type or an ancestor.
+    pub fn default_property(&self, type_name: &str) -> Option<(&TypeDecl, &Member)> {
+        self.members(type_name).into_iter().find(|(_, member)| {
+            member.kind == MemberKind::Property
+                && !member.params.is_empty()
+                && member.has_directive("default")
+        })
+    }
+
+    /// Whether `method` of `type_name` may implement a method of one of the
+    /// interfaces the type lists. Interfaces declared in other units are
+    /// unknown, so every method of a type listing one of them may; the first
+    /// unknown parent is taken to be the ancestor class.
+    pub fn may_implement_interface(&self, type_name: &str, method: &str) -> bool {
+        let Some(decl) = self.get(type_name) else {
+            return false;
+        };
+        let resolved = decl
+            .resolutions
+            .iter()
+            .any(|resolution| resolution.target.eq_ignore_ascii_case(method));
+        resolved
+            || decl
+                .parents
+                .iter()
+                .enumerate()
+                .any(|(i, parent)| match self.get(parent) {
+                    Some(parent) => {
+                        parent.is_interface && self.find_member(&parent.name, method).is_some()
+                    }
+                    None => i > 0,
+                })
+    }
+
+    /// Applies Delphi's visibility rules: strict private members are only
+    /// visible inside their class, strict protected ones also in
+    /// descendants, private and protected ones anywhere in the declaring
+    /// unit and protected ones in descendants.
+    pub fn is_accessible(
+        &self,
+        declaring: &TypeDecl,
+        member: &Member,
+        context: &AccessContext,
+    ) -> bool {
+        let in_class = context
+            .class_name
+            .as_deref()
+            .is_some_and(|class| class.eq_ignore_ascii_case(&declaring.name));
+        let in_descendant = context
+            .class_name
+            .as_deref()
+            .is_some_and(|class| self.is_same_or_descendant(class, &declaring.name));
+
+        match member.visibility {
+            Visibility::StrictPrivate => in_class,
+            Visibility::StrictProtected => in_descendant,
+            Visibility::Private => context.same_unit,
+            Visibility::Protected => context.same_unit || in_descendant,
+            Visibility::Public | Visibility::Published => true,
+        }
+    }
+}
+
+struct Builder<'a> {
+    source: &'a str,
+    line_index: &'a LineIndex,
+}
+
+impl Builder<'_> {
+    fn text(&self, node: Node) -> String {
+        slice_text(self.source, node.byte_range(), || {
+            "the type table".to_string()
+        })
+        .to_string()
+    }
+
+    /// The text of an identifier with its `&` escape removed.
+    fn name(&self, node: Node) -> String {
+        unescape_identifier(&self.text(node))
+    }
+
+    fn range(&self, node: Node) -> Range {
+        self.line_index.byte_range_to_range(node.byte_range())
+    }
+
+    fn collect(&self, node: Node, table: &mut TypeTable) {
+        if matches!(node.kind(), "declClass" | "declIntf" | "declHelper") {
+            if let Some(decl) = self.type_decl(node) {
+                table.types.insert(decl.name.to_lowercase(), decl);
+            }
+        }
+
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            self.collect(child, table);
+        }
+    }
+
+    /// Finds the name of a type body: the `declType` name, or, when an
+    /// incomplete declaration made error recovery drop the `declType`, the
+    /// `Name =` tokens right before the body.
+    fn type_name<'t>(&self, body: Node<'t>) -> Option<(Node<'t>, Node<'t>)> {
+        let parent = body.parent()?;
+        if parent.kind() == "declType" {
+            return Some((parent.child_by_field_name("name")?, parent));
+        }
+        let eq = body.prev_sibling().filter(|n| n.kind() == "kEq")?;
+        let name = eq.prev_sibling().filter(|n| n.kind() == "identifier")?;
+        Some((name, body))
+    }
+
+    fn type_decl(&self, body: Node) -> Option<TypeDecl> {
+        let (name_node, decl_node) = self.type_name(body)?;
+
+        let mut cursor = body.walk();
+        let parents = body
+            .children_by_field_name("parent", &mut cursor)
+            .filter(|parent| parent.kind() == "typeref")
+            .map(|parent| self.name(parent))
+            .collect();
+
+        // Class and interface members without a section are public
+        let mut members = Vec::new();
+        let mut resolutions = Vec::new();
+        self.collect_members(body, Visibility::Public, &mut members, &mut resolutions);
+
+        let start = name_node.start_byte().min(decl_node.start_byte());
+        Some(TypeDecl {
+            name: self.name(name_node),
+            parents,
+            members,
+            resolutions,
+            range: self
+                .line_index
+                .byte_range_to_range(start..decl_node.end_byte()),
+            name_range: self.range(name_node),
+            is_interface: body.kind() == "declIntf",
+        })
+    }
+
+    fn collect_members(
+        &self,
+        node: Node,
+        visibility: Visibility,
+        members: &mut Vec<Member>,
+        resolutions: &mut Vec<MethodResolution>,
+    ) {
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            match child.kind() {
+                "declSection" => {
+                    let visibility = Visibility::from_section(child).unwrap_or(visibility);
+                    self.collect_members(child, visibility, members, resolutions);
+                }
+                "declProc" if child.child_by_field_name("assign").is_some() => {
+                    resolutions.extend(self.method_resolution(child));
+                }
+                "declField" => {
+                    let type_name = child.child_by_field_name("type").map(|t| self.text(t));
+                    let mut names = child.walk();
+                    for name_node in child.children_by_field_name("name", &mut names) {
+                        if name_node.kind() != "identifier" {
+                            continue;
+                        }
+                        members.push(Member {
+                            name: self.name(name_node),
+                            kind: MemberKind::Field,
+                            visibility,
+                            type_name: type_name.clone(),
+                            detail: self.text(child),
+                            range: self.range(child),
+                            params: Vec::new(),
+                            accessors: None,
+                            directives: Vec::new(),
+                        });
+                    }
+                }
+                "declProc" | "declProp" => {
+                    let Some(name_node) = child.child_by_field_name("name") else {
+                        continue;
+                    };
+                    let (kind, accessors) = if child.kind() == "declProc" {
+                        (MemberKind::Method, None)
+                    } else {
+                        (MemberKind::Property, Some(self.property_accessors(child)))
+                    };
+                    let params = child
+                        .child_by_field_name("args")
+                        .map(|args| self.parameters(args))
+                        .unwrap_or_default();
+                    members.push(Member {
+                        name: self.name(name_node),
+                        kind,
+                        visibility,
+                        type_name: child.child_by_field_name("type").map(|t| self.text(t)),
+                        detail: self.text(child),
+                        range: self.range(child),
+                        params,
+                        accessors,
+                        directives: routine_directives(child, self.source),
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Flattens `declArgs`/`declPropArgs` into one parameter per name, so
+    /// `A, B: Integer` yields two parameters.
+    fn parameters(&self, args: Node) -> Vec<Parameter> {
+        let mut params = Vec::new();
+        let mut cursor = args.walk();
+        for arg in args.children(&mut cursor) {
+            if arg.kind() != "declArg" {
+                continue;
+            }
+            let type_name = arg.child_by_field_name("type").map(|t| self.text(t));
+            let mut names = arg.walk();
+            for name in arg.children_by_field_name("name", &mut names) {
+                if name.kind() == "identifier" {
+                    params.push(Parameter {
+                        name: self.name(name),
+                        type_name: type_name.clone(),
+                    });
+                }
+            }
+        }
+        params
+    }
+
+    /// Reads `procedure IMyIntf.DoWork = InternalDoWork;`, parsed as a
+    /// routine with a dotted name and a default value.
+    fn method_resolution(&self, clause: Node) -> Option<MethodResolution> {
+        let name = clause
+            .child_by_field_name("name")
+            .filter(|name| name.kind() == "genericDot")?;
+        let interface = name.child_by_field_name("lhs")?;
+        let method = name.child_by_field_name("rhs")?;
+        let target = clause
+            .child_by_field_name("assign")?
+            .named_child(1)
+            .filter(|target| target.kind() == "identifier")?;
+        Some(MethodResolution {
+            interface: self.name(interface),
+            method: self.name(method),
+            target: self.name(target),
+            method_range: self.range(method),
+            target_range: self.range(target),
+        })
+    }
+
+    fn property_accessors(&self, property: Node) -> PropertyAccessors {
+        let specifier = |field| {
+            property
+                .child_by_field_name(field)
+                .map(|node| (self.name(node), self.range(node)))
+        };
+        PropertyAccessors {
+            getter: specifier("getter"),
+            setter: specifier("setter"),
+            index_specifier: property.child_by_field_name("index").is_some(),
+        }
+    }
+}
@@ -1,5 +1,34 @@
+pub mod analysis_error;
 pub mod analyzer;
+pub mod balance;
+pub mod cache;
+pub mod characters;
+pub mod comments;
+pub mod conditionals;
+pub mod config;
+pub mod constants;
+pub mod directives;
+pub mod docs;
+pub mod document;
+pub mod fixes;
+pub mod flow;
+pub mod format;
+pub mod guid;
+pub mod keywords;
+pub mod members;
+pub mod naming;
 pub mod parser;
+pub mod protocol_ext;
+pub mod rtl;
 pub mod server;
+pub mod session;
+pub mod stats;
+pub mod strings;
+pub mod symbol_id;
+#[cfg(test)]
+pub mod testing;
+pub mod text_position;
+pub mod transport;
+pub mod workspace;
 
 pub use server::DelphiLanguageServer;
@@ -0,0 +1,290 @@
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+use tower_lsp::lsp_types::DiagnosticSeverity;
+
+/// Code of the diagnostic reporting a declaration whose name breaks the
+/// naming convention configured for its category.
+pub const NAMING_CONVENTION: &str = "naming-convention";
+
+/// What a declared name names, as far as naming conventions go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameCategory {
+    Type,
+    Interface,
+    Field,
+    Property,
+    /// Routines and methods.
+    Routine,
+    Parameter,
+    /// Variables declared in a routine.
+    Local,
+    /// Variables declared at unit level.
+    Variable,
+    Constant,
+}
+
+impl NameCategory {
+    pub fn name(self) -> &'static str {
+        match self {
+            NameCategory::Type => "Type",
+            NameCategory::Interface => "Interface",
+            NameCategory::Field => "Field",
+            NameCategory::Property => "Property",
+            NameCategory::Routine => "Routine",
+            NameCategory::Parameter => "Parameter",
+            NameCategory::Local => "Local variable",
+            NameCategory::Variable => "Variable",
+            NameCategory::Constant => "Constant",
+        }
+    }
+}
+
+/// The naming rule of each category, configured by the `naming` settings.
+/// Categories without a rule are not checked.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NamingSettings {
+    pub types: Option<NamingRule>,
+    pub interfaces: Option<NamingRule>,
+    pub fields: Option<NamingRule>,
+    pub properties: Option<NamingRule>,
+    pub routines: Option<NamingRule>,
+    pub parameters: Option<NamingRule>,
+    pub locals: Option<NamingRule>,
+    pub variables: Option<NamingRule>,
+    pub constants: Option<NamingRule>,
+}
+
+impl NamingSettings {
+    pub fn rule(&self, category: NameCategory) -> Option<&NamingRule> {
+        match category {
+            NameCategory::Type => self.types.as_ref(),
+            NameCategory::Interface => self.interfaces.as_ref(),
+            NameCategory::Field => self.fields.as_ref(),
+            NameCategory::Property => self.properties.as_ref(),
+            NameCategory::Routine => self.routines.as_ref(),
+            NameCategory::Parameter => self.parameters.as_ref(),
+            NameCategory::Local => self.locals.as_ref(),
+            NameCategory::Variable => self.variables.as_ref(),
+            NameCategory::Constant => self.constants.as_ref(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        [
+            &self.types,
+            &self.interfaces,
+            &self.fields,
+            &self.properties,
+            &self.routines,
+            &self.parameters,
+            &self.locals,
+            &self.variables,
+            &self.constants,
+        ]
+        .iter()
+        .all(|rule| rule.is_none())
+    }
+}
+
+/// How the names of a category are written: a prefix, such as `F` for
+/// fields, followed by a name in a preset style, and optionally a regular
+/// expression for conventions the presets do not cover.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NamingRule {
+    /// Text names start with, followed by anything but a lowercase letter,
+    /// e.g. `T` for `TCustomer`.
+    pub prefix: Option<String>,
+    /// The style of the name after the prefix.
+    pub style: Option<NamingStyle>,
+    /// A regular expression the whole name must match. An invalid
+    /// expression is ignored.
+    #[serde(deserialize_with = "deserialize_pattern")]
+    pub pattern: Option<Regex>,
+    pub severity: RuleSeverity,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum NamingStyle {
+    /// `CustomerName`
+    #[serde(rename = "pascalCase")]
+    Pascal,
+    /// `customerName`
+    #[serde(rename = "camelCase")]
+    Camel,
+    /// `MAX_CUSTOMERS`
+    #[serde(rename = "upperSnakeCase")]
+    UpperSnake,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RuleSeverity {
+    Error,
+    Warning,
+    Information,
+    #[default]
+    Hint,
+}
+
+impl RuleSeverity {
+    pub fn to_diagnostic_severity(self) -> DiagnosticSeverity {
+        match self {
+            RuleSeverity::Error => DiagnosticSeverity::ERROR,
+            RuleSeverity::Warning => DiagnosticSeverity::WARNING,
+            RuleSeverity::Information => DiagnosticSeverity::INFORMATION,
+            RuleSeverity::Hint => DiagnosticSeverity::HINT,
+        }
+    }
+}
+
+/// Compiles a `pattern` setting anchored at both ends, logging rather than
+/// rejecting the whole settings payload when it is invalid.
+fn deserialize_pattern<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Regex>, D::Error> {
+    let Some(pattern) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    match Regex::new(&format!("^(?:{})$", pattern)) {
+        Ok(regex) => Ok(Some(regex)),
+        Err(error) => {
+            log::warn!("Ignoring invalid naming pattern {:?}: {}", pattern, error);
+            Ok(None)
+        }
+    }
+}
+
+impl NamingRule {
+    pub fn accepts(&self, name: &str) -> bool {
+        let base = match &self.prefix {
+            Some(prefix) => match name.strip_prefix(prefix.as_str()) {
+                Some(base) if !base.is_empty() && !base.starts_with(char::is_lowercase) => base,
+                _ => return false,
+            },
+            None => name,
+        };
+        self.style.is_none_or(|style| style.accepts(base))
+            && self
+                .pattern
+                .as_ref()
+                .is_none_or(|pattern| pattern.is_match(name))
+    }
+
+    /// The name `name` would have under this rule, `None` when the rule
+    /// cannot be met by adding the prefix and changing the style, or when
+    /// the name already complies.
+    pub fn suggest(&self, name: &str) -> Option<String> {
+        if self.accepts(name) {
+            return None;
+        }
+        let mut base = name;
+        if let Some(prefix) = &self.prefix {
+            // `fName` and `FName` both have the prefix of `F`
+            if let Some(rest) = name.get(prefix.len()..).filter(|rest| {
+                name[..prefix.len()].eq_ignore_ascii_case(prefix)
+                    && rest.starts_with(char::is_uppercase)
+            }) {
+                base = rest;
+            }
+        }
+        let base = match self.style {
+            Some(style) if !style.accepts(base) => style.apply(base),
+            Some(_) => base.to_string(),
+            None if self.prefix.is_some() => capitalize(base),
+            None => base.to_string(),
+        };
+        let suggestion = format!("{}{}", self.prefix.as_deref().unwrap_or_default(), base);
+        (suggestion != name && self.accepts(&suggestion)).then_some(suggestion)
+    }
+}
+
+impl NamingStyle {
+    fn accepts(self, name: &str) -> bool {
+        let mut chars = name.chars();
+        let Some(first) = chars.next() else {
+            return false;
+        };
+        let rest = chars.as_str();
+        match self {
+            NamingStyle::Pascal => first.is_uppercase() && rest.chars().all(char::is_alphanumeric),
+            NamingStyle::Camel => first.is_lowercase() && rest.chars().all(char::is_alphanumeric),
+            NamingStyle::UpperSnake => {
+                first.is_uppercase()
+                    && rest
+                        .chars()
+                        .all(|c| c.is_uppercase() || c.is_ascii_digit() || c == '_')
+            }
+        }
+    }
+
+    fn apply(self, name: &str) -> String {
+        let words = split_words(name);
+        match self {
+            NamingStyle::Pascal => words.iter().map(|word| title_case(word)).collect(),
+            NamingStyle::Camel => {
+                let pascal: String = words.iter().map(|word| title_case(word)).collect();
+                let mut chars = pascal.chars();
+                chars
+                    .next()
+                    .map(|first| first.to_lowercase().chain(chars).collect())
+                    .unwrap_or_default()
+            }
+            NamingStyle::UpperSnake => words
+                .iter()
+                .map(|word| word.to_uppercase())
+                .collect::<Vec<_>>()
+                .join("_"),
+        }
+    }
+}
+
+/// Splits an identifier into words at underscores and case changes:
+/// `MAX_SIZE` to `MAX`, `SIZE` and `HTTPServerName` to `HTTP`, `Server`,
+/// `Name`.
+fn split_words(name: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    for part in name.split('_').filter(|part| !part.is_empty()) {
+        let chars: Vec<(usize, char)> = part.char_indices().collect();
+        let mut start = 0;
+        for i in 1..chars.len() {
+            let (offset, c) = chars[i];
+            let previous = chars[i - 1].1;
+            let next = chars.get(i + 1).map(|(_, next)| *next);
+            let boundary = c.is_uppercase()
+                && (previous.is_lowercase()
+                    || previous.is_ascii_digit()
+                    || (previous.is_uppercase() && next.is_some_and(char::is_lowercase)));
+            if boundary {
+                words.push(&part[start..offset]);
+                start = offset;
+            }
+        }
+        words.push(&part[start..]);
+    }
+    words
+}
+
+/// `Word` for `word` and `WORD`; words already mixing cases keep them.
+fn title_case(word: &str) -> String {
+    let mut chars = word.chars();
+    let Some(first) = chars.next() else {
+        return String::new();
+    };
+    let rest = chars.as_str();
+    let rest = if rest.chars().all(|c| !c.is_lowercase()) {
+        rest.to_lowercase()
+    } else {
+        rest.to_string()
+    };
+    first.to_uppercase().chain(rest.chars()).collect()
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    chars
+        .next()
+        .map(|first| first.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
@@ -1,5 +1,16 @@
+use crate::lsp::document::slice_text;
+use crate::lsp::text_position::LineIndex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
 use tower_lsp::lsp_types::*;
-use tree_sitter::{Language, Parser};
+use tree_sitter::{Language, Node, Parser, Tree};
+
+/// Code of the diagnostic reporting a token the parser could not place.
+pub const SYNTAX_ERROR: &str = "syntax-error";
+
+/// Code of the diagnostic reporting a token the parser expected but did not
+/// find, such as a `;` or an `end`.
+pub const MISSING_TOKEN: &str = "missing-token";
 
 extern "C" {
     fn tree_sitter_pascal() -> Language;
@@ -20,44 +31,79 @@ impl DelphiParser {
         Self { parser }
     }
 
-    pub fn parse(&mut self, text: &str) -> Option<tree_sitter::Tree> {
+    pub fn parse(&mut self, text: &str) -> Option<Tree> {
         self.parser.parse(text, None)
     }
 
-    pub fn get_diagnostics(&mut self, text: &str) -> Vec<Diagnostic> {
+    /// Parses `text` reusing the unchanged nodes of `old_tree`, which must
+    /// have been edited to match the text.
+    pub fn parse_incremental(&mut self, text: &str, old_tree: Option<&Tree>) -> Option<Tree> {
+        self.parser.parse(text, old_tree)
+    }
+
+    pub fn get_diagnostics(
+        &self,
+        tree: &Tree,
+        text: &str,
+        line_index: &LineIndex,
+    ) -> Vec<Diagnostic> {
         let mut diagnostics = Vec::new();
 
-        if let Some(tree) = self.parse(text) {
-            if tree.root_node().has_error() {
-                // Walk the tree to find syntax errors
-                let mut cursor = tree.walk();
-                self.collect_error_nodes(&mut cursor, text, &mut diagnostics);
-            }
+        if tree.root_node().has_error() {
+            // Walk the tree to find syntax errors
+            let mut cursor = tree.walk();
+            self.collect_error_nodes(&mut cursor, text, line_index, &mut diagnostics);
         }
 
         diagnostics
     }
 
+    /// Reports the tokens the parser inserted and the innermost ERROR
+    /// nodes, whose enclosing ERROR nodes are only the cascade of the same
+    /// mistake. Errors starting where one was already reported are dropped.
     fn collect_error_nodes(
         &self,
         cursor: &mut tree_sitter::TreeCursor,
-        source: &str,
+        text: &str,
+        line_index: &LineIndex,
         diagnostics: &mut Vec<Diagnostic>,
     ) {
-        if cursor.node().is_error() || cursor.node().is_missing() {
-            let range = self.node_range(cursor.node());
-            diagnostics.push(Diagnostic {
-                range,
-                severity: Some(DiagnosticSeverity::ERROR),
-                message: "Syntax error".to_string(),
-                source: Some("dls".to_string()),
-                ..Diagnostic::default()
-            });
+        let node = cursor.node();
+        let error = if node.is_missing() {
+            // Right after the previous token rather than before the next
+            let offset = text
+                .get(..node.start_byte())
+                .map_or(node.start_byte(), |before| before.trim_end().len());
+            let position = line_index.offset_to_position(offset);
+            Some((
+                MISSING_TOKEN,
+                Range::new(position, position),
+                format!("Missing {}", token_name(node.kind())),
+            ))
+        } else if node.is_error() && !has_error_child(node) {
+            Some(describe_error(node, text, line_index))
+        } else {
+            None
+        };
+        if let Some((code, range, message)) = error {
+            if !diagnostics
+                .iter()
+                .any(|diagnostic| diagnostic.range.start == range.start)
+            {
+                diagnostics.push(Diagnostic {
+                    range,
+                    severity: Some(DiagnosticSeverity::ERROR),
+                    code: Some(NumberOrString::String(code.to_string())),
+                    message,
+                    source: Some("dls".to_string()),
+                    ..Diagnostic::default()
+                });
+            }
         }
 
         if cursor.goto_first_child() {
             loop {
-                self.collect_error_nodes(cursor, source, diagnostics);
+                self.collect_error_nodes(cursor, text, line_index, diagnostics);
                 if !cursor.goto_next_sibling() {
                     break;
                 }
@@ -65,17 +111,346 @@ impl DelphiParser {
             cursor.goto_parent();
         }
     }
+}
 
-    fn node_range(&self, node: tree_sitter::Node) -> Range {
-        Range {
-            start: Position {
-                line: node.start_position().row as u32,
-                character: node.start_position().column as u32,
-            },
-            end: Position {
-                line: node.end_position().row as u32,
-                character: node.end_position().column as u32,
-            },
+/// Idle parsers shared by the handlers of the language server, so that
+/// requests running concurrently do not wait for each other's parse and a
+/// request never pays for constructing a parser. A parser is taken out for
+/// each parse and put back afterwards unless the pool is full.
+pub struct ParserPool {
+    idle: Mutex<Vec<DelphiParser>>,
+    size: AtomicUsize,
+}
+
+impl ParserPool {
+    pub fn new(size: usize) -> Self {
+        Self {
+            idle: Mutex::new(Vec::new()),
+            size: AtomicUsize::new(size),
         }
     }
+
+    /// Changes how many idle parsers the pool keeps, dropping the extra ones.
+    pub fn resize(&self, size: usize) {
+        self.size.store(size, Ordering::Relaxed);
+        self.idle.lock().unwrap().truncate(size);
+    }
+
+    /// Constructs parsers until the pool is full. Returns how many were
+    /// constructed.
+    pub fn fill(&self) -> usize {
+        let size = self.size.load(Ordering::Relaxed);
+        let missing = size.saturating_sub(self.idle.lock().unwrap().len());
+        // Constructed outside the lock, so that parses go on meanwhile
+        let parsers: Vec<DelphiParser> = (0..missing).map(|_| DelphiParser::new()).collect();
+        let mut idle = self.idle.lock().unwrap();
+        idle.extend(parsers);
+        idle.truncate(size);
+        missing
+    }
+
+    /// Runs `f` with an idle parser, or a new one when none is idle.
+    pub fn with<T>(&self, f: impl FnOnce(&mut DelphiParser) -> T) -> T {
+        let parser = self.idle.lock().unwrap().pop();
+        let mut parser = parser.unwrap_or_else(DelphiParser::new);
+        let result = f(&mut parser);
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < self.size.load(Ordering::Relaxed) {
+            idle.push(parser);
+        }
+        result
+    }
+}
+
+fn has_error_child(node: Node) -> bool {
+    let mut cursor = node.walk();
+    let has_error = node
+        .children(&mut cursor)
+        .any(|child| child.is_error() || child.has_error());
+    has_error
+}
+
+/// The code, range and message of an ERROR node: the token the parser
+/// could not place and the construct it appeared in, over the first line of
+/// the node only, as an error can swallow the rest of the file. A whole
+/// unit failing to parse up to its end is reported at the end of the file.
+fn describe_error(node: Node, text: &str, line_index: &LineIndex) -> (&'static str, Range, String) {
+    let parent = node.parent();
+    // A unit cut short parses into a lone ERROR root
+    if parent.is_none_or(|parent| parent.kind() == "root")
+        && node.end_byte() >= text.trim_end().len()
+    {
+        let end = line_index.offset_to_position(text.trim_end().len());
+        return (
+            SYNTAX_ERROR,
+            Range::new(end, end),
+            "Unexpected end of file".to_string(),
+        );
+    }
+
+    let start = line_index.offset_to_position(node.start_byte());
+    let mut end = line_index.offset_to_position(node.end_byte());
+    if end.line != start.line {
+        let line_end = line_index
+            .line_span(start.line as usize)
+            .map_or(node.end_byte(), |span| span.end);
+        end = line_index.offset_to_position(line_end);
+    }
+    let range = Range::new(start, end);
+
+    let mut token = node;
+    while let Some(child) = token.child(0) {
+        token = child;
+    }
+    let token_text = slice_text(text, token.byte_range(), || "a syntax error".to_string());
+    let token_text = token_text.lines().next().unwrap_or_default().trim();
+    let message = if token_text.starts_with('\'')
+        && (token_text.len() == 1 || !token_text.ends_with('\''))
+    {
+        "Unterminated string".to_string()
+    } else if token_text.is_empty() {
+        "Syntax error".to_string()
+    } else {
+        let context = std::iter::successors(parent, |node| node.parent()).find_map(context_name);
+        match context {
+            Some(context) => format!("Unexpected '{}' in {}", token_text, context),
+            None => format!("Unexpected '{}'", token_text),
+        }
+    };
+    (SYNTAX_ERROR, range, message)
+}
+
+/// How a missing token is named in a message: quoted source text for
+/// keywords and symbols, the node kind for anything else.
+fn token_name(kind: &str) -> String {
+    let symbol = match kind {
+        "kAssign" => ":=",
+        "kEndDot" => "end.",
+        "kDot" => ".",
+        "kEq" => "=",
+        "kNeq" => "<>",
+        "kLt" => "<",
+        "kLte" => "<=",
+        "kGt" => ">",
+        "kGte" => ">=",
+        "kAdd" => "+",
+        "kSub" => "-",
+        "kMul" => "*",
+        "kFdiv" => "/",
+        "kHat" => "^",
+        "kAt" => "@",
+        "identifier" => return "identifier".to_string(),
+        _ => match kind.strip_prefix('k') {
+            Some(keyword) if keyword.starts_with(|c: char| c.is_ascii_uppercase()) => {
+                return format!("'{}'", keyword.to_lowercase());
+            }
+            _ if kind.chars().all(|c| !c.is_alphanumeric()) => kind,
+            _ => return kind.to_string(),
+        },
+    };
+    format!("'{}'", symbol)
+}
+
+/// How an error's enclosing construct is named in its message, for the
+/// constructs worth naming.
+fn context_name(node: Node) -> Option<&'static str> {
+    Some(match node.kind() {
+        "declUses" => "uses clause",
+        "declTypes" | "declType" => "type declaration",
+        "declClass" | "declSection" => "class or record declaration",
+        "declIntf" => "interface declaration",
+        "declEnum" => "enumeration",
+        "declVars" | "declVar" => "variable declaration",
+        "declConsts" | "declConst" => "constant declaration",
+        "declArgs" | "declArg" => "parameter list",
+        "declProc" => "routine header",
+        "declProp" => "property declaration",
+        "defProc" => "routine",
+        "if" | "ifElse" => "'if' statement",
+        "case" | "caseCase" => "'case' statement",
+        "for" | "foreach" => "'for' loop",
+        "while" => "'while' loop",
+        "repeat" => "'repeat' loop",
+        "try" => "'try' statement",
+        "with" => "'with' statement",
+        "statement" | "assignment" => "statement",
+        "block" | "blockTr" | "statements" => "block",
+        "interface" => "interface section",
+        "implementation" => "implementation section",
+        "initialization" => "initialization section",
+        "finalization" => "finalization section",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsp::text_position::PositionEncoding;
+
+    /// The code, start, end and message of a diagnostic.
+    type Error = (String, (u32, u32), (u32, u32), String);
+
+    /// The code, range and message of each diagnostic of `snippet`, the
+    /// body of a routine of the implementation section, or a whole unit
+    /// when it starts with one. Ranges are `(line, column)` pairs
+    /// within the snippet.
+    fn errors(snippet: &str) -> Vec<Error> {
+        let (source, first_line) = if snippet.starts_with("unit ") {
+            (snippet.to_string(), 0)
+        } else {
+            (
+                format!(
+                    "unit U;\ninterface\nimplementation\nprocedure P;\n{}",
+                    snippet
+                ),
+                4,
+            )
+        };
+        let mut parser = DelphiParser::new();
+        let tree = parser.parse(&source).unwrap();
+        let line_index = LineIndex::new(&source, PositionEncoding::Utf16);
+        let diagnostics = parser.get_diagnostics(&tree, &source, &line_index);
+        for (i, diagnostic) in diagnostics.iter().enumerate() {
+            assert!(
+                diagnostics[..i]
+                    .iter()
+                    .all(|other| other.range.start != diagnostic.range.start),
+                "two errors at {:?}",
+                diagnostic.range.start
+            );
+        }
+        diagnostics
+            .into_iter()
+            .map(|diagnostic| {
+                let code = match diagnostic.code {
+                    Some(NumberOrString::String(code)) => code,
+                    code => panic!("unexpected code {:?}", code),
+                };
+                let position =
+                    |position: Position| (position.line - first_line, position.character);
+                (
+                    code,
+                    position(diagnostic.range.start),
+                    position(diagnostic.range.end),
+                    diagnostic.message,
+                )
+            })
+            .collect()
+    }
+
+    fn error(code: &str, start: (u32, u32), end: (u32, u32), message: &str) -> Error {
+        (code.to_string(), start, end, message.to_string())
+    }
+
+    #[test]
+    fn reports_no_errors_in_valid_code() {
+        assert!(errors("begin\n  X := (1 + 2) * Y[3];\nend;\nend.\n").is_empty());
+    }
+
+    #[test]
+    fn reports_a_missing_semicolon_after_the_previous_token() {
+        assert_eq!(
+            errors("begin\n  X := 1;\nend\nend.\n"),
+            [error(MISSING_TOKEN, (2, 3), (2, 3), "Missing ';'")]
+        );
+    }
+
+    #[test]
+    fn reports_missing_closing_brackets() {
+        assert_eq!(
+            errors("begin\n  X := (1 + 2;\nend;\nend.\n"),
+            [error(MISSING_TOKEN, (1, 13), (1, 13), "Missing ')'")]
+        );
+        assert_eq!(
+            errors("begin\n  X[1 := 2;\nend;\nend.\n"),
+            [error(MISSING_TOKEN, (1, 5), (1, 5), "Missing ']'")]
+        );
+    }
+
+    #[test]
+    fn reports_a_missing_final_dot() {
+        assert_eq!(
+            errors("unit U;\ninterface\nimplementation\nend\n"),
+            [error(MISSING_TOKEN, (3, 3), (3, 3), "Missing '.'")]
+        );
+    }
+
+    #[test]
+    fn reports_an_unclosed_string() {
+        assert_eq!(
+            errors("begin\n  S := 'abc;\nend;\nend.\n"),
+            [error(SYNTAX_ERROR, (1, 7), (1, 12), "Unterminated string")]
+        );
+    }
+
+    #[test]
+    fn reports_a_stray_end_dot() {
+        assert_eq!(
+            errors("begin\nend;\nend.\nend.\n"),
+            [error(SYNTAX_ERROR, (2, 3), (2, 4), "Unexpected '.'")]
+        );
+    }
+
+    #[test]
+    fn names_the_construct_of_an_unexpected_token() {
+        assert_eq!(
+            errors("unit U;\ninterface\nuses A B;\nimplementation\nend.\n"),
+            [error(
+                SYNTAX_ERROR,
+                (2, 7),
+                (2, 8),
+                "Unexpected 'B' in uses clause"
+            )]
+        );
+        assert_eq!(
+            errors(
+                "unit U;\ninterface\ntype\n  T = class\n    begin\n  end;\nimplementation\nend.\n"
+            ),
+            [error(
+                SYNTAX_ERROR,
+                (4, 4),
+                (4, 9),
+                "Unexpected 'begin' in class or record declaration"
+            )]
+        );
+    }
+
+    #[test]
+    fn reports_each_line_of_repeated_mistakes() {
+        assert_eq!(
+            errors("begin\n  X := ;\n  X := ;\nend;\nend.\n"),
+            [
+                error(SYNTAX_ERROR, (1, 4), (1, 6), "Unexpected ':=' in statement"),
+                error(SYNTAX_ERROR, (2, 4), (2, 6), "Unexpected ':=' in statement"),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_errors_spanning_lines_to_their_first_line() {
+        // The ERROR node runs from `B` to `C` on the next line
+        assert_eq!(
+            errors("unit U;\ninterface\nuses A B\n  C D;\nimplementation\nend.\n"),
+            [error(
+                SYNTAX_ERROR,
+                (2, 7),
+                (2, 8),
+                "Unexpected 'B' in uses clause"
+            )]
+        );
+    }
+
+    #[test]
+    fn reports_a_unit_cut_short_at_the_end_of_the_file() {
+        assert_eq!(
+            errors("begin\n  if X then\n    Y := 1;\n\n"),
+            [error(
+                SYNTAX_ERROR,
+                (2, 11),
+                (2, 11),
+                "Unexpected end of file"
+            )]
+        );
+    }
 }
@@ -0,0 +1,645 @@
+use crate::lsp::analysis_error::AnalysisFailure;
+use crate::lsp::analyzer::{
+    AnalysisMode, EnclosingBlock, ExternalImport, ExternalLibrary, SymbolPath,
+};
+use crate::lsp::directives::Dialect;
+use crate::lsp::members::{MemberKind, Visibility};
+use crate::lsp::stats::RequestLatency;
+use crate::lsp::symbol_id::SymbolId;
+use crate::lsp::text_position::LineIndex;
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::sync::OnceLock;
+use tower_lsp::lsp_types::notification::Notification;
+use tower_lsp::lsp_types::{
+    Diagnostic, DocumentSymbol, Location, Position, ProgressToken, Range, SymbolKind,
+    TextDocumentIdentifier,
+};
+use tree_sitter::Node;
+
+/// The version of the custom requests, notifications and commands below,
+/// reported in `initialize` and by `dls/capabilities`. It is raised on
+/// changes that break existing clients; additions keep it.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Commands handled by `workspace/executeCommand`.
+pub const SELECT_ENCLOSING_BLOCK_COMMAND: &str = "dls.selectEnclosingBlock";
+pub const RESOLVE_SYMBOL_COMMAND: &str = "dls.resolveSymbol";
+pub const SWITCH_COMPANION_COMMAND: &str = "dls.switchCompanion";
+pub const SYMBOL_PATH_COMMAND: &str = "dls.symbolPath";
+pub const OPEN_UNIT_COMMAND: &str = "dls.openUnit";
+pub const SHOW_DOCUMENT_DIAGNOSTICS_COMMAND: &str = "dls.showDocumentDiagnostics";
+pub const ANALYZE_FULLY_COMMAND: &str = "dls.analyzeFully";
+pub const TOGGLE_COMMENT_COMMAND: &str = "dls.toggleComment";
+pub const CLEAR_CACHE_COMMAND: &str = "dls.clearCache";
+
+pub const COMMANDS: &[&str] = &[
+    SELECT_ENCLOSING_BLOCK_COMMAND,
+    RESOLVE_SYMBOL_COMMAND,
+    SWITCH_COMPANION_COMMAND,
+    SYMBOL_PATH_COMMAND,
+    OPEN_UNIT_COMMAND,
+    SHOW_DOCUMENT_DIAGNOSTICS_COMMAND,
+    ANALYZE_FULLY_COMMAND,
+    TOGGLE_COMMENT_COMMAND,
+    CLEAR_CACHE_COMMAND,
+];
+
+/// A custom request, as registered with `LspService::build`. Unlike
+/// `lsp_types::request::Request` it needs no deserializable result, only
+/// the schemas of its payloads.
+pub trait CustomRequest {
+    type Params: JsonSchema;
+    type Result: JsonSchema;
+    const METHOD: &'static str;
+}
+
+/// Lists the custom requests, notifications and commands this build
+/// supports, with the JSON schema of their payloads.
+pub enum Capabilities {}
+
+impl CustomRequest for Capabilities {
+    type Params = CapabilitiesParams;
+    type Result = ProtocolCapabilities;
+    const METHOD: &'static str = "dls/capabilities";
+}
+
+pub enum Externals {}
+
+impl CustomRequest for Externals {
+    type Params = ExternalsParams;
+    type Result = Vec<ExternalLibrary>;
+    const METHOD: &'static str = "dls/externals";
+}
+
+/// Reports how far indexing the workspace got. Lookups are served from
+/// the units indexed so far.
+pub enum IndexStatusRequest {}
+
+impl CustomRequest for IndexStatusRequest {
+    type Params = IndexStatusParams;
+    type Result = IndexStatus;
+    const METHOD: &'static str = "dls/indexStatus";
+}
+
+pub enum Outline {}
+
+impl CustomRequest for Outline {
+    type Params = OutlineParams;
+    type Result = Vec<OutlineSymbol>;
+    const METHOD: &'static str = "dls/outline";
+}
+
+pub enum ParseText {}
+
+impl CustomRequest for ParseText {
+    type Params = ParseTextParams;
+    type Result = ParseTextResult;
+    const METHOD: &'static str = "dls/parseText";
+}
+
+pub enum Status {}
+
+impl CustomRequest for Status {
+    type Params = StatusParams;
+    type Result = Option<DocumentStatus>;
+    const METHOD: &'static str = "dls/status";
+}
+
+pub enum TypeMembersRequest {}
+
+impl CustomRequest for TypeMembersRequest {
+    type Params = TypeMembersParams;
+    type Result = Option<TypeMembers>;
+    const METHOD: &'static str = "dls/typeMembers";
+}
+
+/// `$/progress` carrying a batch of partial results for a request sent with
+/// a `partialResultToken`. `lsp_types::Progress` only models work-done
+/// progress values.
+pub enum PartialResults {}
+
+impl Notification for PartialResults {
+    type Params = PartialResultsParams;
+    const METHOD: &'static str = "$/progress";
+}
+
+/// Sent when the client opens one of the RTL stubs the server extracted,
+/// so the editor can make its buffer read-only.
+pub enum ReadOnlyDocument {}
+
+impl Notification for ReadOnlyDocument {
+    type Params = ReadOnlyDocumentParams;
+    const METHOD: &'static str = "dls/readOnlyDocument";
+}
+
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadOnlyDocumentParams {
+    #[schemars(with = "lsp_schema::TextDocumentIdentifier")]
+    pub text_document: TextDocumentIdentifier,
+}
+
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+pub struct PartialResultsParams {
+    #[schemars(with = "lsp_schema::ProgressToken")]
+    pub token: ProgressToken,
+    /// The batch, shaped like the final result of the request.
+    pub value: Value,
+}
+
+/// Parameters of `dls/capabilities`.
+#[derive(Debug, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CapabilitiesParams {
+    /// The protocol version the client was written against. A version the
+    /// server does not support fails the request with `InvalidParams`,
+    /// listing the supported versions in the error data.
+    #[serde(default)]
+    pub protocol_version: Option<u32>,
+}
+
+/// Result of `dls/capabilities`.
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ProtocolCapabilities {
+    pub protocol_version: u32,
+    /// The methods of the custom requests.
+    pub requests: Vec<String>,
+    /// The methods of the custom notifications, in either direction.
+    pub notifications: Vec<String>,
+    /// The commands of `workspace/executeCommand`.
+    pub commands: Vec<String>,
+    /// The JSON schema of the parameters and results of the requests,
+    /// notifications and commands, as returned by [`protocol_schema`].
+    pub schema: Value,
+}
+
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ExternalsParams {
+    #[schemars(with = "lsp_schema::TextDocumentIdentifier")]
+    pub text_document: TextDocumentIdentifier,
+}
+
+/// Parameters of `dls/outline`. Every filter is optional; a symbol is kept
+/// when it passes all given filters or when one of its descendants does, so
+/// matches keep their enclosing symbols for context.
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OutlineParams {
+    #[schemars(with = "lsp_schema::TextDocumentIdentifier")]
+    pub text_document: TextDocumentIdentifier,
+    /// Only symbols of these kinds.
+    #[serde(default)]
+    #[schemars(with = "Option<Vec<lsp_schema::SymbolKind>>")]
+    pub kinds: Option<Vec<SymbolKind>>,
+    /// Only symbols with one of these visibilities. Symbols outside a type
+    /// count as public in the interface section and as private in the
+    /// implementation section.
+    #[serde(default)]
+    pub visibility: Option<Vec<Visibility>>,
+    /// Only symbols whose name contains this text, ignoring case.
+    #[serde(default)]
+    pub query: Option<String>,
+}
+
+/// The section of a unit a declaration belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum Section {
+    Interface,
+    Implementation,
+}
+
+/// A node of the `dls/outline` tree: a document symbol with the details
+/// `DocumentSymbol` has no room for.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OutlineSymbol {
+    pub name: String,
+    #[schemars(with = "lsp_schema::SymbolKind")]
+    pub kind: SymbolKind,
+    pub detail: Option<String>,
+    #[schemars(with = "lsp_schema::Range")]
+    pub range: Range,
+    #[schemars(with = "lsp_schema::Range")]
+    pub selection_range: Range,
+    pub id: SymbolId,
+    /// The visibility of a type member, `None` outside types.
+    pub visibility: Option<Visibility>,
+    /// Routine directives such as `class`, `static`, `virtual`, `override`
+    /// or `overload`, lowercase and in source order.
+    pub directives: Vec<String>,
+    pub deprecated: bool,
+    /// `None` in programs and libraries, which have no sections.
+    pub section: Option<Section>,
+    pub external: Option<ExternalImport>,
+    pub children: Vec<OutlineSymbol>,
+}
+
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusParams {
+    #[schemars(with = "lsp_schema::TextDocumentIdentifier")]
+    pub text_document: TextDocumentIdentifier,
+}
+
+/// Result of `dls/status`: how the server currently reads a document.
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentStatus {
+    /// The dialect from the `{$MODE}` directive or the file extension.
+    pub dialect: Dialect,
+    /// The version of the analyzed text, `None` for files read from disk.
+    pub version: Option<i32>,
+    /// Whether the document is fully analyzed or, being large, only for
+    /// its outline.
+    pub analysis_mode: AnalysisMode,
+    /// Whether the file of the document was deleted or moved on disk.
+    pub detached: bool,
+    /// Whether the text starts with a byte order mark, which the server
+    /// strips so that positions never count it.
+    pub byte_order_mark: bool,
+    /// The share of the text inside syntax error subtrees, from 0 to 100.
+    /// Features degrade on documents the grammar handles poorly.
+    pub error_percentage: f64,
+    /// The last requests on the document that found nothing, and why.
+    pub recent_failures: Vec<AnalysisFailure>,
+    /// How long the server took to answer requests, on any document.
+    pub request_latencies: Vec<RequestLatency>,
+}
+
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexStatusParams {
+    /// A document whose used units to report on.
+    #[serde(default)]
+    #[schemars(with = "Option<lsp_schema::TextDocumentIdentifier>")]
+    pub text_document: Option<TextDocumentIdentifier>,
+}
+
+/// Result of `dls/indexStatus`.
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexStatus {
+    pub state: IndexState,
+    pub indexed_units: usize,
+    pub total_units: usize,
+    /// The units the open documents use that are not indexed yet, which
+    /// go before the others.
+    pub prioritized_units: usize,
+    /// Whether the workspace units the requested document uses are all
+    /// indexed, so that lookups from it are complete.
+    pub document_ready: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum IndexState {
+    /// The workspace folders are being listed; nothing is served yet.
+    Scanning,
+    /// Some units are indexed, and lookups find what they declare.
+    Partial,
+    Complete,
+}
+
+/// Result of the `dls.showDocumentDiagnostics` command: why hover and go to
+/// definition find nothing at a position.
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PositionDiagnosis {
+    /// Why hover shows nothing, `None` when it shows something.
+    pub hover: Option<AnalysisFailure>,
+    /// Why go to definition finds nothing, `None` when it finds something.
+    pub definition: Option<AnalysisFailure>,
+    pub recent_failures: Vec<AnalysisFailure>,
+    pub request_latencies: Vec<RequestLatency>,
+}
+
+/// Parameters of `dls/parseText`: a snippet to parse on its own, outside
+/// any document.
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ParseTextParams {
+    pub text: String,
+    /// How to return the syntax tree.
+    #[serde(default)]
+    pub format: TreeFormat,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum TreeFormat {
+    /// An s-expression, as printed by the command line parser.
+    #[default]
+    Sexp,
+    /// A [`TreeNode`] tree.
+    Json,
+}
+
+/// Result of `dls/parseText`: what the server makes of a snippet, for
+/// debugging the grammar and the analyzer on it.
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ParseTextResult {
+    /// The tree in the requested format: a string or a [`TreeNode`].
+    #[schemars(with = "SyntaxTree")]
+    pub tree: Value,
+    /// The syntax errors and the analyzer diagnostics of the snippet.
+    #[schemars(with = "Vec<lsp_schema::Diagnostic>")]
+    pub diagnostics: Vec<Diagnostic>,
+    /// The document symbols of the snippet.
+    #[schemars(with = "Vec<lsp_schema::DocumentSymbol>")]
+    pub symbols: Vec<DocumentSymbol>,
+}
+
+/// The schema of [`ParseTextResult::tree`], which is built as a `Value`.
+#[derive(JsonSchema)]
+#[serde(untagged)]
+#[allow(dead_code)]
+enum SyntaxTree {
+    Sexp(String),
+    Json(TreeNode),
+}
+
+/// A named node of a syntax tree, as printed by `--emit json` and returned
+/// by `dls/parseText`. Positions are in the encoding of `line_index`.
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TreeNode {
+    pub kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    pub start_byte: usize,
+    pub end_byte: usize,
+    #[schemars(with = "lsp_schema::Range")]
+    pub range: Range,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<TreeNode>,
+}
+
+impl TreeNode {
+    pub fn new(node: Node, field: Option<&str>, line_index: &LineIndex) -> Self {
+        let mut children = Vec::new();
+        let mut cursor = node.walk();
+        if cursor.goto_first_child() {
+            loop {
+                if cursor.node().is_named() {
+                    children.push(TreeNode::new(
+                        cursor.node(),
+                        cursor.field_name(),
+                        line_index,
+                    ));
+                }
+                if !cursor.goto_next_sibling() {
+                    break;
+                }
+            }
+        }
+        Self {
+            kind: node.kind().to_string(),
+            field: field.map(str::to_string),
+            start_byte: node.start_byte(),
+            end_byte: node.end_byte(),
+            range: line_index.byte_range_to_range(node.byte_range()),
+            children,
+        }
+    }
+}
+
+/// Parameters of `dls/typeMembers`, shaped like
+/// `TextDocumentPositionParams`.
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TypeMembersParams {
+    #[schemars(with = "lsp_schema::TextDocumentIdentifier")]
+    pub text_document: TextDocumentIdentifier,
+    #[schemars(with = "lsp_schema::Position")]
+    pub position: Position,
+}
+
+/// Result of `dls/typeMembers`: a structured type and the members it
+/// declares and inherits.
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TypeMembers {
+    pub name: String,
+    #[schemars(with = "lsp_schema::Location")]
+    pub location: Location,
+    /// The ancestors declared in the document, nearest first.
+    pub ancestors: Vec<String>,
+    /// The members of the type, then those of each ancestor not hidden by
+    /// a descendant, each in declaration order.
+    pub members: Vec<TypeMember>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TypeMember {
+    pub name: String,
+    pub kind: MemberKind,
+    /// The declaration text, such as `procedure Run(X: Integer)`.
+    pub signature: String,
+    /// The type of a field or property, or the result type of a function.
+    pub type_name: Option<String>,
+    pub visibility: Visibility,
+    /// The type declaring the member, an ancestor for inherited members.
+    pub declaring_type: String,
+    pub inherited: bool,
+    #[schemars(with = "lsp_schema::Location")]
+    pub location: Location,
+}
+
+/// The JSON schema of the custom protocol: for each request its `params`
+/// and `result`, for each notification its `params`, and for each command
+/// its `arguments` array and result, with the types they share under
+/// `definitions`. Generated from the types of this module on first use.
+pub fn protocol_schema() -> &'static Value {
+    static SCHEMA: OnceLock<Value> = OnceLock::new();
+    SCHEMA.get_or_init(|| {
+        use lsp_schema::{DocumentUri, Position, Range};
+        let mut schema = ProtocolSchema::default();
+        schema.request::<Capabilities>();
+        schema.request::<Externals>();
+        schema.request::<IndexStatusRequest>();
+        schema.request::<Outline>();
+        schema.request::<ParseText>();
+        schema.request::<Status>();
+        schema.request::<TypeMembersRequest>();
+        schema.notification::<PartialResults>();
+        schema.notification::<ReadOnlyDocument>();
+        schema.command::<(DocumentUri, Position), Vec<EnclosingBlock>>(
+            SELECT_ENCLOSING_BLOCK_COMMAND,
+        );
+        schema.command::<(DocumentUri, SymbolId), Option<lsp_schema::Location>>(
+            RESOLVE_SYMBOL_COMMAND,
+        );
+        schema.command::<(DocumentUri,), Vec<DocumentUri>>(SWITCH_COMPANION_COMMAND);
+        schema.command::<(DocumentUri, Position), Option<SymbolPath>>(SYMBOL_PATH_COMMAND);
+        schema.command::<(String,), Vec<DocumentUri>>(OPEN_UNIT_COMMAND);
+        schema.command::<(DocumentUri, Position), PositionDiagnosis>(
+            SHOW_DOCUMENT_DIAGNOSTICS_COMMAND,
+        );
+        schema.command::<(DocumentUri,), ()>(ANALYZE_FULLY_COMMAND);
+        schema.command::<(DocumentUri, Range), Vec<lsp_schema::TextEdit>>(TOGGLE_COMMENT_COMMAND);
+        schema.command::<[Value; 0], usize>(CLEAR_CACHE_COMMAND);
+        schema.finish()
+    })
+}
+
+/// Collects the schemas of the messages of [`protocol_schema`], sharing
+/// the definitions of the types they use.
+struct ProtocolSchema {
+    generator: SchemaGenerator,
+    requests: Map<String, Value>,
+    notifications: Map<String, Value>,
+    commands: Map<String, Value>,
+}
+
+impl Default for ProtocolSchema {
+    fn default() -> Self {
+        Self {
+            generator: SchemaGenerator::new(SchemaSettings::draft07()),
+            requests: Map::new(),
+            notifications: Map::new(),
+            commands: Map::new(),
+        }
+    }
+}
+
+impl ProtocolSchema {
+    fn subschema<T: JsonSchema>(&mut self) -> Value {
+        serde_json::to_value(self.generator.subschema_for::<T>()).unwrap()
+    }
+
+    fn request<R: CustomRequest>(&mut self) {
+        let entry = json!({
+            "params": self.subschema::<R::Params>(),
+            "result": self.subschema::<R::Result>(),
+        });
+        self.requests.insert(R::METHOD.to_string(), entry);
+    }
+
+    fn notification<N: Notification>(&mut self)
+    where
+        N::Params: JsonSchema,
+    {
+        let entry = json!({ "params": self.subschema::<N::Params>() });
+        self.notifications.insert(N::METHOD.to_string(), entry);
+    }
+
+    /// Adds a command taking the arguments of the tuple `A`.
+    fn command<A: JsonSchema, R: JsonSchema>(&mut self, command: &str) {
+        let entry = json!({
+            "arguments": self.subschema::<A>(),
+            "result": self.subschema::<R>(),
+        });
+        self.commands.insert(command.to_string(), entry);
+    }
+
+    fn finish(self) -> Value {
+        json!({
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "title": "Delphi Language Server protocol extensions",
+            "protocolVersion": PROTOCOL_VERSION,
+            "requests": self.requests,
+            "notifications": self.notifications,
+            "commands": self.commands,
+            "definitions": self.generator.definitions(),
+        })
+    }
+}
+
+/// Schema stand-ins for the `lsp_types` types the protocol uses, which do
+/// not implement `JsonSchema`. They are only named in `#[schemars(with)]`
+/// attributes and never built; they describe the wire format of the LSP
+/// specification, leaving out optional properties the server never sends.
+#[allow(dead_code)]
+pub mod lsp_schema {
+    use schemars::JsonSchema;
+    use serde_json::Value;
+
+    /// A document URI, such as `file:///C:/Project/Unit1.pas`.
+    #[derive(JsonSchema)]
+    #[serde(transparent)]
+    pub struct DocumentUri(String);
+
+    /// A zero-based line and character offset, in the negotiated position
+    /// encoding.
+    #[derive(JsonSchema)]
+    pub struct Position {
+        pub line: u32,
+        pub character: u32,
+    }
+
+    /// A range with an exclusive end.
+    #[derive(JsonSchema)]
+    pub struct Range {
+        pub start: Position,
+        pub end: Position,
+    }
+
+    #[derive(JsonSchema)]
+    pub struct Location {
+        pub uri: DocumentUri,
+        pub range: Range,
+    }
+
+    #[derive(JsonSchema)]
+    pub struct TextDocumentIdentifier {
+        pub uri: DocumentUri,
+    }
+
+    #[derive(JsonSchema)]
+    #[serde(rename_all = "camelCase")]
+    pub struct TextEdit {
+        pub range: Range,
+        pub new_text: String,
+    }
+
+    #[derive(JsonSchema)]
+    #[serde(untagged)]
+    pub enum ProgressToken {
+        Number(i32),
+        String(String),
+    }
+
+    /// The LSP `SymbolKind` number, such as 5 for a class or 12 for a
+    /// function.
+    #[derive(JsonSchema)]
+    #[serde(transparent)]
+    pub struct SymbolKind(u32);
+
+    #[derive(JsonSchema)]
+    #[serde(untagged)]
+    pub enum DiagnosticCode {
+        Number(i32),
+        String(String),
+    }
+
+    #[derive(JsonSchema)]
+    pub struct Diagnostic {
+        pub range: Range,
+        /// 1 for errors, 2 warnings, 3 information and 4 hints.
+        pub severity: Option<u32>,
+        pub code: Option<DiagnosticCode>,
+        pub source: Option<String>,
+        pub message: String,
+        /// What the quick fixes of the diagnostic need, if any.
+        pub data: Option<Value>,
+    }
+
+    #[derive(JsonSchema)]
+    #[serde(rename_all = "camelCase")]
+    pub struct DocumentSymbol {
+        pub name: String,
+        pub detail: Option<String>,
+        pub kind: SymbolKind,
+        pub range: Range,
+        pub selection_range: Range,
+        pub children: Option<Vec<DocumentSymbol>>,
+    }
+}
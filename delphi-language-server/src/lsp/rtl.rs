@@ -0,0 +1,255 @@
+use crate::lsp::cache;
+use crate::lsp::document::slice_text;
+use crate::lsp::members::TypeTable;
+use crate::lsp::parser::DelphiParser;
+use crate::lsp::text_position::{LineIndex, PositionEncoding};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tower_lsp::lsp_types::*;
+use tree_sitter::Node;
+
+/// Declaration-only sources of the RTL units the server knows, embedded in
+/// the binary and extracted to [`stub_dir`] on first use.
+const STUBS: &[(&str, &str)] = &[
+    ("System", include_str!("../../data/rtl/System.pas")),
+    (
+        "System.SysUtils",
+        include_str!("../../data/rtl/System.SysUtils.pas"),
+    ),
+    (
+        "System.Classes",
+        include_str!("../../data/rtl/System.Classes.pas"),
+    ),
+    (
+        "Winapi.Messages",
+        include_str!("../../data/rtl/Winapi.Messages.pas"),
+    ),
+];
+
+/// An identifier the document does not declare, to look up in the RTL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RtlQuery {
+    /// A unit-level routine or type, such as `FreeAndNil` or `TStringList`.
+    Name(String),
+    /// A member reached through a value or type, such as `List.Add`.
+    Member { type_name: String, member: String },
+}
+
+impl RtlQuery {
+    /// The name looked up: the routine or type, or the member.
+    pub fn name(&self) -> &str {
+        match self {
+            RtlQuery::Name(name) => name,
+            RtlQuery::Member { member, .. } => member,
+        }
+    }
+}
+
+/// A declaration found in an RTL stub.
+#[derive(Debug, Clone)]
+pub struct RtlDeclaration {
+    pub unit: &'static str,
+    /// The declaration in the extracted stub file; `None` when the stubs
+    /// could not be written to disk.
+    pub location: Option<Location>,
+    /// The declaration text, shown on hover.
+    pub declaration: String,
+}
+
+struct StubUnit {
+    name: &'static str,
+    uri: Option<Url>,
+    /// Unit-level types, routines and constants by lowercase name, with the
+    /// range of their name and their declaration text.
+    declarations: HashMap<String, (Range, String)>,
+    types: TypeTable,
+}
+
+/// The parsed RTL stubs.
+pub struct RtlStubs {
+    units: Vec<StubUnit>,
+}
+
+impl RtlStubs {
+    /// Parses the embedded stubs and extracts them to [`stub_dir`]. A stub
+    /// is rewritten whenever it differs from the embedded text, so that
+    /// the locations computed here always match the file on disk.
+    pub fn load() -> Self {
+        let dir = stub_dir();
+        if let Err(error) = fs::create_dir_all(&dir) {
+            log::warn!("Cannot create {}: {}", dir.display(), error);
+        }
+        let mut parser = DelphiParser::new();
+        let units = STUBS
+            .iter()
+            .map(|(name, source)| {
+                let path = dir.join(format!("{}.pas", name));
+                let uri = extract(&path, source)
+                    .then(|| Url::from_file_path(&path).ok())
+                    .flatten();
+                let line_index = LineIndex::new(source, PositionEncoding::default());
+                let mut declarations = HashMap::new();
+                let mut types = TypeTable::default();
+                if let Some(tree) = parser.parse(source) {
+                    collect_declarations(tree.root_node(), source, &line_index, &mut declarations);
+                    types = TypeTable::build(tree.root_node(), source, &line_index);
+                }
+                StubUnit {
+                    name,
+                    uri,
+                    declarations,
+                    types,
+                }
+            })
+            .collect();
+        Self { units }
+    }
+
+    /// Extracts the stubs again, after the cache directory was cleared
+    /// under the locations handed out.
+    pub fn restore(&self) {
+        let dir = stub_dir();
+        for (name, source) in STUBS {
+            extract(&dir.join(format!("{}.pas", name)), source);
+        }
+    }
+
+    pub fn find(&self, query: &RtlQuery) -> Option<RtlDeclaration> {
+        match query {
+            RtlQuery::Name(name) => self.units.iter().find_map(|unit| {
+                let (range, declaration) = unit.declarations.get(&name.to_lowercase())?;
+                Some(unit.declaration(*range, declaration.clone()))
+            }),
+            // `SysUtils.FreeAndNil` qualifies a routine by its unit
+            RtlQuery::Member { type_name, member } => {
+                self.find_member(type_name, member).or_else(|| {
+                    self.is_unit_name(type_name)
+                        .then(|| self.find(&RtlQuery::Name(member.clone())))
+                        .flatten()
+                })
+            }
+        }
+    }
+
+    /// Whether `name` names a stub unit, in full or without its unit scope
+    /// (`SysUtils` for `System.SysUtils`).
+    pub fn is_unit_name(&self, name: &str) -> bool {
+        self.units.iter().any(|unit| names_unit(name, unit.name))
+    }
+
+    /// Looks a member up in a type and its ancestors, following the
+    /// inheritance chain across units (`TStringList` to `TObject`).
+    fn find_member(&self, type_name: &str, member_name: &str) -> Option<RtlDeclaration> {
+        let mut type_name = type_name.to_string();
+        // Each step crosses into another unit; bounded against cycles
+        for _ in 0..8 {
+            let unit = self
+                .units
+                .iter()
+                .find(|unit| unit.types.get(&type_name).is_some())?;
+            if let Some((_, member)) = unit.types.find_member(&type_name, member_name) {
+                return Some(unit.declaration(member.range, member.detail.clone()));
+            }
+            type_name = unit
+                .types
+                .ancestors(&type_name)
+                .last()?
+                .parents
+                .first()?
+                .clone();
+        }
+        None
+    }
+}
+
+impl StubUnit {
+    fn declaration(&self, range: Range, declaration: String) -> RtlDeclaration {
+        RtlDeclaration {
+            unit: self.name,
+            location: self.uri.clone().map(|uri| Location { uri, range }),
+            declaration,
+        }
+    }
+}
+
+/// Whether the name of a uses clause entry names `unit`, in full or without
+/// its unit scope (`SysUtils` for `System.SysUtils`).
+pub fn names_unit(name: &str, unit: &str) -> bool {
+    unit.eq_ignore_ascii_case(name)
+        || unit
+            .rsplit('.')
+            .next()
+            .is_some_and(|last| last.eq_ignore_ascii_case(name))
+}
+
+/// Whether every unit uses `unit` without listing it.
+pub fn is_implicitly_used(unit: &str) -> bool {
+    unit.eq_ignore_ascii_case("System") || unit.eq_ignore_ascii_case("SysInit")
+}
+
+/// Where the stubs are extracted: a per-version directory in the user's
+/// cache directory.
+pub fn stub_dir() -> PathBuf {
+    cache::cache_dir()
+        .unwrap_or_else(|| env::temp_dir().join("delphi-language-server"))
+        .join(format!("rtl-{}", env!("CARGO_PKG_VERSION")))
+}
+
+/// Whether `uri` is one of the extracted stubs, which are not to be edited.
+pub fn is_stub(uri: &Url) -> bool {
+    uri.to_file_path()
+        .is_ok_and(|path| path.starts_with(stub_dir()))
+}
+
+/// Writes `source` to `path` unless it already holds it. Returns whether
+/// the file is in place.
+fn extract(path: &Path, source: &str) -> bool {
+    if fs::read_to_string(path).is_ok_and(|existing| existing == source) {
+        return true;
+    }
+    match cache::write_atomic(path, source.as_bytes()) {
+        Ok(()) => true,
+        Err(error) => {
+            log::warn!("Cannot extract RTL stub {}: {}", path.display(), error);
+            false
+        }
+    }
+}
+
+/// Collects the types, routines and constants of the interface section,
+/// without descending into types, whose members the type table covers.
+fn collect_declarations(
+    node: Node,
+    source: &str,
+    line_index: &LineIndex,
+    declarations: &mut HashMap<String, (Range, String)>,
+) {
+    match node.kind() {
+        "implementation" => return,
+        "declType" | "declProc" | "declConst" => {
+            if let Some(name) = node.child_by_field_name("name") {
+                let text = |node: Node| {
+                    slice_text(source, node.byte_range(), || "an RTL stub".to_string())
+                };
+                // The first line of a type, the whole header of a routine or
+                // constant
+                let declaration = text(node).lines().next().unwrap_or_default();
+                declarations.insert(
+                    text(name).to_lowercase(),
+                    (
+                        line_index.byte_range_to_range(name.byte_range()),
+                        declaration.trim_end().to_string(),
+                    ),
+                );
+            }
+            return;
+        }
+        _ => {}
+    }
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        collect_declarations(child, source, line_index, declarations);
+    }
+}
@@ -1,209 +1,3173 @@
-use crate::lsp::analyzer::SymbolAnalyzer;
-use crate::lsp::parser::DelphiParser;
-use std::collections::HashMap;
-use std::sync::Mutex;
-use tower_lsp::jsonrpc::Result;
+use crate::lsp::analysis_error::{AnalysisError, AnalysisFailure, FailureLog};
+use crate::lsp::analyzer::{
+    self, AnalysisMode, AnalysisSnapshot, ChainResolver, ExternalLibrary, Provenance, RenameKind,
+    RenameOccurrence, Resolution, SymbolAnalyzer, DUPLICATE_GUID, INVALID_GUID, MISSING_OVERLOAD,
+    RESERVED_IDENTIFIER, SHADOWED_INTRINSIC, UNDEFINED_CONDITIONAL, UNRESOLVED_UNIT,
+    UNUSED_PRIVATE_MEMBER,
+};
+use crate::lsp::balance;
+use crate::lsp::cache;
+use crate::lsp::characters;
+use crate::lsp::comments;
+use crate::lsp::conditionals::{self, ConditionalSite, ConditionalUse, Definition};
+use crate::lsp::config::{self, ProjectConfig, Settings, PROJECT_FILE};
+use crate::lsp::directives;
+use crate::lsp::document::{read_source, Document};
+use crate::lsp::fixes;
+use crate::lsp::guid::InterfaceGuid;
+use crate::lsp::keywords;
+use crate::lsp::naming::NAMING_CONVENTION;
+use crate::lsp::parser::{DelphiParser, ParserPool};
+use crate::lsp::protocol_ext::{
+    self, Capabilities, CapabilitiesParams, CustomRequest, DocumentStatus, Externals,
+    ExternalsParams, IndexState, IndexStatus, IndexStatusParams, IndexStatusRequest, Outline,
+    OutlineParams, OutlineSymbol, ParseText, ParseTextParams, ParseTextResult, PartialResults,
+    PartialResultsParams, PositionDiagnosis, ProtocolCapabilities, ReadOnlyDocument,
+    ReadOnlyDocumentParams, Status, StatusParams, TreeFormat, TreeNode, TypeMembers,
+    TypeMembersParams, TypeMembersRequest, ANALYZE_FULLY_COMMAND, CLEAR_CACHE_COMMAND, COMMANDS,
+    OPEN_UNIT_COMMAND, PROTOCOL_VERSION, RESOLVE_SYMBOL_COMMAND, SELECT_ENCLOSING_BLOCK_COMMAND,
+    SHOW_DOCUMENT_DIAGNOSTICS_COMMAND, SWITCH_COMPANION_COMMAND, SYMBOL_PATH_COMMAND,
+    TOGGLE_COMMENT_COMMAND,
+};
+use crate::lsp::rtl::{self, RtlDeclaration, RtlQuery, RtlStubs};
+use crate::lsp::session::{self, Session, SessionDocument};
+use crate::lsp::stats::{LatencyLog, RequestLatency};
+use crate::lsp::symbol_id::SymbolId;
+use crate::lsp::text_position::{LineIndex, PositionEncoding};
+use crate::lsp::workspace::{IndexQueue, SymbolQuery, WorkspaceIndex};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Instant;
+use tower_lsp::jsonrpc::{Error, ErrorCode, Result};
 use tower_lsp::lsp_types::*;
-use tower_lsp::{Client, LanguageServer};
+use tower_lsp::{Client, ClientSocket, LanguageServer, LspService};
+use tree_sitter::Tree;
+
+/// The kind of the code action applying every fix enabled by the `fixAll`
+/// settings, which clients run on save through `source.fixAll`.
+const FIX_ALL_KIND: &str = "source.fixAll.dls";
+
+/// The largest snippet `dls/parseText` accepts, in bytes. The playground
+/// sends a request per keystroke, so each must stay cheap.
+const MAX_PARSE_TEXT_LENGTH: usize = 128 * 1024;
+
+/// What the client accepts in a `WorkspaceEdit`, read in `initialize`.
+#[derive(Debug, Clone, Copy, Default)]
+struct EditSupport {
+    /// Versioned `documentChanges` instead of the plain `changes` map.
+    document_changes: bool,
+    /// Annotated edits in `documentChanges`.
+    change_annotations: bool,
+}
+
+impl EditSupport {
+    fn new(capabilities: &ClientCapabilities) -> Self {
+        let workspace_edit = capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.workspace_edit.as_ref());
+        let document_changes =
+            workspace_edit.is_some_and(|edit| edit.document_changes == Some(true));
+        Self {
+            document_changes,
+            change_annotations: document_changes
+                && workspace_edit.is_some_and(|edit| edit.change_annotation_support.is_some()),
+        }
+    }
+}
+
+/// Where the server is in the LSP lifecycle. tower-lsp answers requests
+/// before `initialize` and after `shutdown` itself; this covers the windows
+/// it lets through, from the `initialize` request until the workspace is
+/// scanned in `initialized`, and `shutdown` stopping the indexing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Lifecycle {
+    #[default]
+    Uninitialized,
+    /// `initialize` was answered, `initialized` has not arrived yet.
+    Initializing,
+    /// `initialized` arrived and the workspace is being scanned. Requests
+    /// wait for the scan rather than fail, since the client may send them
+    /// right after `initialized`.
+    Starting,
+    Ready,
+    ShuttingDown,
+}
+
+/// A document notification that arrived before the server was ready, to
+/// be handled once it is.
+enum EarlyNotification {
+    Open(DidOpenTextDocumentParams),
+    Change(DidChangeTextDocumentParams),
+    Close(DidCloseTextDocumentParams),
+}
+
+/// The lifecycle state and the notifications queued until it is `Ready`,
+/// under one lock so that none is queued after the queue was replayed.
+#[derive(Default)]
+struct LifecycleState {
+    lifecycle: Lifecycle,
+    early_notifications: Vec<EarlyNotification>,
+}
 
 pub struct DelphiLanguageServer {
     client: Client,
-    document_map: Mutex<HashMap<String, String>>,
-    parser: Mutex<DelphiParser>,
-    analyzer: Mutex<SymbolAnalyzer>,
+    document_map: Mutex<HashMap<String, Document>>,
+    parsers: ParserPool,
+    settings: Mutex<Settings>,
+    position_encoding: Mutex<PositionEncoding>,
+    edit_support: Mutex<EditSupport>,
+    /// Files deleted or moved away on disk, reported by watched-file events
+    /// and existence checks on save. Open documents among them are detached:
+    /// they keep working on the editor buffer until the file reappears.
+    deleted_files: Mutex<HashSet<Url>>,
+    workspace_roots: Mutex<Vec<PathBuf>>,
+    /// The `dls.toml` governing each folder of a document, `None` where
+    /// none does. Cleared when a `dls.toml` changes.
+    project_configs: Mutex<HashMap<PathBuf, Option<Arc<ProjectConfig>>>>,
+    /// The search paths outside the workspace folders scanned into the
+    /// workspace index.
+    scanned_search_paths: Mutex<HashSet<PathBuf>>,
+    workspace_index: Mutex<WorkspaceIndex>,
+    /// Whether the declarations of all workspace units were indexed.
+    workspace_indexed: AtomicBool,
+    /// The requests each open document failed to answer, most recent last.
+    analysis_failures: Mutex<HashMap<String, FailureLog>>,
+    /// The interface GUIDs of each open document as of its last validation,
+    /// for reporting GUIDs shared across documents.
+    interface_guids: Mutex<HashMap<String, Vec<InterfaceGuid>>>,
+    /// The RTL stubs, parsed and extracted on first use.
+    rtl: OnceLock<RtlStubs>,
+    /// The units left to index, those the open documents use first.
+    index_queue: Mutex<IndexQueue>,
+    /// The large documents the user was told about, once per session.
+    large_file_notices: Mutex<HashSet<Url>>,
+    /// Analyses of the documents open in the previous session, read from
+    /// disk at startup, for `didOpen` to adopt while the text still
+    /// matches. Dropped once the workspace is indexed.
+    prewarmed: Mutex<HashMap<String, SymbolAnalyzer>>,
+    latencies: Mutex<LatencyLog>,
+    lifecycle: Mutex<LifecycleState>,
+    /// Notified when the server leaves `Starting`, waking the requests
+    /// waiting for it.
+    started: tokio::sync::Notify,
+    /// Held while the workspace is indexed, so that `shutdown` can wait
+    /// for the indexing to stop.
+    indexing: tokio::sync::Mutex<()>,
 }
 
-impl DelphiLanguageServer {
-    pub fn new(client: Client) -> Self {
-        Self {
-            client,
-            document_map: Mutex::new(HashMap::new()),
-            parser: Mutex::new(DelphiParser::new()),
-            analyzer: Mutex::new(SymbolAnalyzer::new()),
-        }
+impl DelphiLanguageServer {
+    /// The service serving one session of the client, with the custom
+    /// requests of [`protocol_ext`] registered.
+    pub fn service() -> (LspService<Self>, ClientSocket) {
+        LspService::build(Self::new)
+            .custom_method(Capabilities::METHOD, Self::capabilities)
+            .custom_method(Externals::METHOD, Self::externals)
+            .custom_method(IndexStatusRequest::METHOD, Self::index_status)
+            .custom_method(Outline::METHOD, Self::outline)
+            .custom_method(ParseText::METHOD, Self::parse_text)
+            .custom_method(Status::METHOD, Self::status)
+            .custom_method(TypeMembersRequest::METHOD, Self::type_members)
+            .finish()
+    }
+
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            document_map: Mutex::new(HashMap::new()),
+            parsers: ParserPool::new(Settings::default().performance.parser_pool_size),
+            settings: Mutex::new(Settings::default()),
+            position_encoding: Mutex::new(PositionEncoding::default()),
+            edit_support: Mutex::new(EditSupport::default()),
+            deleted_files: Mutex::new(HashSet::new()),
+            workspace_roots: Mutex::new(Vec::new()),
+            project_configs: Mutex::new(HashMap::new()),
+            scanned_search_paths: Mutex::new(HashSet::new()),
+            workspace_index: Mutex::new(WorkspaceIndex::default()),
+            workspace_indexed: AtomicBool::new(false),
+            analysis_failures: Mutex::new(HashMap::new()),
+            interface_guids: Mutex::new(HashMap::new()),
+            rtl: OnceLock::new(),
+            index_queue: Mutex::new(IndexQueue::default()),
+            large_file_notices: Mutex::new(HashSet::new()),
+            prewarmed: Mutex::new(HashMap::new()),
+            latencies: Mutex::new(LatencyLog::default()),
+            lifecycle: Mutex::new(LifecycleState::default()),
+            started: tokio::sync::Notify::new(),
+            indexing: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    async fn validate_all_documents(&self) {
+        let uris: Vec<String> = self.document_map.lock().unwrap().keys().cloned().collect();
+        for uri in uris {
+            self.validate_document(&uri).await;
+        }
+    }
+
+    /// The names of the units whose file was deleted.
+    fn missing_units(&self) -> Vec<String> {
+        self.deleted_files
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|uri| {
+                let path = uri.to_file_path().ok()?;
+                let extension = path.extension()?.to_str()?.to_lowercase();
+                if !directives::UNIT_EXTENSIONS.contains(&extension.as_str()) {
+                    return None;
+                }
+                Some(path.file_stem()?.to_str()?.to_string())
+            })
+            .collect()
+    }
+
+    /// Checks that the files of open documents, and the files known to be
+    /// deleted, still (or again) exist. Returns whether anything changed.
+    fn refresh_deleted_files(&self) -> bool {
+        let open: Vec<Url> = self
+            .document_map
+            .lock()
+            .unwrap()
+            .keys()
+            .filter_map(|uri| Url::parse(uri).ok())
+            .collect();
+        let mut deleted_files = self.deleted_files.lock().unwrap();
+        let known: Vec<Url> = deleted_files.iter().cloned().chain(open).collect();
+        let mut changed = false;
+        for uri in known {
+            let Ok(path) = uri.to_file_path() else {
+                continue;
+            };
+            changed |= if path.exists() {
+                deleted_files.remove(&uri)
+            } else {
+                deleted_files.insert(uri)
+            };
+        }
+        changed
+    }
+
+    /// Publishes the diagnostics of `uri`, and of the other open documents
+    /// too when the interface GUIDs of `uri` changed, since they may have
+    /// started or stopped sharing one.
+    async fn validate_document(&self, uri: &str) {
+        if self.publish_diagnostics(uri).await {
+            let others: Vec<String> = self
+                .document_map
+                .lock()
+                .unwrap()
+                .keys()
+                .filter(|other| *other != uri)
+                .cloned()
+                .collect();
+            for other in others {
+                self.publish_diagnostics(&other).await;
+            }
+        }
+    }
+
+    /// The interface GUIDs of the open documents other than `uri`, with the
+    /// file name of their document.
+    fn other_interface_guids(&self, uri: &str) -> Vec<(String, InterfaceGuid)> {
+        self.interface_guids
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(other, _)| *other != uri)
+            .flat_map(|(other, guids)| {
+                let file = other.rsplit('/').next().unwrap_or(other).to_string();
+                guids.iter().map(move |guid| (file.clone(), guid.clone()))
+            })
+            .collect()
+    }
+
+    /// Publishes the diagnostics of `uri`. Returns whether the interface
+    /// GUIDs of the document changed.
+    async fn publish_diagnostics(&self, uri: &str) -> bool {
+        let Ok(uri) = Url::parse(uri) else {
+            return false;
+        };
+        let analyzer = self.outline_snapshot(&uri).ok();
+        let Some(document) = self.document_map.lock().unwrap().get(uri.as_str()).cloned() else {
+            return false;
+        };
+        // A change arrived meanwhile; its own validation publishes
+        if !is_snapshot_of(analyzer.as_ref(), &document) {
+            return false;
+        }
+        let missing_units = self.missing_units();
+        let other_guids = self.other_interface_guids(uri.as_str());
+        let mut guids_changed = false;
+        let mut diagnostics = document
+            .tree()
+            .map(|tree| {
+                self.parsers.with(|parser| {
+                    parser.get_diagnostics(tree, document.text(), document.line_index())
+                })
+            })
+            .unwrap_or_default();
+        let analyzed = analyzer.map(|analyzer| {
+            let mut diagnostics = analyzer.get_diagnostics();
+            diagnostics.extend(analyzer.get_missing_unit_diagnostics(&missing_units));
+            if self.settings.lock().unwrap().diagnostics.unresolved_units {
+                let searched = self.search_path(&uri);
+                // Deleted units are reported as missing instead
+                let unresolved: Vec<(String, Option<PathBuf>)> = self
+                    .unresolved_units(&analyzer, searched.as_deref())
+                    .into_iter()
+                    .filter(|(unit, _)| !missing_units.iter().any(|m| m.eq_ignore_ascii_case(unit)))
+                    .collect();
+                let searched =
+                    searched.unwrap_or_else(|| self.workspace_roots.lock().unwrap().clone());
+                diagnostics
+                    .extend(analyzer.get_unresolved_unit_diagnostics(&unresolved, &searched));
+            }
+            diagnostics.extend(analyzer.get_shared_guid_diagnostics(&other_guids));
+            if self
+                .settings
+                .lock()
+                .unwrap()
+                .diagnostics
+                .undefined_conditionals
+            {
+                let known = self.known_conditionals(&analyzer);
+                diagnostics.extend(analyzer.get_undefined_conditional_diagnostics(&known));
+            }
+
+            let guids = analyzer.get_interface_guids();
+            let mut interface_guids = self.interface_guids.lock().unwrap();
+            guids_changed = interface_guids.get(uri.as_str()) != Some(&guids);
+            interface_guids.insert(uri.to_string(), guids);
+
+            if let Ok(path) = uri.to_file_path() {
+                let mut index = self.workspace_index.lock().unwrap();
+                index.set_declarations(path.clone(), analyzer.get_exported_declarations());
+                index.set_conditionals(path, analyzer.get_conditional_sites());
+            }
+            diagnostics
+        });
+        diagnostics.extend(analyzed.unwrap_or_default());
+        let settings = self.settings.lock().unwrap().diagnostics.clone();
+        if settings.line_endings {
+            diagnostics.extend(document.inconsistent_line_endings());
+        }
+        diagnostics.extend(characters::scan(
+            document.text(),
+            document.line_index(),
+            settings.ambiguous_characters,
+        ));
+        if self.deleted_files.lock().unwrap().contains(&uri) {
+            diagnostics.push(document.detached_diagnostic());
+        }
+
+        self.client
+            .publish_diagnostics(uri, diagnostics, None)
+            .await;
+        guids_changed
+    }
+
+    /// The analysis of the current version of the open document `uri`. A
+    /// document is analyzed once per version, outside the document lock,
+    /// and the snapshot is stored only if no change arrived meanwhile, so a
+    /// handler holding it answers from one version even while edits come
+    /// in. Fails when the document is not open or cannot be parsed.
+    /// Fails as well for a large document analyzed in outline mode.
+    fn snapshot(&self, uri: &Url) -> std::result::Result<AnalysisSnapshot, AnalysisError> {
+        self.analysis_of(uri, false)
+    }
+
+    /// Like `snapshot`, but accepts a large document analyzed in outline
+    /// mode, for the requests that only need its symbols and syntax.
+    fn outline_snapshot(&self, uri: &Url) -> std::result::Result<AnalysisSnapshot, AnalysisError> {
+        self.analysis_of(uri, true)
+    }
+
+    fn analysis_of(
+        &self,
+        uri: &Url,
+        accept_outline: bool,
+    ) -> std::result::Result<AnalysisSnapshot, AnalysisError> {
+        let (text, tree, version, mode) = {
+            let document_map = self.document_map.lock().unwrap();
+            let document = document_map
+                .get(uri.as_str())
+                .ok_or(AnalysisError::NotOpen)?;
+            if document.mode() == AnalysisMode::Outline && !accept_outline {
+                return Err(AnalysisError::LargeFile);
+            }
+            if let Some(analysis) = document.analysis() {
+                return Ok(analysis.clone());
+            }
+            (
+                document.text().to_string(),
+                document.tree().cloned(),
+                document.version(),
+                document.mode(),
+            )
+        };
+        let tree = match tree {
+            Some(tree) => tree,
+            None => self
+                .parsers
+                .with(|parser| parser.parse(&text))
+                .ok_or(AnalysisError::ParseFailed)?,
+        };
+        let snapshot = Arc::new(self.analyze(tree, text, uri, Some(version), mode));
+        if let Some(document) = self.document_map.lock().unwrap().get_mut(uri.as_str()) {
+            if document.version() == version
+                && document.mode() == mode
+                && document.analysis().is_none()
+            {
+                document.set_analysis(Some(snapshot.clone()));
+            }
+        }
+        Ok(snapshot)
+    }
+
+    /// Like `snapshot`, but reads units that are not open from disk. Large
+    /// units are analyzed in outline mode.
+    fn unit_snapshot(&self, uri: &Url) -> Option<AnalysisSnapshot> {
+        if self.document_map.lock().unwrap().contains_key(uri.as_str()) {
+            return self.snapshot(uri).ok();
+        }
+        let text = read_source(&uri.to_file_path().ok()?, true).ok()?;
+        let mode = self.mode_for(&text);
+        let tree = self.parsers.with(|parser| parser.parse(&text))?;
+        Some(Arc::new(self.analyze(tree, text, uri, None, mode)))
+    }
+
+    /// The analysis mode of a document opened or read with `text`.
+    fn mode_for(&self, text: &str) -> AnalysisMode {
+        if text.len() > self.settings.lock().unwrap().performance.large_file_size {
+            AnalysisMode::Outline
+        } else {
+            AnalysisMode::Full
+        }
+    }
+
+    fn analyze(
+        &self,
+        tree: Tree,
+        text: String,
+        uri: &Url,
+        version: Option<i32>,
+        mode: AnalysisMode,
+    ) -> SymbolAnalyzer {
+        let mut analyzer = SymbolAnalyzer::new();
+        analyzer.set_settings(self.settings.lock().unwrap().clone());
+        analyzer.set_position_encoding(*self.position_encoding.lock().unwrap());
+        analyzer.set_mode(mode);
+        analyzer.set_content(tree, text, uri.clone(), version);
+        analyzer
+    }
+
+    /// Runs `f` against a snapshot of the open document `uri`.
+    fn with_analyzer<T>(&self, uri: &Url, f: impl FnOnce(&SymbolAnalyzer) -> T) -> Option<T> {
+        self.snapshot(uri).ok().map(|analyzer| f(&analyzer))
+    }
+
+    /// Like `with_analyzer`, but accepts a large document analyzed in
+    /// outline mode.
+    fn with_outline_analyzer<T>(
+        &self,
+        uri: &Url,
+        f: impl FnOnce(&SymbolAnalyzer) -> T,
+    ) -> Option<T> {
+        self.outline_snapshot(uri).ok().map(|analyzer| f(&analyzer))
+    }
+
+    /// Like `with_analyzer`, but reads units that are not open from disk.
+    fn with_unit_analyzer<T>(&self, uri: &Url, f: impl FnOnce(&SymbolAnalyzer) -> T) -> Option<T> {
+        self.unit_snapshot(uri).map(|analyzer| f(&analyzer))
+    }
+
+    /// Parses an open document after a change, reusing the unchanged nodes
+    /// of its previous tree.
+    fn parse_document(&self, document: &mut Document) {
+        let tree = self
+            .parsers
+            .with(|parser| parser.parse_incremental(document.text(), document.tree()));
+        document.set_tree(tree);
+    }
+
+    /// Indexes the exported declarations of a unit that is not open from
+    /// its file, and returns the units it uses. A file that cannot be read
+    /// or parsed keeps the declarations indexed before.
+    fn index_unit(&self, path: PathBuf) -> Vec<String> {
+        let Ok(uri) = Url::from_file_path(&path) else {
+            return Vec::new();
+        };
+        match self.with_unit_analyzer(&uri, |analyzer| {
+            (
+                analyzer.get_exported_declarations(),
+                analyzer.get_conditional_sites(),
+                analyzer.get_used_units(),
+            )
+        }) {
+            Some((declarations, conditionals, used_units)) => {
+                let mut index = self.workspace_index.lock().unwrap();
+                index.set_declarations(path.clone(), declarations);
+                index.set_conditionals(path, conditionals);
+                used_units
+            }
+            None => {
+                log::warn!("Cannot index the declarations of {}", path.display());
+                Vec::new()
+            }
+        }
+    }
+
+    /// Indexes the conditional symbols a Delphi project file defines. A
+    /// file that cannot be read keeps the symbols indexed before.
+    fn index_project(&self, path: PathBuf) {
+        let (Ok(text), Ok(uri)) = (read_source(&path, true), Url::from_file_path(&path)) else {
+            log::warn!("Cannot index the defines of {}", path.display());
+            return;
+        };
+        let line_index = LineIndex::new(&text, *self.position_encoding.lock().unwrap());
+        let defines = conditionals::project_defines(&text)
+            .into_iter()
+            .map(|(name, range)| ConditionalSite {
+                name,
+                usage: ConditionalUse::Define,
+                location: Location {
+                    uri: uri.clone(),
+                    range: line_index.byte_range_to_range(range),
+                },
+            })
+            .collect();
+        self.workspace_index
+            .lock()
+            .unwrap()
+            .set_conditionals(path, defines);
+    }
+
+    /// Prepares the parsers and the RTL stubs, so that the first requests
+    /// do not construct them.
+    fn warm_up(&self) {
+        let started = Instant::now();
+        let parsers = self.parsers.fill();
+        self.rtl.get_or_init(RtlStubs::load);
+        log::info!(
+            "Warmed up {} parsers and the RTL stubs in {} ms",
+            parsers,
+            started.elapsed().as_millis()
+        );
+    }
+
+    /// Indexes the units of the index queue, those the open documents and
+    /// the documents of the previous session use first. Each unit serves
+    /// lookups as soon as it is indexed, and the open documents are
+    /// validated again once the units they use are all indexed. Stops
+    /// early when the server shuts down.
+    async fn index_workspace(&self) {
+        let _indexing = self.indexing.lock().await;
+        let started = Instant::now();
+        let projects = self.workspace_index.lock().unwrap().projects();
+        for project in projects {
+            self.index_project(project);
+        }
+        let depth = self.settings.lock().unwrap().performance.warm_up_depth;
+        loop {
+            tokio::task::yield_now().await;
+            if self.lifecycle() == Lifecycle::ShuttingDown {
+                return;
+            }
+            let Some((unit, level)) = self.index_queue.lock().unwrap().next() else {
+                break;
+            };
+            let used_units = self.index_unit(unit.clone());
+            if let Some(level) = level.filter(|level| *level < depth) {
+                let used_units = self.unit_paths(used_units);
+                self.index_queue
+                    .lock()
+                    .unwrap()
+                    .prioritize(used_units, level + 1, None);
+            }
+            let ready = self.index_queue.lock().unwrap().take(&unit);
+            for document in ready {
+                log::debug!("The units {} uses are indexed", document);
+                self.validate_document(document.as_str()).await;
+            }
+        }
+        let (_, total) = self.index_queue.lock().unwrap().progress();
+        log::info!(
+            "Indexed {} workspace units in {} ms",
+            total,
+            started.elapsed().as_millis()
+        );
+    }
+
+    /// The files of the workspace units named `units`.
+    fn unit_paths(&self, units: Vec<String>) -> Vec<PathBuf> {
+        let index = self.workspace_index.lock().unwrap();
+        units
+            .iter()
+            .filter_map(|unit| index.find_unit(unit))
+            .collect()
+    }
+
+    /// Queues the units an open document uses before the others while the
+    /// workspace is indexed, for its lookups to complete first.
+    fn prioritize_used_units(&self, uri: &Url) {
+        if self.workspace_indexed.load(Ordering::Acquire)
+            || self.settings.lock().unwrap().performance.warm_up_depth == 0
+        {
+            return;
+        }
+        let used_units = self
+            .with_outline_analyzer(uri, |analyzer| analyzer.get_used_units())
+            .unwrap_or_default();
+        let used_units = self.unit_paths(used_units);
+        self.index_queue
+            .lock()
+            .unwrap()
+            .prioritize(used_units, 1, Some(uri));
+    }
+
+    /// Whether workspace units `analyzer` uses are still waiting to be
+    /// indexed, so that lookups from it may miss their declarations.
+    fn uses_pending_units(&self, analyzer: &SymbolAnalyzer) -> bool {
+        if self.workspace_indexed.load(Ordering::Acquire) {
+            return false;
+        }
+        let used_units = self.unit_paths(analyzer.get_used_units());
+        let queue = self.index_queue.lock().unwrap();
+        used_units.iter().any(|unit| queue.is_pending(unit))
+    }
+
+    /// The session file of the workspace, `None` when sessions are not
+    /// restored.
+    fn session_path(&self) -> Option<PathBuf> {
+        if !self.settings.lock().unwrap().performance.restore_session {
+            return None;
+        }
+        session::session_path(&self.workspace_roots.lock().unwrap())
+    }
+
+    /// Fingerprints the settings analyses depend on. `Settings` only
+    /// derives `Debug`, whose output covers every field.
+    fn settings_fingerprint(&self) -> u64 {
+        session::fingerprint(format!("{:?}", self.settings.lock().unwrap()).as_bytes())
+    }
+
+    /// The documents of the previous session whose file did not change
+    /// since it was saved.
+    fn restore_session(&self) -> Vec<SessionDocument> {
+        let Some(session) = self
+            .session_path()
+            .and_then(|path| Session::load(&path, self.settings_fingerprint()))
+        else {
+            return Vec::new();
+        };
+        session
+            .documents
+            .into_iter()
+            .filter(|document| {
+                let bytes = document
+                    .uri
+                    .to_file_path()
+                    .ok()
+                    .and_then(|path| fs::read(path).ok());
+                bytes.is_some_and(|bytes| session::fingerprint(&bytes) == document.fingerprint)
+            })
+            .collect()
+    }
+
+    /// Saves the open documents as the session a restarted server restores.
+    /// The file is written on a blocking thread, since waiting for the
+    /// cache lock of another process polls.
+    async fn save_session(&self) {
+        let Some(path) = self.session_path() else {
+            return;
+        };
+        let uris: Vec<Url> = self
+            .document_map
+            .lock()
+            .unwrap()
+            .keys()
+            .filter_map(|uri| Url::parse(uri).ok())
+            .collect();
+        let documents = uris
+            .into_iter()
+            .filter_map(|uri| {
+                let bytes = fs::read(uri.to_file_path().ok()?).ok()?;
+                let used_units = self
+                    .with_outline_analyzer(&uri, |analyzer| analyzer.get_used_units())
+                    .unwrap_or_default();
+                Some(SessionDocument {
+                    uri,
+                    fingerprint: session::fingerprint(&bytes),
+                    used_units,
+                })
+            })
+            .collect();
+        let session = Session::new(self.settings_fingerprint(), documents);
+        let saved = tokio::task::spawn_blocking(move || {
+            if let Err(e) = session.save(&path) {
+                log::warn!("Cannot save the session to {}: {}", path.display(), e);
+            }
+        });
+        if let Err(e) = saved.await {
+            log::warn!("Cannot save the session: {}", e);
+        }
+    }
+
+    /// Drops the documents and the index of the workspace at shutdown,
+    /// leaving nothing of it to the requests still in flight.
+    fn release_workspace(&self) {
+        self.document_map.lock().unwrap().clear();
+        *self.workspace_index.lock().unwrap() = WorkspaceIndex::default();
+        self.workspace_indexed.store(false, Ordering::Release);
+        self.workspace_roots.lock().unwrap().clear();
+        self.project_configs.lock().unwrap().clear();
+        self.scanned_search_paths.lock().unwrap().clear();
+        self.deleted_files.lock().unwrap().clear();
+        self.analysis_failures.lock().unwrap().clear();
+        self.interface_guids.lock().unwrap().clear();
+        self.prewarmed.lock().unwrap().clear();
+        self.large_file_notices.lock().unwrap().clear();
+        *self.index_queue.lock().unwrap() = IndexQueue::default();
+    }
+
+    /// Analyzes the documents of the previous session from disk, for
+    /// `didOpen` to adopt when the editor reopens them unchanged. Yields
+    /// between documents and skips the ones already open.
+    async fn prewarm(&self, documents: &[SessionDocument]) {
+        let started = Instant::now();
+        for document in documents {
+            tokio::task::yield_now().await;
+            if self.lifecycle() == Lifecycle::ShuttingDown {
+                return;
+            }
+            if self
+                .document_map
+                .lock()
+                .unwrap()
+                .contains_key(document.uri.as_str())
+            {
+                continue;
+            }
+            let Some(text) = document
+                .uri
+                .to_file_path()
+                .ok()
+                .and_then(|path| read_source(&path, true).ok())
+            else {
+                continue;
+            };
+            let mode = self.mode_for(&text);
+            let Some(tree) = self.parsers.with(|parser| parser.parse(&text)) else {
+                continue;
+            };
+            let analyzer = self.analyze(tree, text, &document.uri, None, mode);
+            self.prewarmed
+                .lock()
+                .unwrap()
+                .insert(document.uri.to_string(), analyzer);
+        }
+        if !documents.is_empty() {
+            log::info!(
+                "Analyzed {} documents of the previous session in {} ms",
+                documents.len(),
+                started.elapsed().as_millis()
+            );
+        }
+    }
+
+    fn lifecycle(&self) -> Lifecycle {
+        self.lifecycle.lock().unwrap().lifecycle
+    }
+
+    /// Fails requests the server cannot answer yet, or anymore, with the
+    /// errors the LSP specification prescribes. Requests arriving while the
+    /// server is starting wait until it is ready.
+    async fn check_ready(&self) -> Result<()> {
+        loop {
+            // Registered before the check, so that no wake-up is missed
+            let started = self.started.notified();
+            match self.lifecycle() {
+                Lifecycle::Ready => return Ok(()),
+                Lifecycle::Starting => started.await,
+                Lifecycle::Uninitialized | Lifecycle::Initializing => {
+                    return Err(Error {
+                        code: ErrorCode::ServerError(-32002),
+                        message: "Server not initialized".into(),
+                        data: None,
+                    })
+                }
+                Lifecycle::ShuttingDown => return Err(Error::invalid_request()),
+            }
+        }
+    }
+
+    /// Queues a document notification arriving before the server is
+    /// ready. Returns the notification when it is to be handled now.
+    fn defer(&self, notification: EarlyNotification) -> Option<EarlyNotification> {
+        let mut state = self.lifecycle.lock().unwrap();
+        match state.lifecycle {
+            Lifecycle::Ready => Some(notification),
+            Lifecycle::ShuttingDown => None,
+            Lifecycle::Uninitialized | Lifecycle::Initializing | Lifecycle::Starting => {
+                state.early_notifications.push(notification);
+                None
+            }
+        }
+    }
+
+    /// Handles the queued notifications in arrival order, then marks the
+    /// server ready. Notifications arriving meanwhile are queued and
+    /// handled in the next round, so none overtakes an earlier one.
+    async fn become_ready(&self) {
+        loop {
+            let notifications = {
+                let mut state = self.lifecycle.lock().unwrap();
+                if state.early_notifications.is_empty() {
+                    state.lifecycle = Lifecycle::Ready;
+                    self.started.notify_waiters();
+                    return;
+                }
+                std::mem::take(&mut state.early_notifications)
+            };
+            for notification in notifications {
+                self.handle_notification(notification).await;
+            }
+        }
+    }
+
+    async fn handle_notification(&self, notification: EarlyNotification) {
+        match notification {
+            EarlyNotification::Open(params) => self.open_document(params).await,
+            EarlyNotification::Change(params) => self.change_document(params).await,
+            EarlyNotification::Close(params) => self.close_document(params).await,
+        }
+    }
+
+    async fn open_document(&self, params: DidOpenTextDocumentParams) {
+        if rtl::is_stub(&params.text_document.uri) {
+            self.client
+                .send_notification::<ReadOnlyDocument>(ReadOnlyDocumentParams {
+                    text_document: TextDocumentIdentifier::new(params.text_document.uri.clone()),
+                })
+                .await;
+        }
+        let uri = params.text_document.uri.to_string();
+        let mut document = Document::new(
+            params.text_document.text,
+            params.text_document.version,
+            *self.position_encoding.lock().unwrap(),
+        );
+        let mode = self.mode_for(document.text());
+        document.set_mode(mode);
+        let prewarmed = self
+            .prewarmed
+            .lock()
+            .unwrap()
+            .remove(&uri)
+            .filter(|analyzer| analyzer.get_source() == document.text() && analyzer.mode() == mode);
+        match prewarmed {
+            Some(mut analyzer) => {
+                document.set_tree(analyzer.get_tree().cloned());
+                analyzer.set_version(Some(document.version()));
+                document.set_analysis(Some(Arc::new(analyzer)));
+            }
+            None => self.parse_document(&mut document),
+        }
+        self.document_map
+            .lock()
+            .unwrap()
+            .insert(uri.clone(), document);
+        if mode == AnalysisMode::Outline
+            && self
+                .large_file_notices
+                .lock()
+                .unwrap()
+                .insert(params.text_document.uri.clone())
+        {
+            self.client
+                .show_message(
+                    MessageType::INFO,
+                    format!(
+                        "{} is large: only its outline, folding and syntax errors are analyzed. \
+                         Run \"Delphi: Analyze Large File Fully\" for every feature.",
+                        uri.rsplit('/').next().unwrap_or(&uri)
+                    ),
+                )
+                .await;
+        }
+        self.validate_document(&uri).await;
+        self.prioritize_used_units(&params.text_document.uri);
+    }
+
+    async fn change_document(&self, params: DidChangeTextDocumentParams) {
+        let uri = params.text_document.uri.to_string();
+        {
+            let mut document_map = self.document_map.lock().unwrap();
+            let Some(document) = document_map.get_mut(&uri) else {
+                return;
+            };
+            for change in params.content_changes {
+                document.apply_change(change.range, &change.text);
+            }
+            document.set_version(params.text_document.version);
+            self.parse_document(document);
+        }
+        self.validate_document(&uri).await;
+    }
+
+    async fn close_document(&self, params: DidCloseTextDocumentParams) {
+        let uri = params.text_document.uri.to_string();
+        self.document_map.lock().unwrap().remove(&uri);
+        self.analysis_failures.lock().unwrap().remove(&uri);
+        self.index_queue
+            .lock()
+            .unwrap()
+            .forget(&params.text_document.uri);
+        // Unsaved edits are gone: index the file as it is on disk again
+        if let Ok(path) = params.text_document.uri.to_file_path() {
+            if path.exists() {
+                self.index_unit(path);
+            } else {
+                self.workspace_index.lock().unwrap().remove(&path);
+            }
+        }
+        let had_guids = self
+            .interface_guids
+            .lock()
+            .unwrap()
+            .remove(&uri)
+            .is_some_and(|guids| !guids.is_empty());
+        if had_guids {
+            self.validate_all_documents().await;
+        }
+
+        self.client
+            .log_message(MessageType::INFO, &format!("File closed: {}", uri))
+            .await;
+    }
+
+    /// Records how long the request `operation` took to answer, for
+    /// `dls/status` and `dls.showDocumentDiagnostics` to report.
+    fn record_latency(&self, operation: &str, started: Instant) {
+        self.latencies
+            .lock()
+            .unwrap()
+            .record(operation, started.elapsed());
+    }
+
+    fn request_latencies(&self) -> Vec<RequestLatency> {
+        self.latencies.lock().unwrap().latencies()
+    }
+
+    /// The declaration of the identifier at `position` in a unit the
+    /// document uses, for names the document does not declare: the name,
+    /// the unit as named in the uses clause, and the declaration. A name
+    /// qualified with a unit, `Helpers.TMyHelper`, is looked up in that
+    /// unit only.
+    fn find_workspace_declaration(
+        &self,
+        analyzer: &SymbolAnalyzer,
+        position: Position,
+    ) -> Option<(String, String, Location)> {
+        let used_units = analyzer.get_used_units();
+        let (name, units) = match analyzer.rtl_query(position)?.0 {
+            RtlQuery::Name(name) => (name, used_units),
+            RtlQuery::Member { type_name, member } => {
+                let unit = used_units
+                    .into_iter()
+                    .find(|unit| unit.eq_ignore_ascii_case(&type_name))?;
+                (member, vec![unit])
+            }
+        };
+        let (unit, location) = self
+            .workspace_index
+            .lock()
+            .unwrap()
+            .find_declaration(&units, &name)?;
+        Some((name, unit, location))
+    }
+
+    /// A resolver following member access chains into the units of the
+    /// workspace: a name a unit does not declare is looked up in the units
+    /// it uses, which are read from disk when not open.
+    fn chain_resolver(&self) -> ChainResolver<'_> {
+        ChainResolver::new(|analyzer: &SymbolAnalyzer, name: &str| {
+            let (unit, location) = self
+                .workspace_index
+                .lock()
+                .unwrap()
+                .find_declaration(&analyzer.get_used_units(), name)?;
+            Some((unit, self.unit_snapshot(&location.uri)?))
+        })
+    }
+
+    /// The units of the workspace index and the open documents, except
+    /// `skip`, ordered by file name.
+    fn workspace_units(&self, skip: Option<&Url>) -> Vec<Url> {
+        let mut units: Vec<Url> = self
+            .workspace_index
+            .lock()
+            .unwrap()
+            .sources()
+            .into_iter()
+            .filter_map(|path| Url::from_file_path(path).ok())
+            .collect();
+        for uri in self.document_map.lock().unwrap().keys() {
+            if let Ok(uri) = Url::parse(uri) {
+                if !units.contains(&uri) {
+                    units.push(uri);
+                }
+            }
+        }
+        units.retain(|unit| Some(unit) != skip);
+        units.sort_by_cached_key(|unit| {
+            let name = unit
+                .path_segments()
+                .and_then(|mut segments| segments.next_back());
+            (name.unwrap_or_default().to_lowercase(), unit.to_string())
+        });
+        units
+    }
+
+    /// Moves the files of `used_units` still in `rest` to the end of
+    /// `closure`, for scanning the units a document uses before the others.
+    fn queue_used_units(
+        &self,
+        used_units: Vec<String>,
+        rest: &mut VecDeque<Url>,
+        closure: &mut VecDeque<Url>,
+    ) {
+        let index = self.workspace_index.lock().unwrap();
+        for used in used_units {
+            let Some(used) = index
+                .find_unit(&used)
+                .and_then(|path| Url::from_file_path(path).ok())
+            else {
+                continue;
+            };
+            if let Some(i) = rest.iter().position(|unit| *unit == used) {
+                rest.remove(i);
+                closure.push_back(used);
+            }
+        }
+    }
+
+    /// Delivers a batch of results: streamed as a `$/progress` notification
+    /// when the client gave a partial result token, buffered otherwise.
+    async fn deliver<T: serde::Serialize>(
+        &self,
+        token: &Option<ProgressToken>,
+        batch: Vec<T>,
+        buffered: &mut Vec<T>,
+    ) {
+        match token {
+            Some(token) if !batch.is_empty() => {
+                self.client
+                    .send_notification::<PartialResults>(PartialResultsParams {
+                        token: token.clone(),
+                        value: serde_json::to_value(batch).unwrap(),
+                    })
+                    .await;
+            }
+            Some(_) => {}
+            None => buffered.extend(batch),
+        }
+    }
+
+    /// The RTL declaration of the identifier at `position` and the range of
+    /// the identifier, for symbols the document does not declare. A
+    /// declaration from a unit the document does not use is a guess.
+    fn find_rtl_declaration(
+        &self,
+        analyzer: &SymbolAnalyzer,
+        position: Position,
+    ) -> Option<Resolution<(RtlDeclaration, Range)>> {
+        let (query, range) = analyzer.rtl_query(position)?;
+        let declaration = self.rtl.get_or_init(RtlStubs::load).find(&query)?;
+        let used = rtl::is_implicitly_used(declaration.unit)
+            || analyzer
+                .get_used_units()
+                .iter()
+                .any(|unit| rtl::names_unit(unit, declaration.unit));
+        Some(if used {
+            Resolution::declared((declaration, range))
+        } else {
+            let reason = format!("unit {} is not in the uses clause", declaration.unit);
+            Resolution::heuristic((declaration, range), reason)
+        })
+    }
+
+    /// The workspace units `analyzer` uses without referring to any name
+    /// their interfaces declare. Units whose use may matter without a
+    /// name, as reported by `interface_names`, are never unused.
+    fn unused_units(&self, analyzer: &SymbolAnalyzer) -> Vec<String> {
+        analyzer
+            .get_used_units()
+            .into_iter()
+            .filter(|unit| {
+                let path = self.workspace_index.lock().unwrap().find_unit(unit);
+                let Some(uri) = path.and_then(|path| Url::from_file_path(path).ok()) else {
+                    return false;
+                };
+                self.with_unit_analyzer(&uri, |used| used.interface_names())
+                    .flatten()
+                    .is_some_and(|names| !names.iter().any(|name| analyzer.refers_to(name)))
+            })
+            .collect()
+    }
+
+    /// Where the conditional symbol `name` tested in the document of
+    /// `analyzer` gets defined: by its own directives and those of the
+    /// files it includes, in source order, then by the `defines` setting,
+    /// the project files of the workspace and the compiler.
+    fn conditional_definitions(&self, analyzer: &SymbolAnalyzer, name: &str) -> Vec<Definition> {
+        let mut definitions: Vec<Definition> = analyzer
+            .get_conditional_sites()
+            .into_iter()
+            .chain(analyzer.get_included_conditionals())
+            .filter(|site| {
+                site.usage == ConditionalUse::Define && site.name.eq_ignore_ascii_case(name)
+            })
+            .map(|site| Definition::Directive(site.location))
+            .collect();
+        let settings = self.settings.lock().unwrap().defines.clone();
+        if settings
+            .iter()
+            .any(|define| define.eq_ignore_ascii_case(name))
+        {
+            definitions.push(Definition::Settings);
+        }
+        let index = self.workspace_index.lock().unwrap();
+        definitions.extend(
+            index
+                .find_conditionals(name, |usage| usage == ConditionalUse::Define)
+                .into_iter()
+                .filter(|site| {
+                    site.location
+                        .uri
+                        .to_file_path()
+                        .is_ok_and(|path| is_project_file(&path))
+                })
+                .map(|site| Definition::Project(site.location)),
+        );
+        if conditionals::is_predefined(name) {
+            definitions.push(Definition::Compiler);
+        }
+        definitions
+    }
+
+    /// The conditional symbols defined or undefined anywhere known: by the
+    /// document of `analyzer` and the files it includes, the `defines`
+    /// setting, and the indexed sources and project files.
+    fn known_conditionals(&self, analyzer: &SymbolAnalyzer) -> Vec<String> {
+        let mut known: Vec<String> = analyzer
+            .get_conditional_sites()
+            .into_iter()
+            .chain(analyzer.get_included_conditionals())
+            .filter(|site| site.usage != ConditionalUse::Test)
+            .map(|site| site.name)
+            .collect();
+        known.extend(self.settings.lock().unwrap().defines.iter().cloned());
+        known.extend(self.workspace_index.lock().unwrap().defined_conditionals());
+        known.sort_by_key(|name| name.to_ascii_uppercase());
+        known.dedup_by(|a, b| a.eq_ignore_ascii_case(b));
+        known
+    }
+
+    /// The directives testing the conditional symbol `name` in the
+    /// document `uri`, the files it includes and the indexed sources, and
+    /// those defining or undefining it too when `include_declaration`.
+    fn conditional_references(
+        &self,
+        analyzer: &SymbolAnalyzer,
+        uri: &Url,
+        name: &str,
+        include_declaration: bool,
+    ) -> Vec<Location> {
+        let wanted = |usage| include_declaration || usage == ConditionalUse::Test;
+        let mut sites: Vec<ConditionalSite> = analyzer
+            .get_conditional_sites()
+            .into_iter()
+            .chain(analyzer.get_included_conditionals())
+            .filter(|site| site.name.eq_ignore_ascii_case(name) && wanted(site.usage))
+            .collect();
+        // The index may hold an older version of the document
+        sites.extend(
+            self.workspace_index
+                .lock()
+                .unwrap()
+                .find_conditionals(name, wanted)
+                .into_iter()
+                .filter(|site| site.location.uri != *uri),
+        );
+        let mut locations: Vec<Location> = sites.into_iter().map(|site| site.location).collect();
+        locations.dedup();
+        locations
+    }
+
+    /// Describes where the conditional symbol `name` at `range` of the
+    /// document `uri` is defined.
+    fn conditional_hover(
+        &self,
+        analyzer: &SymbolAnalyzer,
+        uri: &Url,
+        name: &str,
+        range: Range,
+    ) -> Hover {
+        let line = |location: &Location| location.range.start.line + 1;
+        let file_name = |location: &Location| {
+            location
+                .uri
+                .to_file_path()
+                .ok()
+                .and_then(|path| Some(path.file_name()?.to_string_lossy().into_owned()))
+                .unwrap_or_else(|| location.uri.to_string())
+        };
+        let definitions: Vec<String> = self
+            .conditional_definitions(analyzer, name)
+            .iter()
+            .map(|definition| match definition {
+                Definition::Directive(location) if location.uri == *uri => {
+                    format!("- Defined in this file, line {}", line(location))
+                }
+                Definition::Directive(location) => format!(
+                    "- Defined in include file `{}`, line {}",
+                    file_name(location),
+                    line(location)
+                ),
+                Definition::Settings => "- Defined by the `delphi.defines` setting".to_string(),
+                Definition::Project(location) => format!(
+                    "- Defined in project `{}`, line {}",
+                    file_name(location),
+                    line(location)
+                ),
+                Definition::Compiler => "- Predefined by the compiler".to_string(),
+            })
+            .collect();
+        let mut value = format!("Conditional symbol `{}`\n\n", name);
+        if definitions.is_empty() {
+            value.push_str(
+                "Not defined by this file, its include files, the project files or the `delphi.defines` setting",
+            );
+        } else {
+            value.push_str(&definitions.join("\n"));
+        }
+        Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value,
+            }),
+            range: Some(range),
+        }
+    }
+
+    /// The units `analyzer` uses that are neither indexed nor RTL units.
+    /// The `dls.toml` governing the file at `path`, read once per folder,
+    /// with its search paths canonicalized.
+    fn project_config(&self, path: &Path) -> Option<Arc<ProjectConfig>> {
+        let dir = path.parent()?.to_path_buf();
+        if let Some(config) = self.project_configs.lock().unwrap().get(&dir) {
+            return config.clone();
+        }
+        let config = match ProjectConfig::find(path) {
+            Ok(config) => config.map(|mut config| {
+                config.search_paths = config
+                    .search_paths
+                    .into_iter()
+                    .map(|dir| fs::canonicalize(&dir).unwrap_or(dir))
+                    .collect();
+                Arc::new(config)
+            }),
+            Err(e) => {
+                log::warn!("{}", e);
+                None
+            }
+        };
+        self.project_configs
+            .lock()
+            .unwrap()
+            .insert(dir, config.clone());
+        config
+    }
+
+    /// The directories of `search_paths` neither inside a workspace folder
+    /// nor scanned before, recorded as scanned.
+    fn unscanned_search_paths(&self, search_paths: &[PathBuf]) -> Vec<PathBuf> {
+        let roots = self.workspace_roots.lock().unwrap().clone();
+        let mut scanned = self.scanned_search_paths.lock().unwrap();
+        search_paths
+            .iter()
+            .filter(|dir| !roots.iter().any(|root| dir.starts_with(root)))
+            .filter(|dir| scanned.insert(dir.to_path_buf()))
+            .cloned()
+            .collect()
+    }
+
+    /// The folders searched for the units the document at `uri` uses when
+    /// a `dls.toml` governs it: the folder of the document and the search
+    /// paths. `None` without one, when the workspace folders are searched.
+    /// Search paths outside the workspace folders are scanned into the
+    /// workspace index and their units indexed on first use, so that they
+    /// resolve like workspace units.
+    fn search_path(&self, uri: &Url) -> Option<Vec<PathBuf>> {
+        let path = uri.to_file_path().ok()?;
+        let config = self.project_config(&path)?;
+        for dir in self.unscanned_search_paths(&config.search_paths) {
+            let sources: Vec<PathBuf> = {
+                let mut index = self.workspace_index.lock().unwrap();
+                index.scan_dir(&dir);
+                index
+                    .sources()
+                    .into_iter()
+                    .filter(|source| source.starts_with(&dir))
+                    .collect()
+            };
+            for source in sources {
+                self.index_unit(source);
+            }
+        }
+        Some(
+            path.parent()
+                .map(Path::to_path_buf)
+                .into_iter()
+                .chain(config.search_paths.iter().cloned())
+                .collect(),
+        )
+    }
+
+    /// The units used by `analyzer` that resolve neither to a file on
+    /// `searched`, the workspace folders when `None`, nor to an RTL stub.
+    /// Each comes with the folder of a file of that name off the search
+    /// path, for adding it.
+    fn unresolved_units(
+        &self,
+        analyzer: &SymbolAnalyzer,
+        searched: Option<&[PathBuf]>,
+    ) -> Vec<(String, Option<PathBuf>)> {
+        let Some(searched) = searched else {
+            return self
+                .unindexed_units(analyzer)
+                .into_iter()
+                .map(|unit| (unit, None))
+                .collect();
+        };
+        let rtl = self.rtl.get_or_init(RtlStubs::load);
+        let index = self.workspace_index.lock().unwrap();
+        analyzer
+            .get_used_units()
+            .into_iter()
+            .filter(|unit| !rtl.is_unit_name(unit))
+            .filter_map(|unit| {
+                let files = index.find_unit_files(&unit);
+                if files
+                    .iter()
+                    .any(|file| searched.iter().any(|dir| file.starts_with(dir)))
+                {
+                    return None;
+                }
+                let folder = files
+                    .first()
+                    .and_then(|file| file.parent())
+                    .map(Path::to_path_buf);
+                Some((unit, folder))
+            })
+            .collect()
+    }
+
+    /// The quick fix adding `folder` to the search paths of the `dls.toml`
+    /// governing the document at `uri`, relative to the `dls.toml` when
+    /// inside its folder.
+    fn add_search_path_fix(&self, uri: &Url, folder: &Path) -> Option<(Url, TextEdit)> {
+        let config = self.project_config(&uri.to_file_path().ok()?)?;
+        let text = fs::read_to_string(&config.file).ok()?;
+        let dir = config.file.parent()?;
+        let entry = match folder.strip_prefix(dir) {
+            Ok(relative) if relative.as_os_str().is_empty() => ".".to_string(),
+            Ok(relative) => relative.to_string_lossy().replace('\\', "/"),
+            Err(_) => folder.display().to_string(),
+        };
+        let (range, new_text) = config::add_search_path(&text, &entry)?;
+        let line_index = LineIndex::new(&text, *self.position_encoding.lock().unwrap());
+        let edit = TextEdit {
+            range: line_index.byte_range_to_range(range),
+            new_text,
+        };
+        Some((Url::from_file_path(&config.file).ok()?, edit))
+    }
+
+    fn unindexed_units(&self, analyzer: &SymbolAnalyzer) -> Vec<String> {
+        let rtl = self.rtl.get_or_init(RtlStubs::load);
+        let index = self.workspace_index.lock().unwrap();
+        analyzer
+            .get_used_units()
+            .into_iter()
+            .filter(|unit| index.find_unit(unit).is_none() && !rtl.is_unit_name(unit))
+            .collect()
+    }
+
+    /// The hover of `position`: the declaration under it in the document, or
+    /// the RTL declaration of a name the document does not declare. With
+    /// `analysis.strict`, an RTL declaration from a unit the document does
+    /// not use is not shown, and a name left unresolved while used units
+    /// are not indexed says so.
+    fn hover_at(&self, uri: &Url, position: Position) -> std::result::Result<Hover, AnalysisError> {
+        let analyzer = self.snapshot(uri)?;
+        if let Some((name, range)) = analyzer.conditional_at(position) {
+            return Ok(self.conditional_hover(&analyzer, uri, &name, range));
+        }
+        if let Some(hover) = analyzer.get_member_chain_hover(position, &mut self.chain_resolver()) {
+            return Ok(hover);
+        }
+        let error = match analyzer.get_hover_info(position) {
+            Ok(hover) => return Ok(hover),
+            Err(error) => error,
+        };
+        let strict = self.settings.lock().unwrap().analysis.strict;
+        let unresolved = |name: &str, range: Range, reason: &str| Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: format!("`{}` not resolved ({})", name, reason),
+            }),
+            range: Some(range),
+        };
+        let Some(resolution) = self.find_rtl_declaration(&analyzer, position) else {
+            let unindexed = self.unindexed_units(&analyzer);
+            return match analyzer.rtl_query(position) {
+                Some((query, range)) if strict && !unindexed.is_empty() => Ok(unresolved(
+                    query.name(),
+                    range,
+                    &format!("unit {} not indexed", unindexed.join(", ")),
+                )),
+                _ => Err(error),
+            };
+        };
+        let (declaration, range) = match resolution.provenance {
+            Provenance::Heuristic(reason) if strict => {
+                let (declaration, range) = resolution.value;
+                log::trace!(
+                    "Strict analysis left {} unresolved; the heuristic gave RTL unit {} ({})",
+                    declaration.declaration,
+                    declaration.unit,
+                    reason
+                );
+                let name = analyzer
+                    .rtl_query(position)
+                    .map_or(String::new(), |(query, _)| query.name().to_string());
+                return Ok(unresolved(&name, range, &reason));
+            }
+            _ => resolution.value,
+        };
+        Ok(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: format!(
+                    "```pascal\n{}\n```\nDeclared in RTL unit `{}`",
+                    declaration.declaration, declaration.unit
+                ),
+            }),
+            range: Some(range),
+        })
+    }
+
+    /// The declaration of the identifier at `position`, in the document, in
+    /// the units it uses, or in the RTL.
+    fn definition_at(
+        &self,
+        uri: &Url,
+        position: Position,
+    ) -> std::result::Result<Location, AnalysisError> {
+        let analyzer = self.snapshot(uri)?;
+        if let Some((name, _)) = analyzer.conditional_at(position) {
+            return self
+                .conditional_definitions(&analyzer, &name)
+                .iter()
+                .find_map(|definition| definition.location().cloned())
+                .ok_or(AnalysisError::NotDeclared(name));
+        }
+        if let Some(found) = analyzer.member_chain_at(position, &mut self.chain_resolver()) {
+            return Ok(found.location);
+        }
+        let error = match analyzer.find_definition(position) {
+            Ok(location) => return Ok(location),
+            Err(error) => error,
+        };
+        if let Some((_, _, location)) = self.find_workspace_declaration(&analyzer, position) {
+            return Ok(location);
+        }
+        let rtl_declaration = analyzer
+            .accept(self.find_rtl_declaration(&analyzer, position), || {
+                format!("the RTL declaration at {:?}", position)
+            });
+        if let Some(location) = rtl_declaration.and_then(|(declaration, _)| declaration.location) {
+            return Ok(location);
+        }
+        match error {
+            AnalysisError::NotDeclared(name) if self.uses_pending_units(&analyzer) => {
+                let (indexed, total) = self.index_queue.lock().unwrap().progress();
+                log::info!(
+                    "Definition of {} incomplete: {} of {} units indexed",
+                    name,
+                    indexed,
+                    total
+                );
+                Err(AnalysisError::IndexNotReady)
+            }
+            error => Err(error),
+        }
+    }
+
+    /// Records that the request `operation` on `uri` failed, for `dls/status`
+    /// and `dls.showDocumentDiagnostics` to report.
+    fn record_failure(
+        &self,
+        uri: &Url,
+        operation: &str,
+        position: Option<Position>,
+        error: &AnalysisError,
+    ) {
+        log::debug!("{} failed on {}: {}", operation, uri, error);
+        // Closed documents keep no log
+        if !self.document_map.lock().unwrap().contains_key(uri.as_str()) {
+            return;
+        }
+        self.analysis_failures
+            .lock()
+            .unwrap()
+            .entry(uri.to_string())
+            .or_default()
+            .record(AnalysisFailure::new(operation, position, error));
+    }
+
+    /// Like `snapshot`, recording a failure of the request `operation`.
+    fn recorded_snapshot(
+        &self,
+        uri: &Url,
+        operation: &str,
+        position: Position,
+    ) -> Option<AnalysisSnapshot> {
+        self.snapshot(uri)
+            .inspect_err(|error| self.record_failure(uri, operation, Some(position), error))
+            .ok()
+    }
+
+    fn recent_failures(&self, uri: &Url) -> Vec<AnalysisFailure> {
+        self.analysis_failures
+            .lock()
+            .unwrap()
+            .get(uri.as_str())
+            .map(FailureLog::failures)
+            .unwrap_or_default()
+    }
+
+    /// Handles `dls.showDocumentDiagnostics` with arguments `[uri, position]`,
+    /// explaining why hover and go to definition find nothing at the
+    /// position, along with the recent failures of the document.
+    fn show_document_diagnostics(&self, arguments: Vec<Value>) -> Result<Option<Value>> {
+        let uri: Url = command_argument(&arguments, 0)?;
+        let position: Position = command_argument(&arguments, 1)?;
+        let explain = |operation: &str, error: Option<AnalysisError>| {
+            error.map(|error| AnalysisFailure::new(operation, Some(position), &error))
+        };
+        let diagnosis = PositionDiagnosis {
+            hover: explain("hover", self.hover_at(&uri, position).err()),
+            definition: explain("definition", self.definition_at(&uri, position).err()),
+            recent_failures: self.recent_failures(&uri),
+            request_latencies: self.request_latencies(),
+        };
+        Ok(Some(serde_json::to_value(diagnosis).unwrap()))
+    }
+
+    /// Handles `dls.analyzeFully` with arguments `[uri]`, lifting the outline
+    /// mode of a large open document until it is closed.
+    async fn analyze_fully(&self, arguments: Vec<Value>) -> Result<Option<Value>> {
+        let uri: Url = command_argument(&arguments, 0)?;
+        {
+            let mut document_map = self.document_map.lock().unwrap();
+            let Some(document) = document_map.get_mut(uri.as_str()) else {
+                return Err(Error::invalid_params(AnalysisError::NotOpen.to_string()));
+            };
+            if document.mode() == AnalysisMode::Full {
+                return Ok(None);
+            }
+            document.set_mode(AnalysisMode::Full);
+        }
+        self.validate_document(uri.as_str()).await;
+        Ok(None)
+    }
+
+    /// Handles `dls.clearCache`, deleting the sessions and stubs the server
+    /// processes keep on disk and returning how many files went. The stubs
+    /// this server hands out are extracted again.
+    fn clear_cache(&self) -> Result<Option<Value>> {
+        let removed = cache::clear().map_err(|e| Error {
+            code: ErrorCode::InternalError,
+            message: format!("Cannot clear the cache: {}", e).into(),
+            data: None,
+        })?;
+        if let Some(rtl) = self.rtl.get() {
+            rtl.restore();
+        }
+        log::info!("Cleared {} cache files", removed);
+        Ok(Some(json!(removed)))
+    }
+
+    /// Handles `dls.selectEnclosingBlock` with arguments `[uri, position]`.
+    fn select_enclosing_block(&self, arguments: Vec<Value>) -> Result<Option<Value>> {
+        let uri: Url = command_argument(&arguments, 0)?;
+        let position: Position = command_argument(&arguments, 1)?;
+        let blocks = self
+            .with_analyzer(&uri, |analyzer| analyzer.get_enclosing_blocks(position))
+            .flatten()
+            .unwrap_or_default();
+        Ok(Some(serde_json::to_value(blocks).unwrap()))
+    }
+
+    /// Handles `dls.toggleComment` with arguments `[uri, range]`, returning
+    /// the edits commenting or uncommenting the range for the client to
+    /// apply.
+    fn toggle_comment(&self, arguments: Vec<Value>) -> Result<Option<Value>> {
+        let uri: Url = command_argument(&arguments, 0)?;
+        let range: Range = command_argument(&arguments, 1)?;
+        let edits = self
+            .document_map
+            .lock()
+            .unwrap()
+            .get(uri.as_str())
+            .map(|document| comments::toggle_comment(document, range))
+            .unwrap_or_default();
+        Ok(Some(serde_json::to_value(edits).unwrap()))
+    }
+
+    /// Handles `dls.resolveSymbol` with arguments `[uri, symbolId]`, returning
+    /// the current location of the symbol or `null` when it no longer exists.
+    fn resolve_symbol(&self, arguments: Vec<Value>) -> Result<Option<Value>> {
+        let uri: Url = command_argument(&arguments, 0)?;
+        let id: SymbolId = command_argument(&arguments, 1)?;
+        let location = self
+            .with_analyzer(&uri, |analyzer| analyzer.resolve_symbol_id(&id))
+            .flatten();
+        Ok(Some(serde_json::to_value(location).unwrap()))
+    }
+
+    /// Handles `dls.switchCompanion` with arguments `[uri]`, returning the
+    /// URIs of the form file, test unit or tested unit paired with the
+    /// document. Several candidates are all returned for the client to pick.
+    fn switch_companion(&self, arguments: Vec<Value>) -> Result<Option<Value>> {
+        let uri: Url = command_argument(&arguments, 0)?;
+        let Ok(path) = uri.to_file_path() else {
+            return Ok(Some(Value::Array(Vec::new())));
+        };
+        let test_patterns = self
+            .settings
+            .lock()
+            .unwrap()
+            .companions
+            .test_patterns
+            .clone();
+        let companions: Vec<Url> = self
+            .workspace_index
+            .lock()
+            .unwrap()
+            .companions(&path, &test_patterns)
+            .into_iter()
+            .filter_map(|companion| Url::from_file_path(companion).ok())
+            .collect();
+        Ok(Some(serde_json::to_value(companions).unwrap()))
+    }
+
+    /// Handles `dls.openUnit` with arguments `[query]`, returning the URIs
+    /// of the indexed units whose name matches the query, best first.
+    fn open_unit(&self, arguments: Vec<Value>) -> Result<Option<Value>> {
+        let query: String = command_argument(&arguments, 0)?;
+        let units: Vec<Url> = self
+            .workspace_index
+            .lock()
+            .unwrap()
+            .find_units(&query)
+            .into_iter()
+            .filter_map(|unit| Url::from_file_path(unit).ok())
+            .collect();
+        Ok(Some(serde_json::to_value(units).unwrap()))
+    }
+
+    /// Handles `dls.symbolPath` with arguments `[uri, position]`, returning
+    /// the symbols enclosing the position as a display string and as
+    /// segments with ranges.
+    fn symbol_path(&self, arguments: Vec<Value>) -> Result<Option<Value>> {
+        let uri: Url = command_argument(&arguments, 0)?;
+        let position: Position = command_argument(&arguments, 1)?;
+        let path = self
+            .with_analyzer(&uri, |analyzer| analyzer.get_symbol_path(position))
+            .flatten();
+        Ok(Some(serde_json::to_value(path).unwrap()))
+    }
+
+    /// Handles the `dls/capabilities` request: the protocol version, the
+    /// custom requests, notifications and commands of this build, and the
+    /// schema of their payloads. Answered before indexing finishes.
+    pub async fn capabilities(&self, params: CapabilitiesParams) -> Result<ProtocolCapabilities> {
+        if let Some(version) = params.protocol_version {
+            check_protocol_version(Some(u64::from(version)))?;
+        }
+        let schema = protocol_ext::protocol_schema();
+        let methods = |kind: &str| {
+            schema[kind]
+                .as_object()
+                .map(|methods| methods.keys().cloned().collect())
+                .unwrap_or_default()
+        };
+        Ok(ProtocolCapabilities {
+            protocol_version: PROTOCOL_VERSION,
+            requests: methods("requests"),
+            notifications: methods("notifications"),
+            commands: COMMANDS.iter().map(|command| command.to_string()).collect(),
+            schema: schema.clone(),
+        })
+    }
+
+    /// Handles the `dls/outline` request: the document symbol tree with
+    /// visibility, directives, sections and symbol ids, filtered server-side.
+    pub async fn outline(&self, params: OutlineParams) -> Result<Vec<OutlineSymbol>> {
+        self.check_ready().await?;
+        let uri = params.text_document.uri.clone();
+        Ok(self
+            .with_outline_analyzer(&uri, |analyzer| analyzer.get_outline(&params))
+            .flatten()
+            .unwrap_or_default())
+    }
+
+    /// Handles the `dls/status` request: the dialect the document is read in,
+    /// the version of the analyzed text, whether its file still exists and
+    /// how much of it the parser could not make sense of.
+    pub async fn status(&self, params: StatusParams) -> Result<Option<DocumentStatus>> {
+        self.check_ready().await?;
+        let uri = params.text_document.uri;
+        let detached = self.deleted_files.lock().unwrap().contains(&uri);
+        let byte_order_mark = self
+            .document_map
+            .lock()
+            .unwrap()
+            .get(uri.as_str())
+            .is_some_and(|document| document.byte_order_mark());
+        Ok(self.with_outline_analyzer(&uri, |analyzer| DocumentStatus {
+            dialect: analyzer.get_dialect(),
+            analysis_mode: analyzer.mode(),
+            version: analyzer.get_version(),
+            detached,
+            byte_order_mark,
+            error_percentage: analyzer.get_parse_stats().error_percentage(),
+            recent_failures: self.recent_failures(&uri),
+            request_latencies: self.request_latencies(),
+        }))
+    }
+
+    /// Handles the `dls/indexStatus` request: how many workspace units are
+    /// indexed and, for a document, whether the units it uses are.
+    pub async fn index_status(&self, params: IndexStatusParams) -> Result<IndexStatus> {
+        let (indexed_units, total_units) = self.index_queue.lock().unwrap().progress();
+        let state = match self.lifecycle() {
+            Lifecycle::Uninitialized | Lifecycle::Initializing | Lifecycle::Starting => {
+                IndexState::Scanning
+            }
+            _ if self.workspace_indexed.load(Ordering::Acquire) => IndexState::Complete,
+            _ => IndexState::Partial,
+        };
+        let document_ready = params.text_document.and_then(|document| {
+            self.with_outline_analyzer(&document.uri, |analyzer| !self.uses_pending_units(analyzer))
+        });
+        Ok(IndexStatus {
+            state,
+            indexed_units,
+            total_units,
+            prioritized_units: self.index_queue.lock().unwrap().prioritized_len(),
+            document_ready,
+        })
+    }
+
+    /// Handles the `dls/externals` request: all `external` routine imports
+    /// of a document grouped by library.
+    pub async fn externals(&self, params: ExternalsParams) -> Result<Vec<ExternalLibrary>> {
+        self.check_ready().await?;
+        let uri = params.text_document.uri;
+        Ok(self
+            .with_analyzer(&uri, |analyzer| analyzer.get_external_imports())
+            .unwrap_or_default())
+    }
+
+    /// Handles the `dls/typeMembers` request: the type at a position, or
+    /// the type of the variable or expression there, with its declared and
+    /// inherited members.
+    pub async fn type_members(&self, params: TypeMembersParams) -> Result<Option<TypeMembers>> {
+        self.check_ready().await?;
+        let uri = params.text_document.uri;
+        Ok(self
+            .with_analyzer(&uri, |analyzer| {
+                analyzer.get_type_members_at(params.position)
+            })
+            .flatten())
     }
 
-    async fn validate_document(&self, uri: &str, text: &str) {
-        let diagnostics = {
-            let mut parser = self.parser.lock().unwrap();
-            let diagnostics = parser.get_diagnostics(text);
-            if let Some(tree) = parser.parse(text) {
-                let mut analyzer = self.analyzer.lock().unwrap();
-                analyzer.set_content(tree, text.to_string(), Url::parse(uri).unwrap());
+    /// Handles the `dls/parseText` request: the syntax tree, diagnostics and
+    /// document symbols of a snippet. The snippet is parsed by a parser of
+    /// its own and analyzed apart from the open documents, so the request
+    /// never waits for, nor changes, their state.
+    pub async fn parse_text(&self, params: ParseTextParams) -> Result<ParseTextResult> {
+        self.check_ready().await?;
+        if params.text.len() > MAX_PARSE_TEXT_LENGTH {
+            return Err(Error::invalid_params(format!(
+                "Text of {} bytes exceeds the limit of {} bytes",
+                params.text.len(),
+                MAX_PARSE_TEXT_LENGTH
+            )));
+        }
+        let encoding = *self.position_encoding.lock().unwrap();
+        let mut parser = DelphiParser::new();
+        let tree = parser
+            .parse(&params.text)
+            .ok_or_else(Error::internal_error)?;
+        let line_index = LineIndex::new(&params.text, encoding);
+        let mut diagnostics = parser.get_diagnostics(&tree, &params.text, &line_index);
+
+        let mut analyzer = SymbolAnalyzer::new();
+        analyzer.set_settings(self.settings.lock().unwrap().clone());
+        analyzer.set_position_encoding(encoding);
+        let uri = Url::parse("untitled:Playground.pas").unwrap();
+        analyzer.set_content(tree.clone(), params.text, uri, None);
+        diagnostics.extend(analyzer.get_diagnostics());
+        let tree = match params.format {
+            TreeFormat::Sexp => Value::String(tree.root_node().to_sexp()),
+            TreeFormat::Json => {
+                serde_json::to_value(TreeNode::new(tree.root_node(), None, &line_index)).unwrap()
             }
-            diagnostics
         };
+        Ok(ParseTextResult {
+            tree,
+            diagnostics,
+            symbols: analyzer.get_document_symbols().unwrap_or_default(),
+        })
+    }
+}
 
-        self.client
-            .publish_diagnostics(Url::parse(uri).unwrap(), diagnostics, None)
-            .await;
+/// The error answering a rename of the implicit `Result` or `Self`.
+fn implicit_rename_error(name: &str) -> Error {
+    Error::invalid_params(format!(
+        "{} is an implicit identifier and cannot be renamed",
+        name
+    ))
+}
+
+/// The edit renaming `occurrences` of the document `uri` to `new_name`.
+/// Clients supporting it get a versioned document edit, which they reject
+/// once the buffer has changed, with the edits annotated by occurrence
+/// kind so that their preview groups them.
+fn rename_edit(
+    uri: Url,
+    version: Option<i32>,
+    occurrences: Vec<RenameOccurrence>,
+    new_name: &str,
+    support: EditSupport,
+) -> WorkspaceEdit {
+    if !support.document_changes {
+        let edits = occurrences
+            .into_iter()
+            .map(|occurrence| TextEdit::new(occurrence.range, new_name.to_string()))
+            .collect();
+        return WorkspaceEdit {
+            changes: Some(HashMap::from([(uri, edits)])),
+            ..WorkspaceEdit::default()
+        };
+    }
+
+    let mut annotations = HashMap::new();
+    let edits = occurrences
+        .into_iter()
+        .map(|occurrence| {
+            let text_edit = TextEdit::new(occurrence.range, new_name.to_string());
+            if !support.change_annotations {
+                return OneOf::Left(text_edit);
+            }
+            let (id, label, needs_confirmation) = match occurrence.kind {
+                RenameKind::Declaration => ("declaration", "Declarations", false),
+                RenameKind::Reference => ("reference", "References", false),
+                RenameKind::Text => ("textOccurrence", "String and comment occurrences", true),
+            };
+            annotations
+                .entry(id.to_string())
+                .or_insert_with(|| ChangeAnnotation {
+                    label: label.to_string(),
+                    needs_confirmation: Some(needs_confirmation),
+                    description: needs_confirmation.then(|| {
+                        "Words spelled like the identifier, which may not mean it".to_string()
+                    }),
+                });
+            OneOf::Right(AnnotatedTextEdit {
+                text_edit,
+                annotation_id: id.to_string(),
+            })
+        })
+        .collect();
+    WorkspaceEdit {
+        changes: None,
+        document_changes: Some(DocumentChanges::Edits(vec![TextDocumentEdit {
+            text_document: OptionalVersionedTextDocumentIdentifier { uri, version },
+            edits,
+        }])),
+        change_annotations: (!annotations.is_empty()).then_some(annotations),
+    }
+}
+
+/// Whether `path` is a Delphi project file.
+fn is_project_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            directives::PROJECT_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+        })
+}
+
+/// Whether `analyzer`, if any, analyzed the current version of `document`,
+/// so that answers combining both agree.
+fn is_snapshot_of(analyzer: Option<&AnalysisSnapshot>, document: &Document) -> bool {
+    analyzer.is_none_or(|analyzer| analyzer.get_version() == Some(document.version()))
+}
+
+/// The name of the unit in the file of `uri`: its file name without the
+/// extension.
+fn document_unit_name(uri: &Url) -> Option<String> {
+    let path = uri.to_file_path().ok()?;
+    Some(path.file_stem()?.to_str()?.to_string())
+}
+
+/// Fails unless the client asks for the protocol version this build speaks,
+/// with the supported versions in the error data. `None` stands for a
+/// version that is not a number.
+fn check_protocol_version(version: Option<u64>) -> Result<()> {
+    if version == Some(u64::from(PROTOCOL_VERSION)) {
+        return Ok(());
     }
+    Err(Error {
+        code: ErrorCode::InvalidParams,
+        message: match version {
+            Some(version) => format!("Protocol version {} is not supported", version),
+            None => "Protocol version must be a number".to_string(),
+        }
+        .into(),
+        data: Some(json!({
+            "supportedVersions": [PROTOCOL_VERSION],
+            "requestedVersion": version,
+        })),
+    })
+}
+
+/// Deserializes the `index`-th argument of an executeCommand request.
+fn command_argument<T: DeserializeOwned>(arguments: &[Value], index: usize) -> Result<T> {
+    let value = arguments
+        .get(index)
+        .ok_or_else(|| Error::invalid_params(format!("Missing command argument {}", index)))?;
+    serde_json::from_value(value.clone())
+        .map_err(|e| Error::invalid_params(format!("Invalid command argument {}: {}", index, e)))
 }
 
 #[tower_lsp::async_trait]
 impl LanguageServer for DelphiLanguageServer {
-    async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        if let Some(version) = params
+            .initialization_options
+            .as_ref()
+            .and_then(|options| options.get("protocolVersion"))
+        {
+            check_protocol_version(version.as_u64())?;
+        }
+        self.lifecycle.lock().unwrap().lifecycle = Lifecycle::Initializing;
+        if let Some(options) = params.initialization_options {
+            *self.settings.lock().unwrap() = Settings::from_value(options);
+        }
+        self.parsers
+            .resize(self.settings.lock().unwrap().performance.parser_pool_size);
+        let encoding = PositionEncoding::negotiate(&params.capabilities);
+        *self.position_encoding.lock().unwrap() = encoding;
+        *self.edit_support.lock().unwrap() = EditSupport::new(&params.capabilities);
+        let folders = params.workspace_folders.unwrap_or_default();
+        let root_uris = if folders.is_empty() {
+            params.root_uri.into_iter().collect()
+        } else {
+            folders
+                .into_iter()
+                .map(|folder| folder.uri)
+                .collect::<Vec<_>>()
+        };
+        *self.workspace_roots.lock().unwrap() = root_uris
+            .iter()
+            .filter_map(|uri| uri.to_file_path().ok())
+            .collect();
+
         Ok(InitializeResult {
             capabilities: ServerCapabilities {
-                text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::INCREMENTAL,
+                position_encoding: Some(encoding.kind()),
+                text_document_sync: Some(TextDocumentSyncCapability::Options(
+                    TextDocumentSyncOptions {
+                        open_close: Some(true),
+                        change: Some(TextDocumentSyncKind::INCREMENTAL),
+                        save: Some(TextDocumentSyncSaveOptions::Supported(true)),
+                        ..TextDocumentSyncOptions::default()
+                    },
                 )),
                 hover_provider: Some(HoverProviderCapability::Simple(true)),
                 completion_provider: Some(CompletionOptions {
-                    resolve_provider: Some(false),
+                    resolve_provider: Some(true),
                     trigger_characters: Some(vec![".".to_string()]),
                     all_commit_characters: None,
                     work_done_progress_options: Default::default(),
                     completion_item: None,
                 }),
+                signature_help_provider: Some(SignatureHelpOptions {
+                    trigger_characters: Some(vec!["(".to_string(), ",".to_string()]),
+                    retrigger_characters: None,
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                }),
                 definition_provider: Some(OneOf::Left(true)),
+                type_definition_provider: Some(TypeDefinitionProviderCapability::Simple(true)),
                 references_provider: Some(OneOf::Left(true)),
+                rename_provider: Some(OneOf::Right(RenameOptions {
+                    prepare_provider: Some(true),
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                })),
+                document_highlight_provider: Some(OneOf::Left(true)),
                 document_symbol_provider: Some(OneOf::Left(true)),
+                workspace_symbol_provider: Some(OneOf::Left(true)),
+                document_on_type_formatting_provider: Some(DocumentOnTypeFormattingOptions {
+                    first_trigger_character: "\n".to_string(),
+                    more_trigger_character: None,
+                }),
+                document_link_provider: Some(DocumentLinkOptions {
+                    resolve_provider: Some(false),
+                    work_done_progress_options: Default::default(),
+                }),
+                folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+                document_formatting_provider: Some(OneOf::Left(true)),
+                document_range_formatting_provider: Some(OneOf::Left(true)),
+                code_action_provider: Some(CodeActionProviderCapability::Options(
+                    CodeActionOptions {
+                        code_action_kinds: Some(vec![
+                            CodeActionKind::QUICKFIX,
+                            CodeActionKind::REFACTOR_REWRITE,
+                            CodeActionKind::from(FIX_ALL_KIND),
+                        ]),
+                        ..CodeActionOptions::default()
+                    },
+                )),
+                execute_command_provider: Some(ExecuteCommandOptions {
+                    commands: COMMANDS.iter().map(|command| command.to_string()).collect(),
+                    work_done_progress_options: Default::default(),
+                }),
+                experimental: Some(json!({ "dls": { "protocolVersion": PROTOCOL_VERSION } })),
                 ..ServerCapabilities::default()
             },
+            // The build version, then the version of the custom protocol
             server_info: Some(ServerInfo {
                 name: "Delphi Language Server".to_string(),
-                version: Some("0.1.0".to_string()),
+                version: Some(format!(
+                    "{}+protocol.{}",
+                    env!("CARGO_PKG_VERSION"),
+                    PROTOCOL_VERSION
+                )),
             }),
         })
     }
 
     async fn initialized(&self, _: InitializedParams) {
+        self.lifecycle.lock().unwrap().lifecycle = Lifecycle::Starting;
+        self.warm_up();
+        let mut roots = self.workspace_roots.lock().unwrap().clone();
+        let configs: Vec<_> = roots
+            .iter()
+            .filter_map(|root| self.project_config(&root.join(PROJECT_FILE)))
+            .collect();
+        for config in configs {
+            roots.extend(self.unscanned_search_paths(&config.search_paths));
+        }
+        let index = WorkspaceIndex::scan(&roots);
+        let sources = index.sources();
+        *self.workspace_index.lock().unwrap() = index;
+        *self.index_queue.lock().unwrap() = IndexQueue::new(sources);
+        let restored = self.restore_session();
+        let restored_units = self.unit_paths(
+            restored
+                .iter()
+                .flat_map(|document| document.used_units.iter().cloned())
+                .collect(),
+        );
+        if self.settings.lock().unwrap().performance.warm_up_depth > 0 {
+            self.index_queue
+                .lock()
+                .unwrap()
+                .prioritize(restored_units, 1, None);
+        }
+        // Documents opened meanwhile are indexed into the scanned index
+        // and queue the units they use
+        self.become_ready().await;
+        self.prewarm(&restored).await;
+        self.index_workspace().await;
+        // Shutdown may have released the workspace while indexing
+        if self.lifecycle() == Lifecycle::ShuttingDown {
+            return;
+        }
+        // The editor did not reopen these
+        self.prewarmed.lock().unwrap().clear();
+        self.workspace_indexed.store(true, Ordering::Release);
+        // Conditional symbols may be defined by any unit
+        if self
+            .settings
+            .lock()
+            .unwrap()
+            .diagnostics
+            .undefined_conditionals
+        {
+            self.validate_all_documents().await;
+        }
         self.client
             .log_message(MessageType::INFO, "Delphi language server initialized!")
             .await;
     }
 
+    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
+        let settings = Settings::from_value(params.settings);
+        self.parsers.resize(settings.performance.parser_pool_size);
+        *self.settings.lock().unwrap() = settings;
+        // The snapshots were analyzed with the old settings
+        self.prewarmed.lock().unwrap().clear();
+        for document in self.document_map.lock().unwrap().values_mut() {
+            document.set_analysis(None);
+        }
+        self.validate_all_documents().await;
+    }
+
+    /// Tracks deleted and recreated files, so that open documents whose file
+    /// went away are marked detached and units using them are told, and
+    /// both recover when the file comes back.
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        let mut changed = false;
+        let mut modified = Vec::new();
+        {
+            let open = self.document_map.lock().unwrap();
+            let mut deleted_files = self.deleted_files.lock().unwrap();
+            let mut workspace_index = self.workspace_index.lock().unwrap();
+            for event in params.changes {
+                if let Ok(path) = event.uri.to_file_path() {
+                    if event.typ == FileChangeType::DELETED {
+                        workspace_index.remove(&path);
+                    } else {
+                        workspace_index.insert(path.clone());
+                        // Open documents are indexed from their text instead
+                        if !open.contains_key(event.uri.as_str()) {
+                            modified.push(path);
+                        }
+                    }
+                }
+                // A new file may resolve a unit of a uses clause, and so
+                // may new search paths
+                changed |= event.typ == FileChangeType::CREATED;
+                if event
+                    .uri
+                    .path()
+                    .rsplit('/')
+                    .next()
+                    .is_some_and(|name| name.eq_ignore_ascii_case(PROJECT_FILE))
+                {
+                    self.project_configs.lock().unwrap().clear();
+                    changed = true;
+                }
+                changed |= if event.typ == FileChangeType::DELETED {
+                    deleted_files.insert(event.uri)
+                } else {
+                    deleted_files.remove(&event.uri)
+                };
+            }
+        }
+        for path in modified {
+            if is_project_file(&path) {
+                self.index_project(path);
+            } else {
+                self.index_unit(path);
+            }
+        }
+        if changed {
+            self.validate_all_documents().await;
+        }
+    }
+
     async fn document_symbol(
         &self,
         params: DocumentSymbolParams,
     ) -> Result<Option<DocumentSymbolResponse>> {
+        self.check_ready().await?;
+        let started = Instant::now();
         let uri = params.text_document.uri;
-        if let Some(text) = self.document_map.lock().unwrap().get(&uri.to_string()) {
-            let mut parser = self.parser.lock().unwrap();
-            if let Some(tree) = parser.parse(text) {
-                let mut analyzer = self.analyzer.lock().unwrap();
-                analyzer.set_content(tree, text.to_string(), uri);
-                if let Some(symbols) = analyzer.get_document_symbols() {
-                    return Ok(Some(DocumentSymbolResponse::Nested(symbols)));
-                }
-            }
-        }
-        Ok(None)
+        let symbols = self
+            .with_outline_analyzer(&uri, |analyzer| analyzer.get_document_symbols())
+            .flatten();
+        self.record_latency("documentSymbol", started);
+        Ok(symbols.map(DocumentSymbolResponse::Nested))
     }
 
     async fn did_open(&self, params: DidOpenTextDocumentParams) {
-        let uri = params.text_document.uri.to_string();
-        let text = params.text_document.text;
-        {
-            let mut document_map = self.document_map.lock().unwrap();
-            document_map.insert(uri.clone(), text.clone());
+        if let Some(notification) = self.defer(EarlyNotification::Open(params)) {
+            self.handle_notification(notification).await;
         }
-        self.validate_document(&uri, &text).await;
     }
 
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
-        let uri = params.text_document.uri.to_string();
-        let text = {
-            let mut document_map = self.document_map.lock().unwrap();
-            if let Some(content) = document_map.get_mut(&uri) {
-                for change in params.content_changes {
-                    if change.range.is_none() {
-                        *content = change.text;
-                    } else {
-                        // Handle incremental updates if needed
-                        // For now, just replace the entire content
-                        *content = change.text;
-                    }
-                }
-                content.clone()
-            } else {
-                return;
-            }
-        };
-        self.validate_document(&uri, &text).await;
+        if let Some(notification) = self.defer(EarlyNotification::Change(params)) {
+            self.handle_notification(notification).await;
+        }
     }
 
-    async fn did_close(&self, params: DidCloseTextDocumentParams) {
-        let uri = params.text_document.uri.to_string();
-        self.document_map.lock().unwrap().remove(&uri);
+    /// Saving is a cheap moment to notice files that vanished without a
+    /// watched-file event, such as during a branch switch.
+    async fn did_save(&self, _: DidSaveTextDocumentParams) {
+        if self.refresh_deleted_files() {
+            self.validate_all_documents().await;
+        }
+    }
 
-        self.client
-            .log_message(MessageType::INFO, &format!("File closed: {}", uri))
-            .await;
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        if let Some(notification) = self.defer(EarlyNotification::Close(params)) {
+            self.handle_notification(notification).await;
+        }
     }
 
+    /// Stops the workspace indexing and waits for it to stop, and drops
+    /// the notifications still queued, so that no work outlives the
+    /// response.
     async fn shutdown(&self) -> Result<()> {
+        let dropped = {
+            let mut state = self.lifecycle.lock().unwrap();
+            state.lifecycle = Lifecycle::ShuttingDown;
+            std::mem::take(&mut state.early_notifications).len()
+        };
+        self.started.notify_waiters();
+        if dropped > 0 {
+            log::warn!(
+                "Dropped {} notifications received before initialization",
+                dropped
+            );
+        }
+        drop(self.indexing.lock().await);
+        self.save_session().await;
+        self.release_workspace();
         Ok(())
     }
 
     async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+        self.check_ready().await?;
+        let started = Instant::now();
         let uri = params.text_document_position_params.text_document.uri;
         let position = params.text_document_position_params.position;
 
-        if let Some(text) = self.document_map.lock().unwrap().get(&uri.to_string()) {
-            let mut parser = self.parser.lock().unwrap();
-            if let Some(tree) = parser.parse(text) {
-                let mut analyzer = self.analyzer.lock().unwrap();
-                analyzer.set_content(tree, text.to_string(), uri);
-                return Ok(analyzer.get_hover_info(position));
+        let hover = match self.hover_at(&uri, position) {
+            Ok(hover) => Some(hover),
+            Err(error) => {
+                self.record_failure(&uri, "hover", Some(position), &error);
+                None
             }
-        }
-        Ok(None)
+        };
+        self.record_latency("hover", started);
+        Ok(hover)
     }
 
     async fn goto_definition(
         &self,
         params: GotoDefinitionParams,
     ) -> Result<Option<GotoDefinitionResponse>> {
+        self.check_ready().await?;
+        let started = Instant::now();
         let uri = params.text_document_position_params.text_document.uri;
         let position = params.text_document_position_params.position;
 
-        if let Some(text) = self.document_map.lock().unwrap().get(&uri.to_string()) {
-            let mut parser = self.parser.lock().unwrap();
-            if let Some(tree) = parser.parse(text) {
-                let mut analyzer = self.analyzer.lock().unwrap();
-                analyzer.set_content(tree, text.to_string(), uri);
-                if let Some(location) = analyzer.find_definition(position) {
-                    return Ok(Some(GotoDefinitionResponse::Scalar(location)));
-                }
+        let definition = match self.definition_at(&uri, position) {
+            Ok(location) => Some(GotoDefinitionResponse::Scalar(location)),
+            Err(error) => {
+                self.record_failure(&uri, "definition", Some(position), &error);
+                None
             }
-        }
-        Ok(None)
+        };
+        self.record_latency("definition", started);
+        Ok(definition)
+    }
+
+    async fn goto_type_definition(
+        &self,
+        params: request::GotoTypeDefinitionParams,
+    ) -> Result<Option<request::GotoTypeDefinitionResponse>> {
+        self.check_ready().await?;
+        let uri = params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+
+        let Some(analyzer) = self.recorded_snapshot(&uri, "typeDefinition", position) else {
+            return Ok(None);
+        };
+        Ok(match analyzer.find_type_definition(position) {
+            Ok(location) => Some(GotoDefinitionResponse::Scalar(location)),
+            Err(error) => {
+                self.record_failure(&uri, "typeDefinition", Some(position), &error);
+                None
+            }
+        })
+    }
+
+    async fn document_highlight(
+        &self,
+        params: DocumentHighlightParams,
+    ) -> Result<Option<Vec<DocumentHighlight>>> {
+        self.check_ready().await?;
+        let uri = params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+
+        Ok(self
+            .with_analyzer(&uri, |analyzer| analyzer.get_document_highlights(position))
+            .flatten())
     }
 
     async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
+        self.check_ready().await?;
+        let started = Instant::now();
+        let uri = params.text_document_position.text_document.uri;
+        let position = params.text_document_position.position;
+        let trigger_character = params.context.and_then(|ctx| ctx.trigger_character);
+
+        let items = self
+            .with_analyzer(&uri, |analyzer| {
+                analyzer.get_completion_items(position, trigger_character)
+            })
+            .flatten();
+        self.record_latency("completion", started);
+        Ok(items.map(CompletionResponse::Array))
+    }
+
+    async fn signature_help(&self, params: SignatureHelpParams) -> Result<Option<SignatureHelp>> {
+        self.check_ready().await?;
+        let uri = params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+
+        Ok(self
+            .with_analyzer(&uri, |analyzer| analyzer.get_signature_help(position))
+            .flatten())
+    }
+
+    async fn completion_resolve(&self, item: CompletionItem) -> Result<CompletionItem> {
+        self.check_ready().await?;
+        Ok(analyzer::resolve_completion_item(item))
+    }
+
+    /// Finds references in the document and, for symbols its interface
+    /// section declares, in the other units using it. A name the document
+    /// imports from a unit of its uses clause is looked up in the workspace
+    /// index, and its references are those of the declaring unit, the
+    /// document and the units using the declaring unit. Units are scanned in
+    /// order of relevance: the document, the units it uses directly or
+    /// indirectly, then the rest by file name. With a partial result token
+    /// each unit's hits are streamed as they are found and the final
+    /// response is empty; a cancelled request stops between units.
+    async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
+        self.check_ready().await?;
         let uri = params.text_document_position.text_document.uri;
         let position = params.text_document_position.position;
+        let token = params.partial_result_params.partial_result_token;
+        let include_declaration = params.context.include_declaration;
 
-        if let Some(text) = self.document_map.lock().unwrap().get(&uri.to_string()) {
-            let mut parser = self.parser.lock().unwrap();
-            if let Some(tree) = parser.parse(text) {
-                let mut analyzer = self.analyzer.lock().unwrap();
-                analyzer.set_content(tree, text.to_string(), uri);
-                if let Some(items) = analyzer.get_completion_items(
-                    position,
-                    params.context.and_then(|ctx| ctx.trigger_character),
-                ) {
-                    return Ok(Some(CompletionResponse::Array(items)));
+        let Some(analyzer) = self.recorded_snapshot(&uri, "references", position) else {
+            return Ok(None);
+        };
+        if let Some((name, _)) = analyzer.conditional_at(position) {
+            return Ok(Some(self.conditional_references(
+                &analyzer,
+                &uri,
+                &name,
+                include_declaration,
+            )));
+        }
+        let used_units = analyzer.get_used_units();
+        let mut locations = Vec::new();
+        let (name, unit_name, declaring) =
+            match analyzer.find_references(position, include_declaration) {
+                Some(local) => {
+                    self.deliver(&token, local, &mut locations).await;
+                    let (Some(name), Some(unit_name)) =
+                        (analyzer.exported_name(position), document_unit_name(&uri))
+                    else {
+                        return Ok(Some(locations));
+                    };
+                    (name, unit_name, uri.clone())
                 }
+                None => {
+                    let Some((name, unit_name, declaration)) =
+                        self.find_workspace_declaration(&analyzer, position)
+                    else {
+                        return Ok(None);
+                    };
+                    let local = analyzer.find_name_references(&name, include_declaration);
+                    let declared = self
+                        .with_unit_analyzer(&declaration.uri, |analyzer| {
+                            analyzer.find_name_references(&name, include_declaration)
+                        })
+                        .unwrap_or_default();
+                    self.deliver(&token, local, &mut locations).await;
+                    self.deliver(&token, declared, &mut locations).await;
+                    (name, unit_name, declaration.uri)
+                }
+            };
+
+        let mut rest = VecDeque::from(self.workspace_units(Some(&uri)));
+        rest.retain(|unit| *unit != declaring);
+        let mut closure = VecDeque::new();
+        self.queue_used_units(used_units, &mut rest, &mut closure);
+
+        loop {
+            let (unit, in_closure) = match closure.pop_front() {
+                Some(unit) => (unit, true),
+                None => match rest.pop_front() {
+                    Some(unit) => (unit, false),
+                    None => break,
+                },
+            };
+            // Lets a cancellation take effect between units
+            tokio::task::yield_now().await;
+            let Some((used_units, batch)) = self.with_unit_analyzer(&unit, |analyzer| {
+                let used_units = analyzer.get_used_units();
+                let batch = if used_units
+                    .iter()
+                    .any(|used| used.eq_ignore_ascii_case(&unit_name))
+                {
+                    analyzer.find_name_references(&name, include_declaration)
+                } else {
+                    Vec::new()
+                };
+                (used_units, batch)
+            }) else {
+                continue;
+            };
+            if in_closure {
+                self.queue_used_units(used_units, &mut rest, &mut closure);
             }
+            self.deliver(&token, batch, &mut locations).await;
         }
-        Ok(None)
+        Ok(Some(locations))
     }
 
-    async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
+    async fn prepare_rename(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> Result<Option<PrepareRenameResponse>> {
+        self.check_ready().await?;
+        if rtl::is_stub(&params.text_document.uri) {
+            return Ok(None);
+        }
+        let range = self
+            .with_analyzer(&params.text_document.uri, |analyzer| {
+                match analyzer.implicit_identifier_at(params.position) {
+                    Some(name) => Err(implicit_rename_error(&name)),
+                    None => Ok(analyzer.prepare_rename(params.position)),
+                }
+            })
+            .transpose()?
+            .flatten();
+        Ok(range.map(PrepareRenameResponse::Range))
+    }
+
+    /// Renames every identifier of the document spelled like the one at the
+    /// position, ignoring case, to the new name as typed.
+    async fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
+        self.check_ready().await?;
+        let uri = params.text_document_position.text_document.uri;
+        let position = params.text_document_position.position;
+        let new_name = params.new_name;
+        if rtl::is_stub(&uri) {
+            return Ok(None);
+        }
+        let Some(analyzer) = self.recorded_snapshot(&uri, "rename", position) else {
+            return Ok(None);
+        };
+        if let Some(name) = analyzer.implicit_identifier_at(position) {
+            return Err(implicit_rename_error(&name));
+        }
+        if !keywords::is_identifier(&new_name) {
+            return Err(Error::invalid_params(format!(
+                "`{}` is not a valid identifier",
+                new_name
+            )));
+        }
+        if keywords::is_reserved_word(&new_name, analyzer.get_dialect()) {
+            return Err(Error::invalid_params(format!(
+                "`{}` is a reserved word; write `&{}` to use it as an identifier",
+                new_name, new_name
+            )));
+        }
+        let support = *self.edit_support.lock().unwrap();
+        // Text occurrences need confirming, which needs annotations
+        let text_occurrences =
+            support.change_annotations && self.settings.lock().unwrap().rename.text_occurrences;
+        Ok(analyzer
+            .rename_occurrences(position, text_occurrences)
+            .map(|occurrences| {
+                rename_edit(uri, analyzer.get_version(), occurrences, &new_name, support)
+            }))
+    }
+
+    /// Finds the symbols of all workspace units whose name contains the
+    /// query, open documents included, streaming each unit's symbols when
+    /// the client gave a partial result token. A `unit:NAME` word in the
+    /// query limits the search to the units matching `NAME`.
+    async fn symbol(
+        &self,
+        params: WorkspaceSymbolParams,
+    ) -> Result<Option<Vec<SymbolInformation>>> {
+        self.check_ready().await?;
+        let token = params.partial_result_params.partial_result_token;
+        let query = SymbolQuery::parse(&params.query);
+        let mut symbols = Vec::new();
+        for unit in self.workspace_units(None) {
+            let included = document_unit_name(&unit).is_some_and(|name| query.includes_unit(&name));
+            if !included {
+                continue;
+            }
+            tokio::task::yield_now().await;
+            let batch = self
+                .with_unit_analyzer(&unit, |analyzer| {
+                    analyzer.get_workspace_symbols(&query.name)
+                })
+                .unwrap_or_default();
+            self.deliver(&token, batch, &mut symbols).await;
+        }
+        Ok(Some(symbols))
+    }
+
+    async fn on_type_formatting(
+        &self,
+        params: DocumentOnTypeFormattingParams,
+    ) -> Result<Option<Vec<TextEdit>>> {
+        self.check_ready().await?;
+        if params.ch != "\n" {
+            return Ok(None);
+        }
         let uri = params.text_document_position.text_document.uri;
         let position = params.text_document_position.position;
+        let document_map = self.document_map.lock().unwrap();
+        let Some(document) = document_map.get(&uri.to_string()) else {
+            return Ok(None);
+        };
+        Ok(balance::close_block_on_newline(document, position)
+            .or_else(|| comments::continue_comment_on_newline(document, position))
+            .map(|edit| vec![edit]))
+    }
+
+    async fn formatting(&self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>> {
+        self.check_ready().await?;
+        let uri = params.text_document.uri;
+        Ok(self.with_analyzer(&uri, |analyzer| analyzer.format(None, &params.options)))
+    }
+
+    async fn range_formatting(
+        &self,
+        params: DocumentRangeFormattingParams,
+    ) -> Result<Option<Vec<TextEdit>>> {
+        self.check_ready().await?;
+        let uri = params.text_document.uri;
+        Ok(self.with_analyzer(&uri, |analyzer| {
+            analyzer.format(Some(params.range), &params.options)
+        }))
+    }
+
+    async fn document_link(&self, params: DocumentLinkParams) -> Result<Option<Vec<DocumentLink>>> {
+        self.check_ready().await?;
+        let uri = params.text_document.uri;
+        Ok(self.with_analyzer(&uri, |analyzer| analyzer.get_document_links()))
+    }
+
+    async fn folding_range(&self, params: FoldingRangeParams) -> Result<Option<Vec<FoldingRange>>> {
+        self.check_ready().await?;
+        let uri = params.text_document.uri;
+        Ok(self
+            .with_outline_analyzer(&uri, |analyzer| analyzer.get_folding_ranges())
+            .flatten())
+    }
+
+    async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
+        self.check_ready().await?;
+        let uri = params.text_document.uri;
+        let analyzer = self.snapshot(&uri).ok();
+        let Some(document) = self
+            .document_map
+            .lock()
+            .unwrap()
+            .get(&uri.to_string())
+            .cloned()
+        else {
+            return Ok(None);
+        };
+        if !is_snapshot_of(analyzer.as_ref(), &document) {
+            return Ok(None);
+        }
 
-        if let Some(text) = self.document_map.lock().unwrap().get(&uri.to_string()) {
-            let mut parser = self.parser.lock().unwrap();
-            if let Some(tree) = parser.parse(text) {
-                let mut analyzer = self.analyzer.lock().unwrap();
-                analyzer.set_content(tree, text.to_string(), uri);
-                return Ok(analyzer.find_references(position));
+        let mut actions = Vec::new();
+        for diagnostic in &params.context.diagnostics {
+            let code = match &diagnostic.code {
+                Some(NumberOrString::String(code)) => code.as_str(),
+                _ => continue,
+            };
+            if let Some(fix) = fixes::mechanical_fix(&document, diagnostic) {
+                actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                    title: fix.title,
+                    kind: Some(CodeActionKind::QUICKFIX),
+                    diagnostics: Some(vec![diagnostic.clone()]),
+                    edit: Some(WorkspaceEdit {
+                        changes: Some(HashMap::from([(uri.clone(), fix.edits)])),
+                        ..WorkspaceEdit::default()
+                    }),
+                    is_preferred: Some(true),
+                    ..CodeAction::default()
+                }));
+            } else if code == NAMING_CONVENTION {
+                let fix = analyzer
+                    .as_ref()
+                    .and_then(|analyzer| analyzer.naming_fix(diagnostic.range.start));
+                let Some((title, edits)) = fix else {
+                    continue;
+                };
+                actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                    title,
+                    kind: Some(CodeActionKind::QUICKFIX),
+                    diagnostics: Some(vec![diagnostic.clone()]),
+                    edit: Some(WorkspaceEdit {
+                        changes: Some(HashMap::from([(uri.clone(), edits)])),
+                        ..WorkspaceEdit::default()
+                    }),
+                    is_preferred: Some(true),
+                    ..CodeAction::default()
+                }));
+            } else if code == UNRESOLVED_UNIT {
+                let fix = diagnostic
+                    .data
+                    .as_ref()
+                    .and_then(|data| data["containingFolder"].as_str())
+                    .and_then(|folder| self.add_search_path_fix(&uri, Path::new(folder)));
+                let Some((project_file, edit)) = fix else {
+                    continue;
+                };
+                actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                    title: "Add containing folder to search paths".to_string(),
+                    kind: Some(CodeActionKind::QUICKFIX),
+                    diagnostics: Some(vec![diagnostic.clone()]),
+                    edit: Some(WorkspaceEdit {
+                        changes: Some(HashMap::from([(project_file, vec![edit])])),
+                        ..WorkspaceEdit::default()
+                    }),
+                    is_preferred: Some(true),
+                    ..CodeAction::default()
+                }));
+            } else if code == RESERVED_IDENTIFIER || code == SHADOWED_INTRINSIC {
+                let position = diagnostic.range.start;
+                let fixes = analyzer
+                    .as_ref()
+                    .map(|analyzer| match code {
+                        RESERVED_IDENTIFIER => analyzer.reserved_identifier_fixes(position),
+                        _ => analyzer.shadowed_intrinsic_fixes(position),
+                    })
+                    .unwrap_or_default();
+                for (title, edits) in fixes {
+                    actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                        title,
+                        kind: Some(CodeActionKind::QUICKFIX),
+                        diagnostics: Some(vec![diagnostic.clone()]),
+                        edit: Some(WorkspaceEdit {
+                            changes: Some(HashMap::from([(uri.clone(), edits)])),
+                            ..WorkspaceEdit::default()
+                        }),
+                        ..CodeAction::default()
+                    }));
+                }
+            } else if code == INVALID_GUID || code == DUPLICATE_GUID {
+                let position = diagnostic.range.start;
+                let Some(edit) = analyzer
+                    .as_ref()
+                    .and_then(|analyzer| analyzer.replace_guid(position))
+                else {
+                    continue;
+                };
+                actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                    title: "Replace with new GUID".to_string(),
+                    kind: Some(CodeActionKind::QUICKFIX),
+                    diagnostics: Some(vec![diagnostic.clone()]),
+                    edit: Some(WorkspaceEdit {
+                        changes: Some(HashMap::from([(uri.clone(), vec![edit])])),
+                        ..WorkspaceEdit::default()
+                    }),
+                    is_preferred: Some(true),
+                    ..CodeAction::default()
+                }));
+            } else if code == MISSING_OVERLOAD {
+                let position = diagnostic.range.start;
+                let Some(edits) = analyzer
+                    .as_ref()
+                    .and_then(|analyzer| analyzer.overload_fix(position))
+                else {
+                    continue;
+                };
+                actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                    title: "Add 'overload' directive".to_string(),
+                    kind: Some(CodeActionKind::QUICKFIX),
+                    diagnostics: Some(vec![diagnostic.clone()]),
+                    edit: Some(WorkspaceEdit {
+                        changes: Some(HashMap::from([(uri.clone(), edits)])),
+                        ..WorkspaceEdit::default()
+                    }),
+                    is_preferred: Some(true),
+                    ..CodeAction::default()
+                }));
+            } else if code == UNDEFINED_CONDITIONAL {
+                let Some(suggestion) = diagnostic
+                    .data
+                    .as_ref()
+                    .and_then(|data| data.get("suggestion"))
+                    .and_then(Value::as_str)
+                else {
+                    continue;
+                };
+                actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                    title: format!("Change to '{}'", suggestion),
+                    kind: Some(CodeActionKind::QUICKFIX),
+                    diagnostics: Some(vec![diagnostic.clone()]),
+                    edit: Some(WorkspaceEdit {
+                        changes: Some(HashMap::from([(
+                            uri.clone(),
+                            vec![TextEdit::new(diagnostic.range, suggestion.to_string())],
+                        )])),
+                        ..WorkspaceEdit::default()
+                    }),
+                    is_preferred: Some(true),
+                    ..CodeAction::default()
+                }));
+            } else if code == UNUSED_PRIVATE_MEMBER {
+                let position = diagnostic.range.start;
+                let Some(edits) = analyzer
+                    .as_ref()
+                    .and_then(|analyzer| analyzer.remove_unused_declaration(position))
+                else {
+                    continue;
+                };
+                actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                    title: "Remove declaration".to_string(),
+                    kind: Some(CodeActionKind::QUICKFIX),
+                    diagnostics: Some(vec![diagnostic.clone()]),
+                    edit: Some(WorkspaceEdit {
+                        changes: Some(HashMap::from([(uri.clone(), edits)])),
+                        ..WorkspaceEdit::default()
+                    }),
+                    ..CodeAction::default()
+                }));
             }
         }
-        Ok(None)
+
+        let position = params.range.start;
+        if let Some(edit) = analyzer
+            .as_ref()
+            .and_then(|analyzer| analyzer.join_string_literals(position))
+        {
+            actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                title: "Join string literals".to_string(),
+                kind: Some(CodeActionKind::REFACTOR_REWRITE),
+                edit: Some(WorkspaceEdit {
+                    changes: Some(HashMap::from([(uri.clone(), vec![edit])])),
+                    ..WorkspaceEdit::default()
+                }),
+                ..CodeAction::default()
+            }));
+        }
+        if let Some(edit) = analyzer
+            .as_ref()
+            .and_then(|analyzer| analyzer.generate_guid(position))
+        {
+            actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                title: "Generate new GUID".to_string(),
+                kind: Some(CodeActionKind::REFACTOR_REWRITE),
+                edit: Some(WorkspaceEdit {
+                    changes: Some(HashMap::from([(uri.clone(), vec![edit])])),
+                    ..WorkspaceEdit::default()
+                }),
+                ..CodeAction::default()
+            }));
+        }
+
+        let fix_all = CodeActionKind::from(FIX_ALL_KIND);
+        let requested = params.context.only.as_ref().is_none_or(|only| {
+            only.iter()
+                .any(|kind| fix_all.as_str().starts_with(kind.as_str()))
+        });
+        if let Some(analyzer) = analyzer.as_ref().filter(|_| requested) {
+            let unused = if self.settings.lock().unwrap().fix_all.remove_unused_uses {
+                self.unused_units(analyzer)
+            } else {
+                Vec::new()
+            };
+            let edits = analyzer.fix_all(&unused);
+            if !edits.is_empty() {
+                actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                    title: "Fix all auto-fixable problems".to_string(),
+                    kind: Some(fix_all),
+                    edit: Some(WorkspaceEdit {
+                        changes: Some(HashMap::from([(uri.clone(), edits)])),
+                        ..WorkspaceEdit::default()
+                    }),
+                    ..CodeAction::default()
+                }));
+            }
+        }
+        Ok(Some(actions))
+    }
+
+    async fn execute_command(&self, params: ExecuteCommandParams) -> Result<Option<Value>> {
+        self.check_ready().await?;
+        match params.command.as_str() {
+            SELECT_ENCLOSING_BLOCK_COMMAND => self.select_enclosing_block(params.arguments),
+            RESOLVE_SYMBOL_COMMAND => self.resolve_symbol(params.arguments),
+            SWITCH_COMPANION_COMMAND => self.switch_companion(params.arguments),
+            SYMBOL_PATH_COMMAND => self.symbol_path(params.arguments),
+            OPEN_UNIT_COMMAND => self.open_unit(params.arguments),
+            SHOW_DOCUMENT_DIAGNOSTICS_COMMAND => self.show_document_diagnostics(params.arguments),
+            ANALYZE_FULLY_COMMAND => self.analyze_fully(params.arguments).await,
+            TOGGLE_COMMENT_COMMAND => self.toggle_comment(params.arguments),
+            CLEAR_CACHE_COMMAND => self.clear_cache(),
+            command => Err(Error::invalid_params(format!(
+                "Unknown command: {}",
+                command
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsp::testing::{TestClient, TestDir};
+    use std::time::Duration;
+    use tokio::task::JoinSet;
+
+    /// The text of the stress test unit at `version`: its names carry the
+    /// version, and its declarations move down a line per version, so that
+    /// an answer mixing two versions shows in both names and ranges.
+    fn versioned_unit(version: i32) -> String {
+        format!(
+            "unit Stress;\ninterface\n{}procedure Routine{v};\nimplementation\n\
+             procedure Routine{v};\nvar\n  Local{v}: Integer;\nbegin\n  Local{v} := {v};\n\
+             if Local{v} > 0 then\n  begin\n    Routine{v};\n  end;\nend;\nend.\n",
+            "\n".repeat(version as usize % 4),
+            v = version
+        )
+    }
+
+    /// The read-only requests fired during the edits, with their params.
+    fn stress_requests(uri: &Url) -> Vec<(&'static str, Value)> {
+        let document = json!({ "uri": uri });
+        let position = |line, character| json!({ "textDocument": document, "position": {"line": line, "character": character} });
+        vec![
+            (
+                "textDocument/documentSymbol",
+                json!({ "textDocument": document }),
+            ),
+            (
+                "textDocument/foldingRange",
+                json!({ "textDocument": document }),
+            ),
+            ("textDocument/hover", position(8, 4)),
+            ("textDocument/documentHighlight", position(8, 4)),
+            ("textDocument/definition", position(12, 4)),
+        ]
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn answers_requests_sent_while_initializing() {
+        let dir = TestDir::new("initializing");
+        let client = TestClient::start();
+        client.initialize(Some(&dir.0), json!({})).await;
+        let mut uris = Vec::new();
+        for version in 1..=8 {
+            let text = versioned_unit(version);
+            let uri = dir.write(&format!("Stress{}.pas", version), &text);
+            client.open(&uri, version, &text).await;
+            uris.push(uri);
+        }
+        client.notify("initialized", json!({})).await;
+
+        let mut tasks = JoinSet::new();
+        for uri in &uris {
+            for (method, params) in stress_requests(uri) {
+                let client = client.clone();
+                tasks.spawn(async move { (method, client.request(method, params).await) });
+            }
+        }
+        while let Some(task) = tasks.join_next().await {
+            let (method, answer) = task.unwrap();
+            let answer = answer.unwrap_or_else(|e| panic!("{} failed: {}", method, e));
+            // The documents opened before `initialized` were not lost
+            if method == "textDocument/documentSymbol" {
+                assert_eq!(answer[0]["name"], "Stress");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn replays_document_notifications_received_before_initialized() {
+        let dir = TestDir::new("replay");
+        let uri = dir.write("Stress.pas", &versioned_unit(1));
+        let client = TestClient::start();
+        client.initialize(Some(&dir.0), json!({})).await;
+        client.open(&uri, 1, &versioned_unit(1)).await;
+        client.change(&uri, 2, &versioned_unit(2)).await;
+        client.initialized().await;
+
+        let (method, params) = stress_requests(&uri).remove(0);
+        let symbols = client.request(method, params).await.unwrap().to_string();
+        assert!(symbols.contains("Routine2") && !symbols.contains("Routine1"));
+    }
+
+    #[tokio::test]
+    async fn keeps_answering_while_shutdown_waits_for_the_cache_lock() {
+        let dir = TestDir::new("session-lock");
+        let client = TestClient::start();
+        let root = Url::from_directory_path(&dir.0).unwrap();
+        let folder = json!([{ "uri": root, "name": "root" }]);
+        let params = json!({ "capabilities": {}, "workspaceFolders": folder });
+        client.request("initialize", params).await.unwrap();
+        client.initialized().await;
+        // Another process holds the lock of the session file
+        let session = session::session_path(&[root.to_file_path().unwrap()]).unwrap();
+        let lock = session.with_file_name(format!(
+            "{}.lock",
+            session.file_name().unwrap().to_string_lossy()
+        ));
+        fs::create_dir_all(lock.parent().unwrap()).unwrap();
+        fs::write(&lock, "0").unwrap();
+
+        let started = Instant::now();
+        let shutdown = tokio::spawn({
+            let client = client.clone();
+            async move { client.request("shutdown", Value::Null).await }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let _ = client
+            .request("workspace/symbol", json!({ "query": "" }))
+            .await;
+        assert!(started.elapsed() < Duration::from_secs(1));
+        fs::remove_file(&lock).unwrap();
+        shutdown.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_renaming_result_and_self() {
+        let dir = TestDir::new("implicit-rename");
+        let text = "unit U;\ninterface\ntype\n  TForm = class\n    function Run: Integer;\n  \
+                    end;\nimplementation\nfunction TForm.Run: Integer;\nbegin\n  Self.Run;\n  \
+                    Result := 1;\nend;\nend.\n";
+        let uri = dir.write("U.pas", text);
+        let client = TestClient::start();
+        client.initialize(Some(&dir.0), json!({})).await;
+        client.initialized().await;
+        client.open(&uri, 1, text).await;
+
+        let at = |line: u32, character: u32| json!({ "textDocument": { "uri": uri }, "position": { "line": line, "character": character } });
+        for (position, name) in [(at(9, 3), "Self"), (at(10, 3), "Result")] {
+            let message = format!("{} is an implicit identifier and cannot be renamed", name);
+            let error = client
+                .request("textDocument/prepareRename", position.clone())
+                .await
+                .unwrap_err();
+            assert_eq!(error["message"], message);
+            let mut params = position;
+            params["newName"] = json!("Other");
+            let error = client
+                .request("textDocument/rename", params)
+                .await
+                .unwrap_err();
+            assert_eq!(error["message"], message);
+        }
+        let range = client
+            .request("textDocument/prepareRename", at(9, 8))
+            .await
+            .unwrap();
+        assert_eq!(range["start"], json!({ "line": 9, "character": 7 }));
+    }
+
+    #[tokio::test]
+    async fn resolves_units_through_the_search_paths_of_dls_toml() {
+        let dir = TestDir::new("search-paths");
+        let project = dir.write("app/dls.toml", "search_paths = ['src', '../ext']\n");
+        let text =
+            "unit Main;\ninterface\nuses Shapes, Ext;\nvar\n  E: TExt;\nimplementation\nend.\n";
+        let main = dir.write("app/src/Main.pas", text);
+        dir.write(
+            "app/lib/Shapes.pas",
+            "unit Shapes;\ninterface\nimplementation\nend.\n",
+        );
+        let ext = dir.write(
+            "ext/Ext.pas",
+            "unit Ext;\ninterface\ntype\n  TExt = class\n  end;\nimplementation\nend.\n",
+        );
+        let client = TestClient::start();
+        let options = json!({ "diagnostics": { "unresolvedUnits": true } });
+        client.initialize(Some(&dir.0.join("app")), options).await;
+        client.initialized().await;
+        client.open(&main, 1, text).await;
+
+        // Ext lies outside the workspace, on the search path
+        let params = client
+            .notification("textDocument/publishDiagnostics", |params| {
+                params["uri"] == main.as_str()
+            })
+            .await;
+        let unresolved: Vec<&Value> = params["diagnostics"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|diagnostic| diagnostic["code"] == UNRESOLVED_UNIT)
+            .collect();
+        assert_eq!(unresolved.len(), 1);
+        assert_eq!(unresolved[0]["data"]["unit"], "Shapes");
+        let lib = dir.0.join("app").join("lib");
+        assert_eq!(
+            unresolved[0]["data"]["containingFolder"],
+            lib.display().to_string()
+        );
+        let definition = client
+            .request(
+                "textDocument/definition",
+                json!({ "textDocument": { "uri": main }, "position": { "line": 4, "character": 6 } }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(definition["uri"], ext.as_str());
+
+        let actions = client
+            .request(
+                "textDocument/codeAction",
+                json!({
+                    "textDocument": { "uri": main },
+                    "range": unresolved[0]["range"],
+                    "context": { "diagnostics": [unresolved[0]] },
+                }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(actions[0]["title"], "Add containing folder to search paths");
+        assert_eq!(
+            actions[0]["edit"]["changes"][project.as_str()],
+            json!([{
+                "range": { "start": { "line": 0, "character": 31 }, "end": { "line": 0, "character": 31 } },
+                "newText": ", \"lib\"",
+            }])
+        );
+
+        // The warning goes once the folder is on the search path
+        dir.write(
+            "app/dls.toml",
+            "search_paths = ['src', '../ext', \"lib\"]\n",
+        );
+        client
+            .notify(
+                "workspace/didChangeWatchedFiles",
+                json!({ "changes": [{ "uri": project, "type": 2 }] }),
+            )
+            .await;
+        client
+            .notification("textDocument/publishDiagnostics", |params| {
+                params["uri"] == main.as_str() && params["diagnostics"] == json!([])
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn fails_requests_outside_the_session() {
+        let dir = TestDir::new("session");
+        let text = versioned_unit(1);
+        let uri = dir.write("Stress.pas", &text);
+        let client = TestClient::start();
+        client.initialize(Some(&dir.0), json!({})).await;
+        client.open(&uri, 1, &text).await;
+        let (method, params) = stress_requests(&uri).remove(0);
+
+        let error = client.request(method, params.clone()).await.unwrap_err();
+        assert_eq!(error["code"], -32002);
+
+        client.initialized().await;
+        assert!(client.request(method, params.clone()).await.is_ok());
+
+        client.request("shutdown", Value::Null).await.unwrap();
+        client.open(&uri, 2, &versioned_unit(2)).await;
+        let error = client.request(method, params).await.unwrap_err();
+        assert_eq!(error["code"], -32600);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn answers_concurrent_requests_from_a_single_version() {
+        const VERSIONS: i32 = 12;
+        let dir = TestDir::new("stress");
+        let uri = dir.write("Stress.pas", &versioned_unit(1));
+        let client = TestClient::start();
+        client.initialize(None, json!({})).await;
+        client.initialized().await;
+        client.open(&uri, 1, &versioned_unit(1)).await;
+
+        // What each request answers for each text, one request at a time
+        let mut expected: HashMap<&str, Vec<Value>> = HashMap::new();
+        for version in 1..=VERSIONS {
+            client.change(&uri, version, &versioned_unit(version)).await;
+            for (method, params) in stress_requests(&uri) {
+                let answer = client.request(method, params).await.unwrap();
+                expected.entry(method).or_default().push(answer);
+            }
+        }
+
+        // The same texts again, each answer racing the edits
+        let mut tasks = JoinSet::new();
+        let editor = client.clone();
+        let edited = uri.clone();
+        tasks.spawn(async move {
+            for version in 1..=VERSIONS * 4 {
+                let text = versioned_unit((version - 1) % VERSIONS + 1);
+                editor.change(&edited, VERSIONS + version, &text).await;
+                tokio::task::yield_now().await;
+            }
+            Vec::new()
+        });
+        for _ in 0..20 {
+            for (method, params) in stress_requests(&uri) {
+                let client = client.clone();
+                tasks.spawn(async move { vec![(method, client.request(method, params).await)] });
+            }
+        }
+        let mut answers = 0;
+        while let Some(task) = tasks.join_next().await {
+            for (method, answer) in task.unwrap() {
+                let answer = answer.unwrap_or_else(|e| panic!("{} failed: {}", method, e));
+                assert!(
+                    expected[method].contains(&answer),
+                    "{} answered from no single version: {}",
+                    method,
+                    answer
+                );
+                answers += 1;
+            }
+        }
+        assert_eq!(answers, 100);
+
+        let (method, params) = stress_requests(&uri).remove(0);
+        assert_eq!(
+            client.request(method, params).await.unwrap(),
+            expected[method][VERSIONS as usize - 1]
+        );
     }
 }
@@ -0,0 +1,84 @@
+use crate::lsp::cache;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+use tower_lsp::lsp_types::Url;
+
+/// The version of the session file format. Files of another version are
+/// ignored.
+const SESSION_VERSION: u32 = 1;
+
+/// What the server keeps of a workspace across restarts: the documents open
+/// at the last graceful shutdown, so that a restarted server indexes the
+/// units they use and analyzes them before anything else. The client
+/// reopens the documents itself; only the work derived from them is
+/// restored.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Session {
+    version: u32,
+    /// The [`fingerprint`] of the settings the summaries were made with.
+    settings: u64,
+    pub documents: Vec<SessionDocument>,
+}
+
+/// The summary of a document open at shutdown.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionDocument {
+    pub uri: Url,
+    /// The [`fingerprint`] of the file on disk at shutdown. A file changed
+    /// since makes the summary stale.
+    pub fingerprint: u64,
+    /// The units its uses clauses name.
+    pub used_units: Vec<String>,
+}
+
+impl Session {
+    pub fn new(settings: u64, documents: Vec<SessionDocument>) -> Self {
+        Self {
+            version: SESSION_VERSION,
+            settings,
+            documents,
+        }
+    }
+
+    /// Reads the session saved at `path`. `None` when there is none, or it
+    /// was saved by another format version or with other settings.
+    pub fn load(path: &Path, settings: u64) -> Option<Self> {
+        let text = fs::read_to_string(path).ok()?;
+        let session: Session = serde_json::from_str(&text)
+            .map_err(|e| log::warn!("Ignoring invalid session {}: {}", path.display(), e))
+            .ok()?;
+        (session.version == SESSION_VERSION && session.settings == settings).then_some(session)
+    }
+
+    /// Saves the session, atomically since the server processes of other
+    /// editor windows on the workspace may load or save it meanwhile.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        cache::write_atomic(path, &serde_json::to_vec(self)?)
+    }
+}
+
+/// The session file of the workspace with the folders `roots`, in the
+/// [`cache::cache_dir`]. `None` without folders or a cache directory.
+pub fn session_path(roots: &[PathBuf]) -> Option<PathBuf> {
+    if roots.is_empty() {
+        return None;
+    }
+    let cache = cache::cache_dir()?;
+    let folders: Vec<String> = roots
+        .iter()
+        .map(|root| root.to_string_lossy().into_owned())
+        .collect();
+    let name = format!("{:016x}.json", fingerprint(folders.join("\n").as_bytes()));
+    Some(cache.join("sessions").join(name))
+}
+
+/// A 64-bit FNV-1a hash of `bytes`. Unlike the hashers of the standard
+/// library it is the same across runs and builds, so it can be saved.
+pub fn fingerprint(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
@@ -0,0 +1,172 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use tree_sitter::Node;
+
+/// How well error recovery coped with a source: the `ERROR` and `MISSING`
+/// nodes of its tree and how much of the text the error subtrees swallow.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ParseStats {
+    pub node_count: usize,
+    pub error_nodes: usize,
+    pub missing_nodes: usize,
+    /// Bytes covered by outermost `ERROR` subtrees.
+    pub error_bytes: usize,
+    pub total_bytes: usize,
+    /// The constructs error recovery gave up on: the parent kind of each
+    /// `ERROR` node and the kind of each `MISSING` node, with counts.
+    #[serde(skip)]
+    offending_kinds: HashMap<String, usize>,
+}
+
+/// A node kind with the number of errors attributed to it.
+#[derive(Debug, Clone, Serialize)]
+pub struct KindCount {
+    pub kind: String,
+    pub count: usize,
+}
+
+impl ParseStats {
+    pub fn collect(root: Node, total_bytes: usize) -> Self {
+        let mut stats = ParseStats {
+            total_bytes,
+            ..ParseStats::default()
+        };
+        stats.visit(root, false);
+        stats
+    }
+
+    fn visit(&mut self, node: Node, inside_error: bool) {
+        self.node_count += 1;
+        if node.is_error() {
+            self.error_nodes += 1;
+            if !inside_error {
+                self.error_bytes += node.byte_range().len();
+            }
+            let context = node.parent().map_or("root", |parent| parent.kind());
+            *self.offending_kinds.entry(context.to_string()).or_default() += 1;
+        } else if node.is_missing() {
+            self.missing_nodes += 1;
+            *self
+                .offending_kinds
+                .entry(node.kind().to_string())
+                .or_default() += 1;
+        }
+        let inside_error = inside_error || node.is_error();
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            self.visit(child, inside_error);
+        }
+    }
+
+    /// Adds the counts of another source, for corpus totals.
+    pub fn add(&mut self, other: &ParseStats) {
+        self.node_count += other.node_count;
+        self.error_nodes += other.error_nodes;
+        self.missing_nodes += other.missing_nodes;
+        self.error_bytes += other.error_bytes;
+        self.total_bytes += other.total_bytes;
+        for (kind, count) in &other.offending_kinds {
+            *self.offending_kinds.entry(kind.clone()).or_default() += count;
+        }
+    }
+
+    /// The share of the text covered by error subtrees, from 0 to 100.
+    pub fn error_percentage(&self) -> f64 {
+        if self.total_bytes == 0 {
+            return 0.0;
+        }
+        (self.error_bytes.min(self.total_bytes) as f64) * 100.0 / self.total_bytes as f64
+    }
+
+    /// The `limit` most frequent offending kinds, most frequent first and
+    /// by name among equals.
+    pub fn top_kinds(&self, limit: usize) -> Vec<KindCount> {
+        let mut kinds: Vec<KindCount> = self
+            .offending_kinds
+            .iter()
+            .map(|(kind, count)| KindCount {
+                kind: kind.clone(),
+                count: *count,
+            })
+            .collect();
+        kinds.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.kind.cmp(&b.kind)));
+        kinds.truncate(limit);
+        kinds
+    }
+}
+
+/// The latencies of the requests the language server answered, by request.
+/// The first request of each kind is answered cold, paying for whatever
+/// the warm-up did not prepare, and is kept apart from the others.
+#[derive(Debug, Default)]
+pub struct LatencyLog {
+    operations: BTreeMap<String, OperationLatency>,
+}
+
+#[derive(Debug)]
+struct OperationLatency {
+    cold: Duration,
+    warm_count: u32,
+    warm_total: Duration,
+    warm_max: Duration,
+}
+
+/// The latencies of one kind of request, in milliseconds.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestLatency {
+    /// The request, such as `hover`.
+    pub operation: String,
+    /// The latency of the first request.
+    pub cold: f64,
+    /// How many requests followed the first one.
+    pub warm_count: u32,
+    /// The mean latency of the requests after the first, `None` when there
+    /// were none.
+    pub warm_mean: Option<f64>,
+    pub warm_max: Option<f64>,
+}
+
+impl LatencyLog {
+    pub fn record(&mut self, operation: &str, elapsed: Duration) {
+        match self.operations.get_mut(operation) {
+            Some(latency) => {
+                latency.warm_count += 1;
+                latency.warm_total += elapsed;
+                latency.warm_max = latency.warm_max.max(elapsed);
+            }
+            None => {
+                self.operations.insert(
+                    operation.to_string(),
+                    OperationLatency {
+                        cold: elapsed,
+                        warm_count: 0,
+                        warm_total: Duration::ZERO,
+                        warm_max: Duration::ZERO,
+                    },
+                );
+            }
+        }
+    }
+
+    /// The latencies of each kind of request, by request name.
+    pub fn latencies(&self) -> Vec<RequestLatency> {
+        let milliseconds = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        self.operations
+            .iter()
+            .map(|(operation, latency)| {
+                let warm = latency.warm_count > 0;
+                RequestLatency {
+                    operation: operation.clone(),
+                    cold: milliseconds(latency.cold),
+                    warm_count: latency.warm_count,
+                    warm_mean: warm.then(|| milliseconds(latency.warm_total / latency.warm_count)),
+                    warm_max: warm.then(|| milliseconds(latency.warm_max)),
+                }
+            })
+            .collect()
+    }
+}
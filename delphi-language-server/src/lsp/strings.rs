@@ -0,0 +1,169 @@
+use tree_sitter::Node;
+
+/// The longest string literal the Delphi compiler accepts.
+pub const MAX_LITERAL_LENGTH: usize = 255;
+
+/// One character of a string literal in source form: either a character
+/// inside a quoted section (`''` for an embedded quote) or a `#nn`/`#$hh`
+/// character code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct LiteralUnit {
+    text: String,
+    quoted: bool,
+    value: Option<char>,
+}
+
+/// Splits the source text of a string literal such as `'It''s'#13#10'ok'`
+/// into its characters. Returns `None` for malformed literals.
+fn literal_units(text: &str) -> Option<Vec<LiteralUnit>> {
+    let mut units = Vec::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => loop {
+                match chars.next()? {
+                    '\'' if chars.peek() == Some(&'\'') => {
+                        chars.next();
+                        units.push(LiteralUnit {
+                            text: "''".to_string(),
+                            quoted: true,
+                            value: Some('\''),
+                        });
+                    }
+                    '\'' => break,
+                    c => units.push(LiteralUnit {
+                        text: c.to_string(),
+                        quoted: true,
+                        value: Some(c),
+                    }),
+                }
+            },
+            '#' => {
+                let mut code = String::from("#");
+                let hex = chars.peek() == Some(&'$');
+                if hex {
+                    code.push(chars.next()?);
+                }
+                while let Some(d) = chars.peek().filter(|d| d.is_ascii_hexdigit()) {
+                    if !hex && !d.is_ascii_digit() {
+                        break;
+                    }
+                    code.push(*d);
+                    chars.next();
+                }
+                let digits = code.trim_start_matches(['#', '$']);
+                let value = u32::from_str_radix(digits, if hex { 16 } else { 10 }).ok()?;
+                units.push(LiteralUnit {
+                    text: code,
+                    quoted: false,
+                    value: char::from_u32(value),
+                });
+            }
+            c if c.is_whitespace() => {}
+            _ => return None,
+        }
+    }
+    Some(units)
+}
+
+/// Writes units back as a literal, opening and closing quoted sections as
+/// needed so embedded quotes and character codes are preserved verbatim.
+fn encode_units(units: &[LiteralUnit]) -> String {
+    let mut text = String::new();
+    let mut in_quotes = false;
+    for unit in units {
+        if unit.quoted != in_quotes {
+            text.push('\'');
+            in_quotes = unit.quoted;
+        }
+        text.push_str(&unit.text);
+    }
+    if in_quotes {
+        text.push('\'');
+    }
+    if text.is_empty() {
+        text.push_str("''");
+    }
+    text
+}
+
+/// Decodes the value of a string literal, resolving `''` and `#nn` codes.
+pub fn decode_literal(text: &str) -> Option<String> {
+    literal_units(text)?
+        .into_iter()
+        .map(|unit| unit.value)
+        .collect()
+}
+
+/// Joins literals into as few literals as possible, splitting again where a
+/// literal would exceed [`MAX_LITERAL_LENGTH`] characters of source text.
+pub fn join_literals(literals: &[&str]) -> Option<Vec<String>> {
+    let mut units = Vec::new();
+    for literal in literals {
+        units.extend(literal_units(literal)?);
+    }
+
+    let mut joined = Vec::new();
+    let mut chunk: Vec<LiteralUnit> = Vec::new();
+    for unit in units {
+        chunk.push(unit);
+        if chunk.len() > 1 && encode_units(&chunk).len() > MAX_LITERAL_LENGTH {
+            let unit = chunk.pop().unwrap();
+            joined.push(encode_units(&chunk));
+            chunk = vec![unit];
+        }
+    }
+    if !chunk.is_empty() || joined.is_empty() {
+        joined.push(encode_units(&chunk));
+    }
+    Some(joined)
+}
+
+/// Returns the outermost `+` expression containing `node`, if the chain
+/// concatenates at least one string literal with another operand.
+pub fn string_chain_root(node: Node) -> Option<Node> {
+    let mut root = None;
+    let mut current = Some(node);
+    while let Some(n) = current {
+        if is_concatenation(n) {
+            root = Some(n);
+        } else if root.is_some() {
+            break;
+        }
+        current = n.parent();
+    }
+
+    let root = root?;
+    chain_operands(root)
+        .iter()
+        .any(|operand| operand.kind() == "literalString")
+        .then_some(root)
+}
+
+/// Flattens a tree of `+` expressions into its operands, left to right.
+pub fn chain_operands(root: Node) -> Vec<Node> {
+    let mut operands = Vec::new();
+    let mut stack = vec![root];
+    while let Some(node) = stack.pop() {
+        if is_concatenation(node) {
+            if let (Some(lhs), Some(rhs)) = (
+                node.child_by_field_name("lhs"),
+                node.child_by_field_name("rhs"),
+            ) {
+                stack.push(rhs);
+                stack.push(lhs);
+                continue;
+            }
+        }
+        operands.push(node);
+    }
+    operands
+}
+
+fn is_concatenation(node: Node) -> bool {
+    node.kind() == "exprBinary"
+        && node
+            .child_by_field_name("operator")
+            .is_some_and(|operator| operator.kind() == "kAdd")
+}
@@ -0,0 +1,70 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A stable identifier for a declaration that survives edits shifting its
+/// position, used to refer back to a symbol across requests and persisted by
+/// the editor extension (e.g. for pinned symbols).
+///
+/// Format: the dot-separated declaration path starting with the unit name,
+/// followed for routines by a parenthesized, comma-separated list of the
+/// parameter types, which tells overloads apart:
+///
+/// ```text
+/// MyUnit.TCustomer              a type
+/// MyUnit.TCustomer.Add(string)  a routine taking one string parameter
+/// MyUnit.Clear()                a parameterless routine
+/// MyUnit.MAX_ITEMS              a unit-level constant
+/// ```
+///
+/// Comparison is case-insensitive and ignores whitespace, like Delphi
+/// identifiers. A method implementation (`TCustomer.Add`) gets the same id
+/// as its declaration inside the class.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(transparent)]
+pub struct SymbolId(String);
+
+impl SymbolId {
+    /// Builds the id of a declaration named `name` inside `parent`.
+    /// `params` is `Some` for routines.
+    pub fn new(parent: Option<&SymbolId>, name: &str, params: Option<&[String]>) -> Self {
+        let mut id = match parent {
+            Some(parent) => format!("{}.{}", parent.qualified_name(), name),
+            None => name.to_string(),
+        };
+        if let Some(params) = params {
+            id.push('(');
+            id.push_str(&params.join(","));
+            id.push(')');
+        }
+        SymbolId(id)
+    }
+
+    /// The declaration path without the parameter list.
+    pub fn qualified_name(&self) -> &str {
+        self.0.split('(').next().unwrap_or_default()
+    }
+
+    /// Whether both ids name the same declaration, including the overload.
+    pub fn matches(&self, other: &SymbolId) -> bool {
+        normalize(&self.0) == normalize(&other.0)
+    }
+
+    /// Whether both ids name the same declaration path, ignoring overloads.
+    pub fn matches_name(&self, other: &SymbolId) -> bool {
+        normalize(self.qualified_name()) == normalize(other.qualified_name())
+    }
+}
+
+impl fmt::Display for SymbolId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+fn normalize(id: &str) -> String {
+    id.chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_lowercase()
+}
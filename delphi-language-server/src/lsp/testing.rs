@@ -0,0 +1,226 @@
+use crate::lsp::transport::{frame, read_message};
+use crate::lsp::DelphiLanguageServer;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::io::{AsyncWriteExt, BufReader, DuplexStream, WriteHalf};
+use tokio::sync::{oneshot, Notify};
+use tower_lsp::lsp_types::Url;
+
+/// How long a test waits for a response or a notification before failing.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A client talking to a server over in-memory pipes, framing messages as
+/// an editor does over stdio. Clones share the connection, so that tasks
+/// can send requests concurrently.
+#[derive(Clone)]
+pub struct TestClient {
+    connection: Arc<Connection>,
+}
+
+struct Connection {
+    input: tokio::sync::Mutex<WriteHalf<DuplexStream>>,
+    next_id: AtomicI64,
+    responses: Mutex<HashMap<i64, oneshot::Sender<Value>>>,
+    notifications: Mutex<Vec<Value>>,
+    notified: Notify,
+}
+
+impl TestClient {
+    /// Starts a server, with the cache directory moved to a temporary
+    /// directory shared by the tests.
+    pub fn start() -> Self {
+        static CACHE: OnceLock<PathBuf> = OnceLock::new();
+        CACHE.get_or_init(|| {
+            let cache = std::env::temp_dir().join(format!("dls-tests-{}", std::process::id()));
+            std::env::set_var("XDG_CACHE_HOME", &cache);
+            cache
+        });
+
+        let (client, server) = tokio::io::duplex(1 << 20);
+        let (server_output, server_input) = tokio::io::split(server);
+        let (service, socket) = DelphiLanguageServer::service();
+        tokio::spawn(tower_lsp::Server::new(server_output, server_input, socket).serve(service));
+
+        let (output, input) = tokio::io::split(client);
+        let connection = Arc::new(Connection {
+            input: tokio::sync::Mutex::new(input),
+            next_id: AtomicI64::new(1),
+            responses: Mutex::new(HashMap::new()),
+            notifications: Mutex::new(Vec::new()),
+            notified: Notify::new(),
+        });
+        let reader = connection.clone();
+        tokio::spawn(async move {
+            let mut output = BufReader::new(output);
+            while let Ok(Some(body)) = read_message(&mut output).await {
+                let message: Value = serde_json::from_slice(&body).unwrap();
+                match (
+                    message.get("id").and_then(Value::as_i64),
+                    message.get("method"),
+                ) {
+                    (Some(id), None) => {
+                        if let Some(response) = reader.responses.lock().unwrap().remove(&id) {
+                            let _ = response.send(message);
+                        }
+                    }
+                    // The server sends no requests the tests care about
+                    (Some(_), Some(_)) => {
+                        let reply = json!({"jsonrpc": "2.0", "id": message["id"], "result": null});
+                        reader.send(&reply).await;
+                    }
+                    _ => {
+                        reader.notifications.lock().unwrap().push(message);
+                        reader.notified.notify_waiters();
+                    }
+                }
+            }
+        });
+        Self { connection }
+    }
+
+    /// Sends a request and waits for its response: the result, or the
+    /// error object. Null `params` are left out of the request.
+    pub async fn request(&self, method: &str, params: Value) -> Result<Value, Value> {
+        let id = self.connection.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = oneshot::channel();
+        self.connection.responses.lock().unwrap().insert(id, sender);
+        let mut request = json!({"jsonrpc": "2.0", "id": id, "method": method});
+        if !params.is_null() {
+            request["params"] = params;
+        }
+        self.connection.send(&request).await;
+        let mut response = tokio::time::timeout(TIMEOUT, receiver)
+            .await
+            .unwrap_or_else(|_| panic!("no response to {}", method))
+            .unwrap();
+        match response.get_mut("error") {
+            Some(error) => Err(error.take()),
+            None => Ok(response["result"].take()),
+        }
+    }
+
+    pub async fn notify(&self, method: &str, params: Value) {
+        self.connection
+            .send(&json!({"jsonrpc": "2.0", "method": method, "params": params}))
+            .await;
+    }
+
+    /// Runs `initialize` for a workspace folder at `root`, with `options`
+    /// as `initializationOptions`, and returns the result without sending
+    /// `initialized`. The previous session is never restored.
+    pub async fn initialize(&self, root: Option<&Path>, mut options: Value) -> Value {
+        options["performance"]["restoreSession"] = json!(false);
+        let folders = root
+            .map(|root| json!([{"uri": Url::from_directory_path(root).unwrap(), "name": "root"}]));
+        self.request(
+            "initialize",
+            json!({
+                "capabilities": {},
+                "workspaceFolders": folders,
+                "initializationOptions": options,
+            }),
+        )
+        .await
+        .expect("initialize failed")
+    }
+
+    /// Sends `initialized` and waits until the server indexed the
+    /// workspace.
+    pub async fn initialized(&self) {
+        self.notify("initialized", json!({})).await;
+        self.notification("window/logMessage", |params| {
+            params["message"] == "Delphi language server initialized!"
+        })
+        .await;
+    }
+
+    pub async fn open(&self, uri: &Url, version: i32, text: &str) {
+        self.notify(
+            "textDocument/didOpen",
+            json!({"textDocument": {
+                "uri": uri, "languageId": "pascal", "version": version, "text": text,
+            }}),
+        )
+        .await;
+    }
+
+    /// Replaces the whole text of a document.
+    pub async fn change(&self, uri: &Url, version: i32, text: &str) {
+        self.notify(
+            "textDocument/didChange",
+            json!({
+                "textDocument": {"uri": uri, "version": version},
+                "contentChanges": [{"text": text}],
+            }),
+        )
+        .await;
+    }
+
+    /// Waits for a notification of `method` that `matches`, received at
+    /// any time since the server started.
+    pub async fn notification(&self, method: &str, matches: impl Fn(&Value) -> bool) -> Value {
+        let wait = async {
+            loop {
+                let notified = self.connection.notified.notified();
+                let found = self
+                    .connection
+                    .notifications
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .find(|notification| {
+                        notification["method"] == method && matches(&notification["params"])
+                    })
+                    .cloned();
+                if let Some(notification) = found {
+                    return notification["params"].clone();
+                }
+                notified.await;
+            }
+        };
+        tokio::time::timeout(TIMEOUT, wait)
+            .await
+            .unwrap_or_else(|_| panic!("no {} notification", method))
+    }
+}
+
+impl Connection {
+    async fn send(&self, message: &Value) {
+        let bytes = frame(message.to_string().as_bytes());
+        let mut input = self.input.lock().await;
+        input.write_all(&bytes).await.unwrap();
+        input.flush().await.unwrap();
+    }
+}
+
+/// A directory under the temporary directory for the files of one test,
+/// removed on drop.
+pub struct TestDir(pub PathBuf);
+
+impl TestDir {
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("dls-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    /// Writes a file of the directory, creating the folders of `name`, and
+    /// returns its URI.
+    pub fn write(&self, name: &str, text: &str) -> Url {
+        let path = self.0.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, text).unwrap();
+        Url::from_file_path(path).unwrap()
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,494 @@
+use std::ops;
+use tower_lsp::lsp_types::*;
+use tree_sitter::Point;
+
+/// The unit of the `character` of LSP positions, negotiated with the client
+/// in `initialize`. UTF-16 code units are the protocol default; byte columns
+/// are used when the client offers UTF-8.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PositionEncoding {
+    Utf8,
+    #[default]
+    Utf16,
+}
+
+impl PositionEncoding {
+    /// UTF-8 when the client supports it, as it needs no conversion,
+    /// otherwise the mandatory UTF-16.
+    pub fn negotiate(capabilities: &ClientCapabilities) -> Self {
+        let supports_utf8 = capabilities
+            .general
+            .as_ref()
+            .and_then(|general| general.position_encodings.as_ref())
+            .is_some_and(|encodings| encodings.contains(&PositionEncodingKind::UTF8));
+        if supports_utf8 {
+            PositionEncoding::Utf8
+        } else {
+            PositionEncoding::Utf16
+        }
+    }
+
+    pub fn kind(self) -> PositionEncodingKind {
+        match self {
+            PositionEncoding::Utf8 => PositionEncodingKind::UTF8,
+            PositionEncoding::Utf16 => PositionEncodingKind::UTF16,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+    Cr,
+}
+
+impl LineEnding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::CrLf => "CRLF",
+            LineEnding::Cr => "CR",
+        }
+    }
+}
+
+/// A non-ASCII character of a line, whose byte and UTF-16 lengths differ
+/// from its one column in ASCII text.
+#[derive(Debug, Clone, Copy)]
+struct WideChar {
+    /// Byte column of the character within its line.
+    column: usize,
+    len_utf8: usize,
+    len_utf16: usize,
+}
+
+impl WideChar {
+    fn len(self, encoding: PositionEncoding) -> usize {
+        match encoding {
+            PositionEncoding::Utf8 => self.len_utf8,
+            PositionEncoding::Utf16 => self.len_utf16,
+        }
+    }
+}
+
+/// Converts between byte offsets into a text and LSP positions. CR, LF and
+/// CRLF all terminate a line, matching how editors count lines, so positions
+/// computed here stay correct in files with mixed or bare-CR line endings.
+/// Tree-sitter only counts LF when computing rows, which is why node ranges
+/// must be converted from byte offsets rather than from
+/// `Node::start_position`.
+#[derive(Debug, Clone)]
+pub struct LineIndex {
+    /// Byte offset of the first character of each line.
+    line_starts: Vec<usize>,
+    /// Byte offset just past the last character of each line, excluding
+    /// its terminator.
+    line_ends: Vec<usize>,
+    /// The terminator of each line; `None` for the last line.
+    endings: Vec<Option<LineEnding>>,
+    /// The non-ASCII characters of each line, in order.
+    wide_chars: Vec<Vec<WideChar>>,
+    encoding: PositionEncoding,
+}
+
+impl LineIndex {
+    pub fn new(text: &str, encoding: PositionEncoding) -> Self {
+        let mut line_starts = vec![0];
+        let mut line_ends = Vec::new();
+        let mut endings = Vec::new();
+        let mut wide_chars = vec![Vec::new()];
+        let mut chars = text.char_indices().peekable();
+
+        while let Some((i, c)) = chars.next() {
+            let ending = match c {
+                '\n' => Some(LineEnding::Lf),
+                '\r' if chars.next_if(|(_, next)| *next == '\n').is_some() => {
+                    Some(LineEnding::CrLf)
+                }
+                '\r' => Some(LineEnding::Cr),
+                _ => None,
+            };
+            match ending {
+                Some(ending) => {
+                    line_ends.push(i);
+                    endings.push(Some(ending));
+                    line_starts.push(i + ending.as_str().len());
+                    wide_chars.push(Vec::new());
+                }
+                None if !c.is_ascii() => wide_chars.last_mut().unwrap().push(WideChar {
+                    column: i - line_starts.last().unwrap(),
+                    len_utf8: c.len_utf8(),
+                    len_utf16: c.len_utf16(),
+                }),
+                None => {}
+            }
+        }
+        line_ends.push(text.len());
+        endings.push(None);
+
+        Self {
+            line_starts,
+            line_ends,
+            endings,
+            wide_chars,
+            encoding,
+        }
+    }
+
+    pub fn encoding(&self) -> PositionEncoding {
+        self.encoding
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// Converts a byte offset to a position. Offsets inside a CRLF pair or
+    /// past the end are clamped to the line end, offsets inside a character
+    /// to its start.
+    pub fn offset_to_position(&self, offset: usize) -> Position {
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        };
+        let column = offset.min(self.line_ends[line]) - self.line_starts[line];
+        let mut character = column;
+        for wide in &self.wide_chars[line] {
+            if wide.column >= column {
+                break;
+            }
+            if column < wide.column + wide.len_utf8 {
+                character -= column - wide.column;
+                break;
+            }
+            character = character - wide.len_utf8 + wide.len(self.encoding);
+        }
+        Position {
+            line: line as u32,
+            character: character as u32,
+        }
+    }
+
+    /// Converts a position to a byte offset, clamping characters past the
+    /// end of the line and lines past the end of the text. A character
+    /// pointing inside a multi-unit character resolves to its start.
+    pub fn position_to_offset(&self, position: Position) -> usize {
+        let line = position.line as usize;
+        if line >= self.line_count() {
+            return self.line_ends[self.line_count() - 1];
+        }
+        let mut remaining = position.character as usize;
+        let mut column = 0;
+        for wide in &self.wide_chars[line] {
+            if wide.column - column >= remaining {
+                break;
+            }
+            remaining -= wide.column - column;
+            if remaining < wide.len(self.encoding) {
+                remaining = 0;
+                column = wide.column;
+                break;
+            }
+            remaining -= wide.len(self.encoding);
+            column = wide.column + wide.len_utf8;
+        }
+        (self.line_starts[line] + column + remaining).min(self.line_ends[line])
+    }
+
+    pub fn byte_range_to_range(&self, range: ops::Range<usize>) -> Range {
+        Range {
+            start: self.offset_to_position(range.start),
+            end: self.offset_to_position(range.end),
+        }
+    }
+
+    /// Converts a range to a byte range, never inverted even when the range
+    /// is.
+    pub fn range_to_byte_range(&self, range: Range) -> ops::Range<usize> {
+        let start = self.position_to_offset(range.start);
+        let end = self.position_to_offset(range.end).max(start);
+        start..end
+    }
+
+    /// Byte range of a line, excluding its terminator.
+    pub fn line_span(&self, line: usize) -> Option<ops::Range<usize>> {
+        Some(*self.line_starts.get(line)?..self.line_ends[line])
+    }
+
+    pub fn line_ending(&self, line: usize) -> Option<LineEnding> {
+        self.endings.get(line).copied().flatten()
+    }
+
+    /// The terminator used by most lines, `None` for single-line texts.
+    pub fn dominant_line_ending(&self) -> Option<LineEnding> {
+        let count = |ending| self.endings.iter().filter(|e| **e == Some(ending)).count();
+        [LineEnding::CrLf, LineEnding::Lf, LineEnding::Cr]
+            .into_iter()
+            .map(|ending| (ending, count(ending)))
+            .filter(|(_, count)| *count > 0)
+            .max_by_key(|(_, count)| *count)
+            .map(|(ending, _)| ending)
+    }
+}
+
+/// The tree-sitter point of a byte offset, for editing syntax trees. Unlike
+/// LSP positions, points count rows by LF only and columns in bytes.
+pub fn offset_to_point(text: &str, offset: usize) -> Point {
+    let before = &text.as_bytes()[..offset];
+    let row = before.iter().filter(|byte| **byte == b'\n').count();
+    let column = before
+        .iter()
+        .rposition(|byte| *byte == b'\n')
+        .map_or(offset, |newline| offset - newline - 1);
+    Point::new(row, column)
+}
+
+/// Whether `position` lies within `range`, both ends included, so that a
+/// cursor right after a name still counts as on it.
+pub fn range_contains(range: Range, position: Position) -> bool {
+    range.start <= position && position <= range.end
+}
+
+/// Whether two ranges share at least one line.
+pub fn lines_overlap(a: Range, b: Range) -> bool {
+    a.start.line <= b.end.line && b.start.line <= a.end.line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(line: u32, character: u32) -> Position {
+        Position { line, character }
+    }
+
+    fn range(start: (u32, u32), end: (u32, u32)) -> Range {
+        Range {
+            start: pos(start.0, start.1),
+            end: pos(end.0, end.1),
+        }
+    }
+
+    /// Every line ending, one of each: `ab` LF, `cd` CRLF, `ef` CR, `gh`.
+    const MIXED: &str = "ab\ncd\r\nef\rgh";
+
+    #[test]
+    fn counts_lf_crlf_and_cr_lines() {
+        let index = LineIndex::new(MIXED, PositionEncoding::Utf16);
+        assert_eq!(index.line_count(), 4);
+        assert_eq!(index.line_span(0), Some(0..2));
+        assert_eq!(index.line_span(1), Some(3..5));
+        assert_eq!(index.line_span(2), Some(7..9));
+        assert_eq!(index.line_span(3), Some(10..12));
+        assert_eq!(index.line_span(4), None);
+        assert_eq!(index.line_ending(0), Some(LineEnding::Lf));
+        assert_eq!(index.line_ending(1), Some(LineEnding::CrLf));
+        assert_eq!(index.line_ending(2), Some(LineEnding::Cr));
+        assert_eq!(index.line_ending(3), None);
+    }
+
+    #[test]
+    fn converts_offsets_after_each_line_ending() {
+        let index = LineIndex::new(MIXED, PositionEncoding::Utf16);
+        assert_eq!(index.offset_to_position(0), pos(0, 0));
+        assert_eq!(index.offset_to_position(3), pos(1, 0));
+        assert_eq!(index.offset_to_position(7), pos(2, 0));
+        assert_eq!(index.offset_to_position(10), pos(3, 0));
+        assert_eq!(index.position_to_offset(pos(1, 1)), 4);
+        assert_eq!(index.position_to_offset(pos(2, 1)), 8);
+        assert_eq!(index.position_to_offset(pos(3, 2)), 12);
+    }
+
+    #[test]
+    fn clamps_offsets_on_line_terminators() {
+        let index = LineIndex::new(MIXED, PositionEncoding::Utf16);
+        // On the LF, between the CR and LF of a CRLF, and on a lone CR
+        assert_eq!(index.offset_to_position(2), pos(0, 2));
+        assert_eq!(index.offset_to_position(5), pos(1, 2));
+        assert_eq!(index.offset_to_position(6), pos(1, 2));
+        assert_eq!(index.offset_to_position(9), pos(2, 2));
+    }
+
+    #[test]
+    fn clamps_positions_past_the_end_of_a_line() {
+        let index = LineIndex::new(MIXED, PositionEncoding::Utf16);
+        assert_eq!(index.position_to_offset(pos(0, 2)), 2);
+        assert_eq!(index.position_to_offset(pos(0, 40)), 2);
+        assert_eq!(index.position_to_offset(pos(1, 40)), 5);
+        assert_eq!(index.position_to_offset(pos(2, 40)), 9);
+    }
+
+    #[test]
+    fn clamps_past_the_end_of_the_text() {
+        let index = LineIndex::new(MIXED, PositionEncoding::Utf16);
+        assert_eq!(index.offset_to_position(MIXED.len()), pos(3, 2));
+        assert_eq!(index.offset_to_position(MIXED.len() + 10), pos(3, 2));
+        assert_eq!(index.position_to_offset(pos(3, 40)), MIXED.len());
+        assert_eq!(index.position_to_offset(pos(4, 0)), MIXED.len());
+        assert_eq!(index.position_to_offset(pos(40, 3)), MIXED.len());
+    }
+
+    #[test]
+    fn ends_with_an_empty_line_after_a_final_terminator() {
+        for text in ["ab\n", "ab\r\n", "ab\r"] {
+            let index = LineIndex::new(text, PositionEncoding::Utf16);
+            assert_eq!(index.line_count(), 2, "{:?}", text);
+            assert_eq!(
+                index.offset_to_position(text.len()),
+                pos(1, 0),
+                "{:?}",
+                text
+            );
+            assert_eq!(
+                index.position_to_offset(pos(1, 5)),
+                text.len(),
+                "{:?}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn handles_the_empty_text() {
+        let index = LineIndex::new("", PositionEncoding::Utf16);
+        assert_eq!(index.line_count(), 1);
+        assert_eq!(index.offset_to_position(0), pos(0, 0));
+        assert_eq!(index.offset_to_position(3), pos(0, 0));
+        assert_eq!(index.position_to_offset(pos(0, 3)), 0);
+        assert_eq!(index.position_to_offset(pos(2, 0)), 0);
+        assert_eq!(index.line_span(0), Some(0..0));
+        assert_eq!(index.dominant_line_ending(), None);
+    }
+
+    #[test]
+    fn counts_multibyte_characters_per_encoding() {
+        // `é` takes two bytes and one UTF-16 unit
+        let text = "x\u{e9}y";
+        let utf16 = LineIndex::new(text, PositionEncoding::Utf16);
+        let utf8 = LineIndex::new(text, PositionEncoding::Utf8);
+        assert_eq!(utf16.offset_to_position(3), pos(0, 2));
+        assert_eq!(utf8.offset_to_position(3), pos(0, 3));
+        assert_eq!(utf16.position_to_offset(pos(0, 2)), 3);
+        assert_eq!(utf8.position_to_offset(pos(0, 3)), 3);
+        // Inside the character
+        assert_eq!(utf16.offset_to_position(2), pos(0, 1));
+        assert_eq!(utf8.position_to_offset(pos(0, 2)), 1);
+    }
+
+    #[test]
+    fn counts_surrogate_pairs_as_two_utf16_units() {
+        // The emoji takes four bytes and two UTF-16 units
+        let text = "a\u{1f600}b\nc";
+        let utf16 = LineIndex::new(text, PositionEncoding::Utf16);
+        let utf8 = LineIndex::new(text, PositionEncoding::Utf8);
+        assert_eq!(utf16.offset_to_position(5), pos(0, 3));
+        assert_eq!(utf8.offset_to_position(5), pos(0, 5));
+        assert_eq!(utf16.position_to_offset(pos(0, 3)), 5);
+        assert_eq!(utf16.position_to_offset(pos(0, 4)), 6);
+        assert_eq!(utf16.position_to_offset(pos(1, 1)), 8);
+        // Between the surrogates, or inside the UTF-8 sequence
+        assert_eq!(utf16.position_to_offset(pos(0, 2)), 1);
+        assert_eq!(utf8.position_to_offset(pos(0, 3)), 1);
+        assert_eq!(utf16.offset_to_position(3), pos(0, 1));
+    }
+
+    #[test]
+    fn round_trips_every_character_boundary() {
+        let text = "\u{e9}t\u{e9}\r\n\u{1f600}x\ry\u{1f600}\n\u{4e2d}\u{6587}";
+        for encoding in [PositionEncoding::Utf8, PositionEncoding::Utf16] {
+            let index = LineIndex::new(text, encoding);
+            for (offset, c) in text.char_indices() {
+                // The LF of a CRLF has no position of its own
+                if c == '\n' && text[..offset].ends_with('\r') {
+                    continue;
+                }
+                let position = index.offset_to_position(offset);
+                assert_eq!(
+                    index.position_to_offset(position),
+                    offset,
+                    "{:?} at {} in {:?}",
+                    c,
+                    offset,
+                    encoding
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn converts_ranges_without_inverting_them() {
+        let index = LineIndex::new("abc\r\ndef", PositionEncoding::Utf16);
+        assert_eq!(index.byte_range_to_range(1..6), range((0, 1), (1, 1)));
+        assert_eq!(index.range_to_byte_range(range((0, 1), (1, 1))), 1..6);
+        assert_eq!(index.range_to_byte_range(range((1, 2), (0, 1))), 7..7);
+        assert_eq!(index.range_to_byte_range(range((0, 9), (9, 9))), 3..8);
+    }
+
+    #[test]
+    fn finds_the_dominant_line_ending() {
+        let dominant = |text| LineIndex::new(text, PositionEncoding::Utf16).dominant_line_ending();
+        assert_eq!(dominant("a\r\nb\r\nc\nd"), Some(LineEnding::CrLf));
+        assert_eq!(dominant("a\nb\rc\nd"), Some(LineEnding::Lf));
+        assert_eq!(dominant("a\rb\rc"), Some(LineEnding::Cr));
+        assert_eq!(dominant("abc"), None);
+    }
+
+    #[test]
+    fn negotiates_utf8_only_when_offered() {
+        let mut capabilities = ClientCapabilities::default();
+        assert_eq!(
+            PositionEncoding::negotiate(&capabilities),
+            PositionEncoding::Utf16
+        );
+        capabilities.general = Some(GeneralClientCapabilities {
+            position_encodings: Some(vec![
+                PositionEncodingKind::UTF16,
+                PositionEncodingKind::UTF8,
+            ]),
+            ..Default::default()
+        });
+        assert_eq!(
+            PositionEncoding::negotiate(&capabilities),
+            PositionEncoding::Utf8
+        );
+    }
+
+    #[test]
+    fn counts_points_by_lf_only() {
+        let text = "a\r\nbc\rd";
+        assert_eq!(offset_to_point(text, 0), Point::new(0, 0));
+        assert_eq!(offset_to_point(text, 3), Point::new(1, 0));
+        // A lone CR starts no row
+        assert_eq!(offset_to_point(text, 6), Point::new(1, 3));
+        assert_eq!(offset_to_point(text, text.len()), Point::new(1, 4));
+        assert_eq!(offset_to_point("", 0), Point::new(0, 0));
+    }
+
+    #[test]
+    fn contains_both_range_ends() {
+        let name = range((2, 4), (2, 8));
+        assert!(range_contains(name, pos(2, 4)));
+        assert!(range_contains(name, pos(2, 8)));
+        assert!(!range_contains(name, pos(2, 3)));
+        assert!(!range_contains(name, pos(2, 9)));
+        assert!(!range_contains(name, pos(1, 6)));
+        assert!(range_contains(range((1, 9), (3, 0)), pos(2, 100)));
+    }
+
+    #[test]
+    fn overlaps_ranges_sharing_a_line() {
+        assert!(lines_overlap(range((1, 0), (3, 0)), range((3, 5), (4, 0))));
+        assert!(lines_overlap(range((1, 0), (5, 0)), range((2, 0), (3, 0))));
+        assert!(!lines_overlap(range((1, 0), (2, 9)), range((3, 0), (4, 0))));
+        assert!(!lines_overlap(range((5, 0), (6, 0)), range((3, 0), (4, 0))));
+    }
+}
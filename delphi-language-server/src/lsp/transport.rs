@@ -0,0 +1,234 @@
+use serde_json::Value;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader, ReadBuf};
+use tokio::sync::mpsc;
+
+/// The `exit` notification given to a session the client leaves without
+/// one.
+const EXIT: &[u8] = br#"{"jsonrpc":"2.0","method":"exit"}"#;
+
+/// The messages of one session of the client, read by the LSP service
+/// serving it. The input ends with the session.
+pub struct SessionInput {
+    messages: mpsc::UnboundedReceiver<Vec<u8>>,
+    message: Vec<u8>,
+    read: usize,
+}
+
+impl AsyncRead for SessionInput {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        while this.read == this.message.len() {
+            match this.messages.poll_recv(cx) {
+                Poll::Ready(Some(message)) => {
+                    this.message = message;
+                    this.read = 0;
+                }
+                // Reading nothing signals the end of the input
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        let length = buf.remaining().min(this.message.len() - this.read);
+        buf.put_slice(&this.message[this.read..this.read + length]);
+        this.read += length;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// What the client asks of the process next.
+pub enum ClientEvent {
+    /// A session to serve by a new server.
+    Session(SessionInput),
+    /// The client sent `exit`: the process ends with this code, 0 when
+    /// `shutdown` came first and 1 otherwise, once the session is served.
+    Exit(i32),
+}
+
+/// Splits the messages of `input` into the sessions of the client, so that
+/// one process can serve several in a row, each by a new server. Clients
+/// reusing the process send `initialize` after `shutdown` without `exit`;
+/// the previous session then gets an `exit` of its own, and a new one
+/// starts. The `exit` notification ends the last session and the input is
+/// not read any further.
+pub fn split_sessions<R>(input: R) -> mpsc::UnboundedReceiver<ClientEvent>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    let (sessions, received) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let mut input = BufReader::new(input);
+        let mut session: Option<mpsc::UnboundedSender<Vec<u8>>> = None;
+        let mut shut_down = false;
+        loop {
+            let body = match read_message(&mut input).await {
+                Ok(Some(body)) => body,
+                Ok(None) => break,
+                Err(e) => {
+                    log::error!("Cannot read the client's messages: {}", e);
+                    break;
+                }
+            };
+            let method = serde_json::from_slice::<Value>(&body)
+                .ok()
+                .and_then(|message| Some(message.get("method")?.as_str()?.to_string()));
+            if method.as_deref() == Some("initialize") && shut_down {
+                log::info!("initialize after shutdown, starting a new session");
+                if let Some(previous) = session.take() {
+                    let _ = previous.send(frame(EXIT));
+                }
+            }
+            let current = match session.take() {
+                Some(current) => current,
+                None => {
+                    let (sender, messages) = mpsc::unbounded_channel();
+                    let input = SessionInput {
+                        messages,
+                        message: Vec::new(),
+                        read: 0,
+                    };
+                    if sessions.send(ClientEvent::Session(input)).is_err() {
+                        break;
+                    }
+                    shut_down = false;
+                    sender
+                }
+            };
+            let _ = current.send(frame(&body));
+            match method.as_deref() {
+                Some("shutdown") => shut_down = true,
+                Some("exit") => {
+                    // Dropping the sender ends the session
+                    drop(current);
+                    let _ = sessions.send(ClientEvent::Exit(if shut_down { 0 } else { 1 }));
+                    break;
+                }
+                _ => {}
+            }
+            session = Some(current);
+        }
+    });
+    received
+}
+
+/// Reads the body of the next message, `None` at the end of the input.
+pub async fn read_message<R: AsyncRead + Unpin>(
+    input: &mut BufReader<R>,
+) -> io::Result<Option<Vec<u8>>> {
+    let mut length = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if input.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let length = length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "message without Content-Length")
+    })?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body).await?;
+    Ok(Some(body))
+}
+
+/// `body` with the header of a message.
+pub fn frame(body: &[u8]) -> Vec<u8> {
+    let mut message = format!("Content-Length: {}\r\n\r\n", body.len()).into_bytes();
+    message.extend_from_slice(body);
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    /// The events of a client sending messages of `methods`, and the
+    /// methods each session received.
+    async fn events(methods: &[&str]) -> Vec<Result<Vec<String>, i32>> {
+        let (mut client, server) = tokio::io::duplex(1 << 16);
+        let mut received = split_sessions(server);
+        for (id, method) in methods.iter().enumerate() {
+            let message = match *method {
+                "exit" | "initialized" => serde_json::json!({"jsonrpc": "2.0", "method": method}),
+                _ => serde_json::json!({"jsonrpc": "2.0", "id": id, "method": method}),
+            };
+            client
+                .write_all(&frame(message.to_string().as_bytes()))
+                .await
+                .unwrap();
+        }
+        drop(client);
+
+        let mut events = Vec::new();
+        while let Some(event) = received.recv().await {
+            events.push(match event {
+                ClientEvent::Session(input) => {
+                    let mut input = BufReader::new(input);
+                    let mut methods = Vec::new();
+                    while let Some(body) = read_message(&mut input).await.unwrap() {
+                        let message: Value = serde_json::from_slice(&body).unwrap();
+                        methods.push(message["method"].as_str().unwrap().to_string());
+                    }
+                    Ok(methods)
+                }
+                ClientEvent::Exit(code) => Err(code),
+            });
+        }
+        events
+    }
+
+    #[tokio::test]
+    async fn exits_on_the_exit_notification() {
+        assert_eq!(
+            events(&["initialize", "shutdown", "exit", "initialize"]).await,
+            [
+                Ok(vec!["initialize".into(), "shutdown".into(), "exit".into()]),
+                Err(0)
+            ]
+        );
+        assert_eq!(
+            events(&["initialize", "initialized", "exit"]).await,
+            [
+                Ok(vec![
+                    "initialize".into(),
+                    "initialized".into(),
+                    "exit".into()
+                ]),
+                Err(1)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn starts_a_session_on_initialize_after_shutdown() {
+        assert_eq!(
+            events(&["initialize", "shutdown", "initialize", "shutdown", "exit"]).await,
+            [
+                Ok(vec!["initialize".into(), "shutdown".into(), "exit".into()]),
+                Ok(vec!["initialize".into(), "shutdown".into(), "exit".into()]),
+                Err(0)
+            ]
+        );
+        // Without `shutdown`, `initialize` goes to the running session
+        assert_eq!(
+            events(&["initialize", "initialize"]).await,
+            [Ok(vec!["initialize".into(), "initialize".into()])]
+        );
+    }
+}
@@ -0,0 +1,455 @@
+use crate::lsp::conditionals::{ConditionalSite, ConditionalUse};
+use crate::lsp::directives;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tower_lsp::lsp_types::{Location, Range, Url};
+
+/// The Pascal sources, form files and project files of the workspace
+/// folders by lowercase file name, the declarations the interface section
+/// of each unit exports, and the conditional symbols each source and
+/// project file names. Built once when the server starts and kept current from
+/// watched-file events and edits of open documents, so lookups never touch
+/// the filesystem.
+#[derive(Debug, Default)]
+pub struct WorkspaceIndex {
+    files: BTreeMap<String, Vec<PathBuf>>,
+    /// The ranges of the exported declarations of each parsed source, by
+    /// lowercase name.
+    declarations: HashMap<PathBuf, HashMap<String, Vec<Range>>>,
+    /// The conditional symbols the directives of each parsed source define,
+    /// undefine and test, and the defines of each project file.
+    conditionals: HashMap<PathBuf, Vec<ConditionalSite>>,
+}
+
+impl WorkspaceIndex {
+    pub fn scan(roots: &[PathBuf]) -> Self {
+        let mut index = WorkspaceIndex::default();
+        for root in roots {
+            index.scan_dir(root);
+        }
+        index
+    }
+
+    /// Adds the files under `dir`, such as a search path outside the
+    /// workspace folders.
+    pub fn scan_dir(&mut self, dir: &Path) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            log::warn!("Cannot index {}", dir.display());
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                self.scan_dir(&path);
+            } else {
+                self.insert(path);
+            }
+        }
+    }
+
+    /// Adds a file, unless it is neither a Pascal source, a form file nor
+    /// a project file.
+    pub fn insert(&mut self, path: PathBuf) {
+        let Some(extension) = path.extension().and_then(|extension| extension.to_str()) else {
+            return;
+        };
+        let extension = extension.to_lowercase();
+        if !directives::SOURCE_EXTENSIONS.contains(&extension.as_str())
+            && !directives::FORM_EXTENSIONS.contains(&extension.as_str())
+            && !directives::PROJECT_EXTENSIONS.contains(&extension.as_str())
+        {
+            return;
+        }
+        let Some(key) = file_key(&path) else {
+            return;
+        };
+        let paths = self.files.entry(key).or_default();
+        if !paths.contains(&path) {
+            paths.push(path);
+            paths.sort();
+        }
+    }
+
+    pub fn remove(&mut self, path: &Path) {
+        self.declarations.remove(path);
+        self.conditionals.remove(path);
+        let Some(key) = file_key(path) else {
+            return;
+        };
+        if let Some(paths) = self.files.get_mut(&key) {
+            paths.retain(|indexed| indexed != path);
+            if paths.is_empty() {
+                self.files.remove(&key);
+            }
+        }
+    }
+
+    /// The indexed files named `stem` with one of `extensions`, ignoring case.
+    fn find(&self, stem: &str, extensions: &[&str]) -> Vec<&PathBuf> {
+        extensions
+            .iter()
+            .filter_map(|extension| {
+                self.files
+                    .get(&format!("{}.{}", stem, extension).to_lowercase())
+            })
+            .flatten()
+            .collect()
+    }
+
+    /// The file of the unit named `name`, ignoring case.
+    pub fn find_unit(&self, name: &str) -> Option<PathBuf> {
+        self.find(name, directives::UNIT_EXTENSIONS)
+            .first()
+            .map(|path| path.to_path_buf())
+    }
+
+    /// All the files of units named `name`, ignoring case.
+    pub fn find_unit_files(&self, name: &str) -> Vec<PathBuf> {
+        self.find(name, directives::UNIT_EXTENSIONS)
+            .into_iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Replaces the exported declarations of a source, adding the file to
+    /// the index if it lies outside the workspace folders.
+    pub fn set_declarations(&mut self, path: PathBuf, declarations: HashMap<String, Vec<Range>>) {
+        self.declarations.insert(path.clone(), declarations);
+        self.insert(path);
+    }
+
+    /// Replaces the conditional symbols named in a source or project file.
+    pub fn set_conditionals(&mut self, path: PathBuf, conditionals: Vec<ConditionalSite>) {
+        self.conditionals.insert(path, conditionals);
+    }
+
+    /// Every place the indexed files name the conditional symbol `name`,
+    /// ignoring case, that `usage` accepts.
+    pub fn find_conditionals(
+        &self,
+        name: &str,
+        usage: impl Fn(ConditionalUse) -> bool,
+    ) -> Vec<ConditionalSite> {
+        let mut sites: Vec<ConditionalSite> = self
+            .conditionals
+            .values()
+            .flatten()
+            .filter(|site| site.name.eq_ignore_ascii_case(name) && usage(site.usage))
+            .cloned()
+            .collect();
+        sites.sort_by(|a, b| {
+            (a.location.uri.as_str(), a.location.range.start)
+                .cmp(&(b.location.uri.as_str(), b.location.range.start))
+        });
+        sites
+    }
+
+    /// The names of every conditional symbol the indexed files define or
+    /// undefine.
+    pub fn defined_conditionals(&self) -> Vec<String> {
+        self.conditionals
+            .values()
+            .flatten()
+            .filter(|site| site.usage != ConditionalUse::Test)
+            .map(|site| site.name.clone())
+            .collect()
+    }
+
+    /// The indexed Delphi project files.
+    pub fn projects(&self) -> Vec<PathBuf> {
+        self.files
+            .iter()
+            .filter(|(name, _)| {
+                Path::new(name)
+                    .extension()
+                    .and_then(|extension| extension.to_str())
+                    .is_some_and(|extension| directives::PROJECT_EXTENSIONS.contains(&extension))
+            })
+            .flat_map(|(_, paths)| paths.iter().cloned())
+            .collect()
+    }
+
+    /// Finds the declaration of `name` in the units named `units`, ignoring
+    /// case. Like the compiler, the units are searched from the last one,
+    /// so a later unit of a uses clause hides the earlier ones. Returns the
+    /// unit name as given and the declaration.
+    pub fn find_declaration(&self, units: &[String], name: &str) -> Option<(String, Location)> {
+        let name = name.to_lowercase();
+        units.iter().rev().find_map(|unit| {
+            let path = self.find_unit(unit)?;
+            let range = *self.declarations.get(&path)?.get(&name)?.first()?;
+            let uri = Url::from_file_path(&path).ok()?;
+            Some((unit.clone(), Location { uri, range }))
+        })
+    }
+
+    /// The indexed Pascal sources whose name matches `query` after
+    /// [`unit_match_rank`], best matches first.
+    pub fn find_units(&self, query: &str) -> Vec<PathBuf> {
+        let mut units: Vec<(u8, String, PathBuf)> = self
+            .sources()
+            .into_iter()
+            .filter_map(|path| {
+                let name = path.file_stem()?.to_str()?.to_lowercase();
+                Some((unit_match_rank(&name, query)?, name, path))
+            })
+            .collect();
+        units.sort();
+        units.into_iter().map(|(_, _, path)| path).collect()
+    }
+
+    /// All indexed Pascal sources, ordered by file name ignoring case.
+    pub fn sources(&self) -> Vec<PathBuf> {
+        self.files
+            .iter()
+            .filter(|(name, _)| {
+                Path::new(name)
+                    .extension()
+                    .and_then(|extension| extension.to_str())
+                    .is_some_and(|extension| directives::SOURCE_EXTENSIONS.contains(&extension))
+            })
+            .flat_map(|(_, paths)| paths.iter().cloned())
+            .collect()
+    }
+
+    /// The files to switch to from `path`: the form file of a unit and its
+    /// test units, the unit of a form file, or the unit a test unit tests.
+    /// Test units are named after `test_patterns`, in which `{name}` stands
+    /// for the name of the tested unit, e.g. `{name}.Tests` or `Test{name}`.
+    /// Forms pair with the unit in the same directory; test units are found
+    /// anywhere in the workspace.
+    pub fn companions(&self, path: &Path, test_patterns: &[String]) -> Vec<PathBuf> {
+        let (Some(stem), Some(extension)) = (
+            path.file_stem().and_then(|stem| stem.to_str()),
+            path.extension().and_then(|extension| extension.to_str()),
+        ) else {
+            return Vec::new();
+        };
+        let same_directory = |candidate: &&PathBuf| candidate.parent() == path.parent();
+
+        let mut companions: Vec<PathBuf> = Vec::new();
+        if directives::FORM_EXTENSIONS.contains(&extension.to_lowercase().as_str()) {
+            companions.extend(
+                self.find(stem, directives::UNIT_EXTENSIONS)
+                    .into_iter()
+                    .filter(same_directory)
+                    .cloned(),
+            );
+            return companions;
+        }
+
+        companions.extend(
+            self.find(stem, directives::FORM_EXTENSIONS)
+                .into_iter()
+                .filter(same_directory)
+                .cloned(),
+        );
+        for pattern in test_patterns {
+            let test_unit = pattern.replace("{name}", stem);
+            companions.extend(
+                self.find(&test_unit, directives::UNIT_EXTENSIONS)
+                    .into_iter()
+                    .cloned(),
+            );
+            if let Some(tested) = tested_unit(stem, pattern) {
+                companions.extend(
+                    self.find(tested, directives::UNIT_EXTENSIONS)
+                        .into_iter()
+                        .cloned(),
+                );
+            }
+        }
+        let mut unique = Vec::new();
+        for companion in companions {
+            if companion != path && !unique.contains(&companion) {
+                unique.push(companion);
+            }
+        }
+        unique
+    }
+}
+
+/// The workspace units left to index. Lookups are served from the units
+/// indexed so far, so the units the open documents use go first, up to
+/// `warm_up_depth` levels of uses clauses, then the rest by file name.
+#[derive(Debug, Default)]
+pub struct IndexQueue {
+    /// Units to index first, with the level of uses clauses they were
+    /// reached through.
+    prioritized: VecDeque<(PathBuf, usize)>,
+    rest: VecDeque<PathBuf>,
+    /// The units not taken from the queue yet. Units prioritized stay in
+    /// `rest` and are skipped there.
+    pending: HashSet<PathBuf>,
+    total: usize,
+    /// The pending units each open document uses, until all are indexed.
+    waiting: HashMap<Url, HashSet<PathBuf>>,
+}
+
+impl IndexQueue {
+    pub fn new(sources: Vec<PathBuf>) -> Self {
+        Self {
+            pending: sources.iter().cloned().collect(),
+            total: sources.len(),
+            rest: sources.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Moves the pending `units` to the end of the prioritized ones,
+    /// reached through `level` levels of uses clauses. With `document`, it
+    /// waits for them, for [`Self::take`] to say when they are all indexed.
+    pub fn prioritize(&mut self, units: Vec<PathBuf>, level: usize, document: Option<&Url>) {
+        let units: Vec<PathBuf> = units
+            .into_iter()
+            .filter(|unit| self.pending.contains(unit))
+            .collect();
+        if let Some(document) = document.filter(|_| !units.is_empty()) {
+            self.waiting
+                .entry(document.clone())
+                .or_default()
+                .extend(units.iter().cloned());
+        }
+        for unit in units {
+            if !self.prioritized.iter().any(|(queued, _)| *queued == unit) {
+                self.prioritized.push_back((unit, level));
+            }
+        }
+    }
+
+    /// The next unit to index, with its level when it was prioritized.
+    pub fn next(&mut self) -> Option<(PathBuf, Option<usize>)> {
+        while let Some((unit, level)) = self.prioritized.pop_front() {
+            if self.pending.remove(&unit) {
+                return Some((unit, Some(level)));
+            }
+        }
+        while let Some(unit) = self.rest.pop_front() {
+            if self.pending.remove(&unit) {
+                return Some((unit, None));
+            }
+        }
+        None
+    }
+
+    /// Records that `unit` is indexed, returning the documents waiting for
+    /// it as the last of their units.
+    pub fn take(&mut self, unit: &Path) -> Vec<Url> {
+        let mut ready = Vec::new();
+        self.waiting.retain(|document, units| {
+            units.remove(unit);
+            if units.is_empty() {
+                ready.push(document.clone());
+            }
+            !units.is_empty()
+        });
+        ready
+    }
+
+    /// Stops waiting for the units of a closed document.
+    pub fn forget(&mut self, document: &Url) {
+        self.waiting.remove(document);
+    }
+
+    pub fn is_pending(&self, unit: &Path) -> bool {
+        self.pending.contains(unit)
+    }
+
+    /// How many units were taken from the queue, of how many.
+    pub fn progress(&self) -> (usize, usize) {
+        (self.total - self.pending.len(), self.total)
+    }
+
+    /// How many units the open documents use are still pending.
+    pub fn prioritized_len(&self) -> usize {
+        self.prioritized
+            .iter()
+            .filter(|(unit, _)| self.pending.contains(unit))
+            .count()
+    }
+}
+
+/// A `workspace/symbol` query. A `unit:NAME` word anywhere in the query
+/// restricts the search to the units matching `NAME`, so that
+/// `unit:Customer TSave` looks for `TSave` in units like `App.Customer`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolQuery {
+    pub unit: Option<String>,
+    /// The rest of the query, matched against symbol names.
+    pub name: String,
+}
+
+impl SymbolQuery {
+    pub fn parse(query: &str) -> Self {
+        let mut unit = None;
+        let mut words = Vec::new();
+        for word in query.split_whitespace() {
+            match word.get(..5) {
+                Some(prefix) if prefix.eq_ignore_ascii_case("unit:") => {
+                    unit = Some(word[5..].to_string())
+                }
+                _ => words.push(word),
+            }
+        }
+        Self {
+            unit,
+            name: words.join(" "),
+        }
+    }
+
+    /// Whether the unit named `name` is to be searched.
+    pub fn includes_unit(&self, name: &str) -> bool {
+        self.unit
+            .as_ref()
+            .is_none_or(|unit| unit_match_rank(name, unit).is_some())
+    }
+}
+
+/// How well the unit name `name` matches `query`, ignoring case: `None` for
+/// no match, otherwise lower is better. The query is matched against the
+/// full name and against the name without its unit scopes, so `SysUtils`
+/// matches `System.SysUtils` as well as `sysutils`. An exact match ranks
+/// first, then a prefix, a substring, and letters in order (`sysut` or
+/// `sutl`).
+pub fn unit_match_rank(name: &str, query: &str) -> Option<u8> {
+    let name = name.to_lowercase();
+    let query = query.to_lowercase();
+    let suffixes = std::iter::once(name.as_str())
+        .chain(name.match_indices('.').map(|(dot, _)| &name[dot + 1..]));
+    suffixes
+        .filter_map(|candidate| {
+            if candidate == query {
+                Some(0)
+            } else if candidate.starts_with(&query) {
+                Some(1)
+            } else if candidate.contains(&query) {
+                Some(2)
+            } else {
+                let mut letters = candidate.chars();
+                query
+                    .chars()
+                    .all(|c| letters.any(|letter| letter == c))
+                    .then_some(3)
+            }
+        })
+        .min()
+}
+
+fn file_key(path: &Path) -> Option<String> {
+    Some(path.file_name()?.to_str()?.to_lowercase())
+}
+
+/// The name of the unit tested by a unit named `stem` after `pattern`, such
+/// as `Foo` for `Foo.Tests` and `{name}.Tests`.
+fn tested_unit<'a>(stem: &'a str, pattern: &str) -> Option<&'a str> {
+    let (prefix, suffix) = pattern.split_once("{name}")?;
+    let lower = stem.to_lowercase();
+    let is_match = lower.len() > prefix.len() + suffix.len()
+        && lower.starts_with(&prefix.to_lowercase())
+        && lower.ends_with(&suffix.to_lowercase());
+    if !is_match {
+        return None;
+    }
+    stem.get(prefix.len()..stem.len() - suffix.len())
+}
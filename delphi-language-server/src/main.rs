@@ -1,14 +1,30 @@
-use clap::Parser as ClapParser;
+use clap::{Parser as ClapParser, ValueEnum};
+use lsp::analyzer::{AnalysisSnapshot, ChainResolver, SymbolAnalyzer};
+use lsp::characters::{self, CharacterScan};
+use lsp::config::{ProjectConfig, Settings};
+use lsp::document::{read_source, read_source_for_rewrite, write_source, Document};
+use lsp::parser::DelphiParser;
+use lsp::protocol_ext::TreeNode;
+use lsp::rtl::RtlQuery;
+use lsp::stats::{KindCount, ParseStats};
+use lsp::text_position::{LineEnding, LineIndex, PositionEncoding};
+use lsp::transport::ClientEvent;
+use lsp::workspace::WorkspaceIndex;
+use lsp::{directives, docs, fixes};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::path::PathBuf;
-use tree_sitter::Parser;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use tower_lsp::lsp_types::{
+    Diagnostic, DiagnosticSeverity, DocumentSymbol, Location, NumberOrString, Position, SymbolKind,
+    Url,
+};
 
 mod lsp;
 
-extern "C" {
-    fn tree_sitter_pascal() -> tree_sitter::Language;
-}
-
 #[derive(ClapParser)]
 #[command(name = "delphi-parser")]
 #[command(about = "Parse and analyze Pascal/Delphi files")]
@@ -17,57 +33,978 @@ struct Cli {
     #[arg(long, short)]
     lsp: bool,
 
-    /// The path to the Pascal file to parse (only in CLI mode)
-    #[arg(value_name = "FILE")]
-    file: Option<PathBuf>,
+    /// The Pascal files to parse or check, or directories whose sources to
+    /// parse recursively (only in CLI mode)
+    #[arg(value_name = "PATH")]
+    paths: Vec<PathBuf>,
+
+    /// What to print for each parsed file
+    #[arg(long, value_enum, default_value = "sexp")]
+    emit: Emit,
+
+    /// Run an analyzer query at FILE:LINE:COLUMN (1-based, byte column) and print JSON.
+    /// Used units are looked up next to FILE and in the search_paths of its dls.toml
+    #[arg(long, value_name = "FILE:LINE:COL", requires = "what")]
+    at: Option<String>,
+
+    /// The query to run with --at
+    #[arg(long, value_enum, requires = "at")]
+    what: Option<Query>,
+
+    /// Generate a Markdown API page from the interface section of FILE
+    #[arg(long, value_name = "FILE")]
+    doc: Option<PathBuf>,
+
+    /// Generate a Markdown API page for every unit under DIR, plus an index
+    #[arg(long, value_name = "DIR", conflicts_with = "doc", requires = "output")]
+    doc_dir: Option<PathBuf>,
+
+    /// Output file for --doc (stdout if omitted), or output directory for --doc-dir
+    #[arg(long, short, value_name = "PATH")]
+    output: Option<PathBuf>,
+
+    /// Parse every Pascal source under PATH and report error-recovery metrics
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["doc", "doc_dir"])]
+    stats: Option<PathBuf>,
+
+    /// Output format of --stats
+    #[arg(long, value_enum, default_value = "table", requires = "stats")]
+    format: OutputFormat,
+
+    /// Report the diagnostics of every PATH as `FILE:LINE:COL: severity:
+    /// message [code]`, exiting with 1 when there are any
+    #[arg(long, conflicts_with_all = ["doc", "doc_dir", "stats", "at"])]
+    check: bool,
+
+    /// Apply the fixes that are safe without review to the files checked,
+    /// reporting only the diagnostics left
+    #[arg(long, requires = "check")]
+    fix: bool,
+
+    /// Apply only the fixes of these rules: the diagnostic codes
+    /// inconsistent-line-endings and ambiguous-character, and the rules
+    /// unused-uses, sort-uses, keyword-case, trailing-whitespace and
+    /// final-newline. All but sort-uses apply when omitted, since sorting
+    /// decides which unit wins when two declare the same name
+    #[arg(long, value_name = "RULES", value_delimiter = ',', requires = "fix")]
+    fix_only: Vec<String>,
+
+    /// Print the fixes as diffs instead of writing the files
+    #[arg(long, requires = "fix")]
+    dry_run: bool,
 }
 
-#[tokio::main]
-async fn main() {
-    env_logger::init();
-    let args = Cli::parse();
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Table,
+    Json,
+}
 
-    if args.lsp {
-        // LSP server mode
-        let stdin = tokio::io::stdin();
-        let stdout = tokio::io::stdout();
-
-        let (service, socket) =
-            tower_lsp::LspService::new(|client| lsp::server::DelphiLanguageServer::new(client));
-        tower_lsp::Server::new(stdin, stdout, socket)
-            .serve(service)
-            .await;
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Emit {
+    /// The syntax tree as an s-expression
+    Sexp,
+    /// The syntax tree as JSON
+    Json,
+    /// The document symbol outline, one `FILE:LINE: kind name` line each
+    Symbols,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Query {
+    Hover,
+    Definition,
+    References,
+    Completion,
+}
+
+/// Splits `File.pas:120:15` into the path and a zero-based LSP position.
+/// The path itself may contain colons (Windows drive letters).
+fn parse_location(location: &str) -> Option<(PathBuf, Position)> {
+    let mut parts = location.rsplitn(3, ':');
+    let column: u32 = parts.next()?.parse().ok()?;
+    let line: u32 = parts.next()?.parse().ok()?;
+    let file = parts.next().filter(|f| !f.is_empty())?;
+    if line == 0 || column == 0 {
+        return None;
+    }
+    Some((
+        PathBuf::from(file),
+        Position {
+            line: line - 1,
+            character: column - 1,
+        },
+    ))
+}
+
+/// The units a `--at` query resolves names into: those in the directory of
+/// the queried file and in the search paths of its `dls.toml`, analyzed
+/// when first looked into.
+struct UnitSearch {
+    index: WorkspaceIndex,
+    parser: DelphiParser,
+    units: HashMap<PathBuf, AnalysisSnapshot>,
+}
+
+impl UnitSearch {
+    fn new(file: &Path) -> Result<Self, String> {
+        let config = ProjectConfig::find(file)?.unwrap_or_default();
+        let roots: Vec<PathBuf> = file
+            .parent()
+            .map(Path::to_path_buf)
+            .into_iter()
+            .chain(config.search_paths)
+            .map(|root| fs::canonicalize(&root).unwrap_or(root))
+            .collect();
+        Ok(Self {
+            index: WorkspaceIndex::scan(&roots),
+            parser: DelphiParser::new(),
+            units: HashMap::new(),
+        })
+    }
+
+    /// The analysis of the unit `name`, whose exported declarations are
+    /// indexed along the way.
+    fn unit(&mut self, name: &str) -> Option<AnalysisSnapshot> {
+        let path = self.index.find_unit(name)?;
+        if let Some(unit) = self.units.get(&path) {
+            return Some(unit.clone());
+        }
+        let source_code = read_source(&path, true).ok()?;
+        let uri = Url::from_file_path(&path).ok()?;
+        let tree = self.parser.parse(&source_code)?;
+        let mut analyzer = SymbolAnalyzer::new();
+        analyzer.set_position_encoding(PositionEncoding::Utf8);
+        analyzer.set_content(tree, source_code, uri, None);
+        self.index
+            .set_declarations(path.clone(), analyzer.get_exported_declarations());
+        let unit = Arc::new(analyzer);
+        self.units.insert(path, unit.clone());
+        Some(unit)
+    }
+
+    /// Finds the declaration of `name` in the units `analyzer` uses.
+    fn find_declaration(
+        &mut self,
+        analyzer: &SymbolAnalyzer,
+        name: &str,
+    ) -> Option<(String, Location)> {
+        let units = analyzer.get_used_units();
+        for unit in &units {
+            self.unit(unit);
+        }
+        self.index.find_declaration(&units, name)
+    }
+
+    /// Like the server's workspace lookup, the declaration the identifier
+    /// at `position` names in a used unit, for when the file declares
+    /// nothing by that name.
+    fn find_declaration_at(
+        &mut self,
+        analyzer: &SymbolAnalyzer,
+        position: Position,
+    ) -> Option<Location> {
+        match analyzer.rtl_query(position)?.0 {
+            RtlQuery::Name(name) => self.find_declaration(analyzer, &name),
+            RtlQuery::Member { type_name, member } => {
+                let unit = analyzer
+                    .get_used_units()
+                    .into_iter()
+                    .find(|unit| unit.eq_ignore_ascii_case(&type_name))?;
+                self.unit(&unit)?;
+                self.index.find_declaration(&[unit], &member)
+            }
+        }
+        .map(|(_, location)| location)
+    }
+
+    fn resolver(&mut self) -> ChainResolver<'_> {
+        ChainResolver::new(|analyzer: &SymbolAnalyzer, name: &str| {
+            let (unit, _) = self.find_declaration(analyzer, name)?;
+            let snapshot = self.unit(&unit)?;
+            Some((unit, snapshot))
+        })
+    }
+}
+
+/// Runs a single analyzer query outside the LSP runtime and prints the
+/// result using the same JSON shape as the corresponding LSP response.
+/// Hover and definition also look into the units the file uses.
+fn run_query(location: &str, query: Query) -> Result<(), String> {
+    let (file, position) = parse_location(location)
+        .ok_or_else(|| format!("Invalid location '{}', expected FILE:LINE:COL", location))?;
+    let source_code =
+        read_source(&file, false).map_err(|e| format!("Error reading file: {}", e))?;
+    let path = fs::canonicalize(&file).map_err(|e| format!("Error resolving path: {}", e))?;
+    let uri = Url::from_file_path(&path)
+        .map_err(|_| format!("Cannot build URI for {}", path.display()))?;
+
+    let mut parser = DelphiParser::new();
+    let tree = parser
+        .parse(&source_code)
+        .ok_or_else(|| "Error parsing file".to_string())?;
+    let mut analyzer = SymbolAnalyzer::new();
+    analyzer.set_position_encoding(PositionEncoding::Utf8);
+    analyzer.set_content(tree, source_code, uri, None);
+    let mut units = UnitSearch::new(&path)?;
+
+    let result = match query {
+        Query::Hover => serde_json::to_value(
+            analyzer
+                .get_member_chain_hover(position, &mut units.resolver())
+                .or_else(|| analyzer.get_hover_info(position).ok()),
+        ),
+        Query::Definition => {
+            let chained = analyzer.member_chain_at(position, &mut units.resolver());
+            let definition = match chained {
+                Some(member) => Some(member.location),
+                None => analyzer
+                    .find_definition(position)
+                    .ok()
+                    .or_else(|| units.find_declaration_at(&analyzer, position)),
+            };
+            serde_json::to_value(definition)
+        }
+        Query::References => serde_json::to_value(analyzer.find_references(position, true)),
+        Query::Completion => serde_json::to_value(analyzer.get_completion_items(position, None)),
+    }
+    .map_err(|e| format!("Error serializing result: {}", e))?;
+
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&result).expect("JSON value is always serializable")
+    );
+    Ok(())
+}
+
+/// Writes the API page of a single unit to `output`, or stdout.
+fn run_doc(file: &Path, output: Option<&Path>) -> Result<(), String> {
+    let source_code = read_source(file, false).map_err(|e| format!("Error reading file: {}", e))?;
+    let page = docs::generate_unit_page(&mut DelphiParser::new(), &source_code)
+        .ok_or_else(|| format!("No unit found in {}", file.display()))?;
+    match output {
+        Some(output) => fs::write(output, page.markdown)
+            .map_err(|e| format!("Error writing {}: {}", output.display(), e)),
+        None => {
+            print!("{}", page.markdown);
+            Ok(())
+        }
+    }
+}
+
+/// The `--stats` metrics of one file, or of the whole corpus.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StatsReport {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    files: Option<usize>,
+    parse_time_ms: f64,
+    #[serde(flatten)]
+    stats: ParseStats,
+    error_percentage: f64,
+    top_kinds: Vec<KindCount>,
+}
+
+impl StatsReport {
+    fn new(
+        path: Option<String>,
+        files: Option<usize>,
+        parse_time_ms: f64,
+        stats: ParseStats,
+    ) -> Self {
+        Self {
+            path,
+            files,
+            parse_time_ms,
+            error_percentage: stats.error_percentage(),
+            top_kinds: stats.top_kinds(5),
+            stats,
+        }
+    }
+
+    fn table_row(&self, label: &str, label_width: usize) -> String {
+        let kinds: Vec<String> = self
+            .top_kinds
+            .iter()
+            .map(|kind| format!("{}({})", kind.kind, kind.count))
+            .collect();
+        let row = format!(
+            "{:<label_width$}  {:>9.2}  {:>8}  {:>6}  {:>7}  {:>6.2}%  {}",
+            label,
+            self.parse_time_ms,
+            self.stats.node_count,
+            self.stats.error_nodes,
+            self.stats.missing_nodes,
+            self.error_percentage,
+            kinds.join(", ")
+        );
+        row.trim_end().to_string()
+    }
+}
+
+/// Parses every Pascal source under `path` (or the file itself) and prints
+/// error-recovery metrics per file and for the whole corpus.
+fn run_stats(path: &Path, format: OutputFormat) -> Result<(), String> {
+    let mut files = Vec::new();
+    if path.is_dir() {
+        collect_files(path, directives::SOURCE_EXTENSIONS, &mut files)?;
     } else {
-        // CLI parsing mode
-        let file = match args.file {
-            Some(f) => f,
-            None => {
-                eprintln!("Error: File path is required in CLI mode");
-                return;
+        files.push(path.to_path_buf());
+    }
+    files.sort();
+
+    let mut parser = DelphiParser::new();
+    let mut reports = Vec::new();
+    let mut total = ParseStats::default();
+    let mut total_time = 0.0;
+    for file in &files {
+        let source_code = read_source(file, true)
+            .map_err(|e| format!("Error reading {}: {}", file.display(), e))?;
+        let started = Instant::now();
+        let tree = parser
+            .parse(&source_code)
+            .ok_or_else(|| format!("Error parsing {}", file.display()))?;
+        let parse_time_ms = started.elapsed().as_secs_f64() * 1000.0;
+        let stats = ParseStats::collect(tree.root_node(), source_code.len());
+        total.add(&stats);
+        total_time += parse_time_ms;
+        let label = match file.strip_prefix(path) {
+            Ok(relative) if !relative.as_os_str().is_empty() => relative.display().to_string(),
+            _ => file.display().to_string(),
+        };
+        reports.push(StatsReport::new(Some(label), None, parse_time_ms, stats));
+    }
+    let total = StatsReport::new(None, Some(files.len()), total_time, total);
+
+    match format {
+        OutputFormat::Json => {
+            let output = serde_json::json!({ "files": reports, "total": total });
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&output).expect("JSON value is always serializable")
+            );
+        }
+        OutputFormat::Table => {
+            let total_label = format!("Total ({} files)", files.len());
+            let width = reports
+                .iter()
+                .filter_map(|report| report.path.as_deref())
+                .map(|path| path.chars().count())
+                .chain([total_label.len(), "File".len()])
+                .max()
+                .unwrap_or_default();
+            println!(
+                "{:<width$}  {:>9}  {:>8}  {:>6}  {:>7}  {:>7}  Top kinds",
+                "File", "Time (ms)", "Nodes", "Errors", "Missing", "Error %"
+            );
+            for report in &reports {
+                println!(
+                    "{}",
+                    report.table_row(report.path.as_deref().unwrap_or_default(), width)
+                );
+            }
+            println!("{}", total.table_row(&total_label, width));
+        }
+    }
+    Ok(())
+}
+
+/// Writes one API page per unit found under `dir` into `output`, together
+/// with an `index.md` linking them. Units are ordered by name so repeated
+/// runs produce identical output.
+fn run_doc_dir(dir: &Path, output: &Path) -> Result<(), String> {
+    let mut files = Vec::new();
+    collect_files(dir, directives::UNIT_EXTENSIONS, &mut files)?;
+    files.sort();
+
+    let mut parser = DelphiParser::new();
+    let mut pages: Vec<docs::UnitPage> = Vec::new();
+    for file in files {
+        let source_code = read_source(&file, false)
+            .map_err(|e| format!("Error reading {}: {}", file.display(), e))?;
+        let Some(page) = docs::generate_unit_page(&mut parser, &source_code) else {
+            eprintln!("Skipping {}: no unit found", file.display());
+            continue;
+        };
+        if pages
+            .iter()
+            .any(|p| p.name.eq_ignore_ascii_case(&page.name))
+        {
+            eprintln!(
+                "Skipping {}: unit {} already documented",
+                file.display(),
+                page.name
+            );
+            continue;
+        }
+        pages.push(page);
+    }
+    pages.sort_by_key(|page| page.name.to_lowercase());
+
+    fs::create_dir_all(output)
+        .map_err(|e| format!("Error creating {}: {}", output.display(), e))?;
+    let write = |name: String, content: &str| {
+        let path = output.join(name);
+        fs::write(&path, content).map_err(|e| format!("Error writing {}: {}", path.display(), e))
+    };
+    for page in &pages {
+        write(format!("{}.md", page.name), &page.markdown)?;
+    }
+    write("index.md".to_string(), &docs::generate_index(&pages))
+}
+
+/// Parses the file at `path`, or every Pascal source under it, printing
+/// what `emit` selects to stdout and the syntax errors to stderr as
+/// `FILE:LINE:COL: message`. Unreadable files are reported and skipped.
+/// Returns whether all files were read and parsed without errors.
+fn run_parse(path: &Path, emit: Emit) -> Result<bool, String> {
+    let batch = path.is_dir();
+    let mut files = Vec::new();
+    if batch {
+        collect_files(path, directives::SOURCE_EXTENSIONS, &mut files)?;
+    } else {
+        files.push(path.to_path_buf());
+    }
+    files.sort();
+
+    let mut parser = DelphiParser::new();
+    let mut clean = true;
+    let mut trees = Vec::new();
+    for file in &files {
+        let source_code = match read_source(file, false) {
+            Ok(source_code) => source_code,
+            Err(e) => {
+                eprintln!("{}: {}, skipped", file.display(), e);
+                clean = false;
+                continue;
             }
         };
+        let tree = parser
+            .parse(&source_code)
+            .ok_or_else(|| format!("Error parsing {}", file.display()))?;
+        let line_index = LineIndex::new(&source_code, PositionEncoding::Utf8);
+        for diagnostic in parser.get_diagnostics(&tree, &source_code, &line_index) {
+            eprintln!(
+                "{}:{}:{}: {}",
+                file.display(),
+                diagnostic.range.start.line + 1,
+                diagnostic.range.start.character + 1,
+                diagnostic.message
+            );
+            clean = false;
+        }
 
-        // Read the file content
-        let source_code = match fs::read_to_string(&file) {
-            Ok(content) => content,
+        match emit {
+            Emit::Sexp if batch => {
+                println!("{}:\n{}", file.display(), tree.root_node().to_sexp())
+            }
+            Emit::Sexp => println!("Syntax tree:\n{}", tree.root_node().to_sexp()),
+            Emit::Json => {
+                let root = TreeNode::new(tree.root_node(), None, &line_index);
+                trees.push(serde_json::json!({
+                    "path": file.display().to_string(),
+                    "tree": root,
+                }));
+            }
+            Emit::Symbols => {
+                let uri = fs::canonicalize(file)
+                    .ok()
+                    .and_then(|path| Url::from_file_path(path).ok())
+                    .ok_or_else(|| format!("Cannot build URI for {}", file.display()))?;
+                let mut analyzer = SymbolAnalyzer::new();
+                analyzer.set_position_encoding(PositionEncoding::Utf8);
+                analyzer.set_content(tree, source_code, uri, None);
+                let symbols = analyzer.get_document_symbols().unwrap_or_default();
+                print_symbols(file, &symbols, 0);
+            }
+        }
+    }
+
+    // One object for a file, an array of them for a directory
+    if emit == Emit::Json && (batch || !trees.is_empty()) {
+        let output = if batch {
+            serde_json::Value::Array(trees)
+        } else {
+            trees.remove(0)
+        };
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&output).expect("JSON value is always serializable")
+        );
+    }
+    Ok(clean)
+}
+
+/// The fixes `--fix` applies.
+struct FixOptions {
+    codes: Vec<&'static str>,
+    dry_run: bool,
+}
+
+impl FixOptions {
+    /// The batch-safe codes and document rules `only` names, all of them
+    /// but `sort-uses` when empty.
+    fn new(only: &[String], dry_run: bool) -> Result<Self, String> {
+        let rules = || {
+            fixes::BATCH_SAFE_CODES
+                .iter()
+                .chain(fixes::DOCUMENT_RULES)
+                .copied()
+        };
+        if let Some(unknown) = only
+            .iter()
+            .find(|code| !rules().any(|rule| rule == code.as_str()))
+        {
+            return Err(format!(
+                "No batch fix for '{}', expected one of: {}",
+                unknown,
+                rules().collect::<Vec<_>>().join(", ")
+            ));
+        }
+        let codes = rules()
+            .filter(|code| {
+                if only.is_empty() {
+                    *code != fixes::SORT_USES
+                } else {
+                    only.iter().any(|only| only == code)
+                }
+            })
+            .collect();
+        Ok(Self { codes, dry_run })
+    }
+}
+
+/// Checks every Pascal source under `paths`, printing the diagnostics to
+/// stderr. With `fix`, the batch-safe fixes and the document rules it
+/// names are applied first, each file written back in its own encoding,
+/// and only the diagnostics left are printed. Bytes that are not UTF-8 are
+/// replaced when only checking, but make `fix` skip the file. Unreadable
+/// files are reported and skipped. Returns whether no diagnostics remained
+/// and all files were read.
+fn run_check(paths: &[PathBuf], fix: Option<&FixOptions>) -> Result<bool, String> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            collect_files(path, directives::SOURCE_EXTENSIONS, &mut files)?;
+        } else {
+            files.push(path.clone());
+        }
+    }
+    files.sort();
+    files.dedup();
+
+    // The passes whose findings `--fix` can fix are opt-in for editors
+    let mut settings = Settings::default();
+    settings.diagnostics.line_endings = true;
+    settings.diagnostics.ambiguous_characters = CharacterScan::All;
+
+    let mut parser = DelphiParser::new();
+    let interfaces = match fix {
+        Some(options) if options.codes.contains(&fixes::UNUSED_USES) => {
+            unit_interfaces(&mut parser, &files)
+        }
+        _ => HashMap::new(),
+    };
+    let mut clean = true;
+    for file in &files {
+        // Only files about to be rewritten must be valid UTF-8
+        let source = match fix {
+            Some(_) => read_source_for_rewrite(file),
+            None => read_source(file, true).map(|text| (text, false)),
+        };
+        let (text, byte_order_mark) = match source {
+            Ok(source) => source,
             Err(e) => {
-                eprintln!("Error reading file: {}", e);
-                return;
+                eprintln!("{}: {}, skipped", file.display(), e);
+                clean = false;
+                continue;
             }
         };
+        let uri = fs::canonicalize(file)
+            .ok()
+            .and_then(|path| Url::from_file_path(path).ok())
+            .ok_or_else(|| format!("Cannot build URI for {}", file.display()))?;
+        let mut document = Document::new(text, 0, PositionEncoding::Utf8);
+        let (mut diagnostics, analyzer) =
+            check_document(&mut parser, &document, &uri, &settings)
+                .ok_or_else(|| format!("Error parsing {}", file.display()))?;
 
-        // Create a parser
-        let mut parser = Parser::new();
-        parser
-            .set_language(unsafe { tree_sitter_pascal() })
-            .expect("Error loading Pascal grammar");
+        if let Some(options) = fix {
+            let unused: Vec<String> = analyzer
+                .get_used_units()
+                .into_iter()
+                .filter(|unit| {
+                    interfaces
+                        .get(&unit.to_lowercase())
+                        .is_some_and(|names| !names.iter().any(|name| analyzer.refers_to(name)))
+                })
+                .collect();
+            let candidates = diagnostics
+                .iter()
+                .filter(|diagnostic| {
+                    matches!(&diagnostic.code, Some(NumberOrString::String(code))
+                        if options.codes.contains(&code.as_str()))
+                })
+                .filter_map(|diagnostic| fixes::mechanical_fix(&document, diagnostic))
+                .chain(analyzer.batch_fixes(&options.codes, &unused))
+                .collect();
+            let (fixed, applied) =
+                fixes::apply_fixes(document.text(), document.line_index(), candidates);
+            if !applied.is_empty() {
+                if options.dry_run {
+                    print_diff(file, &document, &applied);
+                } else {
+                    write_source(file, &fixed, byte_order_mark)
+                        .map_err(|e| format!("Error writing {}: {}", file.display(), e))?;
+                }
+                let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+                for fix in &applied {
+                    *counts.entry(fix.code).or_default() += 1;
+                }
+                let counts: Vec<String> = counts
+                    .iter()
+                    .map(|(code, count)| format!("{} {}", count, code))
+                    .collect();
+                println!(
+                    "{}: {} {}",
+                    file.display(),
+                    if options.dry_run {
+                        "would fix"
+                    } else {
+                        "fixed"
+                    },
+                    counts.join(", ")
+                );
+                document = Document::new(fixed, 1, PositionEncoding::Utf8);
+                (diagnostics, _) = check_document(&mut parser, &document, &uri, &settings)
+                    .ok_or_else(|| format!("Error parsing {}", file.display()))?;
+            }
+        }
 
-        // Parse the source code
-        let tree = parser
-            .parse(&source_code, None)
-            .expect("Error parsing file");
+        for diagnostic in &diagnostics {
+            eprintln!(
+                "{}:{}:{}: {}: {}{}",
+                file.display(),
+                diagnostic.range.start.line + 1,
+                diagnostic.range.start.character + 1,
+                severity_name(diagnostic.severity),
+                diagnostic.message,
+                match &diagnostic.code {
+                    Some(NumberOrString::String(code)) => format!(" [{}]", code),
+                    _ => String::new(),
+                }
+            );
+        }
+        clean &= diagnostics.is_empty();
+    }
+    Ok(clean)
+}
+
+/// The interface names of the units among `files` that declare any, by
+/// lowercase unit name, for the `unused-uses` rule. Units outside `files`
+/// are never found unused.
+fn unit_interfaces(parser: &mut DelphiParser, files: &[PathBuf]) -> HashMap<String, Vec<String>> {
+    let mut interfaces = HashMap::new();
+    for file in files {
+        let Some(name) = file.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        let Ok(text) = read_source(file, true) else {
+            continue;
+        };
+        let Some(tree) = parser.parse(&text) else {
+            continue;
+        };
+        let Some(uri) = fs::canonicalize(file)
+            .ok()
+            .and_then(|path| Url::from_file_path(path).ok())
+        else {
+            continue;
+        };
+        let mut analyzer = SymbolAnalyzer::new();
+        analyzer.set_content(tree, text, uri, None);
+        if let Some(names) = analyzer.interface_names() {
+            interfaces.insert(name.to_lowercase(), names);
+        }
+    }
+    interfaces
+}
+
+/// The diagnostics the server publishes for `document`, but for those
+/// that need the rest of the workspace, ordered by position, and the
+/// analysis they come from. `None` when the document cannot be parsed.
+fn check_document(
+    parser: &mut DelphiParser,
+    document: &Document,
+    uri: &Url,
+    settings: &Settings,
+) -> Option<(Vec<Diagnostic>, SymbolAnalyzer)> {
+    let tree = parser.parse(document.text())?;
+    let mut diagnostics = parser.get_diagnostics(&tree, document.text(), document.line_index());
+    let mut analyzer = SymbolAnalyzer::new();
+    analyzer.set_settings(settings.clone());
+    analyzer.set_position_encoding(PositionEncoding::Utf8);
+    analyzer.set_content(tree, document.text().to_string(), uri.clone(), None);
+    diagnostics.extend(analyzer.get_diagnostics());
+    diagnostics.extend(document.inconsistent_line_endings());
+    diagnostics.extend(characters::scan(
+        document.text(),
+        document.line_index(),
+        settings.diagnostics.ambiguous_characters,
+    ));
+    diagnostics.sort_by_key(|diagnostic| {
+        (
+            diagnostic.range.start.line,
+            diagnostic.range.start.character,
+        )
+    });
+    Some((diagnostics, analyzer))
+}
+
+fn severity_name(severity: Option<DiagnosticSeverity>) -> &'static str {
+    match severity {
+        Some(DiagnosticSeverity::ERROR) => "error",
+        Some(DiagnosticSeverity::WARNING) => "warning",
+        Some(DiagnosticSeverity::INFORMATION) => "info",
+        _ => "hint",
+    }
+}
+
+/// Prints the lines `applied` changes in `document`, removed lines with
+/// `-` and added ones with `+`, one hunk per run of adjacent lines. Line
+/// terminators are named when a hunk changes them.
+fn print_diff(file: &Path, document: &Document, applied: &[fixes::MechanicalFix]) {
+    let text = document.text();
+    let line_index = document.line_index();
+    let mut edits: Vec<(Range<usize>, &str)> = applied
+        .iter()
+        .flat_map(|fix| &fix.edits)
+        .map(|edit| {
+            (
+                line_index.range_to_byte_range(edit.range),
+                edit.new_text.as_str(),
+            )
+        })
+        .collect();
+    edits.sort_by_key(|(range, _)| (range.start, range.end));
+    // The line of the last character an edit replaces
+    let last_line = |range: &Range<usize>| {
+        let end = line_index.offset_to_position(range.end);
+        if range.end > range.start && end.character == 0 && end.line > 0 {
+            end.line - 1
+        } else {
+            end.line
+        }
+    };
+
+    println!("--- {}\n+++ {}", file.display(), file.display());
+    let mut next = 0;
+    while next < edits.len() {
+        let first = line_index.offset_to_position(edits[next].0.start).line;
+        let mut last = last_line(&edits[next].0);
+        let mut end = next + 1;
+        while end < edits.len()
+            && line_index.offset_to_position(edits[end].0.start).line <= last + 1
+        {
+            last = last.max(last_line(&edits[end].0));
+            end += 1;
+        }
+        let (Some(first_span), Some(last_span)) = (
+            line_index.line_span(first as usize),
+            line_index.line_span(last as usize),
+        ) else {
+            break;
+        };
+        let hunk = first_span.start
+            ..last_span.end
+                + line_index
+                    .line_ending(last as usize)
+                    .map_or(0, |ending| ending.as_str().len());
+        let mut changed = String::new();
+        let mut cursor = hunk.start;
+        for (range, new_text) in &edits[next..end] {
+            changed.push_str(&text[cursor..range.start]);
+            changed.push_str(new_text);
+            cursor = range.end;
+        }
+        changed.push_str(&text[cursor..hunk.end]);
+
+        let old = diff_lines(&text[hunk.clone()]);
+        let new = diff_lines(&changed);
+        let name_endings = old
+            .iter()
+            .map(|(_, ending)| ending)
+            .ne(new.iter().map(|(_, ending)| ending));
+        println!("@@ line {} @@", first + 1);
+        for (prefix, lines) in [("-", &old), ("+", &new)] {
+            for (line, ending) in lines {
+                match ending {
+                    Some(ending) if name_endings => {
+                        println!("{}{} [{}]", prefix, line, ending.name())
+                    }
+                    _ => println!("{}{}", prefix, line),
+                }
+            }
+        }
+        next = end;
+    }
+}
+
+/// The lines of `text` and their terminators, without the empty line
+/// after a final terminator.
+fn diff_lines(text: &str) -> Vec<(&str, Option<LineEnding>)> {
+    let line_index = LineIndex::new(text, PositionEncoding::Utf8);
+    (0..line_index.line_count())
+        .filter_map(|line| {
+            Some((
+                &text[line_index.line_span(line)?],
+                line_index.line_ending(line),
+            ))
+        })
+        .filter(|(line, ending)| !line.is_empty() || ending.is_some())
+        .collect()
+}
+
+/// Prints a symbol outline as `FILE:LINE: kind name`, nested symbols
+/// indented below their parent.
+fn print_symbols(file: &Path, symbols: &[DocumentSymbol], depth: usize) {
+    for symbol in symbols {
+        println!(
+            "{}:{}: {}{} {}",
+            file.display(),
+            symbol.selection_range.start.line + 1,
+            "  ".repeat(depth),
+            symbol_kind_name(symbol.kind),
+            symbol.name
+        );
+        print_symbols(
+            file,
+            symbol.children.as_deref().unwrap_or_default(),
+            depth + 1,
+        );
+    }
+}
+
+fn symbol_kind_name(kind: SymbolKind) -> &'static str {
+    match kind {
+        SymbolKind::MODULE => "module",
+        SymbolKind::CLASS => "type",
+        SymbolKind::FUNCTION => "routine",
+        SymbolKind::METHOD => "method",
+        SymbolKind::FIELD => "field",
+        SymbolKind::PROPERTY => "property",
+        SymbolKind::CONSTANT => "const",
+        SymbolKind::VARIABLE => "var",
+        _ => "symbol",
+    }
+}
 
-        // Print the syntax tree
-        println!("Syntax tree:\n{}", tree.root_node().to_sexp());
+/// Collects the files under `dir` with one of `extensions`, recursively.
+fn collect_files(dir: &Path, extensions: &[&str], files: &mut Vec<PathBuf>) -> Result<(), String> {
+    let entries =
+        fs::read_dir(dir).map_err(|e| format!("Error reading {}: {}", dir.display(), e))?;
+    for entry in entries {
+        let path = entry
+            .map_err(|e| format!("Error reading {}: {}", dir.display(), e))?
+            .path();
+        if path.is_dir() {
+            collect_files(&path, extensions, files)?;
+        } else if path.extension().is_some_and(|ext| {
+            extensions
+                .iter()
+                .any(|extension| ext.eq_ignore_ascii_case(extension))
+        }) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+    let args = Cli::parse();
+
+    if args.lsp {
+        // LSP server mode, each session of the client served by a new
+        // server, as a server cannot be initialized again
+        let mut events = lsp::transport::split_sessions(tokio::io::stdin());
+        while let Some(event) = events.recv().await {
+            match event {
+                ClientEvent::Session(input) => {
+                    let (service, socket) = lsp::DelphiLanguageServer::service();
+                    tower_lsp::Server::new(input, tokio::io::stdout(), socket)
+                        .serve(service)
+                        .await;
+                }
+                ClientEvent::Exit(code) => std::process::exit(code),
+            }
+        }
+    } else if let Some(file) = &args.doc {
+        if let Err(e) = run_doc(file, args.output.as_deref()) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    } else if let Some(path) = &args.stats {
+        if let Err(e) = run_stats(path, args.format) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    } else if let (Some(dir), Some(output)) = (&args.doc_dir, &args.output) {
+        if let Err(e) = run_doc_dir(dir, output) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    } else if let (Some(location), Some(query)) = (&args.at, args.what) {
+        // CLI query mode
+        if let Err(e) = run_query(location, query) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    } else if args.check {
+        if args.paths.is_empty() {
+            eprintln!("Error: File path is required in CLI mode");
+            return;
+        }
+        let fix = match args
+            .fix
+            .then(|| FixOptions::new(&args.fix_only, args.dry_run))
+            .transpose()
+        {
+            Ok(fix) => fix,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(2);
+            }
+        };
+        match run_check(&args.paths, fix.as_ref()) {
+            Ok(true) => {}
+            Ok(false) => std::process::exit(1),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(2);
+            }
+        }
+    } else {
+        // CLI parsing mode
+        if args.paths.is_empty() {
+            eprintln!("Error: File path is required in CLI mode");
+            return;
+        }
+        let mut clean = true;
+        for path in &args.paths {
+            match run_parse(path, args.emit) {
+                Ok(parsed) => clean &= parsed,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        if !clean {
+            std::process::exit(1);
+        }
     }
 }
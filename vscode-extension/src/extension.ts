@@ -4,6 +4,7 @@ import {
 	LanguageClient,
 	LanguageClientOptions,
 	ServerOptions,
+	TextEdit,
 	TransportKind
 } from 'vscode-languageclient/node';
 
@@ -35,8 +36,10 @@ export function activate(context: vscode.ExtensionContext) {
 	// Options to control the language client
 	const clientOptions: LanguageClientOptions = {
 		documentSelector: [{ scheme: 'file', language: 'delphi' }],
+		initializationOptions: vscode.workspace.getConfiguration('delphi'),
 		synchronize: {
-			fileEvents: vscode.workspace.createFileSystemWatcher('**/*.{pas,dpr,dfm}')
+			configurationSection: 'delphi',
+			fileEvents: vscode.workspace.createFileSystemWatcher('**/*.{pas,dpr,dpk,dfm,fmx,pp,lpr,dproj}')
 		}
 	};
 
@@ -48,9 +51,354 @@ export function activate(context: vscode.ExtensionContext) {
 		clientOptions
 	);
 
+	// RTL stubs the server extracted are for reading only
+	client.onNotification('dls/readOnlyDocument', markReadOnly);
+
 	// Start the client and store the disposable
 	client.start();
 	context.subscriptions.push(client);
+
+	context.subscriptions.push(
+		vscode.commands.registerCommand('delphi.switchCompanion', switchCompanion),
+		vscode.commands.registerCommand('delphi.copySymbolPath', copySymbolPath),
+		vscode.commands.registerCommand('delphi.openUnit', openUnit),
+		vscode.commands.registerCommand('delphi.openPlayground', openPlayground),
+		vscode.commands.registerCommand('delphi.showDocumentDiagnostics', showDocumentDiagnostics),
+		vscode.commands.registerCommand('delphi.peekTypeMembers', peekTypeMembers),
+		vscode.commands.registerCommand('delphi.analyzeFully', analyzeFully),
+		vscode.commands.registerCommand('delphi.toggleComment', toggleComment),
+		vscode.commands.registerCommand('delphi.clearCache', clearCache)
+	);
+}
+
+async function markReadOnly(params: { textDocument: { uri: string } }) {
+	const uri = client.protocol2CodeConverter.asUri(params.textDocument.uri);
+	const editor = vscode.window.visibleTextEditors.find(
+		editor => editor.document.uri.toString() === uri.toString()
+	);
+	if (!editor) {
+		return;
+	}
+	await vscode.window.showTextDocument(editor.document, editor.viewColumn);
+	await vscode.commands.executeCommand('workbench.action.files.setActiveEditorReadonlyInSession');
+}
+
+// Opens the form file, test unit or tested unit of the active document,
+// asking which one when there are several
+async function switchCompanion() {
+	const editor = vscode.window.activeTextEditor;
+	if (!editor) {
+		return;
+	}
+	const uris = await client.sendRequest<string[]>('workspace/executeCommand', {
+		command: 'dls.switchCompanion',
+		arguments: [editor.document.uri.toString()]
+	});
+	if (!uris || uris.length === 0) {
+		vscode.window.showInformationMessage('No companion file found.');
+		return;
+	}
+	let target: string | undefined = uris[0];
+	if (uris.length > 1) {
+		const picked = await vscode.window.showQuickPick(
+			uris.map(uri => ({ label: path.basename(vscode.Uri.parse(uri).fsPath), description: vscode.workspace.asRelativePath(vscode.Uri.parse(uri)), uri })),
+			{ placeHolder: 'Switch to' }
+		);
+		target = picked?.uri;
+	}
+	if (target) {
+		await vscode.window.showTextDocument(vscode.Uri.parse(target));
+	}
+}
+
+// Lifts the outline-only mode the server uses for large documents, for
+// the active document until it is closed
+async function analyzeFully() {
+	const editor = vscode.window.activeTextEditor;
+	if (!editor) {
+		return;
+	}
+	await client.sendRequest('workspace/executeCommand', {
+		command: 'dls.analyzeFully',
+		arguments: [editor.document.uri.toString()]
+	});
+}
+
+// Comments out the selection, or uncomments it: whole lines with `//`,
+// a part of a line with `{ }`
+async function toggleComment() {
+	const editor = vscode.window.activeTextEditor;
+	if (!editor) {
+		return;
+	}
+	const edits = await client.sendRequest<TextEdit[] | null>('workspace/executeCommand', {
+		command: 'dls.toggleComment',
+		arguments: [
+			editor.document.uri.toString(),
+			client.code2ProtocolConverter.asRange(editor.selection)
+		]
+	});
+	const converted = await client.protocol2CodeConverter.asTextEdits(edits);
+	if (!converted || converted.length === 0) {
+		return;
+	}
+	await editor.edit(builder => {
+		for (const edit of converted) {
+			builder.replace(edit.range, edit.newText);
+		}
+	});
+}
+
+// Copies the qualified path of the symbol at the cursor, such as
+// `MyUnit / TCustomerList / Add(const Item: TCustomer)`
+async function copySymbolPath() {
+	const editor = vscode.window.activeTextEditor;
+	if (!editor) {
+		return;
+	}
+	const path = await client.sendRequest<{ display: string } | null>('workspace/executeCommand', {
+		command: 'dls.symbolPath',
+		arguments: [
+			editor.document.uri.toString(),
+			client.code2ProtocolConverter.asPosition(editor.selection.active)
+		]
+	});
+	if (!path) {
+		return;
+	}
+	await vscode.env.clipboard.writeText(path.display);
+	vscode.window.setStatusBarMessage(`Copied ${path.display}`, 3000);
+}
+
+// Opens a workspace unit by a partial name, so that `SysUtils` finds
+// System.SysUtils.pas
+async function openUnit() {
+	const query = await vscode.window.showInputBox({ prompt: 'Unit name', placeHolder: 'e.g. SysUtils' });
+	if (query === undefined) {
+		return;
+	}
+	const uris = await client.sendRequest<string[]>('workspace/executeCommand', {
+		command: 'dls.openUnit',
+		arguments: [query]
+	});
+	if (!uris || uris.length === 0) {
+		vscode.window.showInformationMessage(`No unit matches ${query}.`);
+		return;
+	}
+	const picked = await vscode.window.showQuickPick(
+		uris.map(uri => ({ label: path.basename(vscode.Uri.parse(uri).fsPath), description: vscode.workspace.asRelativePath(vscode.Uri.parse(uri)), uri })),
+		{ placeHolder: 'Open unit' }
+	);
+	if (picked) {
+		await vscode.window.showTextDocument(vscode.Uri.parse(picked.uri));
+	}
+}
+
+interface TypeMember {
+	name: string;
+	signature: string;
+	visibility: string;
+	declaringType: string;
+	inherited: boolean;
+	location: { uri: string; range: { start: { line: number; character: number }; end: { line: number; character: number } } };
+}
+
+// Lists the members of the type under the cursor, or of the type of the
+// variable or expression there, previewing each member while it is
+// highlighted and going back unless one is picked
+async function peekTypeMembers() {
+	const editor = vscode.window.activeTextEditor;
+	if (!editor) {
+		return;
+	}
+	const type = await client.sendRequest<{ name: string; ancestors: string[]; members: TypeMember[] } | null>('dls/typeMembers', {
+		textDocument: { uri: editor.document.uri.toString() },
+		position: client.code2ProtocolConverter.asPosition(editor.selection.active)
+	});
+	if (!type) {
+		vscode.window.showInformationMessage('No type found at the cursor.');
+		return;
+	}
+	const items = type.members.map(member => ({
+		label: member.name,
+		description: member.inherited ? `${member.visibility}, from ${member.declaringType}` : member.visibility,
+		detail: member.signature,
+		member
+	}));
+	const quickPick = vscode.window.createQuickPick<typeof items[number]>();
+	quickPick.items = items;
+	quickPick.placeholder = [type.name, ...type.ancestors].join(' < ');
+	const reveal = (member: TypeMember, preview: boolean) => vscode.window.showTextDocument(
+		vscode.Uri.parse(member.location.uri),
+		{ selection: client.protocol2CodeConverter.asRange(member.location.range), preview, preserveFocus: preview }
+	);
+	const original = { document: editor.document, selection: editor.selection };
+	let picked = false;
+	quickPick.onDidChangeActive(active => {
+		if (active[0]) {
+			reveal(active[0].member, true);
+		}
+	});
+	quickPick.onDidAccept(() => {
+		picked = true;
+		const item = quickPick.selectedItems[0];
+		quickPick.hide();
+		if (item) {
+			reveal(item.member, false);
+		}
+	});
+	quickPick.onDidHide(() => {
+		if (!picked) {
+			vscode.window.showTextDocument(original.document, { selection: original.selection });
+		}
+		quickPick.dispose();
+	});
+	quickPick.show();
+}
+
+interface AnalysisFailure {
+	operation: string;
+	position: { line: number; character: number } | null;
+	message: string;
+	timestamp: number;
+}
+
+interface RequestLatency {
+	operation: string;
+	cold: number;
+	warmCount: number;
+	warmMean: number | null;
+	warmMax: number | null;
+}
+
+let analysisOutput: vscode.OutputChannel | undefined;
+
+// Reports why hover and go to definition find nothing at the cursor, and
+// the recent requests on the document that found nothing
+async function showDocumentDiagnostics() {
+	const editor = vscode.window.activeTextEditor;
+	if (!editor) {
+		return;
+	}
+	const diagnosis = await client.sendRequest<{
+		hover: AnalysisFailure | null;
+		definition: AnalysisFailure | null;
+		recentFailures: AnalysisFailure[];
+		requestLatencies: RequestLatency[];
+	}>('workspace/executeCommand', {
+		command: 'dls.showDocumentDiagnostics',
+		arguments: [
+			editor.document.uri.toString(),
+			client.code2ProtocolConverter.asPosition(editor.selection.active)
+		]
+	});
+	if (!diagnosis) {
+		return;
+	}
+	analysisOutput ??= vscode.window.createOutputChannel('Delphi Analysis');
+	const describe = (failure: AnalysisFailure) => {
+		const at = failure.position ? ` at ${failure.position.line + 1}:${failure.position.character + 1}` : '';
+		return `${new Date(failure.timestamp).toLocaleTimeString()} ${failure.operation}${at}: ${failure.message}`;
+	};
+	analysisOutput.clear();
+	analysisOutput.appendLine(`Hover: ${diagnosis.hover?.message ?? 'found'}`);
+	analysisOutput.appendLine(`Definition: ${diagnosis.definition?.message ?? 'found'}`);
+	analysisOutput.appendLine('');
+	analysisOutput.appendLine('Recent failures:');
+	for (const failure of diagnosis.recentFailures) {
+		analysisOutput.appendLine(describe(failure));
+	}
+	analysisOutput.appendLine('');
+	analysisOutput.appendLine('Request latencies (ms):');
+	for (const latency of diagnosis.requestLatencies) {
+		const warm = latency.warmMean === null
+			? 'no warm requests'
+			: `warm mean ${latency.warmMean.toFixed(1)}, max ${latency.warmMax?.toFixed(1)} over ${latency.warmCount}`;
+		analysisOutput.appendLine(`${latency.operation}: cold ${latency.cold.toFixed(1)}, ${warm}`);
+	}
+	analysisOutput.show(true);
+}
+
+interface ParseTextResult {
+	tree: string;
+	diagnostics: { range: { start: { line: number; character: number } }; message: string }[];
+	symbols: { name: string; kind: number; children?: ParseTextResult['symbols'] }[];
+}
+
+// Opens a scratch editor that shows the syntax tree, diagnostics and
+// symbols the server produces for the text typed into it, reparsed on every
+// keystroke
+function openPlayground() {
+	const panel = vscode.window.createWebviewPanel(
+		'delphiPlayground',
+		'Pascal Playground',
+		vscode.ViewColumn.Beside,
+		{ enableScripts: true, retainContextWhenHidden: true }
+	);
+	panel.webview.html = playgroundHtml();
+	// Answers to earlier keystrokes arriving late are dropped
+	let latest = 0;
+	panel.webview.onDidReceiveMessage(async (message: { id: number; text: string }) => {
+		latest = message.id;
+		let output: string;
+		try {
+			const result = await client.sendRequest<ParseTextResult>('dls/parseText', { text: message.text });
+			output = formatParseResult(result);
+		} catch (error) {
+			output = `${error instanceof Error ? error.message : error}`;
+		}
+		if (message.id === latest) {
+			panel.webview.postMessage({ output });
+		}
+	});
+}
+
+function formatParseResult(result: ParseTextResult): string {
+	const diagnostics = result.diagnostics.map(
+		d => `${d.range.start.line + 1}:${d.range.start.character + 1}: ${d.message}`
+	);
+	const symbols: string[] = [];
+	const addSymbols = (list: ParseTextResult['symbols'], depth: number) => {
+		for (const symbol of list) {
+			symbols.push(`${'  '.repeat(depth)}${symbol.name}`);
+			addSymbols(symbol.children ?? [], depth + 1);
+		}
+	};
+	addSymbols(result.symbols, 0);
+	return [
+		'Diagnostics:', ...(diagnostics.length ? diagnostics : ['(none)']), '',
+		'Symbols:', ...(symbols.length ? symbols : ['(none)']), '',
+		'Syntax tree:', result.tree
+	].join('\n');
+}
+
+function playgroundHtml(): string {
+	return `<!DOCTYPE html>
+<html>
+<head>
+<meta charset="UTF-8">
+<meta http-equiv="Content-Security-Policy" content="default-src 'none'; style-src 'unsafe-inline'; script-src 'unsafe-inline';">
+<style>
+	body { display: flex; flex-direction: column; height: 100vh; margin: 0; padding: 8px; box-sizing: border-box; }
+	textarea, pre { flex: 1; font-family: var(--vscode-editor-font-family); font-size: var(--vscode-editor-font-size); }
+	textarea { color: var(--vscode-input-foreground); background: var(--vscode-input-background); resize: none; }
+	pre { overflow: auto; white-space: pre-wrap; }
+</style>
+</head>
+<body>
+<textarea id="source" spellcheck="false" placeholder="Type Pascal code"></textarea>
+<pre id="output"></pre>
+<script>
+	const vscode = acquireVsCodeApi();
+	const source = document.getElementById('source');
+	let id = 0;
+	source.addEventListener('input', () => vscode.postMessage({ id: ++id, text: source.value }));
+	window.addEventListener('message', event => {
+		document.getElementById('output').textContent = event.data.output;
+	});
+</script>
+</body>
+</html>`;
 }
 
 export function deactivate(): Thenable<void> | undefined {
@@ -59,3 +407,13 @@ export function deactivate(): Thenable<void> | undefined {
 	}
 	return client.stop();
 }
+
+// Deletes the sessions and RTL stubs the servers keep on disk, shared by
+// every window
+async function clearCache() {
+	const removed = await client.sendRequest<number>('workspace/executeCommand', {
+		command: 'dls.clearCache',
+		arguments: []
+	});
+	vscode.window.showInformationMessage(`Cleared ${removed} cached files.`);
+}